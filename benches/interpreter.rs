@@ -0,0 +1,146 @@
+//! The standard Lox benchmarks, scaled down to run in milliseconds, so the
+//! effect of a change on the interpreter can be measured with `cargo bench`.
+//! Each run scans, parses and resolves the script beforehand and times only
+//! running it, on a fresh interpreter whose output is discarded.
+
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use lox_interpreter::ast::Ast;
+use lox_interpreter::dead_code::eliminate_dead_code;
+use lox_interpreter::fold::fold_constants;
+use lox_interpreter::interpreter::{Backend, Interpreter};
+use lox_interpreter::parser::Parser;
+use lox_interpreter::platform::StdPlatform;
+use lox_interpreter::resolver::Resolver;
+use lox_interpreter::scanner::Scanner;
+
+const FIB: &str = "
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+print fib(20);
+";
+
+const BINARY_TREES: &str = "
+class Tree {
+    init(item, depth) {
+        this.item = item;
+        this.depth = depth;
+        if (depth > 0) {
+            var item2 = item + item;
+            depth = depth - 1;
+            this.left = Tree(item2 - 1, depth);
+            this.right = Tree(item2, depth);
+        } else {
+            this.left = nil;
+            this.right = nil;
+        }
+    }
+
+    check() {
+        if (this.left == nil) return this.item;
+        return this.item + this.left.check() - this.right.check();
+    }
+}
+
+var total = 0;
+for (var i = 0; i < 4; i = i + 1) {
+    total = total + Tree(i, 8).check();
+}
+print total;
+";
+
+const METHOD_CALL: &str = "
+class Toggle {
+    init(state) { this.state = state; }
+    value() { return this.state; }
+    activate() {
+        this.state = !this.state;
+        return this;
+    }
+}
+
+class NthToggle < Toggle {
+    init(state, maxCounter) {
+        super.init(state);
+        this.countMax = maxCounter;
+        this.count = 0;
+    }
+
+    activate() {
+        this.count = this.count + 1;
+        if (this.count >= this.countMax) {
+            super.activate();
+            this.count = 0;
+        }
+        return this;
+    }
+}
+
+var toggle = Toggle(true);
+var ntoggle = NthToggle(true, 3);
+var value = true;
+for (var i = 0; i < 2000; i = i + 1) {
+    value = toggle.activate().value();
+    value = ntoggle.activate().value();
+}
+print value;
+";
+
+const STRING_EQUALITY: &str = "
+var a1 = \"a1\";
+var a2 = \"a2\";
+var a3 = \"a3\";
+var a4 = \"a4\";
+var count = 0;
+for (var i = 0; i < 5000; i = i + 1) {
+    if (a1 == a1) count = count + 1;
+    if (a1 == a2) count = count + 1;
+    if (a2 == a3) count = count + 1;
+    if (a3 == a4) count = count + 1;
+    if (a4 == \"a4\") count = count + 1;
+    if (\"a1\" + \"\" == a1) count = count + 1;
+}
+print count;
+";
+
+fn prepare(source: &str) -> Ast {
+    let tokens = Scanner::new(source.to_string()).scan_tokens();
+    let mut ast = Parser::new(tokens).parse().expect("Benchmarks should parse.");
+    Resolver::new().run(&mut ast).expect("Benchmarks should resolve.");
+    fold_constants(&mut ast);
+    eliminate_dead_code(&mut ast);
+    ast
+}
+
+fn interpreter(backend: Backend) -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_backend(backend);
+    interpreter.set_platform(Box::new(StdPlatform::with_output(Box::new(io::sink()))));
+    interpreter
+}
+
+fn bench_script(c: &mut Criterion, name: &str, source: &str, backend: Backend) {
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || (interpreter(backend), prepare(source)),
+            |(mut interpreter, ast)| interpreter.run(ast).expect("Benchmarks should run."),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn benchmarks(c: &mut Criterion) {
+    bench_script(c, "fib", FIB, Backend::TreeWalker);
+    bench_script(c, "fib/bytecode", FIB, Backend::Bytecode);
+    bench_script(c, "binary_trees", BINARY_TREES, Backend::TreeWalker);
+    bench_script(c, "method_call", METHOD_CALL, Backend::TreeWalker);
+    bench_script(c, "string_equality", STRING_EQUALITY, Backend::TreeWalker);
+    bench_script(c, "string_equality/bytecode", STRING_EQUALITY, Backend::Bytecode);
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
@@ -2,50 +2,95 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+use crate::symbol::Symbol;
 use crate::token::Token;
+use crate::value::{PropertyCache, Value};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Ast {
     pub declarations: Vec<Declaration>,
 }
 
 pub type Class = Rc<RefCell<ClassStruct>>;
-pub type Depth = Option<u32>;
+/// Where the resolver found a local: how many scopes up, and its position
+/// among the names declared in that scope. None marks a global.
+pub type Depth = Option<Slot>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slot {
+    pub depth: u32,
+    pub index: u32,
+}
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ClassStruct {
-    pub methods: HashMap<String, FunDeclaration>,
+    // Interfaces named after `:`, which the resolver checks the class against.
+    pub interfaces: Vec<Token>,
+    pub methods: HashMap<Symbol, FunDeclaration>,
+    // Classes named after `with`, whose methods are copied into this one.
+    pub mixins: Vec<Expr>,
     pub name: Token,
+    // `set name(value) { ... }` methods, called when name is assigned.
+    pub setters: HashMap<Symbol, FunDeclaration>,
+    // `static var` fields, initialized in order once the class is defined.
+    pub statics: Vec<VarDeclaration>,
     pub superclass: Option<Expr>,
 }
 
-impl PartialEq for ClassStruct {
-    fn eq(&self, other: &ClassStruct) -> bool {
-        self.name == other.name
-    }
+/// A method an interface requires, as `name(params);`.
+#[derive(Debug, PartialEq)]
+pub struct MethodSignature {
+    pub name: Token,
+    pub params: Vec<Token>,
 }
 
-#[derive(Debug)]
+/// `interface Name { method(a, b); }`. Interfaces only exist for the resolver,
+/// which checks that classes declaring them define each method.
+#[derive(Debug, PartialEq)]
+pub struct Interface {
+    pub name: Token,
+    pub methods: Vec<MethodSignature>,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum Declaration {
     Class(Class),
+    Interface(Interface),
     Statement(Statement),
     VarDeclaration(VarDeclaration),
     FunDeclaration(FunDeclaration),
 }
 
 impl Declaration {
+    pub fn new_interface(name: Token, methods: Vec<MethodSignature>) -> Declaration {
+        Declaration::Interface(Interface { name, methods })
+    }
+
     pub fn new_class(
-        methods: HashMap<String, FunDeclaration>,
+        interfaces: Vec<Token>,
+        methods: HashMap<Symbol, FunDeclaration>,
+        mixins: Vec<Expr>,
         name: Token,
+        setters: HashMap<Symbol, FunDeclaration>,
+        statics: Vec<VarDeclaration>,
         superclass: Option<Expr>,
     ) -> Declaration {
         Declaration::Class(Rc::new(RefCell::new(ClassStruct {
+            interfaces,
             methods,
+            mixins,
             name,
+            setters,
+            statics,
             superclass,
         })))
     }
 
+    /// Whether this is a statement that yields, directly or from a nested
+    /// statement. Yields inside nested functions belong to those functions.
+    pub fn yields(&self) -> bool {
+        matches!(self, Declaration::Statement(statement) if statement.yields())
+    }
 }
 
 impl FunDeclarationStruct {
@@ -55,76 +100,214 @@ impl FunDeclarationStruct {
         body: Vec<Declaration>,
     ) -> FunDeclaration {
         Rc::new(RefCell::new(FunDeclarationStruct {
+            generator: body.iter().any(Declaration::yields),
             body,
+            getter: false,
             name,
             params,
         }))
     }
+
+    pub fn new_getter(name: Token, body: Vec<Declaration>) -> FunDeclaration {
+        Rc::new(RefCell::new(FunDeclarationStruct {
+            generator: body.iter().any(Declaration::yields),
+            body,
+            getter: true,
+            name,
+            params: Vec::new(),
+        }))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct VarDeclaration {
     pub name: Token,
     pub initializer: Option<Expr>,
+    pub constant: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct FunDeclarationStruct {
     pub body: Vec<Declaration>,
+    // Whether the body yields, making calls return a generator instead of
+    // running it.
+    pub generator: bool,
+    // A method declared without a parameter list, called on property access.
+    pub getter: bool,
     pub name: Token,
     pub params: Vec<Token>,
 }
 
 pub type FunDeclaration = Rc<RefCell<FunDeclarationStruct>>;
 
-impl PartialEq for FunDeclarationStruct {
-    fn eq(&self, other: &FunDeclarationStruct) -> bool {
-        self.name == other.name
-    }
-}
-
 impl VarDeclaration {
     pub fn new(name: Token, initializer: Option<Expr>) -> VarDeclaration {
-        VarDeclaration { initializer, name }
+        VarDeclaration { initializer, name, constant: false }
+    }
+
+    /// `const name = initializer;`, which may never be assigned again.
+    pub fn new_const(name: Token, initializer: Expr) -> VarDeclaration {
+        VarDeclaration {
+            initializer: Some(initializer),
+            name,
+            constant: true,
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct If {
     pub cond: Expr,
     pub true_branch: Statement,
     pub else_branch: Option<Statement>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct While {
     pub cond: Expr,
     pub body: Statement,
+    pub label: Option<Token>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Initializer {
     VarDeclaration(VarDeclaration),
     Expr(Expr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct For {
     pub initializer: Option<Initializer>,
     pub cond: Option<Expr>,
     pub increment: Option<Expr>,
     pub body: Statement,
+    pub label: Option<Token>,
+}
+
+/// `for (var name in iterable) body`, which runs body once for each element
+/// of a list, range, string or generator.
+#[derive(Debug, PartialEq)]
+pub struct ForIn {
+    pub name: Token,
+    pub iterable: Expr,
+    pub body: Statement,
+    pub label: Option<Token>,
+}
+
+/// A break or continue, with the label of the loop it targets if it names one.
+#[derive(Debug, PartialEq)]
+pub struct Jump {
+    pub keyword: Token,
+    pub label: Option<Token>,
+}
+
+/// `import "path";`, which runs another file once and defines its top-level
+/// names here, or `import "path" as name;`, which defines only name, bound to
+/// a namespace of them. path is the string literal token.
+#[derive(Debug, PartialEq)]
+pub struct Import {
+    pub keyword: Token,
+    pub path: Token,
+    pub alias: Option<Token>,
+}
+
+/// What a match arm requires of the value being matched.
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    // A literal, or a negated number literal, matching values equal to it.
+    Literal(Expr),
+    // A name, matching anything and binding it. `_` matches without binding.
+    Binding(Token),
+    // `[a, b]`, matching lists of exactly that many elements.
+    List(Vec<Pattern>),
+    // `Point(x, y)`, matching instances of the class or its subclasses. The
+    // patterns match the fields named by init's parameters, in order.
+    Class(Expr, Vec<Pattern>),
+}
+
+/// `case pattern: body`. Names the pattern binds are scoped to the body.
+#[derive(Debug, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Statement,
+}
+
+/// `match (value) { case ...: ... }`, which runs the body of the first arm
+/// whose pattern matches, if any.
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub keyword: Token,
+    pub value: Expr,
+    pub arms: Vec<MatchArm>,
+}
+
+/// `assert cond, message;`. The message is optional.
+#[derive(Debug, PartialEq)]
+pub struct Assert {
+    pub keyword: Token,
+    pub cond: Expr,
+    pub message: Option<Expr>,
+}
+
+/// `yield value;`, which suspends a generator. The value is optional.
+#[derive(Debug, PartialEq)]
+pub struct Yield {
+    pub keyword: Token,
+    pub value: Option<Expr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Throw {
+    pub keyword: Token,
+    pub value: Expr,
+}
+
+/// `catch (name) { body }`. The caught value is bound to name in the same
+/// scope as the body's declarations.
+#[derive(Debug, PartialEq)]
+pub struct CatchClause {
+    pub name: Token,
+    pub body: Vec<Declaration>,
+}
+
+/// A try block followed by a catch, a finally, or both.
+#[derive(Debug, PartialEq)]
+pub struct Try {
+    pub body: Vec<Declaration>,
+    pub catch: Option<CatchClause>,
+    pub finally: Option<Vec<Declaration>>,
 }
 
-#[derive(Debug)]
+/// `with (resource) as name { body }`, which calls the resource's close
+/// method however the body finishes.
+#[derive(Debug, PartialEq)]
+pub struct With {
+    pub keyword: Token,
+    pub resource: Expr,
+    pub name: Token,
+    pub body: Vec<Declaration>,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq)]
 pub enum Statement {
+    Assert(Box<Assert>),
     Block(Vec<Declaration>),
+    Break(Jump),
+    Continue(Jump),
     ExprStatement(Expr),
     For(Box<For>),
+    ForIn(Box<ForIn>),
     If(Box<If>),
+    Import(Import),
+    Match(Box<Match>),
     Print(Expr),
     Return(Option<Expr>),
+    Throw(Throw),
+    Try(Box<Try>),
     While(Box<While>),
+    With(Box<With>),
+    Yield(Yield),
 }
 
 impl Statement {
@@ -149,7 +332,7 @@ impl Statement {
     }
 
     pub fn new_while(cond: Expr, body: Statement) -> Statement {
-        Statement::While(Box::new(While { cond, body }))
+        Statement::While(Box::new(While { cond, body, label: None }))
     }
 
     pub fn new_for(
@@ -163,48 +346,175 @@ impl Statement {
             cond,
             increment,
             body,
+            label: None,
         }))
     }
+
+    pub fn new_for_in(name: Token, iterable: Expr, body: Statement) -> Statement {
+        Statement::ForIn(Box::new(ForIn {
+            name,
+            iterable,
+            body,
+            label: None,
+        }))
+    }
+
+    pub fn new_assert(keyword: Token, cond: Expr, message: Option<Expr>) -> Statement {
+        Statement::Assert(Box::new(Assert { keyword, cond, message }))
+    }
+
+    pub fn new_import(keyword: Token, path: Token, alias: Option<Token>) -> Statement {
+        Statement::Import(Import { keyword, path, alias })
+    }
+
+    pub fn new_throw(keyword: Token, value: Expr) -> Statement {
+        Statement::Throw(Throw { keyword, value })
+    }
+
+    pub fn new_match(keyword: Token, value: Expr, arms: Vec<MatchArm>) -> Statement {
+        Statement::Match(Box::new(Match { keyword, value, arms }))
+    }
+
+    pub fn new_yield(keyword: Token, value: Option<Expr>) -> Statement {
+        Statement::Yield(Yield { keyword, value })
+    }
+
+    pub fn new_try(body: Vec<Declaration>, catch: Option<CatchClause>, finally: Option<Vec<Declaration>>) -> Statement {
+        Statement::Try(Box::new(Try { body, catch, finally }))
+    }
+
+    pub fn new_with(keyword: Token, resource: Expr, name: Token, body: Vec<Declaration>) -> Statement {
+        Statement::With(Box::new(With {
+            keyword,
+            resource,
+            name,
+            body,
+        }))
+    }
+
+    /// Whether this statement yields, directly or from a nested statement. The
+    /// resolver rejects yields inside try and with statements, so those are not
+    /// searched.
+    pub fn yields(&self) -> bool {
+        match self {
+            Statement::Yield(_) => true,
+            Statement::Block(declarations) => declarations.iter().any(Declaration::yields),
+            Statement::If(if_statement) => {
+                if_statement.true_branch.yields()
+                    || if_statement.else_branch.as_ref().is_some_and(Statement::yields)
+            }
+            Statement::For(for_statement) => for_statement.body.yields(),
+            Statement::ForIn(for_in) => for_in.body.yields(),
+            Statement::While(while_statement) => while_statement.body.yields(),
+            Statement::Match(match_statement) => match_statement.arms.iter().any(|arm| arm.body.yields()),
+            _ => false,
+        }
+    }
+
+    /// Labels a loop so break and continue can target it by name. Returns
+    /// None when the statement is not a loop.
+    pub fn with_label(mut self, label: Token) -> Option<Statement> {
+        match &mut self {
+            Statement::For(for_statement) => for_statement.label = Some(label),
+            Statement::ForIn(for_in) => for_in.label = Some(label),
+            Statement::While(while_statement) => while_statement.label = Some(label),
+            _ => return None,
+        }
+        Some(self)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct AssignExpr {
     pub depth: Depth,
     pub initializer: Box<Expr>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Expr {
     pub kind: ExprKind,
     pub token: Token,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Call {
     pub arguments: Vec<Expr>,
     pub callee: Expr,
 }
 
-#[derive(Debug)]
+/// `object.name` or `object?.name`, with the token of name, and what the
+/// access found on the last instance it was made on.
+#[derive(Debug, PartialEq)]
+pub struct Get {
+    pub object: Expr,
+    pub cache: PropertyCache,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Set {
     pub object: Expr,
     pub value: Expr,
 }
 
-#[derive(Debug)]
+/// `object[index]`.
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    pub object: Expr,
+    pub index: Expr,
+}
+
+/// `object[index] = value`.
+#[derive(Debug, PartialEq)]
+pub struct SetIndex {
+    pub object: Expr,
+    pub index: Expr,
+    pub value: Expr,
+}
+
+/// `a < b <= c`: comparisons chained through shared operands, each evaluated
+/// once. operators holds one fewer token than operands.
+#[derive(Debug, PartialEq)]
+pub struct Chain {
+    pub operands: Vec<Expr>,
+    pub operators: Vec<Token>,
+}
+
+/// `[element for name in iterable if condition]`, where the condition is
+/// optional.
+#[derive(Debug, PartialEq)]
+pub struct Comprehension {
+    pub element: Expr,
+    pub name: Token,
+    pub iterable: Expr,
+    pub condition: Option<Expr>,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum ExprKind {
     Assign(AssignExpr),
     Binary(Box<BinaryExpr>),
     Call(Box<Call>),
-    Get(Box<Expr>),
+    Chain(Box<Chain>),
+    Comprehension(Box<Comprehension>),
+    Get(Box<Get>),
     Grouping(Box<Expr>),
-    Literal,
+    Index(Box<Index>),
+    // `[a, b]`, whose elements may be spreads.
+    List(Vec<Expr>),
+    // The value of the token, decoded once as the literal is made.
+    Literal(Value),
     Logical(Box<BinaryExpr>),
+    // `object?.name`, which is nil when the object is.
+    OptionalGet(Box<Get>),
     Set(Box<Set>),
-    This(Option<u32>),
+    SetIndex(Box<SetIndex>),
+    // `...list` among call arguments or list elements, which expands to the
+    // list's elements.
+    Spread(Box<Expr>),
+    This(Depth),
     Unary(Box<Expr>),
-    Variable(Option<u32>),
-    Super(Token, Option<u32>),
+    Variable(Depth),
+    Super(Token, Depth),
 }
 
 impl Expr {
@@ -236,7 +546,12 @@ impl Expr {
     }
 
     pub fn new_get(identifier: Token, object: Expr) -> Expr {
-        let kind = ExprKind::Get(Box::new(object));
+        let kind = ExprKind::Get(Box::new(Get { object, cache: PropertyCache::default() }));
+        Expr::new(kind, identifier)
+    }
+
+    pub fn new_optional_get(identifier: Token, object: Expr) -> Expr {
+        let kind = ExprKind::OptionalGet(Box::new(Get { object, cache: PropertyCache::default() }));
         Expr::new(kind, identifier)
     }
 
@@ -246,8 +561,48 @@ impl Expr {
         Expr::new(kind, beginning)
     }
 
+    pub fn new_index(object: Expr, index: Expr, closing_bracket: Token) -> Expr {
+        let kind = ExprKind::Index(Box::new(Index { object, index }));
+        Expr::new(kind, closing_bracket)
+    }
+
+    pub fn new_set_index(object: Expr, index: Expr, value: Expr, closing_bracket: Token) -> Expr {
+        let kind = ExprKind::SetIndex(Box::new(SetIndex { object, index, value }));
+        Expr::new(kind, closing_bracket)
+    }
+
+    pub fn new_list(bracket: Token, elements: Vec<Expr>) -> Expr {
+        Expr::new(ExprKind::List(elements), bracket)
+    }
+
+    pub fn new_chain(operands: Vec<Expr>, operators: Vec<Token>) -> Expr {
+        let token = operators[0].clone();
+        Expr::new(ExprKind::Chain(Box::new(Chain { operands, operators })), token)
+    }
+
+    pub fn new_comprehension(
+        bracket: Token,
+        element: Expr,
+        name: Token,
+        iterable: Expr,
+        condition: Option<Expr>,
+    ) -> Expr {
+        let kind = ExprKind::Comprehension(Box::new(Comprehension {
+            element,
+            name,
+            iterable,
+            condition,
+        }));
+        Expr::new(kind, bracket)
+    }
+
+    pub fn new_spread(ellipsis: Token, list: Expr) -> Expr {
+        Expr::new(ExprKind::Spread(Box::new(list)), ellipsis)
+    }
+
     pub fn new_literal(token: Token) -> Expr {
-        Expr::new(ExprKind::Literal, token)
+        let value = token.literal_value();
+        Expr::new(ExprKind::Literal(value), token)
     }
 
     pub fn new_logical(left: Expr, operator: Token, right: Expr) -> Expr {
@@ -285,14 +640,8 @@ impl Expr {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct BinaryExpr {
     pub left: Expr,
     pub right: Expr,
 }
-
-impl Ast {
-    pub fn print(&self) {
-        println!("{:?}", self);
-    }
-}
@@ -0,0 +1,1600 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::calendar::DateTime;
+use crate::csv;
+use crate::encoding;
+use crate::environment::Environment;
+use crate::gc;
+use crate::generator;
+use crate::interp_error::{InterpError, InterpResult};
+use crate::interpreter::{overflowed_integer, position, Interpreter};
+use crate::memory;
+use crate::random::Random;
+use crate::scheduler::Task;
+use crate::symbol::{intern, Symbol};
+use crate::token::Token;
+use crate::value::{
+    Builtin, Deque, DequeKind, Function, IClassStruct, List, Memoized, Namespace, Object, ObjectStruct, Value,
+    ValueKey, ValueSet,
+};
+
+/// Every native function, and the Math and Time namespaces, keyed by the
+/// global name each is bound to.
+pub fn globals() -> HashMap<Symbol, Value> {
+    let builtins = [
+        Builtin::new("assertEq", 2, assert_eq),
+        Builtin::new("assertTrue", 1, assert_true),
+        Builtin::new("base64Decode", 1, base64_decode),
+        Builtin::new("base64Encode", 1, base64_encode),
+        Builtin::new("basename", 1, basename),
+        Builtin::new("chdir", 1, chdir),
+        Builtin::new("clock", 0, clock),
+        Builtin::new("collect", 0, collect),
+        Builtin::new("contains", 2, collection_contains),
+        Builtin::new("cwd", 0, cwd),
+        Builtin::new_variadic("csvParse", 1, csv_parse),
+        Builtin::new("csvStringify", 1, csv_stringify),
+        Builtin::new("decode", 1, decode),
+        Builtin::new("dirname", 1, dirname),
+        Builtin::new("encode", 1, encode),
+        Builtin::new("epochMillis", 0, epoch_millis),
+        Builtin::new("exists", 1, exists),
+        Builtin::new("exit", 1, exit),
+        Builtin::new("extension", 1, extension),
+        Builtin::new("fields", 1, fields),
+        Builtin::new("filter", 2, filter),
+        Builtin::new_variadic("format", 1, format),
+        Builtin::new("get_field", 2, get_field),
+        Builtin::new("getenv", 1, getenv),
+        Builtin::new("hash", 1, hash),
+        Builtin::new("hexDecode", 1, hex_decode),
+        Builtin::new("hexEncode", 1, hex_encode),
+        Builtin::new("hostname", 0, hostname),
+        Builtin::new("insert", 3, insert),
+        Builtin::new("jsonParse", 1, json_parse),
+        Builtin::new("jsonStringify", 1, json_stringify),
+        Builtin::new("keys", 1, keys),
+        Builtin::new("len", 1, len),
+        Builtin::new("listDir", 1, list_dir),
+        Builtin::new("map", 2, map),
+        Builtin::new("memoize", 1, memoize),
+        Builtin::new("memStats", 0, mem_stats),
+        Builtin::new("methods", 1, methods),
+        Builtin::new("mkdir", 1, mkdir),
+        Builtin::new("monotonicMillis", 0, monotonic_millis),
+        Builtin::new("num", 1, num),
+        Builtin::new_variadic("pathJoin", 1, path_join),
+        Builtin::new("platform", 0, platform),
+        Builtin::new("pop", 1, pop),
+        Builtin::new_variadic("Queue", 0, new_queue),
+        Builtin::new_variadic("printf", 1, printf),
+        Builtin::new("push", 2, push),
+        Builtin::new("randInt", 2, rand_int),
+        Builtin::new("random", 0, random),
+        Builtin::new("readFile", 1, read_file),
+        Builtin::new("readLine", 0, read_line),
+        Builtin::new("reduce", 3, reduce),
+        Builtin::new("remove", 1, remove),
+        Builtin::new("removeAt", 2, remove_at),
+        Builtin::new("runTasks", 0, run_tasks),
+        Builtin::new("seed", 1, seed),
+        Builtin::new_variadic("Set", 0, new_set),
+        Builtin::new("set_field", 3, set_field),
+        Builtin::new("setenv", 2, setenv),
+        Builtin::new("sleep", 1, sleep),
+        Builtin::new_variadic("sort", 1, sort),
+        Builtin::new("spawn", 1, spawn),
+        Builtin::new_variadic("Stack", 0, new_stack),
+        Builtin::new("str", 1, str),
+        Builtin::new("tcpClose", 1, tcp_close),
+        Builtin::new("tcpConnect", 2, tcp_connect),
+        Builtin::new("tcpRecv", 1, tcp_recv),
+        Builtin::new("tcpSend", 2, tcp_send),
+        Builtin::new("test", 2, test),
+        Builtin::new("type", 1, type_of),
+        Builtin::new("values", 1, values),
+        Builtin::new("writeFile", 2, write_file),
+    ];
+    let mut globals = HashMap::new();
+    insert_builtins(&mut globals, builtins);
+    #[cfg(feature = "regex")]
+    insert_builtins(&mut globals, regex_builtins());
+    #[cfg(feature = "hashing")]
+    insert_builtins(&mut globals, hashing_builtins());
+    globals.insert(intern("Math"), math());
+    globals.insert(intern("Time"), time());
+    globals
+}
+
+// A namespace read like an imported module, as in `Math.sqrt(2)`.
+fn namespace(name: &str, members: HashMap<Symbol, Value>) -> Value {
+    Value::Namespace(Rc::new(Namespace {
+        name: name.to_string(),
+        members: members.keys().cloned().collect(),
+        environment: Environment::new_with_values(members),
+    }))
+}
+
+fn insert_builtins(globals: &mut HashMap<Symbol, Value>, builtins: impl IntoIterator<Item = Builtin>) {
+    for builtin in builtins {
+        globals.insert(intern(builtin.name), Value::Function(Function::Builtin(Rc::new(builtin))));
+    }
+}
+
+// Only built with the hashing feature, which pulls in the md-5 and sha2 crates.
+#[cfg(feature = "hashing")]
+fn hashing_builtins() -> [Builtin; 2] {
+    [Builtin::new("md5", 1, md5), Builtin::new("sha256", 1, sha256)]
+}
+
+// Only built with the regex feature, which pulls in the regex crate.
+#[cfg(feature = "regex")]
+fn regex_builtins() -> [Builtin; 3] {
+    [
+        Builtin::new("regexFind", 2, regex_find),
+        Builtin::new("regexMatch", 2, regex_match),
+        Builtin::new("regexReplace", 3, regex_replace),
+    ]
+}
+
+fn math() -> Value {
+    let functions = [
+        Builtin::new("abs", 1, abs),
+        Builtin::new("ceil", 1, ceil),
+        Builtin::new("cos", 1, cos),
+        Builtin::new("floor", 1, floor),
+        Builtin::new_variadic("max", 0, max),
+        Builtin::new_variadic("min", 0, min),
+        Builtin::new("pow", 2, pow),
+        Builtin::new("round", 1, round),
+        Builtin::new("sin", 1, sin),
+        Builtin::new("sqrt", 1, sqrt),
+    ];
+    let mut members = HashMap::new();
+    insert_builtins(&mut members, functions);
+    members.insert(intern("E"), Value::Number(std::f64::consts::E));
+    members.insert(intern("PI"), Value::Number(std::f64::consts::PI));
+    namespace("Math", members)
+}
+
+// Wall-clock time, as seconds since the Unix epoch, and UTC dates.
+fn time() -> Value {
+    let functions = [
+        Builtin::new("format", 2, time_format),
+        Builtin::new("now", 0, time_now),
+        Builtin::new("parse", 2, time_parse),
+    ];
+    let mut members = HashMap::new();
+    insert_builtins(&mut members, functions);
+    namespace("Time", members)
+}
+
+// The methods of each built-in type, made once per thread so reading one off
+// a value allocates nothing.
+type MethodTable = HashMap<&'static str, Rc<Builtin>>;
+
+fn method_table<const N: usize>(builtins: [Builtin; N]) -> MethodTable {
+    builtins.into_iter().map(|builtin| (builtin.name, Rc::new(builtin))).collect()
+}
+
+thread_local! {
+    static STRING_METHODS: MethodTable = method_table([
+        Builtin::new("charAt", 1, char_at),
+        Builtin::new("contains", 1, contains),
+        Builtin::new("endsWith", 1, ends_with),
+        Builtin::new("indexOf", 1, index_of),
+        Builtin::new("length", 0, length),
+        Builtin::new("lower", 0, lower),
+        Builtin::new("replace", 2, replace),
+        Builtin::new("split", 1, split),
+        Builtin::new("startsWith", 1, starts_with),
+        Builtin::new("substring", 2, substring),
+        Builtin::new("trim", 0, trim),
+        Builtin::new("upper", 0, upper),
+    ]);
+    static GENERATOR_METHODS: MethodTable = method_table([
+        Builtin::new("hasNext", 0, generator_has_next),
+        Builtin::new("next", 0, generator_next),
+    ]);
+    static RANGE_METHODS: MethodTable = method_table([
+        Builtin::new("toList", 0, range_to_list),
+    ]);
+    static SET_METHODS: MethodTable = method_table([
+        Builtin::new("add", 1, set_add),
+        Builtin::new("has", 1, set_has),
+        Builtin::new("intersect", 1, set_intersect),
+        Builtin::new("remove", 1, set_remove),
+        Builtin::new("union", 1, set_union),
+    ]);
+    static DEQUE_METHODS: MethodTable = method_table([
+        Builtin::new("isEmpty", 0, deque_is_empty),
+        Builtin::new("peek", 0, deque_peek),
+        Builtin::new("pop", 0, deque_pop),
+        Builtin::new("push", 1, deque_push),
+    ]);
+}
+
+/// Methods available on every string, looked up when a property is read off a
+/// string value.
+pub fn string_method(name: &str) -> Option<Rc<Builtin>> {
+    STRING_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Methods of generator values. next returns the next yielded value, or nil
+/// once the generator is done; hasNext tells the two apart.
+pub fn generator_method(name: &str) -> Option<Rc<Builtin>> {
+    GENERATOR_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Methods of range values.
+pub fn range_method(name: &str) -> Option<Rc<Builtin>> {
+    RANGE_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Methods available on every set.
+pub fn set_method(name: &str) -> Option<Rc<Builtin>> {
+    SET_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+// The receiver of a set method, and the set passed to it for union and
+// intersect.
+fn set_receiver(arguments: &[Value], index: usize, name: &str, token: &Token) -> Result<ValueSet, InterpError> {
+    match &arguments[index] {
+        Value::Set(set) => Ok(set.clone()),
+        _ if index == 0 => unreachable!("Set methods are only bound to sets."),
+        _ => Err(InterpError::new(&format!("{} expects a set.", name), token.clone())),
+    }
+}
+
+// `Set(a, b, ...)`: a set of its arguments.
+fn new_set(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::new_set(arguments))
+}
+
+// Returns whether the value was new to the set.
+fn set_add(_interpreter: &mut Interpreter, mut arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let set = set_receiver(&arguments, 0, "add", token)?;
+    let added = set.borrow_mut().insert(arguments.pop().unwrap());
+    Ok(Value::Boolean(added))
+}
+
+// Returns whether the value was in the set.
+fn set_remove(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let set = set_receiver(&arguments, 0, "remove", token)?;
+    let removed = set.borrow_mut().remove(&arguments[1]);
+    Ok(Value::Boolean(removed))
+}
+
+fn set_has(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let set = set_receiver(&arguments, 0, "has", token)?;
+    let found = set.borrow().contains(&arguments[1]);
+    Ok(Value::Boolean(found))
+}
+
+// A new set of the members of both, the receiver's first.
+fn set_union(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let set = set_receiver(&arguments, 0, "union", token)?;
+    let other = set_receiver(&arguments, 1, "union", token)?;
+    let members: Vec<Value> = set.borrow().members().iter().chain(other.borrow().members()).cloned().collect();
+    Ok(Value::new_set(members))
+}
+
+// A new set of the receiver's members that are also in the other set.
+fn set_intersect(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let set = set_receiver(&arguments, 0, "intersect", token)?;
+    let other = set_receiver(&arguments, 1, "intersect", token)?;
+    let other = other.borrow();
+    let members: Vec<Value> = set.borrow().members().iter().filter(|member| other.contains(member)).cloned().collect();
+    Ok(Value::new_set(members))
+}
+
+/// Methods available on every queue and stack.
+pub fn deque_method(name: &str) -> Option<Rc<Builtin>> {
+    DEQUE_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+fn deque_receiver(arguments: &[Value]) -> Deque {
+    match &arguments[0] {
+        Value::Deque(deque) => deque.clone(),
+        _ => unreachable!("Queue and stack methods are only bound to queues and stacks."),
+    }
+}
+
+// `Queue(a, b, ...)`: a queue that pops its arguments in order.
+fn new_queue(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::new_deque(DequeKind::Queue, arguments))
+}
+
+// `Stack(a, b, ...)`: a stack with its arguments pushed in order, so the last
+// is on top.
+fn new_stack(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::new_deque(DequeKind::Stack, arguments))
+}
+
+fn deque_push(_interpreter: &mut Interpreter, mut arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let deque = deque_receiver(&arguments);
+    deque.borrow_mut().push(arguments.pop().unwrap());
+    Ok(Value::Nil)
+}
+
+fn deque_pop(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let deque = deque_receiver(&arguments);
+    let mut deque = deque.borrow_mut();
+    let message = format!("Can't pop from an empty {}.", deque.kind.name().to_lowercase());
+    deque.pop().ok_or_else(|| InterpError::new(&message, token.clone()))
+}
+
+fn deque_peek(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let deque = deque_receiver(&arguments);
+    let deque = deque.borrow();
+    match deque.peek() {
+        Some(value) => Ok(value.clone()),
+        None => {
+            let message = format!("Can't peek at an empty {}.", deque.kind.name().to_lowercase());
+            Err(InterpError::new(&message, token.clone()))
+        }
+    }
+}
+
+fn deque_is_empty(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::Boolean(deque_receiver(&arguments).borrow().is_empty()))
+}
+
+fn range_to_list(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let Value::Range(range) = &arguments[0] else {
+        unreachable!("Range methods are only bound to ranges.");
+    };
+    Ok(Value::new_list(range.to_vec()))
+}
+
+fn generator_has_next(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let Value::Generator(generator) = &arguments[0] else {
+        unreachable!("Generator methods are only bound to generators.");
+    };
+    Ok(Value::Boolean(generator::has_next(interpreter, generator, token)?))
+}
+
+fn generator_next(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let Value::Generator(generator) = &arguments[0] else {
+        unreachable!("Generator methods are only bound to generators.");
+    };
+    Ok(generator::resume(interpreter, generator, token)?.unwrap_or(Value::Nil))
+}
+
+fn string_argument<'a>(arguments: &'a [Value], index: usize, token: &Token) -> Result<&'a str, InterpError> {
+    match &arguments[index] {
+        Value::StringV(s) => Ok(s),
+        _ => Err(InterpError::new("Expected string argument.", token.clone())),
+    }
+}
+
+fn integer_argument(arguments: &[Value], index: usize, token: &Token) -> Result<i64, InterpError> {
+    match &arguments[index] {
+        Value::Integer(i) => Ok(*i),
+        _ => Err(InterpError::new("Expected integer argument.", token.clone())),
+    }
+}
+
+fn number_argument(arguments: &[Value], index: usize, token: &Token) -> Result<f64, InterpError> {
+    match arguments[index].as_number() {
+        Some(n) => Ok(n),
+        None => Err(InterpError::new("Expected number argument.", token.clone())),
+    }
+}
+
+// String positions count characters rather than bytes.
+fn character_index(s: &str, index: i64, token: &Token) -> Result<usize, InterpError> {
+    match usize::try_from(index) {
+        Ok(index) if index <= s.chars().count() => Ok(index),
+        _ => Err(InterpError::new("String index out of range.", token.clone())),
+    }
+}
+
+fn char_at(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let index = integer_argument(&arguments, 1, token)?;
+    match usize::try_from(index).ok().and_then(|index| s.chars().nth(index)) {
+        Some(c) => Ok(Value::new_string(c.to_string())),
+        None => Err(InterpError::new("String index out of range.", token.clone())),
+    }
+}
+
+fn contains(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let needle = string_argument(&arguments, 1, token)?;
+    Ok(Value::Boolean(s.contains(needle)))
+}
+
+fn ends_with(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let suffix = string_argument(&arguments, 1, token)?;
+    Ok(Value::Boolean(s.ends_with(suffix)))
+}
+
+fn index_of(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let needle = string_argument(&arguments, 1, token)?;
+    match s.find(needle) {
+        Some(byte_index) => Ok(Value::Integer(s[..byte_index].chars().count() as i64)),
+        None => Ok(Value::Integer(-1)),
+    }
+}
+
+fn length(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::Integer(s.chars().count() as i64))
+}
+
+// Case mapping is Unicode-aware, so a character may map to several.
+fn lower(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(s.to_lowercase()))
+}
+
+fn replace(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let from = string_argument(&arguments, 1, token)?;
+    let to = string_argument(&arguments, 2, token)?;
+    if from.is_empty() {
+        return Err(InterpError::new("Cannot replace an empty string.", token.clone()));
+    }
+    Ok(Value::new_string(s.replace(from, to)))
+}
+
+// An empty separator splits a string into its characters.
+fn split(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let separator = string_argument(&arguments, 1, token)?;
+    let parts = if separator.is_empty() {
+        s.chars().map(|c| Value::new_string(c.to_string())).collect()
+    } else {
+        s.split(separator).map(|part| Value::new_string(part.to_string())).collect()
+    };
+    Ok(Value::new_list(parts))
+}
+
+fn starts_with(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let prefix = string_argument(&arguments, 1, token)?;
+    Ok(Value::Boolean(s.starts_with(prefix)))
+}
+
+fn substring(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    let start = character_index(s, integer_argument(&arguments, 1, token)?, token)?;
+    let end = character_index(s, integer_argument(&arguments, 2, token)?, token)?;
+    if start > end {
+        return Err(InterpError::new("Substring start is after its end.", token.clone()));
+    }
+    Ok(Value::new_string(s.chars().skip(start).take(end - start).collect::<String>()))
+}
+
+// Strips Unicode whitespace from both ends.
+fn trim(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(s.trim().to_string()))
+}
+
+fn upper(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(s.to_uppercase()))
+}
+
+fn object_argument(arguments: &[Value], index: usize, token: &Token) -> Result<Object, InterpError> {
+    match &arguments[index] {
+        Value::Object(object) => Ok(object.clone()),
+        _ => Err(InterpError::new("Expected instance argument.", token.clone())),
+    }
+}
+
+// The collection builtins treat an instance as a map from its field names to
+// their values, as indexing does.
+
+fn list_receiver(arguments: &[Value], name: &str, token: &Token) -> Result<List, InterpError> {
+    match &arguments[0] {
+        Value::List(list) => Ok(list.clone()),
+        _ => Err(InterpError::new(&format!("{} expects a list.", name), token.clone())),
+    }
+}
+
+fn instance_receiver(arguments: &[Value], name: &str, token: &Token) -> Result<Object, InterpError> {
+    match &arguments[0] {
+        Value::Object(object) => Ok(object.clone()),
+        _ => Err(InterpError::new(&format!("{} expects an instance.", name), token.clone())),
+    }
+}
+
+fn len(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let len = match &arguments[0] {
+        Value::List(list) => list.borrow().len(),
+        Value::Object(object) => object.borrow().fields.len(),
+        Value::Range(range) => {
+            let len = range.len();
+            return Ok(i64::try_from(len).map_or_else(|_| overflowed_integer(&len.to_string()), Value::Integer));
+        }
+        Value::StringV(s) => s.chars().count(),
+        Value::Set(set) => set.borrow().len(),
+        Value::Deque(deque) => deque.borrow().len(),
+        _ => {
+            let message = "len expects a string, list, queue, range, set, stack or instance.";
+            return Err(InterpError::new(message, token.clone()));
+        }
+    };
+    Ok(Value::Integer(len as i64))
+}
+
+// Whether a string holds a substring, a list an equal element, a range an
+// integer, a set a member, or an instance a field of the given name.
+fn collection_contains(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let found = match (&arguments[0], &arguments[1]) {
+        (Value::List(list), value) => list.borrow().contains(value),
+        (Value::Object(object), Value::StringV(name)) => object.borrow().fields.contains_key(&**name),
+        (Value::Range(range), Value::Integer(i)) => range.contains(*i),
+        (Value::Range(_), _) => false,
+        (Value::Set(set), value) => set.borrow().contains(value),
+        (Value::StringV(s), Value::StringV(needle)) => s.contains(&**needle),
+        (Value::Object(_) | Value::StringV(_), _) => {
+            return Err(InterpError::new("contains expects a string to look for.", token.clone()))
+        }
+        _ => return Err(InterpError::new("contains expects a string, list, range, set or instance.", token.clone())),
+    };
+    Ok(Value::Boolean(found))
+}
+
+fn push(_interpreter: &mut Interpreter, mut arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let list = list_receiver(&arguments, "push", token)?;
+    list.borrow_mut().push(arguments.pop().unwrap());
+    Ok(Value::Nil)
+}
+
+fn pop(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let list = list_receiver(&arguments, "pop", token)?;
+    let popped = list.borrow_mut().pop();
+    popped.ok_or_else(|| InterpError::new("Can't pop from an empty list.", token.clone()))
+}
+
+// Inserts before the element at index, or at the end when index is the length.
+fn insert(_interpreter: &mut Interpreter, mut arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let list = list_receiver(&arguments, "insert", token)?;
+    let value = arguments.pop().unwrap();
+    let i = position(&arguments[1], list.borrow().len() + 1, token)?;
+    list.borrow_mut().insert(i, value);
+    Ok(Value::Nil)
+}
+
+// Removes the element at index and returns it.
+fn remove_at(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let list = list_receiver(&arguments, "removeAt", token)?;
+    let i = position(&arguments[1], list.borrow().len(), token)?;
+    let removed = list.borrow_mut().remove(i);
+    Ok(removed)
+}
+
+// An instance's field names, sorted.
+fn keys(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let object = instance_receiver(&arguments, "keys", token)?;
+    let mut names: Vec<String> = object.borrow().fields.keys().map(|name| name.to_string()).collect();
+    names.sort();
+    Ok(Value::new_list(names.into_iter().map(Value::new_string).collect()))
+}
+
+// An instance's field values, in the order keys gives their names.
+fn values(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let object = instance_receiver(&arguments, "values", token)?;
+    let object = object.borrow();
+    let mut fields: Vec<(&Symbol, &Value)> = object.fields.iter().collect();
+    fields.sort_by_key(|(name, _)| *name);
+    Ok(Value::new_list(fields.into_iter().map(|(_, value)| value.clone()).collect()))
+}
+
+// Reads a field, or a bound method, by a computed name, as `object.name` would.
+fn get_field(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let object = object_argument(&arguments, 0, token)?;
+    let name = string_argument(&arguments, 1, token)?;
+    let identifier = Token {
+        content: intern(name),
+        ..token.clone()
+    };
+    ObjectStruct::get(&object, &identifier)
+}
+
+fn set_field(_interpreter: &mut Interpreter, mut arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let object = object_argument(&arguments, 0, token)?;
+    let name = intern(string_argument(&arguments, 1, token)?);
+    let value = arguments.pop().unwrap();
+    object.borrow_mut().fields.insert(name, value.clone());
+    Ok(value)
+}
+
+// The names of an instance's fields, sorted so the order is stable.
+fn fields(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let object = object_argument(&arguments, 0, token)?;
+    let mut names: Vec<String> = object.borrow().fields.keys().map(|name| name.to_string()).collect();
+    names.sort();
+    Ok(Value::new_list(names.into_iter().map(Value::new_string).collect()))
+}
+
+// The names of a class's methods, including inherited ones.
+fn methods(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match &arguments[0] {
+        Value::Class(class) => {
+            let names = class.borrow().method_names();
+            Ok(Value::new_list(names.into_iter().map(Value::new_string).collect()))
+        }
+        _ => Err(InterpError::new("Expected class argument.", token.clone())),
+    }
+}
+
+// Wraps a function so each distinct list of arguments, compared as ValueKeys,
+// only runs it once. Only sensible for functions without side effects.
+fn memoize(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match &arguments[0] {
+        Value::Function(function) => Ok(Value::Function(Function::Memoized(Memoized {
+            function: Box::new(function.clone()),
+            cache: Rc::new(RefCell::new(HashMap::new())),
+        }))),
+        _ => Err(InterpError::new("memoize expects a function.", token.clone())),
+    }
+}
+
+// The list and function that map, filter and reduce take first. The elements
+// are copied out, so the function can change the list as it goes.
+fn list_and_function(arguments: &[Value], name: &str, token: &Token) -> Result<(Vec<Value>, Function), InterpError> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::List(list), Value::Function(function)) => Ok((list.borrow().clone(), function.clone())),
+        _ => Err(InterpError::new(&format!("{} expects a list and a function.", name), token.clone())),
+    }
+}
+
+fn map(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let (elements, function) = list_and_function(&arguments, "map", token)?;
+    let name = function.name();
+    let mut mapped = Vec::new();
+    for element in elements {
+        mapped.push(interpreter.call_function(function.clone(), vec![element], &name, token)?);
+    }
+    Ok(Value::new_list(mapped))
+}
+
+fn filter(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let (elements, function) = list_and_function(&arguments, "filter", token)?;
+    let name = function.name();
+    let mut kept = Vec::new();
+    for element in elements {
+        if interpreter.call_function(function.clone(), vec![element.clone()], &name, token)?.is_truthy() {
+            kept.push(element);
+        }
+    }
+    Ok(Value::new_list(kept))
+}
+
+// Folds the list from the left, calling the function with the running value
+// and each element in turn.
+fn reduce(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let (elements, function) = list_and_function(&arguments, "reduce", token)?;
+    let name = function.name();
+    let mut accumulator = arguments[2].clone();
+    for element in elements {
+        accumulator = interpreter.call_function(function.clone(), vec![accumulator, element], &name, token)?;
+    }
+    Ok(accumulator)
+}
+
+// A sorted copy of the list. Without a comparator, numbers and strings sort in
+// the order `<` puts them. A comparator returns a negative number when its
+// first argument goes first, a positive one when its second does, and zero to
+// keep their order; the sort is stable.
+fn sort(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let (elements, comparator) = match &arguments[..] {
+        [Value::List(list)] => (list.borrow().clone(), None),
+        [Value::List(list), Value::Function(function)] => (list.borrow().clone(), Some(function.clone())),
+        _ => return Err(InterpError::new("sort expects a list and an optional comparator function.", token.clone())),
+    };
+    let sorted = match comparator {
+        Some(comparator) => {
+            let name = comparator.name();
+            merge_sort(elements, &mut |a, b| {
+                let order = interpreter.call_function(comparator.clone(), vec![a.clone(), b.clone()], &name, token)?;
+                match order.as_number() {
+                    Some(order) => Ok(order <= 0.0),
+                    None => Err(InterpError::new("sort's comparator must return a number.", token.clone())),
+                }
+            })?
+        }
+        None => merge_sort(elements, &mut |a, b| natural_order(a, b, token))?,
+    };
+    Ok(Value::new_list(sorted))
+}
+
+// Whether a may come before b when sorting without a comparator.
+fn natural_order(a: &Value, b: &Value, token: &Token) -> Result<bool, InterpError> {
+    match (a, b) {
+        (Value::StringV(s1), Value::StringV(s2)) => return Ok(s1 <= s2),
+        (Value::Integer(i1), Value::Integer(i2)) => return Ok(i1 <= i2),
+        _ => {}
+    }
+    #[cfg(feature = "bignum")]
+    if let (Some(b1), Some(b2)) = (a.as_big_integer(), b.as_big_integer()) {
+        return Ok(b1 <= b2);
+    }
+    match (a.as_number(), b.as_number()) {
+        (Some(n1), Some(n2)) => Ok(n1 <= n2),
+        _ => Err(InterpError::new("sort can only compare numbers with numbers and strings with strings.", token.clone())),
+    }
+}
+
+// A stable merge sort that stops at the first error from in_order, which says
+// whether its two arguments are already in order. Unlike the standard library
+// sorts, it is fine with an inconsistent comparison.
+fn merge_sort(
+    mut values: Vec<Value>,
+    in_order: &mut dyn FnMut(&Value, &Value) -> Result<bool, InterpError>,
+) -> Result<Vec<Value>, InterpError> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let mut left = merge_sort(values, in_order)?.into_iter().peekable();
+    let mut right = merge_sort(right, in_order)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        let next = if in_order(a, b)? { left.next() } else { right.next() };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+// Queues a function to run as a task. Tasks give up their turn with `yield;`.
+// Nothing runs them until the script calls runTasks, or the embedder calls
+// Interpreter::run_task_round; tasks still queued when the script ends are
+// left unrun, so an embedder can keep giving them turns.
+fn spawn(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match &arguments[0] {
+        Value::Function(function) if function.arity() == 0 => {
+            interpreter.scheduler.push(Task::Start(function.clone()), token.clone());
+            Ok(Value::Nil)
+        }
+        Value::Function(_) => Err(InterpError::new("Tasks must take no arguments.", token.clone())),
+        _ => Err(InterpError::new("spawn expects a function.", token.clone())),
+    }
+}
+
+// Runs rounds of tasks until every task has finished.
+fn run_tasks(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    while interpreter.has_tasks() {
+        interpreter.run_task_round()?;
+    }
+    Ok(Value::Nil)
+}
+
+/// How a placeholder lays out its argument: `{index:<width.precision}`, where
+/// every part is optional.
+struct Placeholder {
+    index: usize,
+    align: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+}
+
+// The largest width or precision a placeholder may ask for, so a typo can't
+// ask for more memory than exists.
+const MAX_PLACEHOLDER_SIZE: usize = 65535;
+
+// Parses the text between a placeholder's braces. Placeholders without an
+// index take the argument after the last one taken that way.
+fn placeholder(text: &str, next: &mut usize) -> Result<Placeholder, String> {
+    let invalid = || format!("Invalid placeholder '{{{}}}' in format string.", text);
+    let (index, spec) = text.split_once(':').unwrap_or((text, ""));
+    let index = if index.is_empty() {
+        *next += 1;
+        *next - 1
+    } else {
+        index.parse().map_err(|_| invalid())?
+    };
+    let align = spec.chars().next().filter(|c| matches!(c, '<' | '^' | '>'));
+    let spec = &spec[align.map_or(0, char::len_utf8)..];
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, Some(precision.parse().map_err(|_| invalid())?)),
+        None => (spec, None),
+    };
+    let width = if width.is_empty() { 0 } else { width.parse().map_err(|_| invalid())? };
+    if width > MAX_PLACEHOLDER_SIZE || precision.is_some_and(|precision| precision > MAX_PLACEHOLDER_SIZE) {
+        return Err(invalid());
+    }
+    Ok(Placeholder {
+        index,
+        align,
+        width,
+        precision,
+    })
+}
+
+// A placeholder's text before padding. A precision gives numbers that many
+// decimal places and cuts other values down to that many characters.
+fn format_argument(
+    interpreter: &mut Interpreter,
+    value: &Value,
+    precision: Option<usize>,
+    token: &Token,
+) -> Result<String, InterpError> {
+    match (value.as_number(), precision) {
+        (Some(n), Some(precision)) => Ok(format!("{:.*}", precision, n)),
+        (_, precision) => {
+            let text = interpreter.stringify(value, token)?;
+            Ok(match precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text,
+            })
+        }
+    }
+}
+
+// `format("{} is {:>6.2}", name, n)`. Numbers align right by default and
+// everything else left; `{{` and `}}` stand for literal braces.
+fn format(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    Ok(Value::new_string(format_arguments(interpreter, &arguments, "format", token)?))
+}
+
+// Like format, but writes the text out without adding a newline.
+fn printf(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let text = format_arguments(interpreter, &arguments, "printf", token)?;
+    interpreter
+        .platform
+        .write_output(&text)
+        .map_err(|_| InterpError::new("Could not write output.", token.clone()))?;
+    Ok(Value::Nil)
+}
+
+// Fills in the template that is the first of arguments with the rest.
+fn format_arguments(
+    interpreter: &mut Interpreter,
+    arguments: &[Value],
+    name: &str,
+    token: &Token,
+) -> Result<String, InterpError> {
+    let Value::StringV(template) = &arguments[0] else {
+        return Err(InterpError::new(&format!("{} expects a string.", name), token.clone()));
+    };
+    let values = &arguments[1..];
+    let mut output = String::new();
+    let mut next = 0;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                output.push('}');
+            }
+            '}' => return Err(InterpError::new("Unmatched '}' in format string.", token.clone())),
+            '{' => {
+                let Some((text, rest)) = chars.as_str().split_once('}') else {
+                    return Err(InterpError::new("Unmatched '{' in format string.", token.clone()));
+                };
+                chars = rest.chars();
+                let placeholder =
+                    placeholder(text, &mut next).map_err(|message| InterpError::new(&message, token.clone()))?;
+                let Some(value) = values.get(placeholder.index) else {
+                    let message = format!(
+                        "Format string refers to argument {}, but only {} were given.",
+                        placeholder.index,
+                        values.len()
+                    );
+                    return Err(InterpError::new(&message, token.clone()));
+                };
+                let text = format_argument(interpreter, value, placeholder.precision, token)?;
+                let default_align = if value.as_number().is_some() { '>' } else { '<' };
+                let padding = placeholder.width.saturating_sub(text.chars().count());
+                let (before, after) = match placeholder.align.unwrap_or(default_align) {
+                    '<' => (0, padding),
+                    '^' => (padding / 2, padding - padding / 2),
+                    _ => (padding, 0),
+                };
+                output.push_str(&" ".repeat(before));
+                output.push_str(&text);
+                output.push_str(&" ".repeat(after));
+            }
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+// Seconds, as in jlox. jlox counts from the Unix epoch, but scripts only ever
+// subtract one reading from another, so this counts on the monotonic clock
+// from when the interpreter started, and is immune to the wall clock jumping.
+fn clock(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let time = interpreter.platform.now() - interpreter.start;
+    Ok(Value::Number(time.as_secs_f64()))
+}
+
+// Whole milliseconds since the interpreter started, on the monotonic clock.
+fn monotonic_millis(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let time = interpreter.platform.now() - interpreter.start;
+    Ok(Value::Integer(time.as_millis() as i64))
+}
+
+// Whole milliseconds since the Unix epoch, on the wall clock.
+fn epoch_millis(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::Integer(interpreter.platform.wall_time().as_millis() as i64))
+}
+
+// Pauses for a number of milliseconds. Returns nil.
+fn sleep(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let millis = number_argument(&arguments, 0, token)?;
+    match Duration::try_from_secs_f64(millis / 1000.0) {
+        Ok(duration) => interpreter.sleep(duration).map(|()| Value::Nil),
+        Err(_) => Err(InterpError::new("sleep expects a non-negative number of milliseconds.", token.clone())),
+    }
+}
+
+// The next line of input without its line ending, or nil at the end of input.
+fn read_line(interpreter: &mut Interpreter, _arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match interpreter.platform.read_line() {
+        Ok(Some(line)) => {
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            Ok(Value::new_string(line.strip_suffix('\r').unwrap_or(line).to_string()))
+        }
+        Ok(None) => Ok(Value::Nil),
+        Err(error) => Err(InterpError::new(&format!("Could not read input: {}.", error), token.clone())),
+    }
+}
+
+fn read_file(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    match interpreter.platform.read_file(path) {
+        Ok(contents) => Ok(Value::new_string(contents)),
+        Err(error) => Err(InterpError::new(&format!("Could not read file '{}': {}", path, error), token.clone())),
+    }
+}
+
+// Replaces the file's contents, creating it if needed. Returns true.
+fn write_file(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    let contents = string_argument(&arguments, 1, token)?;
+    match interpreter.platform.write_file(path, contents) {
+        Ok(()) => Ok(Value::Boolean(true)),
+        Err(error) => Err(InterpError::new(&format!("Could not write file '{}': {}", path, error), token.clone())),
+    }
+}
+
+// Connections are numbered handles, kept open by the platform until closed.
+fn tcp_connect(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let host = string_argument(&arguments, 0, token)?;
+    let Ok(port) = u16::try_from(integer_argument(&arguments, 1, token)?) else {
+        return Err(InterpError::new("Port out of range.", token.clone()));
+    };
+    match interpreter.platform.tcp_connect(host, port) {
+        Ok(handle) => Ok(Value::Integer(handle)),
+        Err(error) => Err(InterpError::new(&format!("Could not connect to {}:{}: {}", host, port, error), token.clone())),
+    }
+}
+
+// Sends the whole string. Returns true.
+fn tcp_send(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let handle = integer_argument(&arguments, 0, token)?;
+    let data = string_argument(&arguments, 1, token)?;
+    match interpreter.platform.tcp_send(handle, data.as_bytes()) {
+        Ok(()) => Ok(Value::Boolean(true)),
+        Err(error) => Err(InterpError::new(&format!("Could not send: {}", error), token.clone())),
+    }
+}
+
+// The next data to arrive, or nil once the connection is closed. Bytes that
+// aren't valid UTF-8 are replaced.
+fn tcp_recv(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let handle = integer_argument(&arguments, 0, token)?;
+    loop {
+        let data = match interpreter.platform.tcp_recv(handle) {
+            Ok(data) => data,
+            Err(error) => return Err(InterpError::new(&format!("Could not receive: {}", error), token.clone())),
+        };
+        let mut bytes = interpreter.tcp_partial.remove(&handle).unwrap_or_default();
+        if data.is_empty() && bytes.is_empty() {
+            return Ok(Value::Nil);
+        }
+        if data.is_empty() {
+            // The other end closed the connection partway through a character.
+            return Ok(Value::new_string(String::from_utf8_lossy(&bytes).into_owned()));
+        }
+        bytes.extend(data);
+        let partial = bytes.split_off(bytes.len() - incomplete_tail(&bytes));
+        if !partial.is_empty() {
+            interpreter.tcp_partial.insert(handle, partial);
+        }
+        if !bytes.is_empty() {
+            return Ok(Value::new_string(String::from_utf8_lossy(&bytes).into_owned()));
+        }
+    }
+}
+
+// How many bytes at the end of data begin a character the rest of which has
+// not arrived yet.
+fn incomplete_tail(data: &[u8]) -> usize {
+    for length in 1..=data.len().min(3) {
+        let byte = data[data.len() - length];
+        // Skip continuation bytes back to the byte that starts the character.
+        if byte & 0xC0 == 0x80 {
+            continue;
+        }
+        let width = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if width > length { length } else { 0 };
+    }
+    0
+}
+
+fn tcp_close(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let handle = integer_argument(&arguments, 0, token)?;
+    interpreter.tcp_partial.remove(&handle);
+    match interpreter.platform.tcp_close(handle) {
+        Ok(()) => Ok(Value::Nil),
+        Err(error) => Err(InterpError::new(&format!("Could not close connection: {}", error), token.clone())),
+    }
+}
+
+// The operating system's name, such as "linux", "macos" or "windows".
+fn platform(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::new_string(interpreter.platform.os_name()))
+}
+
+fn cwd(interpreter: &mut Interpreter, _arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match interpreter.platform.current_dir() {
+        Ok(dir) => Ok(Value::new_string(dir)),
+        Err(error) => Err(InterpError::new(&format!("Could not get the current directory: {}", error), token.clone())),
+    }
+}
+
+// Changes the directory relative paths are resolved from. Returns nil.
+fn chdir(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    match interpreter.platform.set_current_dir(path) {
+        Ok(()) => Ok(Value::Nil),
+        Err(error) => Err(InterpError::new(&format!("Could not change directory to '{}': {}", path, error), token.clone())),
+    }
+}
+
+// Runs body as the test called name. See Interpreter::run_test.
+fn test(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let name = string_argument(&arguments, 0, token)?;
+    let Value::Function(body) = &arguments[1] else {
+        return Err(InterpError::new("test expects a name and a function.", token.clone()));
+    };
+    interpreter.run_test(name, body.clone(), token)?;
+    Ok(Value::Nil)
+}
+
+// Fails unless actual == expected, with == as Lox compares.
+fn assert_eq(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let (actual, expected) = (&arguments[0], &arguments[1]);
+    if interpreter.values_equal(actual, expected, token)? {
+        return Ok(Value::Nil);
+    }
+    let message = format!(
+        "assertEq failed: expected {}, got {}.",
+        interpreter.stringify(expected, token)?,
+        interpreter.stringify(actual, token)?
+    );
+    Err(InterpError::new(&message, token.clone()))
+}
+
+fn assert_true(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    if arguments[0].is_truthy() {
+        return Ok(Value::Nil);
+    }
+    let message = format!("assertTrue failed: got {}.", interpreter.stringify(&arguments[0], token)?);
+    Err(InterpError::new(&message, token.clone()))
+}
+
+fn hostname(interpreter: &mut Interpreter, _arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match interpreter.platform.hostname() {
+        Ok(name) => Ok(Value::new_string(name)),
+        Err(error) => Err(InterpError::new(&format!("Could not get the hostname: {}", error), token.clone())),
+    }
+}
+
+// The value of an environment variable, or nil if it is unset.
+fn getenv(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let name = string_argument(&arguments, 0, token)?;
+    Ok(interpreter.platform.env_var(name).map_or(Value::Nil, Value::new_string))
+}
+
+fn setenv(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let name = string_argument(&arguments, 0, token)?;
+    let value = string_argument(&arguments, 1, token)?;
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(InterpError::new(&format!("Invalid environment variable name '{}'.", name), token.clone()));
+    }
+    if value.contains('\0') {
+        return Err(InterpError::new("Environment variable values can't contain NUL.", token.clone()));
+    }
+    interpreter.platform.set_env_var(name, value);
+    Ok(Value::Nil)
+}
+
+fn exists(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    Ok(Value::Boolean(interpreter.platform.exists(path)))
+}
+
+fn list_dir(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    match interpreter.platform.list_dir(path) {
+        Ok(names) => Ok(Value::new_list(names.into_iter().map(Value::new_string).collect())),
+        Err(error) => Err(InterpError::new(&format!("Could not list directory '{}': {}", path, error), token.clone())),
+    }
+}
+
+// Creates the directory and any missing parents. Returns false if it was
+// already there.
+fn mkdir(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    let existed = interpreter.platform.exists(path);
+    match interpreter.platform.create_dir(path) {
+        Ok(()) => Ok(Value::Boolean(!existed)),
+        Err(error) => Err(InterpError::new(&format!("Could not create directory '{}': {}", path, error), token.clone())),
+    }
+}
+
+// Removes a file or an empty directory. Returns false if there was nothing to
+// remove.
+fn remove(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = string_argument(&arguments, 0, token)?;
+    if !interpreter.platform.exists(path) {
+        return Ok(Value::Boolean(false));
+    }
+    match interpreter.platform.remove(path) {
+        Ok(()) => Ok(Value::Boolean(true)),
+        Err(error) => Err(InterpError::new(&format!("Could not remove '{}': {}", path, error), token.clone())),
+    }
+}
+
+// Joins path parts with the platform's separator. A part that is an absolute
+// path starts the result over.
+fn path_join(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let mut path = PathBuf::new();
+    for index in 0..arguments.len() {
+        path.push(string_argument(&arguments, index, token)?);
+    }
+    Ok(Value::new_string(path.to_string_lossy().into_owned()))
+}
+
+// The parts of a path below are nil when it has no such part, as with the
+// extension of "notes" or the basename of "/".
+fn path_part(part: Option<&OsStr>) -> Value {
+    match part {
+        Some(part) => Value::new_string(part.to_string_lossy().into_owned()),
+        None => Value::Nil,
+    }
+}
+
+fn dirname(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = Path::new(string_argument(&arguments, 0, token)?);
+    Ok(path_part(path.parent().map(Path::as_os_str)))
+}
+
+fn basename(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = Path::new(string_argument(&arguments, 0, token)?);
+    Ok(path_part(path.file_name()))
+}
+
+fn extension(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let path = Path::new(string_argument(&arguments, 0, token)?);
+    Ok(path_part(path.extension()))
+}
+
+fn random(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::Number(interpreter.random.next_f64()))
+}
+
+// An integer from low to high, both included.
+fn rand_int(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let low = integer_argument(&arguments, 0, token)?;
+    let high = integer_argument(&arguments, 1, token)?;
+    if low > high {
+        return Err(InterpError::new("randInt's low bound is above its high bound.", token.clone()));
+    }
+    Ok(Value::Integer(interpreter.random.range(low, high)))
+}
+
+fn seed(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let seed = integer_argument(&arguments, 0, token)?;
+    interpreter.random = Random::new(seed as u64);
+    Ok(Value::Nil)
+}
+
+// Parses a number from a string, ignoring surrounding whitespace. Whole
+// numbers become integers. Returns nil if the string is not a number.
+fn num(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let text = match &arguments[0] {
+        Value::StringV(s) => s.trim(),
+        _ => return Err(InterpError::new("num expects a string.", token.clone())),
+    };
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(match text.parse() {
+            Ok(i) => Value::Integer(i),
+            Err(_) => overflowed_integer(text),
+        });
+    }
+    match text.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+        _ => Ok(Value::Nil),
+    }
+}
+
+// Any value as the text print would show for it.
+fn str(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    Ok(Value::new_string(interpreter.stringify(&arguments[0], token)?))
+}
+
+fn exit(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match i32::try_from(integer_argument(&arguments, 0, token)?) {
+        Ok(code) => Err(InterpError::Exit(code)),
+        Err(_) => Err(InterpError::new("Exit code out of range.", token.clone())),
+    }
+}
+
+// The runtime type of a value; instances report their class's name.
+fn type_of(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let name = match &arguments[0] {
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(_) => "number",
+        Value::Integer(_) | Value::Number(_) => "number",
+        Value::Boolean(_) => "boolean",
+        Value::Class(_) => "class",
+        Value::Deque(deque) => return Ok(Value::new_string(deque.borrow().kind.name().to_lowercase())),
+        Value::Function(_) => "function",
+        Value::Generator(_) => "generator",
+        Value::List(_) => "list",
+        Value::Namespace(_) => "namespace",
+        Value::Nil => "nil",
+        Value::Object(object) => return Ok(Value::new_string(object.borrow().class.borrow().name.clone())),
+        Value::Range(_) => "range",
+        Value::Set(_) => "set",
+        Value::StringV(_) => "string",
+    };
+    Ok(Value::new_string(name.to_string()))
+}
+
+fn encode(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match arguments[0].serialize() {
+        Ok(text) => Ok(Value::new_string(text)),
+        Err(message) => Err(InterpError::new(&message, token.clone())),
+    }
+}
+
+fn decode(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    if let Value::StringV(text) = &arguments[0] {
+        let find_class = |name: &str| match interpreter.global(name) {
+            Some(Value::Class(class)) => Some(class),
+            _ => None,
+        };
+        Value::deserialize(text, &find_class).map_err(|message| InterpError::new(&message, token.clone()))
+    } else {
+        Err(InterpError::new("decode expects a string.", token.clone()))
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_argument(arguments: &[Value], token: &Token) -> Result<regex::Regex, InterpError> {
+    let pattern = string_argument(arguments, 0, token)?;
+    regex::Regex::new(pattern).map_err(|error| InterpError::new(&format!("Invalid regex: {}", error), token.clone()))
+}
+
+// Whether the pattern matches anywhere in the string; anchor it with ^ and $
+// to match the whole string.
+#[cfg(feature = "regex")]
+fn regex_match(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let regex = regex_argument(&arguments, token)?;
+    Ok(Value::Boolean(regex.is_match(string_argument(&arguments, 1, token)?)))
+}
+
+// The first match followed by each of its capture groups, nil for groups that
+// took no part, or nil if nothing matches.
+#[cfg(feature = "regex")]
+fn regex_find(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let regex = regex_argument(&arguments, token)?;
+    let Some(captures) = regex.captures(string_argument(&arguments, 1, token)?) else {
+        return Ok(Value::Nil);
+    };
+    let groups = captures
+        .iter()
+        .map(|group| group.map_or(Value::Nil, |group| Value::new_string(group.as_str().to_string())))
+        .collect();
+    Ok(Value::new_list(groups))
+}
+
+// Replaces every match. The replacement refers to groups as $1 or ${name}.
+#[cfg(feature = "regex")]
+fn regex_replace(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let regex = regex_argument(&arguments, token)?;
+    let text = string_argument(&arguments, 1, token)?;
+    let replacement = string_argument(&arguments, 2, token)?;
+    Ok(Value::new_string(regex.replace_all(text, replacement).into_owned()))
+}
+
+fn time_now(interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::Number(interpreter.platform.wall_time().as_secs_f64()))
+}
+
+// Formats a timestamp as a UTC date, ignoring any fraction of a second.
+fn time_format(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let timestamp = number_argument(&arguments, 0, token)?.floor() as i64;
+    let format = string_argument(&arguments, 1, token)?;
+    match DateTime::from_timestamp(timestamp).format(format) {
+        Ok(text) => Ok(Value::new_string(text)),
+        Err(message) => Err(InterpError::new(&message, token.clone())),
+    }
+}
+
+// The timestamp of a UTC date, or nil if the text doesn't match the format.
+fn time_parse(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let text = string_argument(&arguments, 0, token)?;
+    let format = string_argument(&arguments, 1, token)?;
+    match DateTime::parse(text, format) {
+        Ok(date) => Ok(date.map_or(Value::Nil, |date| Value::Integer(date.timestamp()))),
+        Err(message) => Err(InterpError::new(&message, token.clone())),
+    }
+}
+
+// A 64-bit hash that agrees with memoize's keys: 1 and 1.0 hash alike, and
+// lists and instances hash by identity. Stable within a build of the
+// interpreter, but not across versions.
+fn hash(_interpreter: &mut Interpreter, arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let mut hasher = DefaultHasher::new();
+    ValueKey(arguments[0].clone()).hash(&mut hasher);
+    Ok(Value::Integer(hasher.finish() as i64))
+}
+
+// The digest of a string's UTF-8 bytes, in lowercase hex.
+#[cfg(feature = "hashing")]
+fn sha256(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    use sha2::{Digest, Sha256};
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(encoding::hex_encode(&Sha256::digest(s.as_bytes()))))
+}
+
+#[cfg(feature = "hashing")]
+fn md5(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    use md5::{Digest, Md5};
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(encoding::hex_encode(&Md5::digest(s.as_bytes()))))
+}
+
+// Encodes a string's UTF-8 bytes.
+fn base64_encode(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(encoding::base64_encode(s.as_bytes())))
+}
+
+fn base64_decode(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let text = string_argument(&arguments, 0, token)?;
+    decoded_string(encoding::base64_decode(text), token)
+}
+
+fn hex_encode(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let s = string_argument(&arguments, 0, token)?;
+    Ok(Value::new_string(encoding::hex_encode(s.as_bytes())))
+}
+
+fn hex_decode(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let text = string_argument(&arguments, 0, token)?;
+    decoded_string(encoding::hex_decode(text), token)
+}
+
+// Strings hold text, so decoded bytes must be UTF-8.
+fn decoded_string(bytes: Result<Vec<u8>, String>, token: &Token) -> InterpResult {
+    let bytes = bytes.map_err(|message| InterpError::new(&message, token.clone()))?;
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(Value::new_string(s)),
+        Err(_) => Err(InterpError::new("Decoded bytes are not valid UTF-8.", token.clone())),
+    }
+}
+
+// A list of rows, each a list of strings. With true as a second argument, the
+// first row is a header naming the fields of instances that the other rows
+// become; fields missing at the end of a row are nil.
+fn csv_parse(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let (text, header) = match &arguments[..] {
+        [Value::StringV(text)] => (text, false),
+        [Value::StringV(text), header] => (text, header.is_truthy()),
+        _ => return Err(InterpError::new("csvParse expects a string and an optional header flag.", token.clone())),
+    };
+    let rows = csv::parse(text).map_err(|message| InterpError::new(&message, token.clone()))?;
+    let to_list = |row: Vec<String>| Value::new_list(row.into_iter().map(Value::new_string).collect());
+    let mut rows = rows.into_iter();
+    let names = match (header, rows.next()) {
+        (true, Some(names)) => names,
+        (true, None) => return Ok(Value::new_list(Vec::new())),
+        (false, first) => return Ok(Value::new_list(first.into_iter().chain(rows).map(to_list).collect())),
+    };
+    let class = IClassStruct::new_i_class(HashMap::new(), HashMap::new(), "CsvRow", None);
+    let mut records = Vec::new();
+    for (i, row) in rows.enumerate() {
+        if row.len() > names.len() {
+            return Err(InterpError::new(&format!("Row {} has more fields than the header.", i + 2), token.clone()));
+        }
+        let record = ObjectStruct::new_object(&class);
+        let mut fields = row.into_iter().map(Value::new_string);
+        for name in &names {
+            record.borrow_mut().fields.insert(intern(name), fields.next().unwrap_or(Value::Nil));
+        }
+        records.push(Value::Object(record));
+    }
+    Ok(Value::new_list(records))
+}
+
+// Fields that aren't strings are written as print would show them, except nil,
+// which is written as an empty field as csvParse reads one.
+fn csv_stringify(interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let error = || InterpError::new("csvStringify expects a list of lists.", token.clone());
+    let Value::List(rows) = &arguments[0] else {
+        return Err(error());
+    };
+    let mut table = Vec::new();
+    for row in rows.borrow().clone() {
+        let Value::List(fields) = row else {
+            return Err(error());
+        };
+        let fields = fields.borrow().clone();
+        let mut texts = Vec::new();
+        for field in &fields {
+            texts.push(match field {
+                Value::Nil => String::new(),
+                field => interpreter.stringify(field, token)?,
+            });
+        }
+        table.push(texts);
+    }
+    Ok(Value::new_string(csv::stringify(&table)))
+}
+
+// JSON objects become instances of a class of their own, JsonObject.
+fn json_parse(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    let text = string_argument(&arguments, 0, token)?;
+    let class = IClassStruct::new_i_class(HashMap::new(), HashMap::new(), "JsonObject", None);
+    Value::from_json(text, &class).map_err(|message| InterpError::new(&message, token.clone()))
+}
+
+// Frees what is only kept alive by cycles, returning how many instances,
+// classes, collections and scopes that was.
+fn collect(_interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    Ok(Value::Integer(gc::collect() as i64))
+}
+
+// The live counts, taken before the instance holding them is made, so it and
+// its class are not counted.
+fn mem_stats(_interpreter: &mut Interpreter, _arguments: Vec<Value>, _token: &Token) -> InterpResult {
+    let counts = memory::live();
+    let class = IClassStruct::new_i_class(HashMap::new(), HashMap::new(), "MemStats", None);
+    let object = ObjectStruct::new_object(&class);
+    {
+        let fields = &mut object.borrow_mut().fields;
+        fields.insert(intern("objects"), Value::Integer(counts.objects as i64));
+        fields.insert(intern("classes"), Value::Integer(counts.classes as i64));
+        fields.insert(intern("environments"), Value::Integer(counts.environments as i64));
+        fields.insert(intern("collections"), Value::Integer(gc::collections() as i64));
+    }
+    Ok(Value::Object(object))
+}
+
+fn json_stringify(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match arguments[0].to_json() {
+        Ok(text) => Ok(Value::new_string(text)),
+        Err(message) => Err(InterpError::new(&message, token.clone())),
+    }
+}
+
+fn abs(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    match &arguments[0] {
+        Value::Integer(i) => Ok(match i.checked_abs() {
+            Some(i) => Value::Integer(i),
+            None => overflowed_integer(&(*i as i128).abs().to_string()),
+        }),
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(b) => Ok(Value::from_big_integer(b.magnitude().clone().into())),
+        _ => Ok(Value::Number(number_argument(&arguments, 0, token)?.abs())),
+    }
+}
+
+// Rounds a float with round_float, giving an integer when the result fits one.
+// Integers are already whole, so they come back unchanged.
+fn round_with(arguments: &[Value], token: &Token, round_float: fn(f64) -> f64) -> InterpResult {
+    match &arguments[0] {
+        Value::Integer(_) => Ok(arguments[0].clone()),
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(_) => Ok(arguments[0].clone()),
+        _ => {
+            let n = round_float(number_argument(arguments, 0, token)?);
+            // i64::MAX as f64 rounds up to 2^63, which does not fit.
+            if n.is_finite() && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+                Ok(Value::Integer(n as i64))
+            } else {
+                Ok(Value::Number(n))
+            }
+        }
+    }
+}
+
+fn floor(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    round_with(&arguments, token, f64::floor)
+}
+
+fn ceil(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    round_with(&arguments, token, f64::ceil)
+}
+
+// Halves round away from zero.
+fn round(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    round_with(&arguments, token, f64::round)
+}
+
+fn sqrt(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    Ok(Value::Number(number_argument(&arguments, 0, token)?.sqrt()))
+}
+
+fn sin(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    Ok(Value::Number(number_argument(&arguments, 0, token)?.sin()))
+}
+
+fn cos(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    Ok(Value::Number(number_argument(&arguments, 0, token)?.cos()))
+}
+
+// An integer raised to a non-negative integer power stays an integer, as the
+// arithmetic operators keep integers.
+fn pow(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    if let (Value::Integer(base), Value::Integer(exponent)) = (&arguments[0], &arguments[1]) {
+        if let Ok(exponent) = u32::try_from(*exponent) {
+            if let Some(power) = base.checked_pow(exponent) {
+                return Ok(Value::Integer(power));
+            }
+            #[cfg(feature = "bignum")]
+            return Ok(Value::from_big_integer(num_bigint::BigInt::from(*base).pow(exponent)));
+        }
+    }
+    let base = number_argument(&arguments, 0, token)?;
+    let exponent = number_argument(&arguments, 1, token)?;
+    Ok(Value::Number(base.powf(exponent)))
+}
+
+// The first of the arguments that no other comes before in order, keeping its
+// type. name is the function's, for the error when there are no arguments.
+fn extreme(name: &str, arguments: Vec<Value>, token: &Token, before: fn(f64, f64) -> bool) -> InterpResult {
+    if arguments.is_empty() {
+        return Err(InterpError::new(&format!("{} expects at least one number.", name), token.clone()));
+    }
+    let mut best = 0;
+    for index in 1..arguments.len() {
+        if before(number_argument(&arguments, index, token)?, number_argument(&arguments, best, token)?) {
+            best = index;
+        }
+    }
+    number_argument(&arguments, best, token)?;
+    Ok(arguments[best].clone())
+}
+
+fn min(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    extreme("Math.min", arguments, token, |a, b| a < b)
+}
+
+fn max(_interpreter: &mut Interpreter, arguments: Vec<Value>, token: &Token) -> InterpResult {
+    extreme("Math.max", arguments, token, |a, b| a > b)
+}
@@ -0,0 +1,189 @@
+//! Conversions between Unix timestamps, in seconds, and UTC dates and times,
+//! using the proleptic Gregorian calendar. Formats use strftime-style fields:
+//!
+//! - `%Y` the year, four digits
+//! - `%m` `%d` the month and day of the month, two digits
+//! - `%H` `%M` `%S` the hour, minute and second, two digits
+//! - `%%` a literal `%`
+//!
+//! Everything else in a format is copied, or when parsing matched, as is.
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+#[derive(Debug, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01, counting in 400-year eras that start on March 1st so
+// leap days fall at the end of each year.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl DateTime {
+    pub fn from_timestamp(timestamp: i64) -> DateTime {
+        let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+        let seconds = timestamp.rem_euclid(SECONDS_PER_DAY);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour * 3600
+            + self.minute * 60
+            + self.second
+    }
+
+    pub fn format(&self, format: &str) -> Result<String, String> {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", self.year)),
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('M') => out.push_str(&format!("{:02}", self.minute)),
+                Some('S') => out.push_str(&format!("{:02}", self.second)),
+                Some('%') => out.push('%'),
+                Some(c) => return Err(format!("Unknown time format field '%{}'.", c)),
+                None => return Err("Time format ends with '%'.".to_string()),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Reads text laid out as format describes. Fields the format leaves out
+    /// default to 1970-01-01 00:00:00. None if the text does not match or is
+    /// not a real date.
+    pub fn parse(text: &str, format: &str) -> Result<Option<DateTime>, String> {
+        let mut date = DateTime::from_timestamp(0);
+        let mut rest = text;
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            let expected = if c == '%' { chars.next() } else { None };
+            let (field, width) = match (c, expected) {
+                ('%', Some('Y')) => (&mut date.year, 4),
+                ('%', Some('m')) => (&mut date.month, 2),
+                ('%', Some('d')) => (&mut date.day, 2),
+                ('%', Some('H')) => (&mut date.hour, 2),
+                ('%', Some('M')) => (&mut date.minute, 2),
+                ('%', Some('S')) => (&mut date.second, 2),
+                ('%', Some(c)) if c != '%' => return Err(format!("Unknown time format field '%{}'.", c)),
+                ('%', None) => return Err("Time format ends with '%'.".to_string()),
+                (c, _) => match rest.strip_prefix(c) {
+                    Some(after) => {
+                        rest = after;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+            let digits = rest.bytes().take(width).take_while(u8::is_ascii_digit).count();
+            if digits != width {
+                return Ok(None);
+            }
+            *field = rest[..width].parse().unwrap();
+            rest = &rest[width..];
+        }
+        let valid = rest.is_empty()
+            && (1..=12).contains(&date.month)
+            && (1..=days_in_month(date.year, date.month)).contains(&date.day)
+            && date.hour < 24
+            && date.minute < 60
+            && date.second < 60;
+        Ok(valid.then_some(date))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamps() {
+        let cases = [
+            (0, "1970-01-01 00:00:00"),
+            (951_782_400, "2000-02-29 00:00:00"),
+            (1_700_000_000, "2023-11-14 22:13:20"),
+            (-1, "1969-12-31 23:59:59"),
+            (-2_208_988_800, "1900-01-01 00:00:00"),
+            (4_107_542_399, "2100-02-28 23:59:59"),
+        ];
+        for (timestamp, text) in cases {
+            let date = DateTime::from_timestamp(timestamp);
+            assert_eq!(date.format("%Y-%m-%d %H:%M:%S").unwrap(), text);
+            assert_eq!(date.timestamp(), timestamp);
+            let parsed = DateTime::parse(text, "%Y-%m-%d %H:%M:%S").unwrap().unwrap();
+            assert_eq!(parsed.timestamp(), timestamp);
+        }
+        for days in -1_000_000..1_000_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let date = DateTime::from_timestamp(1_700_000_000);
+        assert_eq!(date.format("%d/%m/%Y at %H%% %%Y").unwrap(), "14/11/2023 at 22% %Y");
+        assert!(date.format("%q").is_err());
+        assert!(date.format("100%").is_err());
+
+        let parsed = DateTime::parse("[14/11/2023]", "[%d/%m/%Y]").unwrap().unwrap();
+        assert_eq!(parsed.timestamp(), 1_699_920_000);
+        assert_eq!(DateTime::parse("12:30", "%H:%M").unwrap().unwrap().timestamp(), 45_000);
+        for text in ["2023-02-29", "2023-13-01", "2023-1-01", "2023-01-01x", "2023/01/01"] {
+            assert_eq!(DateTime::parse(text, "%Y-%m-%d").unwrap(), None, "{}", text);
+        }
+        assert!(DateTime::parse("1", "%x").is_err());
+    }
+}
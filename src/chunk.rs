@@ -0,0 +1,235 @@
+//! Bytecode for the VM backend. A chunk holds one function's instructions,
+//! with the constants and tokens they refer to by index, and the source line
+//! of each instruction. Jumps name the index of the instruction to go to.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::token::Token;
+use crate::value::{Function, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Pushes the constant at this index.
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Pops this many values, the locals of a scope being left.
+    PopN(u32),
+    /// Pushes the local in this slot of the current call's frame.
+    GetLocal(u32),
+    /// Stores the top of the stack, leaving it there, in this slot.
+    SetLocal(u32),
+    /// The global named by the token at this index.
+    DefineGlobal(u32),
+    DefineConstant(u32),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    /// `object.name`, with the token of name.
+    GetProperty(u32),
+    /// `object.name = value`, leaving the value.
+    SetProperty(u32),
+    /// `object[index]`, with the token of the closing bracket.
+    Index(u32),
+    /// `object[index] = value`, leaving the value.
+    SetIndex(u32),
+    /// Any binary operator, by the token at this index.
+    Binary(u32),
+    /// One link of a chained comparison: compares the top two values by the
+    /// operator token, and if that fails, leaves false and jumps to the end of
+    /// the chain. Otherwise leaves the right operand to compare with the next.
+    Chain(u32, u32),
+    Negate(u32),
+    Not,
+    /// Replaces the top of the stack with its truthiness.
+    ToBoolean,
+    Jump(u32),
+    /// Pops the condition, jumping if it is falsey.
+    JumpIfFalse(u32),
+    /// `and`: if the top of the stack is falsey, replaces it with false and
+    /// jumps, and otherwise pops it.
+    And(u32),
+    /// `or`: if the top of the stack is truthy, replaces it with true and
+    /// jumps, and otherwise pops it.
+    Or(u32),
+    /// `??`: jumps, leaving the top of the stack, unless it is nil, which is
+    /// popped.
+    JumpIfNotNil(u32),
+    /// `?.`: jumps, leaving the nil, when the top of the stack is nil.
+    JumpIfNil(u32),
+    /// Calls the callee below this many arguments. The tokens are the callee's,
+    /// naming it in arity errors, and the closing parenthesis.
+    Call(u32, u32, u32),
+    /// A list of this many values.
+    List(u32),
+    Print(u32),
+    /// A failed assertion, with the keyword token and whether a message is on
+    /// the stack.
+    AssertFailed(u32, bool),
+    /// Makes a closure of the function constant at this index, capturing the
+    /// variables its captures name.
+    Closure(u32),
+    /// Pushes the captured variable at this index of the running closure.
+    GetUpvalue(u32),
+    /// Stores the top of the stack, leaving it there, in this captured variable.
+    SetUpvalue(u32),
+    /// Closes over the locals in this slot and above, which are about to be
+    /// popped, moving their values off the stack into the closures sharing them.
+    CloseUpvalues(u32),
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub lines: Vec<usize>,
+    pub constants: Vec<Value>,
+    pub tokens: Vec<Token>,
+}
+
+impl Chunk {
+    /// Appends an instruction, returning its index.
+    pub fn write(&mut self, instruction: Instruction, line: usize) -> usize {
+        self.code.push(instruction);
+        self.lines.push(line);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        (self.constants.len() - 1) as u32
+    }
+
+    pub fn add_token(&mut self, token: &Token) -> u32 {
+        self.tokens.push(token.clone());
+        (self.tokens.len() - 1) as u32
+    }
+
+    /// A listing of the instructions, one per line with its index and source
+    /// line, followed by those of any functions among the constants.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for (i, instruction) in self.code.iter().enumerate() {
+            let line = if i > 0 && self.lines[i] == self.lines[i - 1] {
+                "   |".to_string()
+            } else {
+                format!("{:4}", self.lines[i])
+            };
+            let _ = writeln!(out, "{:04} {} {}", i, line, self.describe(*instruction));
+        }
+        for constant in &self.constants {
+            if let Value::Function(Function::Compiled(closure)) = constant {
+                out.push_str(&closure.function.chunk.disassemble(&closure.function.name));
+            }
+        }
+        out
+    }
+
+    fn describe(&self, instruction: Instruction) -> String {
+        let token = |i: u32| &self.tokens[i as usize].content;
+        // Operator tokens carry no text, so they are shown by kind.
+        let operator = |i: u32| self.tokens[i as usize].kind;
+        match instruction {
+            Instruction::Constant(i) => match &self.constants[i as usize] {
+                Value::Function(Function::Compiled(closure)) => {
+                    format!("Constant {} <fn {}>", i, closure.function.name)
+                }
+                constant => format!("Constant {} '{}'", i, constant),
+            },
+            Instruction::Closure(i) => match &self.constants[i as usize] {
+                Value::Function(Function::Compiled(closure)) => {
+                    let captures: Vec<String> = closure
+                        .function
+                        .captures
+                        .iter()
+                        .map(|capture| format!("{} {}", if capture.local { "local" } else { "upvalue" }, capture.index))
+                        .collect();
+                    format!("Closure {} <fn {}> [{}]", i, closure.function.name, captures.join(", "))
+                }
+                constant => format!("Closure {} '{}'", i, constant),
+            },
+            Instruction::DefineGlobal(i) => format!("DefineGlobal '{}'", token(i)),
+            Instruction::DefineConstant(i) => format!("DefineConstant '{}'", token(i)),
+            Instruction::GetGlobal(i) => format!("GetGlobal '{}'", token(i)),
+            Instruction::SetGlobal(i) => format!("SetGlobal '{}'", token(i)),
+            Instruction::GetProperty(i) => format!("GetProperty '{}'", token(i)),
+            Instruction::SetProperty(i) => format!("SetProperty '{}'", token(i)),
+            Instruction::Binary(i) => format!("Binary {:?}", operator(i)),
+            Instruction::Chain(i, end) => format!("Chain {:?} -> {}", operator(i), end),
+            Instruction::Call(count, callee, _) => format!("Call {} '{}'", count, token(callee)),
+            Instruction::Index(_) => "Index".to_string(),
+            Instruction::SetIndex(_) => "SetIndex".to_string(),
+            Instruction::Negate(_) => "Negate".to_string(),
+            Instruction::Print(_) => "Print".to_string(),
+            Instruction::AssertFailed(_, message) => format!("AssertFailed {}", message),
+            Instruction::Jump(target) => format!("Jump -> {}", target),
+            Instruction::JumpIfFalse(target) => format!("JumpIfFalse -> {}", target),
+            Instruction::And(target) => format!("And -> {}", target),
+            Instruction::Or(target) => format!("Or -> {}", target),
+            Instruction::JumpIfNotNil(target) => format!("JumpIfNotNil -> {}", target),
+            Instruction::JumpIfNil(target) => format!("JumpIfNil -> {}", target),
+            other => format!("{:?}", other),
+        }
+    }
+}
+
+/// A function compiled to bytecode, or the top level of a script, which takes
+/// no arguments.
+#[derive(Debug)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    /// The variables of enclosing functions this one uses, in the order its
+    /// GetUpvalue and SetUpvalue instructions index them.
+    pub captures: Vec<Capture>,
+}
+
+/// Where a closure finds one of its captured variables as it is made: a local
+/// of the function making it, by slot, or one that function captured itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capture {
+    pub local: bool,
+    pub index: u32,
+}
+
+// Compiled functions are only equal to themselves, as other functions are.
+impl PartialEq for CompiledFunction {
+    fn eq(&self, other: &CompiledFunction) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+pub type Compiled = Rc<CompiledFunction>;
+
+/// A captured variable, shared by every closure that captured it: the stack
+/// slot of a local still in scope, or its value once that scope has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A compiled function with the variables it captured when it was made. Those
+/// capturing nothing, as constants in a chunk are, have no upvalues.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Compiled,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Closure {
+    pub fn new(function: Compiled) -> Closure {
+        Closure { function, upvalues: Vec::new() }
+    }
+}
+
+// Like the functions they are made from, closures are only equal to themselves.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
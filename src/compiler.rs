@@ -0,0 +1,605 @@
+//! Lowers a resolved Ast to bytecode for the VM backend, one chunk per
+//! function. The VM covers the core of the language: variables, control flow,
+//! functions and closures, lists, properties, and calls to anything callable.
+//! Classes, generators, exceptions, imports and the other statements beyond
+//! that core are Unsupported, and the interpreter runs scripts using them on
+//! the tree-walker instead.
+//!
+//! Locals live in stack slots, resolved here as clox does. A function using a
+//! local of an enclosing function captures just that variable as an upvalue,
+//! which stays on the stack until its scope ends and is then closed over.
+//! Names that are neither are globals, looked up at runtime.
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::chunk::{Capture, Chunk, Closure, Compiled, CompiledFunction, Instruction};
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenKind};
+use crate::value::{Function, Value};
+
+/// A construct the VM backend does not cover, and the line it is on.
+#[derive(Debug, PartialEq)]
+pub struct Unsupported {
+    pub construct: &'static str,
+    pub line: usize,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] The bytecode compiler does not support {}.", self.line, self.construct)
+    }
+}
+
+type CompileResult = Result<(), Unsupported>;
+
+fn unsupported(construct: &'static str, line: usize) -> CompileResult {
+    Err(Unsupported { construct, line })
+}
+
+struct Local {
+    name: Symbol,
+    depth: u32,
+    // Whether a closure captures it, so it must be closed over, not just
+    // popped, when its scope ends.
+    captured: bool,
+}
+
+// Where a name is found, from the function being compiled.
+enum Resolved {
+    Local(u32),
+    Upvalue(u32),
+    Global,
+}
+
+struct Loop {
+    label: Option<Symbol>,
+    // How many locals were in scope as the body began, which break and
+    // continue pop back to.
+    locals: usize,
+    // Where continue goes, when known before the body is compiled.
+    continue_target: Option<usize>,
+    continue_jumps: Vec<usize>,
+    break_jumps: Vec<usize>,
+}
+
+struct FunctionState {
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    captures: Vec<Capture>,
+    // 0 only at the top level of the script, where variables are globals.
+    scope_depth: u32,
+    loops: Vec<Loop>,
+}
+
+impl FunctionState {
+    fn new(name: &str, params: &[Token]) -> FunctionState {
+        FunctionState {
+            name: name.to_string(),
+            arity: params.len(),
+            chunk: Chunk::default(),
+            locals: params
+                .iter()
+                .map(|param| Local { name: param.content.clone(), depth: 1, captured: false })
+                .collect(),
+            captures: Vec::new(),
+            scope_depth: 1,
+            loops: Vec::new(),
+        }
+    }
+}
+
+/// Compiles a script to the function the VM runs as its top level.
+pub fn compile(ast: &Ast) -> Result<Compiled, Unsupported> {
+    let mut script = FunctionState::new("script", &[]);
+    script.scope_depth = 0;
+    let mut compiler = Compiler { functions: vec![script] };
+    for declaration in &ast.declarations {
+        compiler.declaration(declaration)?;
+    }
+    Ok(compiler.finish())
+}
+
+struct Compiler {
+    // The functions being compiled, innermost last.
+    functions: Vec<FunctionState>,
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instruction: Instruction, line: usize) -> usize {
+        self.current().chunk.write(instruction, line)
+    }
+
+    fn here(&mut self) -> u32 {
+        self.current().chunk.code.len() as u32
+    }
+
+    // The line of the last instruction, for those with no token of their own.
+    fn line(&mut self) -> usize {
+        self.current().chunk.lines.last().copied().unwrap_or(0)
+    }
+
+    fn token(&mut self, token: &Token) -> u32 {
+        self.current().chunk.add_token(token)
+    }
+
+    fn constant(&mut self, value: Value, line: usize) {
+        let index = self.current().chunk.add_constant(value);
+        self.emit(Instruction::Constant(index), line);
+    }
+
+    // Points the jump at index to the next instruction to be emitted.
+    fn patch(&mut self, index: usize) {
+        let target = self.here();
+        let code = &mut self.current().chunk.code;
+        code[index] = match code[index] {
+            Instruction::Jump(_) => Instruction::Jump(target),
+            Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+            Instruction::And(_) => Instruction::And(target),
+            Instruction::Or(_) => Instruction::Or(target),
+            Instruction::JumpIfNotNil(_) => Instruction::JumpIfNotNil(target),
+            Instruction::JumpIfNil(_) => Instruction::JumpIfNil(target),
+            Instruction::Chain(operator, _) => Instruction::Chain(operator, target),
+            instruction => unreachable!("{:?} is not a jump.", instruction),
+        };
+    }
+
+    // Ends the function being compiled with an implicit `return nil;`.
+    fn finish(&mut self) -> Compiled {
+        let line = self.line();
+        self.emit(Instruction::Nil, line);
+        self.emit(Instruction::Return, line);
+        let state = self.functions.pop().unwrap();
+        Rc::new(CompiledFunction {
+            name: state.name,
+            arity: state.arity,
+            chunk: state.chunk,
+            captures: state.captures,
+        })
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let first = state.locals.len() - state.locals.iter().rev().take_while(|local| local.depth > depth).count();
+        let captured = self.current().locals[first..].iter().any(|local| local.captured);
+        self.pop_locals(first, captured);
+        self.current().locals.truncate(first);
+    }
+
+    // Pops the locals from slot first up, closing over them first if close.
+    fn pop_locals(&mut self, first: usize, close: bool) {
+        let line = self.line();
+        let count = self.current().locals.len() - first;
+        if close && count > 0 {
+            self.emit(Instruction::CloseUpvalues(first as u32), line);
+        }
+        match count {
+            0 => {}
+            1 => {
+                self.emit(Instruction::Pop, line);
+            }
+            _ => {
+                self.emit(Instruction::PopN(count as u32), line);
+            }
+        }
+    }
+
+    // Binds the value on top of the stack to name: a new global at the top
+    // level of the script, and otherwise a new local in that value's slot.
+    fn define(&mut self, name: &Token, constant: bool) {
+        if self.current().scope_depth == 0 {
+            let index = self.token(name);
+            let instruction = if constant { Instruction::DefineConstant(index) } else { Instruction::DefineGlobal(index) };
+            self.emit(instruction, name.line);
+        } else {
+            let state = self.current();
+            let depth = state.scope_depth;
+            state.locals.push(Local { name: name.content.clone(), depth, captured: false });
+        }
+    }
+
+    fn resolve(&mut self, name: &Token) -> Resolved {
+        let current = self.functions.len() - 1;
+        if let Some(slot) = self.functions[current].locals.iter().rposition(|local| local.name == name.content) {
+            return Resolved::Local(slot as u32);
+        }
+        match self.resolve_upvalue(current, &name.content) {
+            Some(index) => Resolved::Upvalue(index),
+            None => Resolved::Global,
+        }
+    }
+
+    // The index among the captures of the function at depth of name, a local
+    // of some enclosing function, capturing it through each function between.
+    fn resolve_upvalue(&mut self, depth: usize, name: &Symbol) -> Option<u32> {
+        let enclosing = depth.checked_sub(1)?;
+        if let Some(slot) = self.functions[enclosing].locals.iter().rposition(|local| &local.name == name) {
+            self.functions[enclosing].locals[slot].captured = true;
+            return Some(self.add_capture(depth, Capture { local: true, index: slot as u32 }));
+        }
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_capture(depth, Capture { local: false, index }))
+    }
+
+    fn add_capture(&mut self, depth: usize, capture: Capture) -> u32 {
+        let captures = &mut self.functions[depth].captures;
+        let index = captures.iter().position(|existing| *existing == capture).unwrap_or_else(|| {
+            captures.push(capture);
+            captures.len() - 1
+        });
+        index as u32
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) -> CompileResult {
+        match declaration {
+            Declaration::Class(class) => unsupported("classes", class.borrow().name.line),
+            Declaration::Interface(_) => Ok(()),
+            Declaration::Statement(statement) => self.statement(statement),
+            Declaration::VarDeclaration(var_declaration) => self.var_declaration(var_declaration),
+            Declaration::FunDeclaration(fun_declaration) => self.fun_declaration(fun_declaration),
+        }
+    }
+
+    fn var_declaration(&mut self, var_declaration: &VarDeclaration) -> CompileResult {
+        match &var_declaration.initializer {
+            Some(initializer) => self.expr(initializer)?,
+            None => {
+                self.emit(Instruction::Nil, var_declaration.name.line);
+            }
+        }
+        self.define(&var_declaration.name, var_declaration.constant);
+        Ok(())
+    }
+
+    fn fun_declaration(&mut self, fun_declaration: &FunDeclaration) -> CompileResult {
+        let declaration = fun_declaration.borrow();
+        let name = &declaration.name;
+        if declaration.generator {
+            return unsupported("generators", name.line);
+        }
+        // A local function is in scope in its own body, so it calls itself
+        // through an upvalue there.
+        let global = self.current().scope_depth == 0;
+        if !global {
+            self.define(name, false);
+        }
+        self.functions.push(FunctionState::new(&name.content, &declaration.params));
+        for body_declaration in &declaration.body {
+            self.declaration(body_declaration)?;
+        }
+        let function = self.finish();
+        let capturing = !function.captures.is_empty();
+        let value = Value::Function(Function::Compiled(Rc::new(Closure::new(function))));
+        if capturing {
+            let index = self.current().chunk.add_constant(value);
+            self.emit(Instruction::Closure(index), name.line);
+        } else {
+            self.constant(value, name.line);
+        }
+        if global {
+            self.define(name, false);
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> CompileResult {
+        match statement {
+            Statement::ExprStatement(expr) => {
+                self.expr(expr)?;
+                self.emit(Instruction::Pop, expr.token.line);
+            }
+            Statement::Print(expr) => {
+                self.expr(expr)?;
+                let token = self.token(&expr.token);
+                self.emit(Instruction::Print(token), expr.token.line);
+            }
+            Statement::Assert(assert) => {
+                let line = assert.keyword.line;
+                self.expr(&assert.cond)?;
+                let failed = self.emit(Instruction::JumpIfFalse(0), line);
+                let passed = self.emit(Instruction::Jump(0), line);
+                self.patch(failed);
+                if let Some(message) = &assert.message {
+                    self.expr(message)?;
+                }
+                let keyword = self.token(&assert.keyword);
+                self.emit(Instruction::AssertFailed(keyword, assert.message.is_some()), line);
+                self.patch(passed);
+            }
+            Statement::Block(declarations) => {
+                self.begin_scope();
+                for declaration in declarations {
+                    self.declaration(declaration)?;
+                }
+                self.end_scope();
+            }
+            Statement::Break(jump) => self.jump_out_of_loop(jump, true),
+            Statement::Continue(jump) => self.jump_out_of_loop(jump, false),
+            Statement::If(if_statement) => {
+                let line = if_statement.cond.token.line;
+                self.expr(&if_statement.cond)?;
+                let skip_true = self.emit(Instruction::JumpIfFalse(0), line);
+                self.statement(&if_statement.true_branch)?;
+                match &if_statement.else_branch {
+                    Some(else_branch) => {
+                        let skip_else = self.emit(Instruction::Jump(0), line);
+                        self.patch(skip_true);
+                        self.statement(else_branch)?;
+                        self.patch(skip_else);
+                    }
+                    None => self.patch(skip_true),
+                }
+            }
+            Statement::While(while_statement) => {
+                let line = while_statement.cond.token.line;
+                let start = self.here();
+                self.expr(&while_statement.cond)?;
+                let exit = self.emit(Instruction::JumpIfFalse(0), line);
+                self.begin_loop(&while_statement.label, Some(start as usize));
+                self.statement(&while_statement.body)?;
+                self.emit(Instruction::Jump(start), line);
+                self.patch(exit);
+                self.end_loop();
+            }
+            Statement::For(for_statement) => self.for_statement(for_statement)?,
+            Statement::Return(value) => {
+                match value {
+                    Some(expr) => self.expr(expr)?,
+                    None => {
+                        let line = self.line();
+                        self.emit(Instruction::Nil, line);
+                    }
+                }
+                let line = self.line();
+                self.emit(Instruction::Return, line);
+            }
+            Statement::ForIn(for_in) => return unsupported("for-in loops", for_in.name.line),
+            Statement::Import(import) => return unsupported("imports", import.keyword.line),
+            Statement::Match(match_statement) => return unsupported("match statements", match_statement.keyword.line),
+            Statement::Throw(throw) => return unsupported("exceptions", throw.keyword.line),
+            Statement::Try(_) => return unsupported("exceptions", self.line()),
+            Statement::With(with) => return unsupported("with statements", with.keyword.line),
+            Statement::Yield(yield_statement) => return unsupported("generators", yield_statement.keyword.line),
+        }
+        Ok(())
+    }
+
+    // The initializer's variable is scoped to the loop, as in the tree-walker.
+    fn for_statement(&mut self, for_statement: &For) -> CompileResult {
+        self.begin_scope();
+        match &for_statement.initializer {
+            Some(Initializer::VarDeclaration(var_declaration)) => self.var_declaration(var_declaration)?,
+            Some(Initializer::Expr(expr)) => {
+                self.expr(expr)?;
+                self.emit(Instruction::Pop, expr.token.line);
+            }
+            None => {}
+        }
+        let start = self.here();
+        let exit = match &for_statement.cond {
+            Some(cond) => {
+                self.expr(cond)?;
+                Some(self.emit(Instruction::JumpIfFalse(0), cond.token.line))
+            }
+            None => None,
+        };
+        self.begin_loop(&for_statement.label, None);
+        self.statement(&for_statement.body)?;
+        let continue_jumps = std::mem::take(&mut self.current().loops.last_mut().unwrap().continue_jumps);
+        for jump in continue_jumps {
+            self.patch(jump);
+        }
+        if let Some(increment) = &for_statement.increment {
+            self.expr(increment)?;
+            self.emit(Instruction::Pop, increment.token.line);
+        }
+        let line = self.line();
+        self.emit(Instruction::Jump(start), line);
+        if let Some(exit) = exit {
+            self.patch(exit);
+        }
+        self.end_loop();
+        self.end_scope();
+        Ok(())
+    }
+
+    fn begin_loop(&mut self, label: &Option<Token>, continue_target: Option<usize>) {
+        let state = self.current();
+        let locals = state.locals.len();
+        state.loops.push(Loop {
+            label: label.as_ref().map(|label| label.content.clone()),
+            locals,
+            continue_target,
+            continue_jumps: Vec::new(),
+            break_jumps: Vec::new(),
+        });
+    }
+
+    // Points the loop's breaks past its last instruction.
+    fn end_loop(&mut self) {
+        let finished = self.current().loops.pop().unwrap();
+        for jump in finished.break_jumps {
+            self.patch(jump);
+        }
+    }
+
+    fn jump_out_of_loop(&mut self, jump: &Jump, is_break: bool) {
+        let line = jump.keyword.line;
+        let label = jump.label.as_ref().map(|label| &*label.content);
+        let state = self.current();
+        // The resolver rejects breaks and continues outside a matching loop.
+        let index = state
+            .loops
+            .iter()
+            .rposition(|target| label.is_none() || target.label.as_deref() == label)
+            .unwrap();
+        let first = state.loops[index].locals;
+        let continue_target = state.loops[index].continue_target;
+        // A closure made later in the loop's body may capture these locals,
+        // on an iteration before this jump is taken, so they are always closed.
+        self.pop_locals(first, true);
+        match continue_target {
+            Some(target) if !is_break => {
+                self.emit(Instruction::Jump(target as u32), line);
+            }
+            _ => {
+                let at = self.emit(Instruction::Jump(0), line);
+                let target = &mut self.current().loops[index];
+                if is_break {
+                    target.break_jumps.push(at);
+                } else {
+                    target.continue_jumps.push(at);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> CompileResult {
+        let token = &expr.token;
+        let line = token.line;
+        match &expr.kind {
+            ExprKind::Literal(value) => match token.kind {
+                TokenKind::True => {
+                    self.emit(Instruction::True, line);
+                }
+                TokenKind::False => {
+                    self.emit(Instruction::False, line);
+                }
+                TokenKind::Nil => {
+                    self.emit(Instruction::Nil, line);
+                }
+                _ => self.constant(value.clone(), line),
+            },
+            ExprKind::Variable(_) => {
+                let instruction = match self.resolve(token) {
+                    Resolved::Local(slot) => Instruction::GetLocal(slot),
+                    Resolved::Upvalue(index) => Instruction::GetUpvalue(index),
+                    Resolved::Global => Instruction::GetGlobal(self.token(token)),
+                };
+                self.emit(instruction, line);
+            }
+            ExprKind::Assign(assign) => {
+                self.expr(&assign.initializer)?;
+                let instruction = match self.resolve(token) {
+                    Resolved::Local(slot) => Instruction::SetLocal(slot),
+                    Resolved::Upvalue(index) => Instruction::SetUpvalue(index),
+                    Resolved::Global => Instruction::SetGlobal(self.token(token)),
+                };
+                self.emit(instruction, line);
+            }
+            ExprKind::Binary(binary) => {
+                self.expr(&binary.left)?;
+                self.expr(&binary.right)?;
+                let operator = self.token(token);
+                self.emit(Instruction::Binary(operator), line);
+            }
+            ExprKind::Chain(chain) => {
+                self.expr(&chain.operands[0])?;
+                let mut failed = Vec::new();
+                for (i, (operator, operand)) in chain.operators.iter().zip(&chain.operands[1..]).enumerate() {
+                    self.expr(operand)?;
+                    let operator_index = self.token(operator);
+                    if i + 1 == chain.operators.len() {
+                        self.emit(Instruction::Binary(operator_index), operator.line);
+                    } else {
+                        failed.push(self.emit(Instruction::Chain(operator_index, 0), operator.line));
+                    }
+                }
+                for jump in failed {
+                    self.patch(jump);
+                }
+            }
+            ExprKind::Logical(logical) => {
+                self.expr(&logical.left)?;
+                let jump = match token.kind {
+                    TokenKind::And => self.emit(Instruction::And(0), line),
+                    TokenKind::Or => self.emit(Instruction::Or(0), line),
+                    _ => self.emit(Instruction::JumpIfNotNil(0), line),
+                };
+                self.expr(&logical.right)?;
+                if token.kind != TokenKind::QuestionQuestion {
+                    self.emit(Instruction::ToBoolean, line);
+                }
+                self.patch(jump);
+            }
+            ExprKind::Unary(operand) => {
+                self.expr(operand)?;
+                let instruction = match token.kind {
+                    TokenKind::Minus => Instruction::Negate(self.token(token)),
+                    _ => Instruction::Not,
+                };
+                self.emit(instruction, line);
+            }
+            ExprKind::Grouping(inner) => self.expr(inner)?,
+            ExprKind::Call(call) => {
+                if let ExprKind::OptionalGet(_) = call.callee.kind {
+                    return unsupported("optional calls", line);
+                }
+                self.expr(&call.callee)?;
+                self.elements(&call.arguments)?;
+                let callee = self.token(&call.callee.token);
+                let paren = self.token(token);
+                self.emit(Instruction::Call(call.arguments.len() as u32, callee, paren), line);
+            }
+            ExprKind::Get(get) => {
+                self.expr(&get.object)?;
+                let name = self.token(token);
+                self.emit(Instruction::GetProperty(name), line);
+            }
+            ExprKind::OptionalGet(get) => {
+                self.expr(&get.object)?;
+                let skip = self.emit(Instruction::JumpIfNil(0), line);
+                let name = self.token(token);
+                self.emit(Instruction::GetProperty(name), line);
+                self.patch(skip);
+            }
+            ExprKind::Set(set) => {
+                self.expr(&set.object)?;
+                self.expr(&set.value)?;
+                let name = self.token(token);
+                self.emit(Instruction::SetProperty(name), line);
+            }
+            ExprKind::Index(index) => {
+                self.expr(&index.object)?;
+                self.expr(&index.index)?;
+                let bracket = self.token(token);
+                self.emit(Instruction::Index(bracket), line);
+            }
+            ExprKind::SetIndex(set_index) => {
+                self.expr(&set_index.object)?;
+                self.expr(&set_index.index)?;
+                self.expr(&set_index.value)?;
+                let bracket = self.token(token);
+                self.emit(Instruction::SetIndex(bracket), line);
+            }
+            ExprKind::List(elements) => {
+                self.elements(elements)?;
+                self.emit(Instruction::List(elements.len() as u32), line);
+            }
+            ExprKind::Comprehension(_) => return unsupported("list comprehensions", line),
+            ExprKind::Spread(_) => return unsupported("spreads", line),
+            ExprKind::This(_) | ExprKind::Super(_, _) => return unsupported("classes", line),
+        }
+        Ok(())
+    }
+
+    fn elements(&mut self, elements: &[Expr]) -> CompileResult {
+        for element in elements {
+            self.expr(element)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+//! Comma-separated values as RFC 4180 lays them out. Fields holding a comma, a
+//! quote or a line break are quoted, with quotes inside doubled. Lines may end
+//! in LF or CRLF, and a line ending after the last row is optional.
+
+pub fn parse(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    // Whether the current field was quoted, and so is complete at its closing
+    // quote.
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("Unterminated quoted field on line {}.", line)),
+                    }
+                }
+            }
+            ',' => {
+                row.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                quoted = false;
+                line += 1;
+            }
+            _ if quoted => return Err(format!("Unexpected character after a quoted field on line {}.", line)),
+            '"' => return Err(format!("Unexpected quote in an unquoted field on line {}.", line)),
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() || quoted {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn write_field(field: &str, out: &mut String) {
+    if field.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+/// Each row on its own line, ending in LF.
+pub fn stringify(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        for (i, field) in row.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_field(field, &mut out);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|field| field.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("a,b\n1,2\n").unwrap(), rows(&[&["a", "b"], &["1", "2"]]));
+        assert_eq!(parse("a,b\r\n1,2").unwrap(), rows(&[&["a", "b"], &["1", "2"]]));
+        assert_eq!(parse("x,,\n\n").unwrap(), rows(&[&["x", "", ""], &[""]]));
+        assert_eq!(parse("\"a, \"\"b\"\"\nc\",\"\"\n").unwrap(), rows(&[&["a, \"b\"\nc", ""]]));
+        assert_eq!(parse("").unwrap(), rows(&[]));
+        assert_eq!(parse("\"\"").unwrap(), rows(&[&[""]]));
+
+        assert_eq!(parse("a,\"b\nc").unwrap_err(), "Unterminated quoted field on line 2.");
+        assert_eq!(parse("\"a\"b").unwrap_err(), "Unexpected character after a quoted field on line 1.");
+        assert_eq!(parse("x\na\"b").unwrap_err(), "Unexpected quote in an unquoted field on line 2.");
+    }
+
+    #[test]
+    fn test_stringify() {
+        let table = rows(&[&["name", "note"], &["lox", "says \"hi\", twice"], &["", "two\nlines"]]);
+        let text = stringify(&table);
+        assert_eq!(text, "name,note\nlox,\"says \"\"hi\"\", twice\"\n,\"two\nlines\"\n");
+        assert_eq!(parse(&text).unwrap(), table);
+    }
+}
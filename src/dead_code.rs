@@ -0,0 +1,179 @@
+//! Dead code elimination, run after constant folding. Branches of if and while
+//! statements whose conditions folded to a literal that never takes them are
+//! removed, as are statements following a return, break, continue or throw in
+//! the same block. Each removal is reported as a warning, which hosts may show
+//! or ignore; the resolver has already checked the removed code.
+
+use crate::ast::*;
+use crate::error::Diagnostic;
+use crate::token::TokenKind;
+
+/// Prunes unreachable code from ast, returning a warning for each piece removed.
+pub fn eliminate_dead_code(ast: &mut Ast) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    prune_declarations(&mut ast.declarations, &mut warnings);
+    warnings
+}
+
+fn warn(warnings: &mut Vec<Diagnostic>, line: Option<usize>) {
+    if let Some(line) = line {
+        warnings.push(Diagnostic::new(line, "Unreachable code."));
+    }
+}
+
+fn prune_declarations(declarations: &mut Vec<Declaration>, warnings: &mut Vec<Diagnostic>) {
+    for declaration in declarations.iter_mut() {
+        prune_declaration(declaration, warnings);
+    }
+    if let Some(last) = declarations.iter().position(terminates) {
+        if last + 1 < declarations.len() {
+            warn(warnings, declarations[last + 1..].iter().find_map(declaration_line));
+            declarations.truncate(last + 1);
+        }
+    }
+    // What is left of an if or while removed above.
+    declarations.retain(|declaration| {
+        !matches!(declaration, Declaration::Statement(Statement::Block(block)) if block.is_empty())
+    });
+}
+
+fn prune_declaration(declaration: &mut Declaration, warnings: &mut Vec<Diagnostic>) {
+    match declaration {
+        Declaration::Class(class) => {
+            let class = class.borrow();
+            for method in class.methods.values().chain(class.setters.values()) {
+                prune_function(method, warnings);
+            }
+        }
+        Declaration::FunDeclaration(function) => prune_function(function, warnings),
+        Declaration::Statement(statement) => prune_statement(statement, warnings),
+        Declaration::Interface(_) | Declaration::VarDeclaration(_) => {}
+    }
+}
+
+fn prune_function(function: &FunDeclaration, warnings: &mut Vec<Diagnostic>) {
+    prune_declarations(&mut function.borrow_mut().body, warnings);
+}
+
+fn prune_statement(statement: &mut Statement, warnings: &mut Vec<Diagnostic>) {
+    match statement {
+        Statement::Block(declarations) => prune_declarations(declarations, warnings),
+        Statement::For(for_statement) => prune_statement(&mut for_statement.body, warnings),
+        Statement::ForIn(for_in) => prune_statement(&mut for_in.body, warnings),
+        Statement::If(if_statement) => {
+            prune_statement(&mut if_statement.true_branch, warnings);
+            if let Some(else_branch) = &mut if_statement.else_branch {
+                prune_statement(else_branch, warnings);
+            }
+            if let Some(truthy) = constant_truthiness(&if_statement.cond) {
+                // The branch taken replaces the if. An if opens no scope of its
+                // own, so the resolver's depths inside it still hold.
+                let (taken, skipped) = if truthy {
+                    (Some(&mut if_statement.true_branch), if_statement.else_branch.as_ref())
+                } else {
+                    (if_statement.else_branch.as_mut(), Some(&if_statement.true_branch))
+                };
+                if let Some(skipped) = skipped {
+                    warn(warnings, statement_line(skipped));
+                }
+                let taken = match taken {
+                    Some(taken) => std::mem::replace(taken, Statement::Block(Vec::new())),
+                    None => Statement::Block(Vec::new()),
+                };
+                *statement = taken;
+            }
+        }
+        Statement::Match(match_statement) => {
+            for arm in &mut match_statement.arms {
+                prune_statement(&mut arm.body, warnings);
+            }
+        }
+        Statement::Try(try_statement) => {
+            prune_declarations(&mut try_statement.body, warnings);
+            if let Some(catch) = &mut try_statement.catch {
+                prune_declarations(&mut catch.body, warnings);
+            }
+            if let Some(finally) = &mut try_statement.finally {
+                prune_declarations(finally, warnings);
+            }
+        }
+        Statement::While(while_statement) => {
+            if constant_truthiness(&while_statement.cond) == Some(false) {
+                warn(warnings, statement_line(&while_statement.body));
+                *statement = Statement::Block(Vec::new());
+            } else {
+                prune_statement(&mut while_statement.body, warnings);
+            }
+        }
+        Statement::With(with) => prune_declarations(&mut with.body, warnings),
+        Statement::Assert(_)
+        | Statement::Break(_)
+        | Statement::Continue(_)
+        | Statement::ExprStatement(_)
+        | Statement::Import(_)
+        | Statement::Print(_)
+        | Statement::Return(_)
+        | Statement::Throw(_)
+        | Statement::Yield(_) => {}
+    }
+}
+
+// Whether a condition is always truthy or always falsey, for a literal.
+fn constant_truthiness(cond: &Expr) -> Option<bool> {
+    if !matches!(cond.kind, ExprKind::Literal(_)) {
+        return None;
+    }
+    Some(!matches!(cond.token.kind, TokenKind::False | TokenKind::Nil))
+}
+
+// Whether control never reaches the declaration after this one.
+fn terminates(declaration: &Declaration) -> bool {
+    matches!(declaration, Declaration::Statement(statement) if statement_terminates(statement))
+}
+
+fn statement_terminates(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) | Statement::Break(_) | Statement::Continue(_) | Statement::Throw(_) => true,
+        Statement::Block(declarations) => declarations.last().is_some_and(terminates),
+        Statement::If(if_statement) => {
+            statement_terminates(&if_statement.true_branch)
+                && if_statement.else_branch.as_ref().is_some_and(statement_terminates)
+        }
+        _ => false,
+    }
+}
+
+// The line a declaration starts on, as near as its tokens tell.
+fn declaration_line(declaration: &Declaration) -> Option<usize> {
+    match declaration {
+        Declaration::Class(class) => Some(class.borrow().name.line),
+        Declaration::Interface(interface) => Some(interface.name.line),
+        Declaration::Statement(statement) => statement_line(statement),
+        Declaration::VarDeclaration(var) => Some(var.name.line),
+        Declaration::FunDeclaration(function) => Some(function.borrow().name.line),
+    }
+}
+
+fn statement_line(statement: &Statement) -> Option<usize> {
+    match statement {
+        Statement::Assert(assert) => Some(assert.keyword.line),
+        Statement::Block(declarations) => declarations.iter().find_map(declaration_line),
+        Statement::Break(jump) | Statement::Continue(jump) => Some(jump.keyword.line),
+        Statement::ExprStatement(expr) | Statement::Print(expr) => Some(expr.token.line),
+        Statement::For(for_statement) => match &for_statement.initializer {
+            Some(Initializer::VarDeclaration(var)) => Some(var.name.line),
+            Some(Initializer::Expr(expr)) => Some(expr.token.line),
+            None => statement_line(&for_statement.body),
+        },
+        Statement::ForIn(for_in) => Some(for_in.name.line),
+        Statement::If(if_statement) => Some(if_statement.cond.token.line),
+        Statement::Import(import) => Some(import.keyword.line),
+        Statement::Match(match_statement) => Some(match_statement.keyword.line),
+        Statement::Return(value) => value.as_ref().map(|value| value.token.line),
+        Statement::Throw(throw) => Some(throw.keyword.line),
+        Statement::Try(try_statement) => try_statement.body.iter().find_map(declaration_line),
+        Statement::While(while_statement) => Some(while_statement.cond.token.line),
+        Statement::With(with) => Some(with.keyword.line),
+        Statement::Yield(yield_statement) => Some(yield_statement.keyword.line),
+    }
+}
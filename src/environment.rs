@@ -1,152 +1,385 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+//! Scopes live in a per-thread arena rather than in allocations of their own.
+//! A handle is an index into it with a count of the handles to each scope, so
+//! opening a block reuses a freed slot of the arena, and walking out to an
+//! enclosing scope follows indices within it. A generation, bumped each time a
+//! slot is freed, marks handles to scopes that no longer exist.
 
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::Slot;
+use crate::gc::{self, Id};
 use crate::interp_error::{InterpError, InterpResult};
+use crate::memory::{self, Kind};
+use crate::symbol::{intern, Symbol};
 use crate::token::Token;
 use crate::value::*;
 
-type Link = Rc<RefCell<Node>>;
-type Scope = HashMap<String, Value>;
+// Globals are looked up by name. A block's locals are kept in the order they
+// were declared, which is the order the resolver numbered their slots in.
+enum Scope {
+    Globals(HashMap<Symbol, Value>),
+    Locals(Vec<(Symbol, Value)>),
+}
+
+impl Scope {
+    fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Scope::Globals(globals) => globals.get(name),
+            Scope::Locals(locals) => locals.iter().find(|(local, _)| &**local == name).map(|(_, value)| value),
+        }
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        match self {
+            Scope::Globals(globals) => globals.get_mut(name),
+            Scope::Locals(locals) => locals.iter_mut().find(|(local, _)| &**local == name).map(|(_, value)| value),
+        }
+    }
+
+    // The local in slot, falling back to a search by name for scopes the
+    // interpreter fills in a different order than the resolver counted.
+    fn get_slot(&self, slot: u32, name: &Symbol) -> Option<&Value> {
+        if let Scope::Locals(locals) = self {
+            if let Some((local, value)) = locals.get(slot as usize) {
+                if local == name {
+                    return Some(value);
+                }
+            }
+        }
+        self.get(name)
+    }
+
+    fn insert(&mut self, name: Symbol, value: Value) {
+        match self.get_mut(&name) {
+            Some(existing) => *existing = value,
+            None => match self {
+                Scope::Globals(globals) => {
+                    globals.insert(name, value);
+                }
+                Scope::Locals(locals) => locals.push((name, value)),
+            },
+        }
+    }
+
+    fn values(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
+        match self {
+            Scope::Globals(globals) => Box::new(globals.values()),
+            Scope::Locals(locals) => Box::new(locals.iter().map(|(_, value)| value)),
+        }
+    }
+
+    fn clear(&mut self) -> Scope {
+        match self {
+            Scope::Globals(globals) => Scope::Globals(std::mem::take(globals)),
+            Scope::Locals(locals) => Scope::Locals(std::mem::take(locals)),
+        }
+    }
+}
 
-#[derive(PartialEq)]
 struct Node {
-    parent: Option<Link>,
+    parent: Option<Environment>,
     scope: Scope,
+    // Names in scope declared with const.
+    constants: HashSet<Symbol>,
 }
 
-impl Node {
-    fn new_link() -> Link {
-        Node::new_with_scope(HashMap::new())
+impl Drop for Node {
+    fn drop(&mut self) {
+        memory::dropped(Kind::Environment);
     }
+}
 
-    fn new_with_parent(parent: Link) -> Link {
-        Rc::new(RefCell::new(Node {
-            parent: Some(parent),
-            scope: HashMap::new(),
-        }))
-    }
+// A slot of the arena, empty while it is on the free list.
+struct Entry {
+    generation: Cell<u32>,
+    handles: Cell<u32>,
+    node: RefCell<Option<Node>>,
+}
 
-    fn new_with_scope(scope: HashMap<String, Value>) -> Link {
-        Rc::new(RefCell::new(Node {
-            parent: None,
-            scope,
-        }))
-    }
+// Entries are only added to while no scope is being read or written, so
+// reading a value, which can clone a closure's handle, and dropping one, which
+// can free a scope, share the borrow of entries with the access doing it.
+#[derive(Default)]
+struct Arena {
+    entries: RefCell<Vec<Entry>>,
+    free: RefCell<Vec<u32>>,
+}
+
+thread_local! {
+    static ARENA: Arena = Arena::default();
+}
+
+fn allocate(node: Node) -> Environment {
+    memory::created(Kind::Environment);
+    ARENA.with(|arena| {
+        let reused = arena.free.borrow_mut().pop();
+        match reused {
+            Some(index) => {
+                let entries = arena.entries.borrow();
+                let entry = &entries[index as usize];
+                entry.handles.set(1);
+                *entry.node.borrow_mut() = Some(node);
+                Environment { index, generation: entry.generation.get() }
+            }
+            None => {
+                let mut entries = arena.entries.borrow_mut();
+                let index = u32::try_from(entries.len()).expect("Too many scopes.");
+                entries.push(Entry {
+                    generation: Cell::new(0),
+                    handles: Cell::new(1),
+                    node: RefCell::new(Some(node)),
+                });
+                Environment { index, generation: 0 }
+            }
+        }
+    })
+}
+
+/// Every scope alive on this thread, with how many handles it has, for the
+/// collector.
+pub(crate) fn scopes() -> Vec<(u32, u32)> {
+    ARENA.with(|arena| {
+        arena
+            .entries
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.handles.get() > 0)
+            .map(|(index, entry)| (index as u32, entry.handles.get()))
+            .collect()
+    })
+}
+
+/// Calls visit with each container the scope at index holds, as gc's Trace
+/// does, returning false if it is being written.
+pub(crate) fn trace_scope(index: u32, visit: &mut dyn FnMut(Id)) -> bool {
+    ARENA.with(|arena| {
+        let entries = arena.entries.borrow();
+        let Ok(node) = entries[index as usize].node.try_borrow() else {
+            return false;
+        };
+        if let Some(node) = node.as_ref() {
+            if let Some(parent) = &node.parent {
+                visit(parent.gc_id());
+            }
+            node.scope.values().for_each(|value| gc::trace_value(value, visit));
+        }
+        true
+    })
+}
+
+/// A new handle to the live scope at index.
+pub(crate) fn hold_scope(index: u32) -> Environment {
+    ARENA.with(|arena| {
+        let entries = arena.entries.borrow();
+        let entry = &entries[index as usize];
+        entry.handles.set(entry.handles.get() + 1);
+        Environment { index, generation: entry.generation.get() }
+    })
 }
 
 pub struct Environment {
-    current: Link,
+    index: u32,
+    generation: u32,
 }
 
 impl Clone for Environment {
     fn clone(&self) -> Environment {
+        ARENA.with(|arena| {
+            let entries = arena.entries.borrow();
+            let handles = &entries[self.index as usize].handles;
+            handles.set(handles.get() + 1);
+        });
         Environment {
-            current: self.current.clone()
+            index: self.index,
+            generation: self.generation,
         }
     }
 }
 
+impl Drop for Environment {
+    fn drop(&mut self) {
+        // The arena is gone if this runs as the thread exits.
+        let _ = ARENA.try_with(|arena| {
+            let node = {
+                let entries = arena.entries.borrow();
+                let entry = &entries[self.index as usize];
+                let handles = entry.handles.get() - 1;
+                entry.handles.set(handles);
+                if handles > 0 {
+                    return;
+                }
+                entry.generation.set(entry.generation.get().wrapping_add(1));
+                let node = entry.node.borrow_mut().take();
+                node
+            };
+            arena.free.borrow_mut().push(self.index);
+            // Freeing the scope drops its parent's handle, and any closures
+            // it held, once the arena is no longer borrowed.
+            drop(node);
+        });
+    }
+}
 
 impl Environment {
-    pub fn new() -> Environment {
-        Environment {
-            current: Node::new_link()
-        }
+    /// Two environments are the same scope exactly when their ids are equal.
+    pub fn id(&self) -> (u32, u32) {
+        (self.index, self.generation)
+    }
+
+    pub(crate) fn gc_id(&self) -> Id {
+        Id::Scope(self.index)
+    }
+
+    // Runs f on the scope depth levels out from this one.
+    fn with_node<R>(&self, depth: u32, f: impl FnOnce(&mut Node) -> R) -> R {
+        ARENA.with(|arena| {
+            let entries = arena.entries.borrow();
+            let entry = &entries[self.index as usize];
+            assert_eq!(entry.generation.get(), self.generation, "Use of a freed scope.");
+            let mut index = self.index;
+            for _ in 0..depth {
+                index = entries[index as usize].node.borrow().as_ref().unwrap().parent.as_ref().unwrap().index;
+            }
+            let mut node = entries[index as usize].node.borrow_mut();
+            f(node.as_mut().unwrap())
+        })
     }
 
     pub fn assign(&mut self, token: &Token, value: Value) -> InterpResult {
-        let mut bn = self.current.borrow_mut();
-        if bn.scope.contains_key(&token.content) {
-            bn.scope.insert(token.content.clone(), value.clone());
-            Ok(value)
-        } else {
-            Err(InterpError::new("Variable not found in scope.", token.clone()))
-        }
+        self.with_node(0, |node| {
+            if node.constants.contains(&token.content) {
+                Err(InterpError::new(&format!("Can't assign to constant '{}'.", token.content), token.clone()))
+            } else if let Some(existing) = node.scope.get_mut(&token.content) {
+                *existing = value.clone();
+                Ok(value)
+            } else {
+                Err(InterpError::new("Variable not found in scope.", token.clone()))
+            }
+        })
     }
 
-    pub fn assign_at(&mut self, depth: u32, name: String, value: Value) {
-        self.ancestor(depth)
-            .borrow_mut()
-            .scope
-            .insert(name, value)
-            .unwrap();
+    pub fn assign_slot(&mut self, slot: Slot, name: &Symbol, value: Value) {
+        self.with_node(slot.depth, |node| {
+            if let Scope::Locals(locals) = &mut node.scope {
+                if let Some((local, existing)) = locals.get_mut(slot.index as usize) {
+                    if local == name {
+                        *existing = value;
+                        return;
+                    }
+                }
+            }
+            *node.scope.get_mut(name).unwrap() = value;
+        })
     }
 
     pub fn bind_this(&mut self, object: &Object) {
         self.insert("this", Value::Object(object.clone()));
     }
 
-    pub fn declare_and_assign(&mut self, token: &Token, new_value: Value) {
-        self.current
-            .borrow_mut()
-            .scope
-            .insert(token.content.clone(), new_value);
+    /// Defines a name in the current scope, which fails if it already holds a
+    /// constant, such as one defined by an earlier line in the REPL.
+    pub fn declare_and_assign(&mut self, token: &Token, new_value: Value) -> Result<(), InterpError> {
+        self.with_node(0, |node| {
+            if node.constants.contains(&token.content) {
+                return Err(InterpError::new(&format!("Can't redefine constant '{}'.", token.content), token.clone()));
+            }
+            node.scope.insert(token.content.clone(), new_value);
+            Ok(())
+        })
+    }
+
+    /// The outermost scope, which holds the globals of the script or module
+    /// this environment belongs to.
+    pub fn globals(&self) -> Environment {
+        let mut environment = self.clone();
+        while let Some(parent) = environment.with_node(0, |node| node.parent.clone()) {
+            environment = parent;
+        }
+        environment
+    }
+
+    pub fn declare_constant(&mut self, token: &Token, new_value: Value) -> Result<(), InterpError> {
+        self.declare_and_assign(token, new_value)?;
+        self.with_node(0, |node| node.constants.insert(token.content.clone()));
+        Ok(())
     }
 
     pub fn get(&self, token: &Token) -> InterpResult {
-        if let Some(value) = self.current.borrow().scope.get(&token.content) {
-            Ok(value.clone())
-        } else {
-            Err(InterpError::new("Variable not found.", token.clone()))
+        match self.with_node(0, |node| node.scope.get(&token.content).cloned()) {
+            Some(value) => Ok(value),
+            None => Err(InterpError::new("Variable not found.", token.clone())),
         }
-    } 
+    }
 
     pub fn get_at(&self, depth: u32, name: &str) -> Value {
-        println!("getting ... {}", name);
-        self.ancestor(depth)
-            .borrow()
-            .scope
-            .get(name)
-            .unwrap()
-            .clone()
+        self.with_node(depth, |node| node.scope.get(name).unwrap().clone())
+    }
+
+    /// The local the resolver found in slot, which it resolved name to.
+    pub fn get_slot(&self, slot: Slot, name: &Symbol) -> Value {
+        self.with_node(slot.depth, |node| node.scope.get_slot(slot.index, name).unwrap().clone())
     }
 
     pub fn insert(&mut self, key: &str, value: Value) {
-        self.current
-            .borrow_mut()
-            .scope
-            .insert(key.to_string(), value);
+        self.with_node(0, |node| node.scope.insert(intern(key), value));
     }
 
     pub fn maybe_get_at(&self, depth: u32, name: &str) -> Option<Value> {
-        if let Some(value) = self.ancestor(depth)
-            .borrow()
-            .scope
-            .get(name) {
-                Some(value.clone())
-        } else {
-            None
-        }
+        self.with_node(depth, |node| node.scope.get(name).cloned())
     }
 
     pub fn bind_arguments(&mut self, arguments: Vec<Value>, parameters: &Vec<Token>) {
-        for (arg, param) in arguments.into_iter().zip(parameters) {
-            self.declare_and_assign(param, arg);
-        }
+        self.with_node(0, |node| {
+            for (arg, param) in arguments.into_iter().zip(parameters) {
+                node.scope.insert(param.content.clone(), arg);
+            }
+        })
     }
-    
+
     pub fn new_block(&self) -> Environment {
-        Environment {
-            current: Node::new_with_parent(self.current.clone())
-        }
+        allocate(Node {
+            parent: Some(self.clone()),
+            scope: Scope::Locals(Vec::new()),
+            constants: HashSet::new(),
+        })
     }
 
-    pub fn new_with_values(values: HashMap<String, Value>) -> Environment {
-        Environment {
-            current: Node::new_with_scope(values) 
-        }
+    pub fn new_with_values(values: HashMap<Symbol, Value>) -> Environment {
+        allocate(Node {
+            parent: None,
+            scope: Scope::Globals(values),
+            constants: HashSet::new(),
+        })
     }
-    
-    fn ancestor(&self, depth: u32) -> Link {
-        let mut node = self.current.clone();
-        for _ in 0..depth {
-            node = {
-                let borrowed_node = node.borrow();
-                borrowed_node.parent.as_ref().unwrap().clone()
-            }
-        }
-        node
+
+    /// Drops what the scope holds, for the collector to break a cycle through
+    /// it. The contents are dropped once the arena is no longer borrowed.
+    pub(crate) fn clear(&self) {
+        let contents = self.with_node(0, |node| (node.parent.take(), node.scope.clear()));
+        drop(contents);
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arena() {
+        let globals = Environment::new_with_values(HashMap::new());
+        let block = globals.new_block();
+        let (index, generation) = block.id();
+        drop(block);
+        assert_eq!(globals.new_block().id(), (index, generation + 1));
+
+        // An inner scope keeps the scopes enclosing it alive.
+        let mut outer = globals.new_block();
+        outer.insert("x", Value::Integer(1));
+        let inner = outer.new_block();
+        drop(outer);
+        assert_eq!(inner.maybe_get_at(1, "x"), Some(Value::Integer(1)));
+        assert_eq!(globals.id(), inner.globals().id());
+    }
+}
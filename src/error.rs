@@ -1,3 +1,27 @@
+use std::fmt;
+
 pub fn report(line: usize, message: &str) {
     println!("[line {}] {}", line, message);
 }
+
+/// An error reported while scanning, parsing, resolving or running a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(line: usize, message: &str) -> Diagnostic {
+        Diagnostic {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.message)
+    }
+}
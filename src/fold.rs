@@ -0,0 +1,379 @@
+//! Constant folding, run after the resolver. Arithmetic, concatenation,
+//! comparisons and boolean logic whose operands are all literals are replaced
+//! with the literal they evaluate to, so `1 + 2 * 3` is just `7` by the time
+//! it runs. Only expressions that evaluate the same way under every interpreter
+//! setting, and without error, are folded: anything involving nil arithmetic,
+//! integer overflow or division by zero is left for the interpreter to report.
+
+use crate::ast::*;
+use crate::symbol::intern;
+use crate::token::{Token, TokenKind};
+use crate::value::Value;
+
+pub fn fold_constants(ast: &mut Ast) {
+    fold_declarations(&mut ast.declarations);
+}
+
+fn fold_declarations(declarations: &mut [Declaration]) {
+    for declaration in declarations {
+        fold_declaration(declaration);
+    }
+}
+
+fn fold_declaration(declaration: &mut Declaration) {
+    match declaration {
+        Declaration::Class(class) => {
+            let mut class = class.borrow_mut();
+            for method in class.methods.values().chain(class.setters.values()) {
+                fold_function(method);
+            }
+            for field in &mut class.statics {
+                fold_var(field);
+            }
+        }
+        Declaration::Interface(_) => {}
+        Declaration::Statement(statement) => fold_statement(statement),
+        Declaration::VarDeclaration(var) => fold_var(var),
+        Declaration::FunDeclaration(function) => fold_function(function),
+    }
+}
+
+fn fold_function(function: &FunDeclaration) {
+    fold_declarations(&mut function.borrow_mut().body);
+}
+
+fn fold_var(var: &mut VarDeclaration) {
+    if let Some(initializer) = &mut var.initializer {
+        fold_expr(initializer);
+    }
+}
+
+fn fold_statement(statement: &mut Statement) {
+    match statement {
+        Statement::Assert(assert) => {
+            fold_expr(&mut assert.cond);
+            if let Some(message) = &mut assert.message {
+                fold_expr(message);
+            }
+        }
+        Statement::Block(declarations) => fold_declarations(declarations),
+        Statement::Break(_) | Statement::Continue(_) | Statement::Import(_) => {}
+        Statement::ExprStatement(expr) | Statement::Print(expr) => fold_expr(expr),
+        Statement::For(for_statement) => {
+            match &mut for_statement.initializer {
+                Some(Initializer::VarDeclaration(var)) => fold_var(var),
+                Some(Initializer::Expr(expr)) => fold_expr(expr),
+                None => {}
+            }
+            for expr in [&mut for_statement.cond, &mut for_statement.increment].into_iter().flatten() {
+                fold_expr(expr);
+            }
+            fold_statement(&mut for_statement.body);
+        }
+        Statement::ForIn(for_in) => {
+            fold_expr(&mut for_in.iterable);
+            fold_statement(&mut for_in.body);
+        }
+        Statement::If(if_statement) => {
+            fold_expr(&mut if_statement.cond);
+            fold_statement(&mut if_statement.true_branch);
+            if let Some(else_branch) = &mut if_statement.else_branch {
+                fold_statement(else_branch);
+            }
+        }
+        // Patterns are matched against, not evaluated, so only the arms' bodies
+        // are folded.
+        Statement::Match(match_statement) => {
+            fold_expr(&mut match_statement.value);
+            for arm in &mut match_statement.arms {
+                fold_statement(&mut arm.body);
+            }
+        }
+        Statement::Return(value) => {
+            if let Some(value) = value {
+                fold_expr(value);
+            }
+        }
+        Statement::Throw(throw) => fold_expr(&mut throw.value),
+        Statement::Try(try_statement) => {
+            fold_declarations(&mut try_statement.body);
+            if let Some(catch) = &mut try_statement.catch {
+                fold_declarations(&mut catch.body);
+            }
+            if let Some(finally) = &mut try_statement.finally {
+                fold_declarations(finally);
+            }
+        }
+        Statement::While(while_statement) => {
+            fold_expr(&mut while_statement.cond);
+            fold_statement(&mut while_statement.body);
+        }
+        Statement::With(with) => {
+            fold_expr(&mut with.resource);
+            fold_declarations(&mut with.body);
+        }
+        Statement::Yield(yield_statement) => {
+            if let Some(value) = &mut yield_statement.value {
+                fold_expr(value);
+            }
+        }
+    }
+}
+
+fn fold_expr(expr: &mut Expr) {
+    let folded = match &mut expr.kind {
+        ExprKind::Assign(assign) => {
+            fold_expr(&mut assign.initializer);
+            None
+        }
+        ExprKind::Binary(binary) => {
+            fold_expr(&mut binary.left);
+            fold_expr(&mut binary.right);
+            match (literal(&binary.left), literal(&binary.right)) {
+                (Some(left), Some(right)) => binary_constant(&left, &right, expr.token.kind),
+                _ => None,
+            }
+        }
+        ExprKind::Call(call) => {
+            fold_expr(&mut call.callee);
+            call.arguments.iter_mut().for_each(fold_expr);
+            None
+        }
+        ExprKind::Chain(chain) => {
+            chain.operands.iter_mut().for_each(fold_expr);
+            None
+        }
+        ExprKind::Comprehension(comprehension) => {
+            fold_expr(&mut comprehension.element);
+            fold_expr(&mut comprehension.iterable);
+            if let Some(condition) = &mut comprehension.condition {
+                fold_expr(condition);
+            }
+            None
+        }
+        ExprKind::Get(get) | ExprKind::OptionalGet(get) => {
+            fold_expr(&mut get.object);
+            None
+        }
+        ExprKind::Spread(object) => {
+            fold_expr(object);
+            None
+        }
+        ExprKind::Grouping(inner) => {
+            fold_expr(inner);
+            literal(inner)
+        }
+        ExprKind::Index(index) => {
+            fold_expr(&mut index.object);
+            fold_expr(&mut index.index);
+            None
+        }
+        ExprKind::List(elements) => {
+            elements.iter_mut().for_each(fold_expr);
+            None
+        }
+        ExprKind::Logical(logical) => {
+            fold_expr(&mut logical.left);
+            fold_expr(&mut logical.right);
+            logical_constant(logical, expr.token.kind)
+        }
+        ExprKind::Set(set) => {
+            fold_expr(&mut set.object);
+            fold_expr(&mut set.value);
+            None
+        }
+        ExprKind::SetIndex(set_index) => {
+            fold_expr(&mut set_index.object);
+            fold_expr(&mut set_index.index);
+            fold_expr(&mut set_index.value);
+            None
+        }
+        ExprKind::Unary(operand) => {
+            fold_expr(operand);
+            literal(operand).and_then(|value| unary_constant(value, expr.token.kind))
+        }
+        ExprKind::Literal(_) | ExprKind::Super(_, _) | ExprKind::This(_) | ExprKind::Variable(_) => None,
+    };
+    if let Some(value) = folded {
+        if let Some(token) = literal_token(&value, expr.token.line) {
+            *expr = Expr::new_literal(token);
+        }
+    }
+}
+
+// The value of a literal expression.
+fn literal(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        ExprKind::Literal(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+// The token of a literal that evaluates to value, if there is one. Floats
+// whose shortest form lacks a decimal point, such as 1e20, would read back as
+// integers, and infinities and NaN have no literal.
+fn literal_token(value: &Value, line: usize) -> Option<Token> {
+    let (kind, content) = match value {
+        Value::Integer(i) => (TokenKind::Number, i.to_string()),
+        Value::Number(n) => {
+            let content = format!("{:?}", n);
+            if !n.is_finite() || !content.contains('.') {
+                return None;
+            }
+            (TokenKind::Number, content)
+        }
+        Value::StringV(s) => (TokenKind::StringT, s.to_string()),
+        Value::Boolean(true) => (TokenKind::True, "true".to_string()),
+        Value::Boolean(false) => (TokenKind::False, "false".to_string()),
+        Value::Nil => (TokenKind::Nil, "nil".to_string()),
+        _ => return None,
+    };
+    Some(Token { kind, line, content: intern(&content) })
+}
+
+fn binary_constant(left: &Value, right: &Value, operator: TokenKind) -> Option<Value> {
+    match operator {
+        TokenKind::EqualEqual => return Some(Value::Boolean(left == right)),
+        TokenKind::BangEqual => return Some(Value::Boolean(left != right)),
+        _ => {}
+    }
+    match (left, right) {
+        (Value::StringV(s1), Value::StringV(s2)) => match operator {
+            TokenKind::Plus => Some(Value::new_string(format!("{}{}", s1, s2))),
+            TokenKind::Less => Some(Value::Boolean(s1 < s2)),
+            TokenKind::LessEqual => Some(Value::Boolean(s1 <= s2)),
+            TokenKind::Greater => Some(Value::Boolean(s1 > s2)),
+            TokenKind::GreaterEqual => Some(Value::Boolean(s1 >= s2)),
+            _ => None,
+        },
+        (Value::Integer(i1), Value::Integer(i2)) => match operator {
+            TokenKind::Plus => i1.checked_add(*i2).map(Value::Integer),
+            TokenKind::Minus => i1.checked_sub(*i2).map(Value::Integer),
+            TokenKind::Star => i1.checked_mul(*i2).map(Value::Integer),
+            // Integer division stays integral only when it is exact. Dividing
+            // by zero, or i64::MIN by -1, is left to the interpreter.
+            TokenKind::Slash => match i1.checked_rem(*i2)? {
+                0 => Some(Value::Integer(i1 / i2)),
+                _ => number_constant(*i1 as f64, *i2 as f64, operator),
+            },
+            TokenKind::Less => Some(Value::Boolean(i1 < i2)),
+            TokenKind::LessEqual => Some(Value::Boolean(i1 <= i2)),
+            TokenKind::Greater => Some(Value::Boolean(i1 > i2)),
+            TokenKind::GreaterEqual => Some(Value::Boolean(i1 >= i2)),
+            _ => None,
+        },
+        (Value::Integer(_) | Value::Number(_), Value::Integer(_) | Value::Number(_)) => {
+            number_constant(left.as_number()?, right.as_number()?, operator)
+        }
+        _ => None,
+    }
+}
+
+fn number_constant(n1: f64, n2: f64, operator: TokenKind) -> Option<Value> {
+    let value = match operator {
+        TokenKind::Plus => Value::Number(n1 + n2),
+        TokenKind::Minus => Value::Number(n1 - n2),
+        TokenKind::Star => Value::Number(n1 * n2),
+        TokenKind::Slash => Value::Number(n1 / n2),
+        TokenKind::Less => Value::Boolean(n1 < n2),
+        TokenKind::LessEqual => Value::Boolean(n1 <= n2),
+        TokenKind::Greater => Value::Boolean(n1 > n2),
+        TokenKind::GreaterEqual => Value::Boolean(n1 >= n2),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn unary_constant(value: Value, operator: TokenKind) -> Option<Value> {
+    match (operator, value) {
+        (TokenKind::Bang, value) => Some(Value::Boolean(!value.is_truthy())),
+        (TokenKind::Minus, Value::Integer(i)) => i.checked_neg().map(Value::Integer),
+        (TokenKind::Minus, Value::Number(n)) => Some(Value::Number(-n)),
+        _ => None,
+    }
+}
+
+// `and` and `or` evaluate to a boolean, so they fold once both sides are
+// literals, or once the left side decides the result alone. `??` folds when
+// its left side is a literal.
+fn logical_constant(logical: &BinaryExpr, operator: TokenKind) -> Option<Value> {
+    let left = literal(&logical.left)?;
+    let right = literal(&logical.right);
+    match operator {
+        TokenKind::And if !left.is_truthy() => Some(Value::Boolean(false)),
+        TokenKind::Or if left.is_truthy() => Some(Value::Boolean(true)),
+        TokenKind::And | TokenKind::Or => right.map(|right| Value::Boolean(right.is_truthy())),
+        TokenKind::QuestionQuestion if left == Value::Nil => right,
+        TokenKind::QuestionQuestion => Some(left),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::scan_parse;
+
+    // The initializer of each var in source once folded, as literal text, or
+    // None where it is not a literal.
+    fn folded(source: &str) -> Vec<Option<String>> {
+        let mut ast = scan_parse(source);
+        fold_constants(&mut ast);
+        ast.declarations
+            .iter()
+            .map(|declaration| match declaration {
+                Declaration::VarDeclaration(VarDeclaration { initializer: Some(expr), .. }) => {
+                    literal(expr).map(|value| format!("{:?}", value))
+                }
+                _ => panic!("Expected a var declaration."),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_folds_constants() {
+        let source = "
+        var a = 1 + 2 * 3;
+        var b = (10 - 4) / 3;
+        var c = 7 / 2;
+        var d = \"con\" + \"cat\";
+        var e = !(1 < 2) or 2.5 >= 2;
+        var f = -(0.5 + 0.25);
+        var g = nil ?? \"default\";
+        var h = false and x;
+        var i = 1 == 1.0;";
+        let expected = [
+            "Integer(7)",
+            "Integer(2)",
+            "Number(3.5)",
+            "StringV(\"concat\")",
+            "Boolean(true)",
+            "Number(-0.75)",
+            "StringV(\"default\")",
+            "Boolean(false)",
+            "Boolean(true)",
+        ];
+        let expected: Vec<_> = expected.iter().map(|value| Some(value.to_string())).collect();
+        assert_eq!(folded(source), expected);
+    }
+
+    #[test]
+    fn test_leaves_errors_and_variables() {
+        let source = "
+        var a = 1 / 0;
+        var b = nil + 1;
+        var c = 9223372036854775807 + 1;
+        var d = x + 1 * 2;
+        var e = true and x;
+        var f = \"a\" + 1;
+        var g = 100000000000000000000.0 * 10.0;";
+        assert_eq!(folded(source), vec![None; 7]);
+
+        // The operands a fold left in place are still folded themselves.
+        let mut ast = scan_parse("var d = x + 1 * 2;");
+        fold_constants(&mut ast);
+        let Declaration::VarDeclaration(var) = &ast.declarations[0] else { unreachable!() };
+        let ExprKind::Binary(binary) = &var.initializer.as_ref().unwrap().kind else { unreachable!() };
+        assert_eq!(literal(&binary.right), Some(Value::Integer(2)));
+    }
+}
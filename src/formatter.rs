@@ -0,0 +1,940 @@
+use std::fmt;
+
+use crate::ast::*;
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenKind};
+
+const INDENT: &str = "    ";
+
+/// Pretty-prints an Ast back into Lox source that parses to the same tree.
+struct AstFormatter {
+    output: String,
+    depth: usize,
+}
+
+impl AstFormatter {
+    fn new() -> AstFormatter {
+        AstFormatter {
+            output: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.output.push_str(s);
+    }
+
+    // A string literal in quotes, escaped so the scanner reads back the same
+    // text. Newlines stay as they are, keeping every token on its line.
+    fn push_string(&mut self, s: &str) {
+        self.output.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\t' => self.output.push_str("\\t"),
+                '\r' => self.output.push_str("\\r"),
+                '\0' => self.output.push_str("\\0"),
+                c => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+
+    fn newline(&mut self) {
+        self.output.push('\n');
+        for _ in 0..self.depth {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    fn format_declarations(&mut self, declarations: &[Declaration]) {
+        for (i, declaration) in declarations.iter().enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            self.format_declaration(declaration);
+        }
+    }
+
+    fn format_declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Class(class) => self.format_class(&class.borrow()),
+            Declaration::Interface(interface) => self.format_interface(interface),
+            Declaration::FunDeclaration(fun_declaration) => {
+                self.push("fun ");
+                self.format_function(&fun_declaration.borrow());
+            }
+            Declaration::Statement(statement) => self.format_statement(statement),
+            Declaration::VarDeclaration(var_declaration) => self.format_var_declaration(var_declaration),
+        }
+    }
+
+    fn format_class(&mut self, class: &ClassStruct) {
+        self.push("class ");
+        self.push(&class.name.content);
+        if let Some(superclass) = &class.superclass {
+            self.push(" < ");
+            self.format_expr(superclass);
+        }
+        for (i, mixin) in class.mixins.iter().enumerate() {
+            self.push(if i == 0 { " with " } else { ", " });
+            self.format_expr(mixin);
+        }
+        for (i, interface) in class.interfaces.iter().enumerate() {
+            self.push(if i == 0 { " : " } else { ", " });
+            self.push(&interface.content);
+        }
+        self.push(" {");
+        self.depth += 1;
+        for field in &class.statics {
+            self.newline();
+            self.push("static ");
+            self.format_var_declaration(field);
+        }
+        let mut names: Vec<&Symbol> = class.methods.keys().collect();
+        names.sort();
+        for name in names {
+            self.newline();
+            self.format_function(&class.methods[name].borrow());
+        }
+        let mut names: Vec<&Symbol> = class.setters.keys().collect();
+        names.sort();
+        for name in names {
+            self.newline();
+            self.push("set ");
+            self.format_function(&class.setters[name].borrow());
+        }
+        self.depth -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    fn format_interface(&mut self, interface: &Interface) {
+        self.push("interface ");
+        self.push(&interface.name.content);
+        self.push(" {");
+        self.depth += 1;
+        for method in &interface.methods {
+            self.newline();
+            self.push(&method.name.content);
+            self.push("(");
+            let params: Vec<&str> = method.params.iter().map(|p| &*p.content).collect();
+            self.push(&params.join(", "));
+            self.push(");");
+        }
+        self.depth -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    fn format_function(&mut self, function: &FunDeclarationStruct) {
+        self.push(&function.name.content);
+        if !function.getter {
+            self.push("(");
+            let params: Vec<&str> = function.params.iter().map(|p| &*p.content).collect();
+            self.push(&params.join(", "));
+            self.push(")");
+        }
+        self.push(" ");
+        self.format_block(&function.body);
+    }
+
+    fn format_block(&mut self, declarations: &[Declaration]) {
+        self.push("{");
+        self.depth += 1;
+        for declaration in declarations {
+            self.newline();
+            self.format_declaration(declaration);
+        }
+        self.depth -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    fn format_var_declaration(&mut self, var_declaration: &VarDeclaration) {
+        self.push(if var_declaration.constant { "const " } else { "var " });
+        self.push(&var_declaration.name.content);
+        if let Some(initializer) = &var_declaration.initializer {
+            self.push(" = ");
+            self.format_expr(initializer);
+        }
+        self.push(";");
+    }
+
+    fn format_label(&mut self, label: &Option<Token>) {
+        if let Some(label) = label {
+            self.push(&label.content);
+            self.push(": ");
+        }
+    }
+
+    fn format_jump(&mut self, jump: &Jump) {
+        self.push(&jump.keyword.content);
+        if let Some(label) = &jump.label {
+            self.push(" ");
+            self.push(&label.content);
+        }
+        self.push(";");
+    }
+
+    fn format_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Block(declarations) => self.format_block(declarations),
+            Statement::Break(jump) | Statement::Continue(jump) => self.format_jump(jump),
+            Statement::ExprStatement(expr) => {
+                self.format_expr(expr);
+                self.push(";");
+            }
+            Statement::For(for_statement) => {
+                self.format_label(&for_statement.label);
+                self.push("for (");
+                match &for_statement.initializer {
+                    Some(Initializer::VarDeclaration(var_declaration)) => {
+                        self.format_var_declaration(var_declaration)
+                    }
+                    Some(Initializer::Expr(expr)) => {
+                        self.format_expr(expr);
+                        self.push(";");
+                    }
+                    None => self.push(";"),
+                }
+                if let Some(cond) = &for_statement.cond {
+                    self.push(" ");
+                    self.format_expr(cond);
+                }
+                self.push(";");
+                if let Some(increment) = &for_statement.increment {
+                    self.push(" ");
+                    self.format_expr(increment);
+                }
+                self.push(") ");
+                self.format_statement(&for_statement.body);
+            }
+            Statement::ForIn(for_in) => {
+                self.format_label(&for_in.label);
+                self.push("for (var ");
+                self.push(&for_in.name.content);
+                self.push(" in ");
+                self.format_expr(&for_in.iterable);
+                self.push(") ");
+                self.format_statement(&for_in.body);
+            }
+            Statement::If(if_statement) => {
+                self.push("if (");
+                self.format_expr(&if_statement.cond);
+                self.push(") ");
+                self.format_statement(&if_statement.true_branch);
+                if let Some(else_branch) = &if_statement.else_branch {
+                    self.push(" else ");
+                    self.format_statement(else_branch);
+                }
+            }
+            Statement::Import(import) => {
+                self.push("import ");
+                self.push_string(&import.path.content);
+                if let Some(alias) = &import.alias {
+                    self.push(" as ");
+                    self.push(&alias.content);
+                }
+                self.push(";");
+            }
+            Statement::Print(expr) => {
+                self.push("print ");
+                self.format_expr(expr);
+                self.push(";");
+            }
+            Statement::Return(value) => {
+                self.push("return");
+                if let Some(expr) = value {
+                    self.push(" ");
+                    self.format_expr(expr);
+                }
+                self.push(";");
+            }
+            Statement::Assert(assert) => {
+                self.push("assert ");
+                self.format_expr(&assert.cond);
+                if let Some(message) = &assert.message {
+                    self.push(", ");
+                    self.format_expr(message);
+                }
+                self.push(";");
+            }
+            Statement::Match(match_statement) => {
+                self.push("match (");
+                self.format_expr(&match_statement.value);
+                self.push(") {");
+                self.depth += 1;
+                for arm in &match_statement.arms {
+                    self.newline();
+                    self.push("case ");
+                    self.format_pattern(&arm.pattern);
+                    self.push(": ");
+                    self.format_statement(&arm.body);
+                }
+                self.depth -= 1;
+                self.newline();
+                self.push("}");
+            }
+            Statement::Yield(yield_statement) => {
+                self.push("yield");
+                if let Some(expr) = &yield_statement.value {
+                    self.push(" ");
+                    self.format_expr(expr);
+                }
+                self.push(";");
+            }
+            Statement::Throw(throw) => {
+                self.push("throw ");
+                self.format_expr(&throw.value);
+                self.push(";");
+            }
+            Statement::Try(try_statement) => {
+                self.push("try ");
+                self.format_block(&try_statement.body);
+                if let Some(catch) = &try_statement.catch {
+                    self.push(" catch (");
+                    self.push(&catch.name.content);
+                    self.push(") ");
+                    self.format_block(&catch.body);
+                }
+                if let Some(finally) = &try_statement.finally {
+                    self.push(" finally ");
+                    self.format_block(finally);
+                }
+            }
+            Statement::With(with) => {
+                self.push("with (");
+                self.format_expr(&with.resource);
+                self.push(") as ");
+                self.push(&with.name.content);
+                self.push(" ");
+                self.format_block(&with.body);
+            }
+            Statement::While(while_statement) => {
+                self.format_label(&while_statement.label);
+                self.push("while (");
+                self.format_expr(&while_statement.cond);
+                self.push(") ");
+                self.format_statement(&while_statement.body);
+            }
+        }
+    }
+
+    fn format_patterns(&mut self, patterns: &[Pattern]) {
+        for (i, pattern) in patterns.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.format_pattern(pattern);
+        }
+    }
+
+    fn format_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Literal(expr) => self.format_expr(expr),
+            Pattern::Binding(name) => self.push(&name.content),
+            Pattern::List(patterns) => {
+                self.push("[");
+                self.format_patterns(patterns);
+                self.push("]");
+            }
+            Pattern::Class(class, patterns) => {
+                self.format_expr(class);
+                self.push("(");
+                self.format_patterns(patterns);
+                self.push(")");
+            }
+        }
+    }
+
+    fn format_elements(&mut self, elements: &[Expr]) {
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.format_expr(element);
+        }
+    }
+
+    fn format_binary(&mut self, binary_expr: &BinaryExpr, kind: TokenKind) {
+        self.format_expr(&binary_expr.left);
+        self.push(" ");
+        self.push(kind.operator());
+        self.push(" ");
+        self.format_expr(&binary_expr.right);
+    }
+
+    fn format_expr(&mut self, expr: &Expr) {
+        let token = &expr.token;
+        match &expr.kind {
+            ExprKind::Assign(assign_expr) => {
+                self.push(&token.content);
+                self.push(" = ");
+                self.format_expr(&assign_expr.initializer);
+            }
+            ExprKind::Binary(binary_expr) | ExprKind::Logical(binary_expr) => {
+                self.format_binary(binary_expr, token.kind)
+            }
+            ExprKind::Call(call) => {
+                self.format_expr(&call.callee);
+                self.push("(");
+                self.format_elements(&call.arguments);
+                self.push(")");
+            }
+            ExprKind::Index(index) => {
+                self.format_expr(&index.object);
+                self.push("[");
+                self.format_expr(&index.index);
+                self.push("]");
+            }
+            ExprKind::SetIndex(set_index) => {
+                self.format_expr(&set_index.object);
+                self.push("[");
+                self.format_expr(&set_index.index);
+                self.push("] = ");
+                self.format_expr(&set_index.value);
+            }
+            ExprKind::List(elements) => {
+                self.push("[");
+                self.format_elements(elements);
+                self.push("]");
+            }
+            ExprKind::Chain(chain) => {
+                self.format_expr(&chain.operands[0]);
+                for (operator, operand) in chain.operators.iter().zip(&chain.operands[1..]) {
+                    self.push(" ");
+                    self.push(operator.kind.operator());
+                    self.push(" ");
+                    self.format_expr(operand);
+                }
+            }
+            ExprKind::Comprehension(comprehension) => {
+                self.push("[");
+                self.format_expr(&comprehension.element);
+                self.push(" for ");
+                self.push(&comprehension.name.content);
+                self.push(" in ");
+                self.format_expr(&comprehension.iterable);
+                if let Some(condition) = &comprehension.condition {
+                    self.push(" if ");
+                    self.format_expr(condition);
+                }
+                self.push("]");
+            }
+            ExprKind::Spread(list) => {
+                self.push("...");
+                self.format_expr(list);
+            }
+            ExprKind::Get(get) => {
+                self.format_expr(&get.object);
+                self.push(".");
+                self.push(&token.content);
+            }
+            ExprKind::OptionalGet(get) => {
+                self.format_expr(&get.object);
+                self.push("?.");
+                self.push(&token.content);
+            }
+            ExprKind::Grouping(inner) => {
+                self.push("(");
+                self.format_expr(inner);
+                self.push(")");
+            }
+            ExprKind::Literal(_) => {
+                if token.kind == TokenKind::StringT {
+                    self.push_string(&token.content);
+                } else {
+                    self.push(&token.content);
+                }
+            }
+            ExprKind::Set(set) => {
+                self.format_expr(&set.object);
+                self.push(".");
+                self.push(&token.content);
+                self.push(" = ");
+                self.format_expr(&set.value);
+            }
+            ExprKind::This(_) => self.push("this"),
+            ExprKind::Unary(operand) => {
+                self.push(token.kind.operator());
+                self.format_expr(operand);
+            }
+            ExprKind::Variable(_) => self.push(&token.content),
+            ExprKind::Super(method, _) => {
+                self.push("super.");
+                self.push(&method.content);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut formatter = AstFormatter::new();
+        formatter.format_declarations(&self.declarations);
+        writeln!(f, "{}", formatter.output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::ast::*;
+    use crate::symbol::intern;
+    use crate::testing::scan_parse;
+    use crate::token::{Token, TokenKind};
+
+    const NAMES: [&str; 6] = ["a", "b", "count", "foo", "bar_2", "Baz"];
+    const MAX_DEPTH: usize = 4;
+
+    // Precedence tiers, lowest first, mirroring the descent order in Parser.
+    const ASSIGNMENT: u8 = 0;
+    const COALESCE: u8 = 1;
+    const OR: u8 = 2;
+    const AND: u8 = 3;
+    const EQUALITY: u8 = 4;
+    const COMPARISON: u8 = 5;
+    const RANGE: u8 = 6;
+    const TERM: u8 = 7;
+    const FACTOR: u8 = 8;
+    const UNARY: u8 = 9;
+    const CALL: u8 = 10;
+    const PRIMARY: u8 = 11;
+
+    const ORDERINGS: [TokenKind; 4] = [
+        TokenKind::Greater,
+        TokenKind::GreaterEqual,
+        TokenKind::Less,
+        TokenKind::LessEqual,
+    ];
+
+    fn token(kind: TokenKind, content: &str) -> Token {
+        Token {
+            kind,
+            line: 0,
+            content: intern(content),
+        }
+    }
+
+    /// Builds random but well-formed Asts: every subexpression whose precedence is
+    /// lower than its position allows is wrapped in an explicit grouping, just as
+    /// the parser would require.
+    struct Generator {
+        state: u64,
+        depth: usize,
+    }
+
+    impl Generator {
+        fn new(seed: u64) -> Generator {
+            Generator {
+                state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+                depth: 0,
+            }
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            (self.state % n as u64) as usize
+        }
+
+        fn pick<T: Copy>(&mut self, options: &[T]) -> T {
+            options[self.below(options.len())]
+        }
+
+        fn name(&mut self) -> Token {
+            token(TokenKind::Identifier, self.pick(&NAMES))
+        }
+
+        fn exhausted(&self) -> bool {
+            self.depth >= MAX_DEPTH
+        }
+
+        fn literal(&mut self) -> Expr {
+            let token = match self.below(5) {
+                0 => token(TokenKind::Number, &self.below(1000).to_string()),
+                1 => {
+                    let number = format!("{}.{}", self.below(100), self.below(100));
+                    token(TokenKind::Number, &number)
+                }
+                2 => {
+                    let string = self.pick(&[
+                        "",
+                        "hello",
+                        "two words",
+                        "1 + 2",
+                        "say \"hi\"",
+                        "a\\b\tc",
+                        "h\u{e9}llo \u{1F600}",
+                    ]);
+                    token(TokenKind::StringT, string)
+                }
+                3 if self.below(2) == 0 => token(TokenKind::True, "true"),
+                3 => token(TokenKind::False, "false"),
+                _ => token(TokenKind::Nil, "nil"),
+            };
+            Expr::new_literal(token)
+        }
+
+        fn expr(&mut self, min: u8) -> Expr {
+            self.depth += 1;
+            let choice = if self.exhausted() { self.below(3) } else { self.below(24) };
+            let (expr, precedence) = match choice {
+                0 => (self.literal(), PRIMARY),
+                1 => (Expr::new_variable(self.name()), PRIMARY),
+                2 => (Expr::new_this(token(TokenKind::This, "this")), PRIMARY),
+                3 => {
+                    let super_token = token(TokenKind::Super, "super");
+                    (Expr::new_super(self.name(), super_token), PRIMARY)
+                }
+                4 => {
+                    let inner = self.expr(ASSIGNMENT);
+                    (Expr::new_grouping(token(TokenKind::RightParen, ""), inner), PRIMARY)
+                }
+                5 => {
+                    let callee = self.expr(CALL);
+                    let count = self.below(3);
+                    let arguments = (0..count).map(|_| self.element()).collect();
+                    let paren = token(TokenKind::RightParen, "");
+                    (Expr::new_call(callee, arguments, paren), CALL)
+                }
+                6 => {
+                    let object = self.expr(CALL);
+                    (Expr::new_get(self.name(), object), CALL)
+                }
+                7 => {
+                    let operator = token(self.pick(&[TokenKind::Bang, TokenKind::Minus]), "");
+                    (Expr::new_unary(operator, self.expr(UNARY)), UNARY)
+                }
+                8 => self.binary(FACTOR, &[TokenKind::Slash, TokenKind::Star]),
+                9 => self.binary(TERM, &[TokenKind::Minus, TokenKind::Plus]),
+                // An ordering after another would chain with it.
+                10 if self.below(2) == 0 => {
+                    let left = self.expr(RANGE);
+                    let operator = token(self.pick(&ORDERINGS), "");
+                    (Expr::new_binary(left, operator, self.expr(RANGE)), COMPARISON)
+                }
+                10 => self.binary(COMPARISON, &[TokenKind::Is, TokenKind::In]),
+                11 => self.binary(EQUALITY, &[TokenKind::BangEqual, TokenKind::EqualEqual]),
+                20 => self.binary(RANGE, &[TokenKind::DotDot, TokenKind::DotDotEqual]),
+                21 => {
+                    let element = self.expr(ASSIGNMENT);
+                    let name = self.name();
+                    let iterable = self.expr(ASSIGNMENT);
+                    let condition = if self.below(2) == 0 { Some(self.expr(ASSIGNMENT)) } else { None };
+                    let bracket = token(TokenKind::LeftBracket, "");
+                    (Expr::new_comprehension(bracket, element, name, iterable, condition), PRIMARY)
+                }
+                22 => {
+                    let count = 2 + self.below(2);
+                    let operators = (0..count).map(|_| token(self.pick(&ORDERINGS), "")).collect();
+                    let operands = (0..=count).map(|_| self.expr(RANGE)).collect();
+                    (Expr::new_chain(operands, operators), COMPARISON)
+                }
+                12 => {
+                    let left = self.expr(AND);
+                    let right = self.expr(EQUALITY);
+                    (Expr::new_logical(left, token(TokenKind::And, "and"), right), AND)
+                }
+                13 => {
+                    let left = self.expr(OR);
+                    let right = self.expr(AND);
+                    (Expr::new_logical(left, token(TokenKind::Or, "or"), right), OR)
+                }
+                14 => {
+                    let left = self.expr(COALESCE);
+                    let right = self.expr(OR);
+                    (Expr::new_logical(left, token(TokenKind::QuestionQuestion, ""), right), COALESCE)
+                }
+                15 => {
+                    let object = self.expr(CALL);
+                    (Expr::new_optional_get(self.name(), object), CALL)
+                }
+                16 => {
+                    let name = self.name();
+                    (Expr::new_assign(name, self.expr(ASSIGNMENT)), ASSIGNMENT)
+                }
+                17 => {
+                    let elements = (0..self.below(3)).map(|_| self.element()).collect();
+                    (Expr::new_list(token(TokenKind::LeftBracket, ""), elements), PRIMARY)
+                }
+                18 => {
+                    let object = self.expr(CALL);
+                    let index = self.expr(ASSIGNMENT);
+                    (Expr::new_index(object, index, token(TokenKind::RightBracket, "")), CALL)
+                }
+                19 => {
+                    let object = self.expr(CALL);
+                    let index = self.expr(ASSIGNMENT);
+                    let value = self.expr(ASSIGNMENT);
+                    (Expr::new_set_index(object, index, value, token(TokenKind::RightBracket, "")), ASSIGNMENT)
+                }
+                _ => {
+                    let name = self.name();
+                    let object = self.expr(CALL);
+                    let value = self.expr(ASSIGNMENT);
+                    (Expr::new_set(name, object, value), ASSIGNMENT)
+                }
+            };
+            self.depth -= 1;
+            if precedence < min {
+                Expr::new_grouping(token(TokenKind::RightParen, ""), expr)
+            } else {
+                expr
+            }
+        }
+
+        fn element(&mut self) -> Expr {
+            if self.below(4) == 0 {
+                Expr::new_spread(token(TokenKind::Ellipsis, ""), self.expr(ASSIGNMENT))
+            } else {
+                self.expr(ASSIGNMENT)
+            }
+        }
+
+        fn binary(&mut self, precedence: u8, operators: &[TokenKind]) -> (Expr, u8) {
+            let left = self.expr(precedence);
+            let kind = self.pick(operators);
+            // Keyword operators keep their text, like the scanner's keywords.
+            let content = match kind {
+                TokenKind::Is | TokenKind::In => kind.operator(),
+                _ => "",
+            };
+            let operator = token(kind, content);
+            let right = self.expr(precedence + 1);
+            (Expr::new_binary(left, operator, right), precedence)
+        }
+
+        fn var_declaration(&mut self) -> VarDeclaration {
+            let name = self.name();
+            let initializer = if self.below(2) == 0 {
+                Some(self.expr(ASSIGNMENT))
+            } else {
+                None
+            };
+            VarDeclaration::new(name, initializer)
+        }
+
+        fn option_expr(&mut self) -> Option<Expr> {
+            if self.below(3) == 0 {
+                None
+            } else {
+                Some(self.expr(ASSIGNMENT))
+            }
+        }
+
+        fn block(&mut self) -> Vec<Declaration> {
+            let count = if self.exhausted() { 0 } else { self.below(3) };
+            (0..count).map(|_| self.declaration()).collect()
+        }
+
+        fn statement(&mut self) -> Statement {
+            self.depth += 1;
+            let choice = if self.exhausted() { self.below(3) } else { self.below(12) };
+            let statement = match choice {
+                0 => Statement::new_print(self.expr(ASSIGNMENT)),
+                1 => Statement::new_expr_statement(self.expr(ASSIGNMENT)),
+                2 => match self.below(7) {
+                    0 => Statement::Return(self.option_expr()),
+                    4 => {
+                        let path = token(TokenKind::StringT, self.pick(&["lib.lox", "../util/math.lox"]));
+                        let alias = if self.below(2) == 0 { Some(self.name()) } else { None };
+                        Statement::new_import(token(TokenKind::Import, "import"), path, alias)
+                    }
+                    3 => {
+                        let cond = self.expr(ASSIGNMENT);
+                        Statement::new_assert(token(TokenKind::Assert, "assert"), cond, self.option_expr())
+                    }
+                    1 => Statement::Break(self.jump(TokenKind::Break, "break")),
+                    2 => Statement::new_throw(token(TokenKind::Throw, "throw"), self.expr(ASSIGNMENT)),
+                    5 => Statement::new_yield(token(TokenKind::Yield, "yield"), self.option_expr()),
+                    _ => Statement::Continue(self.jump(TokenKind::Continue, "continue")),
+                },
+                3 => Statement::new_block(self.block()),
+                4 => {
+                    let cond = self.expr(ASSIGNMENT);
+                    if self.below(2) == 0 {
+                        Statement::new_if(cond, self.statement(), None)
+                    } else {
+                        // A braced true branch keeps the else from attaching to a nested if.
+                        let true_branch = Statement::new_block(self.block());
+                        Statement::new_if(cond, true_branch, Some(self.statement()))
+                    }
+                }
+                5 => {
+                    let cond = self.expr(ASSIGNMENT);
+                    Statement::new_while(cond, self.statement())
+                }
+                6 => {
+                    let cond = self.expr(ASSIGNMENT);
+                    let label = self.name();
+                    Statement::new_while(cond, self.statement()).with_label(label).unwrap()
+                }
+                7 => {
+                    let body = self.block();
+                    let choice = self.below(3);
+                    let catch = if choice != 1 {
+                        let name = self.name();
+                        Some(CatchClause { name, body: self.block() })
+                    } else {
+                        None
+                    };
+                    let finally = if choice != 0 { Some(self.block()) } else { None };
+                    Statement::new_try(body, catch, finally)
+                }
+                8 => {
+                    let value = self.expr(ASSIGNMENT);
+                    let arms = (0..self.below(3))
+                        .map(|_| MatchArm {
+                            pattern: self.pattern(),
+                            body: self.statement(),
+                        })
+                        .collect();
+                    Statement::new_match(token(TokenKind::Match, "match"), value, arms)
+                }
+                9 => {
+                    let name = self.name();
+                    let iterable = self.expr(ASSIGNMENT);
+                    Statement::new_for_in(name, iterable, self.statement())
+                }
+                10 => {
+                    let resource = self.expr(ASSIGNMENT);
+                    let name = self.name();
+                    Statement::new_with(token(TokenKind::With, "with"), resource, name, self.block())
+                }
+                _ => {
+                    let initializer = match self.below(3) {
+                        0 => None,
+                        1 => Some(Initializer::VarDeclaration(self.var_declaration())),
+                        _ => Some(Initializer::Expr(self.expr(ASSIGNMENT))),
+                    };
+                    let cond = self.option_expr();
+                    let increment = self.option_expr();
+                    Statement::new_for(initializer, cond, increment, self.statement())
+                }
+            };
+            self.depth -= 1;
+            statement
+        }
+
+        fn pattern(&mut self) -> Pattern {
+            self.depth += 1;
+            let choice = if self.exhausted() { self.below(3) } else { self.below(5) };
+            let pattern = match choice {
+                0 => Pattern::Literal(self.literal()),
+                1 => {
+                    let number = token(TokenKind::Number, &self.below(100).to_string());
+                    Pattern::Literal(Expr::new_unary(token(TokenKind::Minus, ""), Expr::new_literal(number)))
+                }
+                2 => Pattern::Binding(self.name()),
+                3 => Pattern::List((0..self.below(3)).map(|_| self.pattern()).collect()),
+                _ => {
+                    let class = Expr::new_variable(self.name());
+                    Pattern::Class(class, (0..self.below(3)).map(|_| self.pattern()).collect())
+                }
+            };
+            self.depth -= 1;
+            pattern
+        }
+
+        fn jump(&mut self, kind: TokenKind, keyword: &str) -> Jump {
+            let label = if self.below(2) == 0 { Some(self.name()) } else { None };
+            Jump {
+                keyword: token(kind, keyword),
+                label,
+            }
+        }
+
+        fn function(&mut self, name: Token) -> FunDeclaration {
+            let count = self.below(3);
+            let params = NAMES[..count].iter().map(|p| token(TokenKind::Identifier, p)).collect();
+            let body = self.block();
+            FunDeclarationStruct::new_fun_declaration(name, params, body)
+        }
+
+        fn declaration(&mut self) -> Declaration {
+            self.depth += 1;
+            let choice = if self.exhausted() { 0 } else { self.below(7) };
+            let declaration = match choice {
+                0 | 1 => Declaration::Statement(self.statement()),
+                2 => Declaration::VarDeclaration(self.var_declaration()),
+                3 => {
+                    let name = self.name();
+                    Declaration::VarDeclaration(VarDeclaration::new_const(name, self.expr(ASSIGNMENT)))
+                }
+                4 => {
+                    let name = self.name();
+                    Declaration::FunDeclaration(self.function(name))
+                }
+                5 => {
+                    let name = self.name();
+                    let methods = (0..self.below(3))
+                        .map(|_| {
+                            let count = self.below(3);
+                            let params = NAMES[..count].iter().map(|p| token(TokenKind::Identifier, p)).collect();
+                            MethodSignature { name: self.name(), params }
+                        })
+                        .collect();
+                    Declaration::new_interface(name, methods)
+                }
+                _ => {
+                    let superclass = if self.below(2) == 0 {
+                        Some(Expr::new_variable(self.name()))
+                    } else {
+                        None
+                    };
+                    let mut methods = HashMap::new();
+                    for _ in 0..self.below(3) {
+                        let name = self.name();
+                        let method = if self.below(3) == 0 {
+                            FunDeclarationStruct::new_getter(name.clone(), self.block())
+                        } else {
+                            self.function(name.clone())
+                        };
+                        methods.insert(name.content.clone(), method);
+                    }
+                    let mut setters = HashMap::new();
+                    if self.below(3) == 0 {
+                        let name = self.name();
+                        let param = vec![self.name()];
+                        setters.insert(name.content.clone(), FunDeclarationStruct::new_fun_declaration(name, param, self.block()));
+                    }
+                    let statics = (0..self.below(2)).map(|_| self.var_declaration()).collect();
+                    let mixins = (0..self.below(3)).map(|_| Expr::new_variable(self.name())).collect();
+                    let interfaces = (0..self.below(3)).map(|_| self.name()).collect();
+                    Declaration::new_class(interfaces, methods, mixins, self.name(), setters, statics, superclass)
+                }
+            };
+            self.depth -= 1;
+            declaration
+        }
+
+        fn ast(&mut self) -> Ast {
+            let count = 1 + self.below(4);
+            Ast {
+                declarations: (0..count).map(|_| self.declaration()).collect(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..500 {
+            let ast = Generator::new(seed).ast();
+            let source = ast.to_string();
+            // Joining lines keeps every reparsed token on line 0, like the generated ones.
+            let reparsed = scan_parse(&source.replace('\n', " "));
+            assert_eq!(reparsed, ast, "seed {} did not round-trip:\n{}", seed, source);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let source = "class A < B {\n    f(a) {\n        return (a + 1) * 2;\n    }\n}\nif (x) {\n    print \"y\";\n} else while (z) z = z - 1;\n";
+        assert_eq!(scan_parse(source).to_string(), source);
+    }
+}
@@ -0,0 +1,207 @@
+//! A cycle collector for the reference counted heap. Instances, classes,
+//! lists, sets and deques are tracked as they are made, and the scopes in the
+//! environment arena are found there.
+//! collect finds those kept alive only by references from each other, such as
+//! an instance holding one of its own bound methods, whose closure holds the
+//! instance, and breaks the cycles so reference counting frees them.
+//!
+//! Nothing needs to say where the roots are. A tracked container with more
+//! strong references than the other tracked containers account for is held
+//! from somewhere else: a variable of the host, a value on the interpreter's
+//! stack, or a container that is not tracked, such as a generator. Those are
+//! kept along with everything they reach; the rest is garbage.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::environment::{self, Environment};
+use crate::value::{Function, Value};
+
+/// What the collector knows a container by: the address of a reference
+/// counted one, or the index of a scope in the environment arena.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Id {
+    Shared(*const ()),
+    Scope(u32),
+}
+
+pub(crate) fn shared<T>(container: &Rc<T>) -> Id {
+    Id::Shared(Rc::as_ptr(container) as *const ())
+}
+
+/// A container of values the collector can see into.
+pub(crate) trait Trace {
+    /// Calls visit with each container this one holds a strong reference to,
+    /// once per reference. Returns false if the container is borrowed mutably
+    /// and cannot be looked into.
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool;
+
+    /// Drops what the container holds, unless it is borrowed. The collector
+    /// holds on to all the garbage while clearing it, so no container is freed
+    /// while another is being cleared.
+    fn clear(&self);
+}
+
+// Dead entries are pruned when the heap outgrows this, or twice its size after
+// the last pruning, so tracking costs amortized constant time.
+const MIN_PRUNE_LENGTH: usize = 1024;
+
+#[derive(Default)]
+struct Heap {
+    containers: Vec<Weak<dyn Trace>>,
+    prune_length: usize,
+    // How many times collect has run on this thread.
+    collections: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+/// Registers a newly made container with the collector.
+pub(crate) fn track<T: Trace + 'static>(container: &Rc<T>) {
+    let weak: Weak<dyn Trace> = Rc::downgrade(container) as Weak<dyn Trace>;
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.containers.push(weak);
+        if heap.containers.len() >= heap.prune_length.max(MIN_PRUNE_LENGTH) {
+            heap.containers.retain(|weak| weak.strong_count() > 0);
+            heap.prune_length = heap.containers.len() * 2;
+        }
+    });
+}
+
+/// The addresses of the tracked containers value holds directly.
+pub(crate) fn trace_value(value: &Value, visit: &mut dyn FnMut(Id)) {
+    match value {
+        Value::Object(object) => visit(shared(object)),
+        Value::Class(class) => visit(shared(class)),
+        Value::List(list) => visit(shared(list)),
+        Value::Set(set) => visit(shared(set)),
+        Value::Deque(deque) => visit(shared(deque)),
+        Value::Function(function) => trace_function(function, visit),
+        _ => {}
+    }
+}
+
+pub(crate) fn trace_function(function: &Function, visit: &mut dyn FnMut(Id)) {
+    match function {
+        Function::UserDefined(user_defined) => visit(user_defined.environment.gc_id()),
+        Function::BoundBuiltin(_, receiver) => trace_value(receiver, visit),
+        Function::Memoized(memoized) => trace_function(&memoized.function, visit),
+        Function::Builtin(_) | Function::Compiled(_) => {}
+    }
+}
+
+// A container the collector looks at: one it tracks, or a scope.
+enum Container {
+    Shared(Weak<dyn Trace>),
+    Scope(u32),
+}
+
+impl Container {
+    fn id(&self) -> Id {
+        match self {
+            Container::Shared(weak) => Id::Shared(weak.as_ptr() as *const ()),
+            Container::Scope(index) => Id::Scope(*index),
+        }
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool {
+        match self {
+            Container::Shared(weak) => weak.upgrade().is_none_or(|container| container.trace(visit)),
+            Container::Scope(index) => environment::trace_scope(*index, visit),
+        }
+    }
+
+    // A strong reference, keeping the container alive while it is cleared.
+    fn hold(&self) -> Option<Held> {
+        match self {
+            Container::Shared(weak) => weak.upgrade().map(Held::Shared),
+            Container::Scope(index) => Some(Held::Scope(environment::hold_scope(*index))),
+        }
+    }
+}
+
+enum Held {
+    Shared(Rc<dyn Trace>),
+    Scope(Environment),
+}
+
+impl Held {
+    fn clear(&self) {
+        match self {
+            Held::Shared(container) => container.clear(),
+            Held::Scope(environment) => environment.clear(),
+        }
+    }
+}
+
+/// How many times collect has run on this thread.
+pub fn collections() -> usize {
+    HEAP.with(|heap| heap.borrow().collections)
+}
+
+/// Frees every tracked container that only other unreachable containers hold,
+/// returning how many were freed.
+pub fn collect() -> usize {
+    let shared: Vec<Weak<dyn Trace>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.containers.retain(|weak| weak.strong_count() > 0);
+        heap.prune_length = heap.containers.len() * 2;
+        heap.collections += 1;
+        heap.containers.clone()
+    });
+    // Counts are read before anything is upgraded, which would add to them.
+    let mut external: Vec<usize> = shared.iter().map(Weak::strong_count).collect();
+    let scopes = environment::scopes();
+    external.extend(scopes.iter().map(|(_, handles)| *handles as usize));
+    let containers: Vec<Container> = shared
+        .into_iter()
+        .map(Container::Shared)
+        .chain(scopes.into_iter().map(|(index, _)| Container::Scope(index)))
+        .collect();
+    let index: HashMap<Id, usize> = containers.iter().enumerate().map(|(i, container)| (container.id(), i)).collect();
+
+    let mut traceable = vec![true; containers.len()];
+    for (i, container) in containers.iter().enumerate() {
+        traceable[i] = container.trace(&mut |child| {
+            if let Some(&child) = index.get(&child) {
+                external[child] = external[child].saturating_sub(1);
+            }
+        });
+    }
+
+    // Whatever is held from outside, or cannot be looked into, is reachable,
+    // as is everything it reaches.
+    let mut reachable = vec![false; containers.len()];
+    let mut pending: Vec<usize> = (0..containers.len())
+        .filter(|&i| external[i] > 0 || !traceable[i])
+        .collect();
+    while let Some(i) = pending.pop() {
+        if mem::replace(&mut reachable[i], true) || !traceable[i] {
+            continue;
+        }
+        containers[i].trace(&mut |child| {
+            if let Some(&child) = index.get(&child) {
+                if !reachable[child] {
+                    pending.push(child);
+                }
+            }
+        });
+    }
+
+    // Held here, the garbage outlives the clearing of every container in it.
+    let garbage: Vec<Held> = containers
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| !**reachable)
+        .filter_map(|(container, _)| container.hold())
+        .collect();
+    for container in &garbage {
+        container.clear();
+    }
+    garbage.len()
+}
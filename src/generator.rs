@@ -0,0 +1,330 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{Declaration, FunDeclaration, Statement};
+use crate::environment::Environment;
+use crate::interp_error::InterpError;
+use crate::interpreter::{Interpreter, Iteration};
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::value::Value;
+
+// The interpreter walks the tree with Rust recursion, which can't be paused
+// halfway. A generator instead keeps its own stack of frames for the
+// statements that contain yields, and hands every other statement to the
+// interpreter to run whole.
+
+pub type Generator = Rc<RefCell<GeneratorState>>;
+
+// A statement of a function body, found by indexing into the body, then into
+// each statement's children: a block's declarations, an if's branches (0 for
+// the true branch, 1 for the else branch), a loop's body (0), or a match's
+// arm bodies.
+type Path = Vec<usize>;
+
+enum Frame {
+    // The declarations of the block at path, or of the function body when path
+    // is empty. next is the index of the next one to run.
+    Block { path: Path, next: usize, environment: Environment },
+    // A statement that yields, about to start.
+    Statement { path: Path, environment: Environment },
+    // A while or for loop whose body yields. iterating is set once the body
+    // has started, so the next visit runs the increment before the condition.
+    Loop { path: Path, environment: Environment, iterating: bool },
+    // A for-in loop whose body yields, and where it is in its iterable.
+    ForIn { path: Path, environment: Environment, iteration: Iteration },
+}
+
+enum Status {
+    Suspended(Vec<Frame>),
+    // Running on the interpreter, which owns the frames until it suspends.
+    Running,
+    // Returned, ran off the end of its body, or raised an error.
+    Done,
+}
+
+/// A call to a function that yields. Each resume runs its body up to the next
+/// yield.
+pub struct GeneratorState {
+    pub name: String,
+    declaration: FunDeclaration,
+    status: Status,
+    // A value hasNext ran ahead to, returned by the next resume.
+    buffered: Option<Value>,
+}
+
+impl GeneratorState {
+    /// A generator that will run declaration's body in environment, which
+    /// already holds the arguments.
+    pub fn new_generator(declaration: &FunDeclaration, environment: Environment) -> Generator {
+        let frame = Frame::Block {
+            path: Vec::new(),
+            next: 0,
+            environment,
+        };
+        Rc::new(RefCell::new(GeneratorState {
+            name: declaration.borrow().name.content.to_string(),
+            declaration: declaration.clone(),
+            status: Status::Suspended(vec![frame]),
+            buffered: None,
+        }))
+    }
+}
+
+impl fmt::Debug for GeneratorState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generator({})", self.name)
+    }
+}
+
+fn statement_at<'a>(body: &'a [Declaration], path: &[usize]) -> &'a Statement {
+    let Declaration::Statement(statement) = &body[path[0]] else {
+        unreachable!("Generator frames only point at statements.");
+    };
+    let mut statement = statement;
+    for &index in &path[1..] {
+        statement = match (statement, index) {
+            (Statement::Block(declarations), _) => match &declarations[index] {
+                Declaration::Statement(statement) => statement,
+                _ => unreachable!("Generator frames only point at statements."),
+            },
+            (Statement::If(if_statement), 0) => &if_statement.true_branch,
+            (Statement::If(if_statement), _) => if_statement.else_branch.as_ref().unwrap(),
+            (Statement::For(for_statement), _) => &for_statement.body,
+            (Statement::ForIn(for_in), _) => &for_in.body,
+            (Statement::While(while_statement), _) => &while_statement.body,
+            (Statement::Match(match_statement), _) => &match_statement.arms[index].body,
+            _ => unreachable!("Generator frames only point into yielding statements."),
+        };
+    }
+    statement
+}
+
+fn declarations_at<'a>(body: &'a [Declaration], path: &[usize]) -> &'a [Declaration] {
+    if path.is_empty() {
+        return body;
+    }
+    match statement_at(body, path) {
+        Statement::Block(declarations) => declarations,
+        _ => unreachable!("Block frames only point at blocks."),
+    }
+}
+
+fn child(path: &[usize], index: usize) -> Path {
+    let mut child = path.to_vec();
+    child.push(index);
+    child
+}
+
+// Runs the statement at path: a frame if it yields, otherwise all at once.
+fn start(
+    interpreter: &mut Interpreter,
+    body: &[Declaration],
+    frames: &mut Vec<Frame>,
+    path: Path,
+    mut environment: Environment,
+) -> Result<(), InterpError> {
+    let statement = statement_at(body, &path);
+    if statement.yields() {
+        frames.push(Frame::Statement { path, environment });
+        Ok(())
+    } else {
+        interpreter.visit_statement(&mut environment, statement)
+    }
+}
+
+// Runs the frame on top of the stack one step, returning the value when the
+// step yields.
+fn step(interpreter: &mut Interpreter, body: &[Declaration], frames: &mut Vec<Frame>) -> Result<Option<Value>, InterpError> {
+    let Some(frame) = frames.pop() else {
+        return Ok(None);
+    };
+    match frame {
+        Frame::Block { path, next, mut environment } => {
+            let Some(declaration) = declarations_at(body, &path).get(next) else {
+                return Ok(None);
+            };
+            frames.push(Frame::Block {
+                path: path.clone(),
+                next: next + 1,
+                environment: environment.clone(),
+            });
+            if declaration.yields() {
+                start(interpreter, body, frames, child(&path, next), environment)?;
+            } else {
+                interpreter.visit_declaration(declaration, &mut environment)?;
+            }
+        }
+        Frame::Statement { path, mut environment } => match statement_at(body, &path) {
+            Statement::Yield(yield_statement) => {
+                let value = match &yield_statement.value {
+                    Some(expr) => interpreter.visit_expr(&mut environment, expr)?,
+                    None => Value::Nil,
+                };
+                return Ok(Some(value));
+            }
+            Statement::Block(_) => frames.push(Frame::Block {
+                path,
+                next: 0,
+                environment: environment.new_block(),
+            }),
+            Statement::If(if_statement) => {
+                if interpreter.visit_expr(&mut environment, &if_statement.cond)?.is_truthy() {
+                    start(interpreter, body, frames, child(&path, 0), environment)?;
+                } else if if_statement.else_branch.is_some() {
+                    start(interpreter, body, frames, child(&path, 1), environment)?;
+                }
+            }
+            Statement::ForIn(for_in) => {
+                let iterable = interpreter.visit_expr(&mut environment, &for_in.iterable)?;
+                let iteration = Iteration::new(iterable, &for_in.iterable.token)?;
+                frames.push(Frame::ForIn {
+                    path,
+                    environment,
+                    iteration,
+                });
+            }
+            Statement::Match(match_statement) => {
+                if let Some((arm, arm_environment)) = interpreter.select_arm(&mut environment, match_statement)? {
+                    start(interpreter, body, frames, child(&path, arm), arm_environment)?;
+                }
+            }
+            Statement::While(_) => frames.push(Frame::Loop {
+                path,
+                environment,
+                iterating: false,
+            }),
+            Statement::For(for_statement) => {
+                let mut environment = environment.new_block();
+                if let Some(initializer) = &for_statement.initializer {
+                    interpreter.visit_initializer(&mut environment, initializer)?;
+                }
+                frames.push(Frame::Loop {
+                    path,
+                    environment,
+                    iterating: false,
+                });
+            }
+            _ => unreachable!("Only yielding statements get frames."),
+        },
+        Frame::Loop { path, mut environment, iterating } => {
+            let (cond, increment) = match statement_at(body, &path) {
+                Statement::While(while_statement) => (Some(&while_statement.cond), None),
+                Statement::For(for_statement) => (for_statement.cond.as_ref(), for_statement.increment.as_ref()),
+                _ => unreachable!("Loop frames only point at loops."),
+            };
+            if let (true, Some(increment)) = (iterating, increment) {
+                interpreter.visit_expr(&mut environment, increment)?;
+            }
+            if let Some(cond) = cond {
+                if !interpreter.visit_expr(&mut environment, cond)?.is_truthy() {
+                    return Ok(None);
+                }
+            }
+            frames.push(Frame::Loop {
+                path: path.clone(),
+                environment: environment.clone(),
+                iterating: true,
+            });
+            start(interpreter, body, frames, child(&path, 0), environment)?;
+        }
+        Frame::ForIn { path, environment, mut iteration } => {
+            let Statement::ForIn(for_in) = statement_at(body, &path) else {
+                unreachable!("For-in frames only point at for-in loops.");
+            };
+            let Some(element) = iteration.next(interpreter, &for_in.name)? else {
+                return Ok(None);
+            };
+            let mut element_environment = environment.new_block();
+            element_environment.insert(&for_in.name.content, element);
+            frames.push(Frame::ForIn {
+                path: path.clone(),
+                environment,
+                iteration,
+            });
+            start(interpreter, body, frames, child(&path, 0), element_environment)?;
+        }
+    }
+    Ok(None)
+}
+
+// Pops frames up to the loop a break or continue targets. A continue leaves
+// the loop on the stack to run its next iteration. Returns false if no frame
+// is a matching loop.
+fn unwind(body: &[Declaration], frames: &mut Vec<Frame>, target: &Option<Symbol>, is_break: bool) -> bool {
+    while let Some(frame) = frames.pop() {
+        let (Frame::Loop { path, .. } | Frame::ForIn { path, .. }) = &frame else {
+            continue;
+        };
+        let label = match statement_at(body, path) {
+            Statement::For(for_statement) => &for_statement.label,
+            Statement::ForIn(for_in) => &for_in.label,
+            Statement::While(while_statement) => &while_statement.label,
+            _ => unreachable!("Loop frames only point at loops."),
+        };
+        let targets_this_loop = match target {
+            Some(target) => label.as_ref().is_some_and(|label| &label.content == target),
+            None => true,
+        };
+        if targets_this_loop {
+            if !is_break {
+                frames.push(frame);
+            }
+            return true;
+        }
+    }
+    false
+}
+
+// Steps until the body yields or finishes. A return ends the generator, its
+// value discarded.
+fn run(interpreter: &mut Interpreter, body: &[Declaration], frames: &mut Vec<Frame>) -> Result<Option<Value>, InterpError> {
+    while !frames.is_empty() {
+        interpreter.check_deadline()?;
+        match step(interpreter, body, frames) {
+            Ok(Some(value)) => return Ok(Some(value)),
+            Ok(None) => {}
+            Err(InterpError::Return(_)) => return Ok(None),
+            Err(InterpError::Break(target)) if unwind(body, frames, &target, true) => {}
+            Err(InterpError::Continue(target)) if unwind(body, frames, &target, false) => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(None)
+}
+
+/// Runs the generator to its next yield and returns the yielded value, or
+/// None once the generator is done. token locates the error raised when a
+/// generator resumes itself.
+pub fn resume(interpreter: &mut Interpreter, generator: &Generator, token: &Token) -> Result<Option<Value>, InterpError> {
+    let (declaration, mut frames) = {
+        let mut state = generator.borrow_mut();
+        if let Some(value) = state.buffered.take() {
+            return Ok(Some(value));
+        }
+        match std::mem::replace(&mut state.status, Status::Running) {
+            Status::Suspended(frames) => (state.declaration.clone(), frames),
+            Status::Running => return Err(InterpError::new("Generator is already running.", token.clone())),
+            Status::Done => {
+                state.status = Status::Done;
+                return Ok(None);
+            }
+        }
+    };
+    let result = run(interpreter, &declaration.borrow().body, &mut frames);
+    generator.borrow_mut().status = match result {
+        Ok(Some(_)) => Status::Suspended(frames),
+        _ => Status::Done,
+    };
+    result
+}
+
+/// Whether resuming the generator would yield a value. Runs ahead to find out,
+/// keeping the value for the next resume.
+pub fn has_next(interpreter: &mut Interpreter, generator: &Generator, token: &Token) -> Result<bool, InterpError> {
+    let value = resume(interpreter, generator, token)?;
+    let has_next = value.is_some();
+    generator.borrow_mut().buffered = value;
+    Ok(has_next)
+}
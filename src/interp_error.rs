@@ -1,3 +1,5 @@
+use crate::error::Diagnostic;
+use crate::symbol::Symbol;
 use crate::token::Token;
 use crate::value::Value;
 
@@ -16,18 +18,53 @@ impl Error {
             token,
         }
     }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line(&self) -> usize {
+        self.token.line
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.token.line, &self.message)
+    }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum InterpError {
     Error(Error),
     Return(Value),
+    // Unwind to the innermost enclosing loop, or the loop with this label.
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+    // A value thrown by a throw statement, with the throw's keyword token so
+    // an uncaught exception can be reported at its line.
+    Thrown(Value, Token),
+    // The interpreter's deadline passed; unwinds the whole run.
+    Timeout,
+    // The interpreter's interrupt flag was set, as another thread or a signal
+    // handler may do; unwinds the whole run.
+    Interrupted,
+    // The fuel given to the interpreter ran out; unwinds the whole run.
+    OutOfFuel,
+    // The script called exit with this status code. Unwinds the whole run,
+    // running finally bodies on the way, and leaves ending the process to the
+    // host.
+    Exit(i32),
 }
 
 impl InterpError {
     pub fn new(message: &str, token: Token) -> InterpError {
         InterpError::Error(Error::new(message, token))
     }
+
+    /// Whether the error stops the run without running finally bodies or
+    /// closing resources: a timeout, an interrupt or running out of fuel.
+    pub fn halts(&self) -> bool {
+        matches!(self, InterpError::Timeout | InterpError::Interrupted | InterpError::OutOfFuel)
+    }
 }
 
 pub type StatementResult = Result<(), InterpError>;
@@ -1,30 +1,73 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
 
 use crate::ast::*;
+use crate::builtins;
+use crate::compiler;
+use crate::dead_code::eliminate_dead_code;
 use crate::environment::*;
-use crate::interp_error::{InterpError, InterpResult, StatementResult};
+use crate::error::Diagnostic;
+use crate::fold::fold_constants;
+use crate::generator::{self, Generator, GeneratorState};
+use crate::interp_error::{Error, InterpError, InterpResult, StatementResult};
+use crate::parser::Parser;
+use crate::platform::{Platform, StdPlatform};
+use crate::profiler::Profiler;
+use crate::random::Random;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::scheduler::{Scheduler, Task};
+use crate::symbol::{intern, Symbol};
 use crate::token::{Token, TokenKind};
 use crate::value::*;
+use crate::vm;
 
+// Two integers stay integral unless the operation overflows, in which case the
+// result is promoted to a float (or a big integer in bignum mode), as is any
+// operation mixing integers and floats.
 macro_rules! number_operation {
-    ($value1: expr, $value2: expr, $operator: tt, $token: expr) => {
-        if let Value::Number(n1) = $value1 {
-            if let Value::Number(n2) = $value2 {
-                return Ok(Value::Number(n1 $operator n2));
+    ($value1: expr, $value2: expr, $checked: ident, $operator: tt, $token: expr) => {
+        if let (Value::Integer(i1), Value::Integer(i2)) = (&$value1, &$value2) {
+            if let Some(i) = i1.$checked(*i2) {
+                return Ok(Value::Integer(i));
             }
         }
+        #[cfg(feature = "bignum")]
+        if let (Some(b1), Some(b2)) = ($value1.as_big_integer(), $value2.as_big_integer()) {
+            return Ok(Value::from_big_integer(b1 $operator b2));
+        }
+        if let (Some(n1), Some(n2)) = ($value1.as_number(), $value2.as_number()) {
+            return Ok(Value::Number(n1 $operator n2));
+        }
         return Err(InterpError::new("Expected number in expression.", $token.clone()));
     }
 
 }
 
+// Two strings compare lexicographically by code point; anything else must be
+// a pair of numbers.
 macro_rules! number_comparison {
     ($value1: expr, $value2: expr, $operator: tt, $token: expr) => {
-        if let Value::Number(n1) = $value1 {
-            if let Value::Number(n2) = $value2 {
-                return Ok(Value::Boolean(n1 $operator n2))
-            }
+        if let (Value::StringV(s1), Value::StringV(s2)) = (&$value1, &$value2) {
+            return Ok(Value::Boolean(s1 $operator s2));
+        }
+        if let (Value::Integer(i1), Value::Integer(i2)) = (&$value1, &$value2) {
+            return Ok(Value::Boolean(i1 $operator i2));
+        }
+        #[cfg(feature = "bignum")]
+        if let (Some(b1), Some(b2)) = ($value1.as_big_integer(), $value2.as_big_integer()) {
+            return Ok(Value::Boolean(b1 $operator b2));
+        }
+        if let (Some(n1), Some(n2)) = ($value1.as_number(), $value2.as_number()) {
+            return Ok(Value::Boolean(n1 $operator n2));
         }
         return Err(InterpError::new("Expected number in expression.", $token.clone()));
     }
@@ -32,40 +75,557 @@ macro_rules! number_comparison {
 
 type DeclarationResult = Result<(), InterpError>;
 
+/// How deeply calls may nest before failing with "Stack overflow." Each call
+/// takes several kilobytes of the native stack, more in debug builds, so the
+/// thread running the interpreter needs a stack big enough for this.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 4096;
+
+// Library code written in Lox, run into the globals of every interpreter.
+const PRELUDE: &str = "
+class Error {
+    init(message) {
+        this.message = message;
+        this.line = nil;
+    }
+
+    toString() {
+        return this.message;
+    }
+}
+";
+
+fn builtin_globals() -> Environment {
+    Environment::new_with_values(builtins::globals())
+}
+
+/// The value of an integer too wide for an i64, given as decimal digits.
+#[cfg(feature = "bignum")]
+pub(crate) fn overflowed_integer(digits: &str) -> Value {
+    Value::BigInteger(Box::new(digits.parse::<BigInt>().unwrap()))
+}
+
+#[cfg(not(feature = "bignum"))]
+pub(crate) fn overflowed_integer(digits: &str) -> Value {
+    Value::Number(digits.parse::<f64>().unwrap())
+}
+
 impl Token {
-    fn visit(&self) -> InterpResult {
-        let v = match &self.kind {
+    /// The value a literal token stands for.
+    pub(crate) fn literal_value(&self) -> Value {
+        match &self.kind {
             TokenKind::Number => {
-                let n = self.content.parse::<f64>().unwrap();
-                Value::Number(n)
+                if self.content.contains('.') {
+                    Value::Number(self.content.parse::<f64>().unwrap())
+                } else if let Ok(i) = self.content.parse::<i64>() {
+                    Value::Integer(i)
+                } else {
+                    overflowed_integer(&self.content)
+                }
             }
             TokenKind::StringT => Value::StringV(self.content.clone()),
             TokenKind::True => Value::Boolean(true),
             TokenKind::False => Value::Boolean(false),
             TokenKind::Nil => Value::Nil,
             _ => unreachable!(),
-        };
-        Ok(v)
+        }
+    }
+}
+
+/// How arithmetic and comparison operators treat a nil operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NilOperands {
+    /// A runtime error naming the nil operand.
+    Strict,
+    /// nil stands in for "" next to a string and for 0 otherwise, as in
+    /// JavaScript's looser operators.
+    Permissive,
+}
+
+/// How much the interpreter and the command line report about their own
+/// work, on standard error, alongside the script's output.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    /// Only what the script prints, and its errors.
+    Quiet,
+    /// Also the tokens and syntax tree of each script before it runs.
+    Verbose,
+    /// Also each call, class definition and field store as the script runs.
+    Trace,
+}
+
+/// Which engine Interpreter::run executes scripts with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Walks the Ast directly.
+    TreeWalker,
+    /// Compiles the Ast to bytecode and runs it on a stack VM. Scripts using
+    /// anything the compiler does not cover, such as classes, run on the
+    /// tree-walker instead.
+    Bytecode,
+}
+
+/// One test() call, recorded while the interpreter collects test results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// The line of the test() call.
+    pub line: usize,
+    /// Why the test failed, at the line where it did, or None if it passed.
+    pub failure: Option<Diagnostic>,
+}
+
+/// `start..end` or `start..=end`, by the operator token.
+fn range(start: &Value, end: &Value, token: &Token) -> InterpResult {
+    match (start, end) {
+        (Value::Integer(start), Value::Integer(end)) => Ok(Value::Range(Range {
+            start: *start,
+            end: *end,
+            inclusive: token.kind == TokenKind::DotDotEqual,
+        })),
+        _ => Err(InterpError::new("Range bounds must be integers.", token.clone())),
+    }
+}
+
+/// Where a for-in loop is in the value it iterates over.
+pub(crate) enum Iteration {
+    // Lists are read by position as the loop goes, so it sees elements that
+    // the body changes or appends.
+    List(List, usize),
+    Range(Range, Option<i64>),
+    Chars(Vec<char>, usize),
+    Generator(Generator),
+}
+
+impl Iteration {
+    pub(crate) fn new(value: Value, token: &Token) -> Result<Iteration, InterpError> {
+        match value {
+            Value::List(list) => Ok(Iteration::List(list, 0)),
+            Value::Range(range) => Ok(Iteration::Range(range, range.first())),
+            Value::StringV(s) => Ok(Iteration::Chars(s.chars().collect(), 0)),
+            Value::Generator(generator) => Ok(Iteration::Generator(generator)),
+            // Over a copy of the members, so the body can add and remove them.
+            Value::Set(set) => Ok(Iteration::List(Rc::new(RefCell::new(set.borrow().members().to_vec())), 0)),
+            _ => Err(InterpError::new(
+                "Can only iterate over lists, ranges, sets, strings and generators.",
+                token.clone(),
+            )),
+        }
+    }
+
+    pub(crate) fn next(&mut self, interpreter: &mut Interpreter, token: &Token) -> Result<Option<Value>, InterpError> {
+        match self {
+            Iteration::List(list, i) => {
+                let element = list.borrow().get(*i).cloned();
+                *i += 1;
+                Ok(element)
+            }
+            Iteration::Range(range, i) => {
+                let current = *i;
+                *i = current.and_then(|current| range.after(current));
+                Ok(current.map(Value::Integer))
+            }
+            Iteration::Chars(chars, i) => {
+                let element = chars.get(*i).map(|c| Value::new_string(c.to_string()));
+                *i += 1;
+                Ok(element)
+            }
+            Iteration::Generator(generator) => generator::resume(interpreter, generator, token),
+        }
+    }
+}
+
+enum Module {
+    // Still running, so importing it again means the imports form a cycle.
+    Loading,
+    // The module's globals, and the names it declared at the top level.
+    Loaded(Environment, Vec<Token>),
+}
+
+// Joins path onto dir and removes `.` and `..` components, so one file always
+// gets the same key in the module cache.
+fn normalize_path(dir: &Path, path: &str) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
     }
+    normalized
+}
+
+// The names a module exposes to files that import it.
+fn top_level_names(ast: &Ast) -> Vec<Token> {
+    ast.declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Class(class) => Some(class.borrow().name.clone()),
+            Declaration::FunDeclaration(function) => Some(function.borrow().name.clone()),
+            Declaration::VarDeclaration(var_declaration) => Some(var_declaration.name.clone()),
+            _ => None,
+        })
+        .collect()
 }
 
 pub struct Interpreter {
-    globals: Environment,
-    start: SystemTime,
+    // The command line arguments following the script's path, bound to ARGS.
+    arguments: Vec<String>,
+    backend: Backend,
+    // Calls being evaluated, by either backend.
+    pub(crate) call_depth: usize,
+    // Platform time past which runs fail with InterpError::Timeout.
+    deadline: Option<Duration>,
+    // The prelude's Error class, which runtime errors become when caught.
+    error_class: Option<IClass>,
+    // Statements and expressions left to evaluate before runs fail with
+    // InterpError::OutOfFuel.
+    fuel: Option<u64>,
+    pub(crate) globals: Environment,
+    // Set from anywhere to stop the run with InterpError::Interrupted.
+    interrupted: Arc<AtomicBool>,
+    // Directories of the script and of the modules being imported, innermost
+    // last. Import paths are relative to the last one.
+    module_dirs: Vec<PathBuf>,
+    // Every module imported so far, keyed by normalized path.
+    modules: HashMap<PathBuf, Module>,
+    pub(crate) max_call_depth: usize,
+    nil_operands: NilOperands,
+    pub(crate) platform: Box<dyn Platform>,
+    // Call counts and times, when profiling.
+    profiler: Option<Profiler>,
+    pub(crate) random: Random,
+    // Tasks started with spawn, waiting for their turns.
+    pub(crate) scheduler: Scheduler,
+    // Platform time when the interpreter was created or last reset.
+    pub(crate) start: Duration,
+    // The start of a character split across reads, by connection, waiting
+    // for the rest of it.
+    pub(crate) tcp_partial: HashMap<i64, Vec<u8>>,
+    // What each test() call has done, once collect_test_results is called.
+    test_results: Option<Vec<TestResult>>,
+    verbosity: Verbosity,
+    // The stack of the bytecode VM.
+    pub(crate) vm: vm::VmState,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        let values = hashmap!{
-            "clock".to_string() => Value::Function(Function::Builtin),
+        let mut interpreter = Interpreter {
+            arguments: Vec::new(),
+            backend: Backend::TreeWalker,
+            call_depth: 0,
+            deadline: None,
+            error_class: None,
+            fuel: None,
+            globals: builtin_globals(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            module_dirs: Vec::new(),
+            modules: HashMap::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            nil_operands: NilOperands::Strict,
+            platform: Box::new(StdPlatform::new()),
+            profiler: None,
+            random: Random::from_entropy(),
+            scheduler: Scheduler::default(),
+            start: Duration::ZERO,
+            tcp_partial: HashMap::new(),
+            test_results: None,
+            verbosity: Verbosity::Quiet,
+            vm: vm::VmState::default(),
+        };
+        interpreter.run_prelude();
+        interpreter
+    }
+
+    fn run_prelude(&mut self) {
+        let tokens = Scanner::new(PRELUDE.to_string()).scan_tokens();
+        let mut ast = Parser::new(tokens).parse().expect("The prelude should parse.");
+        Resolver::new().run(&mut ast).expect("The prelude should resolve.");
+        self.run(ast).expect("The prelude should run.");
+        if let Some(Value::Class(class)) = self.global("Error") {
+            self.error_class = Some(class);
+        }
+        self.globals.insert("ARGS", self.arguments_list());
+    }
+
+    // A fresh list each time, so one script or module changing its ARGS
+    // leaves the others' alone.
+    fn arguments_list(&self) -> Value {
+        Value::new_list(self.arguments.iter().map(|argument| Value::new_string(argument.clone())).collect())
+    }
+
+    // Runs the module at path in globals of its own the first time it is
+    // imported, and returns those globals and the names it declared.
+    fn load_module(&mut self, path: PathBuf, token: &Token) -> Result<(Environment, Vec<Token>), InterpError> {
+        match self.modules.get(&path) {
+            Some(Module::Loaded(module, names)) => return Ok((module.clone(), names.clone())),
+            Some(Module::Loading) => {
+                let message = format!("Import cycle: '{}' is already being imported.", path.display());
+                return Err(InterpError::new(&message, token.clone()));
+            }
+            None => {}
+        }
+        let source = self.platform.read_file(&path.to_string_lossy()).map_err(|error| {
+            InterpError::new(&format!("Could not read module '{}': {}", path.display(), error), token.clone())
+        })?;
+        let module_error = |line: usize, message: &str| {
+            let message = format!("In module '{}' on line {}: {}", path.display(), line, message);
+            InterpError::new(&message, token.clone())
+        };
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        if let Some(diagnostic) = scanner.diagnostics().first() {
+            return Err(module_error(diagnostic.line, &diagnostic.message));
+        }
+        let mut parser = Parser::new(tokens);
+        let parsed = parser.parse();
+        let mut ast = match (parsed, parser.diagnostics().first()) {
+            (Ok(ast), None) => ast,
+            (_, Some(diagnostic)) => return Err(module_error(diagnostic.line, &diagnostic.message)),
+            (Err(()), None) => return Err(module_error(0, "Parse error.")),
+        };
+        Resolver::new().run(&mut ast).map_err(|error| module_error(error.line(), error.message()))?;
+        fold_constants(&mut ast);
+        eliminate_dead_code(&mut ast);
+
+        // A module sees the builtins and the prelude's classes, but none of the
+        // globals of the script importing it.
+        let mut module = builtin_globals();
+        if let Some(error_class) = &self.error_class {
+            module.insert("Error", Value::Class(error_class.clone()));
+        }
+        module.insert("ARGS", self.arguments_list());
+        let names = top_level_names(&ast);
+        self.modules.insert(path.clone(), Module::Loading);
+        self.module_dirs.push(path.parent().unwrap_or(Path::new("")).to_path_buf());
+        let result = self.visit_declarations(&ast.declarations, &mut module);
+        self.module_dirs.pop();
+        if let Err(error) = result {
+            self.modules.remove(&path);
+            return Err(error);
+        }
+        self.modules.insert(path, Module::Loaded(module.clone(), names.clone()));
+        Ok((module, names))
+    }
+
+    fn is_error(&self, object: &Object) -> bool {
+        self.error_class
+            .as_ref()
+            .is_some_and(|error_class| is_subclass(&object.borrow().class, error_class))
+    }
+
+    /// An instance of the prelude's Error class describing a runtime error.
+    fn error_object(&self, error: &Error) -> Value {
+        let object = ObjectStruct::new_object(self.error_class.as_ref().unwrap());
+        {
+            let fields = &mut object.borrow_mut().fields;
+            fields.insert(intern("message"), Value::new_string(error.message().to_string()));
+            fields.insert(intern("line"), Value::Integer(error.line() as i64));
+        }
+        Value::Object(object)
+    }
+
+    /// Discards every global, task, connection and test result left by
+    /// previous runs, as well as any deadline, fuel, pending interrupt or
+    /// random seed.
+    pub fn reset(&mut self) {
+        self.deadline = None;
+        self.fuel = None;
+        self.interrupted.store(false, Ordering::Relaxed);
+        self.globals = builtin_globals();
+        self.modules.clear();
+        self.random = Random::from_entropy();
+        self.scheduler = Scheduler::default();
+        self.platform.tcp_close_all();
+        self.tcp_partial.clear();
+        if let Some(results) = &mut self.test_results {
+            results.clear();
+        }
+        self.start = self.platform.now();
+        self.run_prelude();
+    }
+
+    /// Where the script being run lives, so its imports are found relative to it.
+    pub fn set_script_path(&mut self, path: &str) {
+        let dir = Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf();
+        self.module_dirs = vec![dir];
+    }
+
+    /// Binds the global ARGS to a list of these strings, such as the command
+    /// line arguments following the script's path. ARGS is empty until then.
+    pub fn set_arguments(&mut self, arguments: Vec<String>) {
+        self.arguments = arguments;
+        self.globals.insert("ARGS", self.arguments_list());
+    }
+
+    /// Replaces the StdPlatform every interpreter starts with.
+    pub fn set_platform(&mut self, platform: Box<dyn Platform>) {
+        self.platform = platform;
+        self.start = self.platform.now();
+    }
+
+    /// Seeds random() and randInt(), as a script's seed() call would.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn set_nil_operands(&mut self, nil_operands: NilOperands) {
+        self.nil_operands = nil_operands;
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Starts recording how often each function is called and how long it
+    /// takes, or stops and discards the record.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiler = profiling.then(Profiler::default);
+    }
+
+    /// What has been recorded since profiling began.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Calls nested deeper than max_call_depth fail with "Stack overflow."
+    /// rather than overflowing the native stack, on either backend. The
+    /// default is DEFAULT_MAX_CALL_DEPTH.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    // Reports what the script is doing when tracing. message is only made
+    // then, so other runs pay for a comparison.
+    fn trace(&self, message: impl FnOnce() -> String) {
+        if self.verbosity >= Verbosity::Trace {
+            eprintln!("[trace] {}", message());
+        }
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.maybe_get_at(0, name)
+    }
+
+    pub fn platform(&mut self) -> &mut dyn Platform {
+        self.platform.as_mut()
+    }
+
+    /// Code run from now on fails with InterpError::Timeout once timeout has
+    /// passed on the platform clock. None removes the limit.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| self.platform.now() + timeout);
+    }
+
+    /// Code run from now on fails with InterpError::OutOfFuel once it has
+    /// evaluated fuel statements and expressions. The bytecode VM spends one
+    /// per call and per loop iteration. None removes the limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// The fuel left, if it is limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// A flag that stops the code running, with InterpError::Interrupted, once
+    /// it is set. It can be set from another thread or a signal handler, and
+    /// is cleared when the interrupt is seen.
+    pub fn interrupt(&self) -> Arc<AtomicBool> {
+        self.interrupted.clone()
+    }
+
+    /// From now on a failing test() is recorded, and the script carries on,
+    /// rather than the failure stopping the script like any other error.
+    pub fn collect_test_results(&mut self) {
+        self.test_results.get_or_insert_with(Vec::new);
+    }
+
+    /// The results recorded since collecting began or this was last called.
+    pub fn take_test_results(&mut self) -> Vec<TestResult> {
+        self.test_results.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Calls a test's body, recording whether it passed when collecting test
+    /// results. Timeouts and exit stop the script either way.
+    pub(crate) fn run_test(&mut self, name: &str, body: Function, token: &Token) -> StatementResult {
+        let result = self.call_function(body, Vec::new(), name, token);
+        if self.test_results.is_none() {
+            return result.map(drop);
+        }
+        let failure = match result {
+            Ok(_) => None,
+            Err(InterpError::Error(error)) => Some(error.diagnostic()),
+            Err(InterpError::Thrown(value, thrown)) => {
+                let message = format!("Uncaught exception: {}", self.stringify(&value, &thrown)?);
+                Some(Diagnostic::new(thrown.line, &message))
+            }
+            Err(error) => return Err(error),
         };
-        Interpreter {
-            globals: Environment::new_with_values(values),
-            start: SystemTime::now(),
+        if let Some(results) = &mut self.test_results {
+            results.push(TestResult {
+                name: name.to_string(),
+                line: token.line,
+                failure,
+            });
         }
+        Ok(())
+    }
+
+    /// Gives every spawned task one turn, running it until it yields or
+    /// finishes. Tasks spawned during the round wait for the next one, so an
+    /// embedder can call this once per frame or tick.
+    pub fn run_task_round(&mut self) -> StatementResult {
+        for _ in 0..self.scheduler.len() {
+            let Some((task, token)) = self.scheduler.pop() else {
+                break;
+            };
+            let generator = match task {
+                Task::Start(function) => match self.call_function(function, Vec::new(), "task", &token)? {
+                    Value::Generator(generator) => generator,
+                    _ => continue,
+                },
+                Task::Resume(generator) => generator,
+            };
+            if generator::resume(self, &generator, &token)?.is_some() {
+                self.scheduler.push(Task::Resume(generator), token);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_tasks(&self) -> bool {
+        !self.scheduler.is_empty()
     }
 
     pub fn run(&mut self, ast: Ast) -> StatementResult {
+        if self.backend == Backend::Bytecode {
+            if let Ok(script) = compiler::compile(&ast) {
+                return vm::run_script(self, script);
+            }
+        }
         let mut environment = self.globals.clone();
         for declaration in &ast.declarations {
             self.visit_declaration(declaration, &mut environment)?;
@@ -73,24 +633,24 @@ impl Interpreter {
         Ok(())
     }
 
-    fn assign_global(&mut self, token: &Token, value: Value) -> InterpResult {
-        self.globals.assign(token, value)
+    // Globals belong to the script or module that declared them, found at the
+    // root of the environment chain, so a module's functions keep seeing its own.
+    fn assign_global(&mut self, environment: &Environment, token: &Token, value: Value) -> InterpResult {
+        environment.globals().assign(token, value)
     }
 
-    fn declare_and_assign(&mut self, environment: &mut Environment, token: &Token, new_value: Value) {
-        environment.declare_and_assign(token, new_value);
+    fn declare_and_assign(&mut self, environment: &mut Environment, token: &Token, new_value: Value) -> Result<(), InterpError> {
+        environment.declare_and_assign(token, new_value)
     }
 
-    fn finish_class(&mut self, class: ClassStruct) {}
-
     fn visit_class(&mut self, class: &Class, environment: &mut Environment) -> DeclarationResult {
         let borrowed_class = class.borrow();
-        let (methods, superclass) = if let Some(Expr { token, kind: ExprKind::Variable(depth) }) = &borrowed_class.superclass {
-            println!("Storing superclass");
+        let (mut methods, mut setters, superclass) = if let Some(Expr { token, kind: ExprKind::Variable(depth) }) = &borrowed_class.superclass {
             let superclass_value = self.visit_var_expr(depth, environment, token)?;
             let mut environment = environment.new_block();
             environment.insert("super", superclass_value.clone());
             (generate_methods(&borrowed_class.methods, &mut environment),
+            generate_methods(&borrowed_class.setters, &mut environment),
             if let Value::Class(i_superclass) = superclass_value {
                 Some(i_superclass.clone())
             } else {
@@ -98,16 +658,50 @@ impl Interpreter {
             })
         } else {
             (generate_methods(&borrowed_class.methods, environment),
+            generate_methods(&borrowed_class.setters, environment),
             None)
         };
-        let class_struct = IClassStruct::new_i_class(methods, &borrowed_class.name.content, superclass);
-        self.declare_and_assign(environment, &class.borrow().name, Value::Class(class_struct));
+        // Methods of a class's own win over those it mixes in, and a later mixin
+        // wins over an earlier one.
+        let mut mixed_methods = HashMap::new();
+        let mut mixed_setters = HashMap::new();
+        for mixin in &borrowed_class.mixins {
+            match self.visit_expr(environment, mixin)? {
+                Value::Class(mixin) => {
+                    let (mixin_methods, mixin_setters) = mixin.borrow().method_table();
+                    mixed_methods.extend(mixin_methods);
+                    mixed_setters.extend(mixin_setters);
+                }
+                _ => return Err(InterpError::new("Can only mix in classes.", mixin.token.clone())),
+            }
+        }
+        for (name, method) in mixed_methods {
+            methods.entry(name).or_insert(method);
+        }
+        for (name, setter) in mixed_setters {
+            setters.entry(name).or_insert(setter);
+        }
+        self.trace(|| match &superclass {
+            Some(superclass) => format!("class {} < {}", borrowed_class.name.content, superclass.borrow().name),
+            None => format!("class {}", borrowed_class.name.content),
+        });
+        let class_struct = IClassStruct::new_i_class(methods, setters, &borrowed_class.name.content, superclass);
+        self.declare_and_assign(environment, &borrowed_class.name, Value::Class(class_struct.clone()))?;
+        for field in &borrowed_class.statics {
+            let value = match &field.initializer {
+                Some(expr) => self.visit_expr(environment, expr)?,
+                None => Value::Nil,
+            };
+            class_struct.borrow_mut().fields.insert(field.name.content.clone(), value);
+        }
         Ok(())
     }
 
-    fn visit_declaration(&mut self, declaration: &Declaration, environment: &mut Environment) -> DeclarationResult {
+    pub(crate) fn visit_declaration(&mut self, declaration: &Declaration, environment: &mut Environment) -> DeclarationResult {
         match declaration {
             Declaration::Class(class) => self.visit_class(class, environment),
+            // Interfaces are checked by the resolver and leave nothing behind.
+            Declaration::Interface(_) => Ok(()),
             Declaration::FunDeclaration(fun_declaration) => {
                 self.visit_fun_declaration(environment, fun_declaration)
             }
@@ -131,8 +725,11 @@ impl Interpreter {
         } else {
             Value::Nil
         };
-        self.declare_and_assign(environment, &var_declaration.name, value);
-        Ok(())
+        if var_declaration.constant {
+            environment.declare_constant(&var_declaration.name, value)
+        } else {
+            self.declare_and_assign(environment, &var_declaration.name, value)
+        }
     }
 
     fn visit_for(&mut self, environment: &mut Environment, for_statement: &For) -> StatementResult {
@@ -150,7 +747,9 @@ impl Interpreter {
         }
 
         while bool_value.is_truthy() {
-            self.visit_statement(environment, &for_statement.body)?;
+            if !self.visit_loop_body(environment, &for_statement.body, &for_statement.label)? {
+                break;
+            }
 
             if let Some(increment) = &for_statement.increment {
                 self.visit_expr(environment, increment)?;
@@ -164,19 +763,162 @@ impl Interpreter {
         Ok(())
     }
 
+    // Each iteration gets a fresh scope holding the element, so closures made in
+    // the body each see their own.
+    fn visit_for_in(&mut self, environment: &mut Environment, for_in: &ForIn) -> StatementResult {
+        let iterable = self.visit_expr(environment, &for_in.iterable)?;
+        let mut iteration = Iteration::new(iterable, &for_in.iterable.token)?;
+        while let Some(element) = iteration.next(self, &for_in.name)? {
+            let mut environment = environment.new_block();
+            environment.insert(&for_in.name.content, element);
+            if !self.visit_loop_body(&mut environment, &for_in.body, &for_in.label)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Like a for-in loop, each element is computed in a fresh scope holding the
+    // loop variable.
+    fn visit_comprehension(&mut self, environment: &mut Environment, comprehension: &Comprehension) -> InterpResult {
+        let iterable = self.visit_expr(environment, &comprehension.iterable)?;
+        let mut iteration = Iteration::new(iterable, &comprehension.iterable.token)?;
+        let mut elements = Vec::new();
+        while let Some(value) = iteration.next(self, &comprehension.name)? {
+            let mut environment = environment.new_block();
+            environment.insert(&comprehension.name.content, value);
+            if let Some(condition) = &comprehension.condition {
+                if !self.visit_expr(&mut environment, condition)?.is_truthy() {
+                    continue;
+                }
+            }
+            elements.push(self.visit_expr(&mut environment, &comprehension.element)?);
+        }
+        Ok(Value::new_list(elements))
+    }
+
+    // A finally body runs however the try and catch bodies finish. Unless it
+    // raises, returns or jumps itself, their result then carries on unwinding.
+    fn visit_try(&mut self, environment: &mut Environment, try_statement: &Try) -> StatementResult {
+        let result = match (self.visit_block(&try_statement.body, environment), &try_statement.catch) {
+            (Err(InterpError::Error(error)), Some(catch)) => {
+                let mut catch_environment = environment.new_block();
+                let value = self.error_object(&error);
+                self.declare_and_assign(&mut catch_environment, &catch.name, value)
+                    .and_then(|()| self.visit_declarations(&catch.body, &mut catch_environment))
+            }
+            (Err(InterpError::Thrown(value, _)), Some(catch)) => {
+                let mut catch_environment = environment.new_block();
+                self.declare_and_assign(&mut catch_environment, &catch.name, value)
+                    .and_then(|()| self.visit_declarations(&catch.body, &mut catch_environment))
+            }
+            (result, _) => result,
+        };
+        if let Some(finally) = &try_statement.finally {
+            // A timeout or interrupt stops the whole run without running cleanup code.
+            if !result.as_ref().is_err_and(InterpError::halts) {
+                self.visit_block(finally, environment)?;
+            }
+        }
+        result
+    }
+
+    // The resource's close method is looked up before the body runs, and called
+    // however the body finishes, as a finally body would be.
+    fn visit_with(&mut self, environment: &mut Environment, with: &With) -> StatementResult {
+        let resource = self.visit_expr(environment, &with.resource)?;
+        let close_name = Token {
+            kind: TokenKind::Identifier,
+            content: intern("close"),
+            ..with.keyword.clone()
+        };
+        let close = match self.get_property(resource.clone(), &close_name) {
+            Ok(Value::Function(close)) => close,
+            _ => {
+                let message = "The resource of a with statement must have a close method.";
+                return Err(InterpError::new(message, with.keyword.clone()));
+            }
+        };
+        let mut body_environment = environment.new_block();
+        body_environment.insert(&with.name.content, resource);
+        let result = self.visit_declarations(&with.body, &mut body_environment);
+        if !result.as_ref().is_err_and(InterpError::halts) {
+            self.call_function(close, Vec::new(), "close", &with.keyword)?;
+        }
+        result
+    }
+
+    // Runs one iteration, returning false if the loop should stop.
+    fn visit_loop_body(&mut self, environment: &mut Environment, body: &Statement, label: &Option<Token>) -> Result<bool, InterpError> {
+        let targets_this_loop = |target: &Option<Symbol>| match target {
+            Some(target) => label.as_ref().is_some_and(|label| &label.content == target),
+            None => true,
+        };
+        match self.visit_statement(environment, body) {
+            Ok(()) => Ok(true),
+            Err(InterpError::Break(target)) if targets_this_loop(&target) => Ok(false),
+            Err(InterpError::Continue(target)) if targets_this_loop(&target) => Ok(true),
+            Err(error) => Err(error),
+        }
+    }
+
     fn visit_fun_declaration(&mut self, environment: &mut Environment, fun_declaration: &FunDeclaration) -> DeclarationResult {
         let new_function = Value::new_function(fun_declaration, environment.clone(), false);
         let fun_declaration = fun_declaration.borrow();
-        self.declare_and_assign(environment, &fun_declaration.name, new_function);
-        Ok(())
+        self.declare_and_assign(environment, &fun_declaration.name, new_function)
     }
 
     fn visit_block(&mut self, declarations: &Vec<Declaration>, environment: &mut Environment) -> StatementResult {
-        let result = self.visit_declarations(declarations, &mut environment.new_block());
-        result
+        self.visit_declarations(declarations, &mut environment.new_block())
     }
 
-    fn visit_statement(&mut self, environment: &mut Environment, statement: &Statement) -> StatementResult {
+    pub(crate) fn check_deadline(&self) -> StatementResult {
+        match self.deadline {
+            Some(deadline) if self.platform.now() >= deadline => Err(InterpError::Timeout),
+            _ => Ok(()),
+        }
+    }
+
+    // Spends a unit of fuel and checks for an interrupt, before each statement
+    // and expression.
+    pub(crate) fn tick(&mut self) -> StatementResult {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(InterpError::OutOfFuel);
+            }
+            *fuel -= 1;
+        }
+        self.check_interrupt()
+    }
+
+    fn check_interrupt(&self) -> StatementResult {
+        if self.interrupted.load(Ordering::Relaxed) && self.interrupted.swap(false, Ordering::Relaxed) {
+            return Err(InterpError::Interrupted);
+        }
+        Ok(())
+    }
+
+    // Sleeps no later than the deadline, and in slices short enough that an
+    // interrupt or timeout stops a sleeping script as it would a running one.
+    pub(crate) fn sleep(&mut self, duration: Duration) -> StatementResult {
+        const SLICE: Duration = Duration::from_millis(10);
+        let mut end = self.platform.now() + duration;
+        if let Some(deadline) = self.deadline {
+            end = end.min(deadline);
+        }
+        loop {
+            self.check_interrupt()?;
+            let remaining = end.saturating_sub(self.platform.now());
+            if remaining.is_zero() {
+                return self.check_deadline();
+            }
+            self.platform.sleep(remaining.min(SLICE));
+        }
+    }
+
+    pub(crate) fn visit_statement(&mut self, environment: &mut Environment, statement: &Statement) -> StatementResult {
+        self.tick()?;
+        self.check_deadline()?;
         match statement {
             Statement::ExprStatement(expr) => {
                 self.visit_expr(environment, expr)?;
@@ -184,10 +926,24 @@ impl Interpreter {
             }
             Statement::Print(expr) => {
                 let value = self.visit_expr(environment, expr)?;
-                println!("{}", value.to_string());
-                Ok(())
+                self.print_value(&value, &expr.token)
+            }
+            Statement::Assert(assert) => {
+                if self.visit_expr(environment, &assert.cond)?.is_truthy() {
+                    return Ok(());
+                }
+                let message = match &assert.message {
+                    Some(expr) => {
+                        let value = self.visit_expr(environment, expr)?;
+                        format!("Assertion failed: {}", self.stringify(&value, &expr.token)?)
+                    }
+                    None => "Assertion failed.".to_string(),
+                };
+                Err(InterpError::new(&message, assert.keyword.clone()))
             }
             Statement::Block(declarations) => self.visit_block(declarations, environment),
+            Statement::Break(jump) => Err(InterpError::Break(jump.label.as_ref().map(|label| label.content.clone()))),
+            Statement::Continue(jump) => Err(InterpError::Continue(jump.label.as_ref().map(|label| label.content.clone()))),
             Statement::If(if_statement) => {
                 let bool_value = self.visit_expr(environment, &if_statement.cond)?;
                 if bool_value.is_truthy() {
@@ -203,14 +959,55 @@ impl Interpreter {
             Statement::While(while_statement) => {
                 let mut bool_value = self.visit_expr(environment, &while_statement.cond)?;
                 while bool_value.is_truthy() {
-                    self.visit_statement(environment, &while_statement.body)?;
+                    if !self.visit_loop_body(environment, &while_statement.body, &while_statement.label)? {
+                        break;
+                    }
                     bool_value = self.visit_expr(environment, &while_statement.cond)?;
                 }
 
                 Ok(())
             }
             Statement::For(for_statement) => {
-                self.visit_for(&mut environment.new_block(), &for_statement)
+                self.visit_for(&mut environment.new_block(), for_statement)
+            }
+            Statement::ForIn(for_in) => self.visit_for_in(environment, for_in),
+            Statement::Throw(throw) => {
+                let value = self.visit_expr(environment, &throw.value)?;
+                // Errors record where they were first thrown.
+                if let Value::Object(object) = &value {
+                    if self.is_error(object) && object.borrow().fields.get("line") == Some(&Value::Nil) {
+                        let line = Value::Integer(throw.keyword.line as i64);
+                        object.borrow_mut().fields.insert(intern("line"), line);
+                    }
+                }
+                Err(InterpError::Thrown(value, throw.keyword.clone()))
+            }
+            Statement::Try(try_statement) => self.visit_try(environment, try_statement),
+            Statement::With(with) => self.visit_with(environment, with),
+            Statement::Match(match_statement) => match self.select_arm(environment, match_statement)? {
+                Some((arm, mut arm_environment)) => {
+                    self.visit_statement(&mut arm_environment, &match_statement.arms[arm].body)
+                }
+                None => Ok(()),
+            },
+            Statement::Import(import) => {
+                let dir = self.module_dirs.last().cloned().unwrap_or_default();
+                let path = normalize_path(&dir, &import.path.content);
+                let (module, names) = self.load_module(path, &import.path)?;
+                if let Some(alias) = &import.alias {
+                    let namespace = Rc::new(Namespace {
+                        name: alias.content.to_string(),
+                        environment: module,
+                        members: names.iter().map(|name| name.content.clone()).collect(),
+                    });
+                    return environment.globals().declare_and_assign(alias, Value::Namespace(namespace));
+                }
+                for name in &names {
+                    if let Some(value) = module.maybe_get_at(0, &name.content) {
+                        environment.globals().declare_and_assign(name, value)?;
+                    }
+                }
+                Ok(())
             }
             Statement::Return(return_value) => {
                 let value = match return_value {
@@ -219,10 +1016,98 @@ impl Interpreter {
                 };
                 Err(InterpError::Return(value))
             }
+            // The resolver only allows yields in functions, and a function
+            // that yields runs as a generator, which handles them itself.
+            Statement::Yield(yield_statement) => {
+                Err(InterpError::new("Can't yield outside of a function.", yield_statement.keyword.clone()))
+            }
+        }
+    }
+
+    pub(crate) fn print_value(&mut self, value: &Value, token: &Token) -> StatementResult {
+        let text = self.stringify(value, token)?;
+        self.platform
+            .write_output(&format!("{}\n", text))
+            .map_err(|_| InterpError::new("Could not write output.", token.clone()))
+    }
+
+    /// The index of the first arm whose pattern matches the match's value, with
+    /// an environment for its body holding the names the pattern bound.
+    pub(crate) fn select_arm(
+        &mut self,
+        environment: &mut Environment,
+        match_statement: &Match,
+    ) -> Result<Option<(usize, Environment)>, InterpError> {
+        let value = self.visit_expr(environment, &match_statement.value)?;
+        for (i, arm) in match_statement.arms.iter().enumerate() {
+            let mut arm_environment = environment.new_block();
+            if self.match_pattern(&mut arm_environment, &arm.pattern, &value)? {
+                return Ok(Some((i, arm_environment)));
+            }
         }
+        Ok(None)
     }
 
-    fn visit_initializer(&mut self, environment: &mut Environment, initializer: &Initializer) -> InterpResult {
+    fn match_pattern(&mut self, environment: &mut Environment, pattern: &Pattern, value: &Value) -> Result<bool, InterpError> {
+        match pattern {
+            Pattern::Literal(expr) => Ok(self.visit_expr(environment, expr)? == *value),
+            Pattern::Binding(name) => {
+                if &*name.content != "_" {
+                    environment.insert(&name.content, value.clone());
+                }
+                Ok(true)
+            }
+            Pattern::List(patterns) => {
+                let Value::List(list) = value else {
+                    return Ok(false);
+                };
+                let elements = list.borrow();
+                if elements.len() != patterns.len() {
+                    return Ok(false);
+                }
+                for (pattern, element) in patterns.iter().zip(elements.iter()) {
+                    if !self.match_pattern(environment, pattern, element)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::Class(class_expr, patterns) => {
+                let Value::Class(class) = self.visit_expr(environment, class_expr)? else {
+                    return Err(InterpError::new("Class patterns must name a class.", class_expr.token.clone()));
+                };
+                let Value::Object(object) = value else {
+                    return Ok(false);
+                };
+                if !is_subclass(&object.borrow().class, &class) {
+                    return Ok(false);
+                }
+                let fields: Vec<Symbol> = match class.borrow().find_method("init") {
+                    Some(init) => init.declaration.borrow().params.iter().map(|param| param.content.clone()).collect(),
+                    None => Vec::new(),
+                };
+                if patterns.len() > fields.len() {
+                    let message = format!(
+                        "Class pattern has {} fields but {}'s init takes {} parameters.",
+                        patterns.len(),
+                        class.borrow().name,
+                        fields.len()
+                    );
+                    return Err(InterpError::new(&message, class_expr.token.clone()));
+                }
+                for (pattern, field) in patterns.iter().zip(&fields) {
+                    let field_value = object.borrow().fields.get(field).cloned();
+                    match field_value {
+                        Some(field_value) if self.match_pattern(environment, pattern, &field_value)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    pub(crate) fn visit_initializer(&mut self, environment: &mut Environment, initializer: &Initializer) -> InterpResult {
         match initializer {
             Initializer::VarDeclaration(var_declaration) => {
                 self.visit_var_declaration(environment, var_declaration)?;
@@ -235,42 +1120,89 @@ impl Interpreter {
     fn visit_binary_expr(&mut self, binary_expr: &BinaryExpr, environment: &mut Environment, token: &Token) -> InterpResult {
         let left_v = self.visit_expr(environment, &binary_expr.left)?;
         let right_v = self.visit_expr(environment, &binary_expr.right)?;
+        self.binary_operation(left_v, right_v, token)
+    }
+
+    // Stops at the first comparison that fails, leaving later operands
+    // unevaluated, as `and` would.
+    fn visit_chain(&mut self, environment: &mut Environment, chain: &Chain) -> InterpResult {
+        let mut left_v = self.visit_expr(environment, &chain.operands[0])?;
+        for (operator, operand) in chain.operators.iter().zip(&chain.operands[1..]) {
+            let right_v = self.visit_expr(environment, operand)?;
+            if !self.binary_operation(left_v, right_v.clone(), operator)?.is_truthy() {
+                return Ok(Value::Boolean(false));
+            }
+            left_v = right_v;
+        }
+        Ok(Value::Boolean(true))
+    }
+
+    // Kept apart from visit_binary_expr, which recursion passes through, so the
+    // locals of every operator don't weigh on deeply nested calls.
+    pub(crate) fn binary_operation(&mut self, left_v: Value, right_v: Value, token: &Token) -> InterpResult {
+        let (left_v, right_v) = match token.kind {
+            TokenKind::BangEqual | TokenKind::EqualEqual | TokenKind::Is | TokenKind::In => (left_v, right_v),
+            _ => self.nil_operands(left_v, right_v, token)?,
+        };
 
         match &token.kind {
-            TokenKind::Plus => match left_v {
-                Value::StringV(left_s) => {
-                    if let Value::StringV(right_s) = right_v {
-                        Ok(Value::StringV(format!("{}{}", left_s, right_s)))
-                    } else {
-                        Err(InterpError::new(
-                                "Expected string in concatenation operation.",
-                                token.clone(),
-                        ))
-                    }
+            TokenKind::Plus => match (&left_v, &right_v) {
+                (Value::StringV(left_s), Value::StringV(right_s)) => Ok(Value::new_string([&**left_s, &**right_s].concat())),
+                // Instances concatenate as their toString, or their default text without one.
+                (Value::StringV(left_s), Value::Object(_)) => {
+                    let right_s = self.stringify(&right_v, token)?;
+                    Ok(Value::new_string([&**left_s, &right_s].concat()))
                 }
-                Value::Number(left_n) => {
-                    if let Value::Number(right_n) = right_v {
-                        Ok(Value::Number(left_n + right_n))
-                    } else {
-                        Err(InterpError::new(
-                                "Expected number in expression.",
-                                token.clone(),
-                        ))
-                    }
+                (Value::Object(_), Value::StringV(right_s)) => {
+                    let left_s = self.stringify(&left_v, token)?;
+                    Ok(Value::new_string([&left_s, &**right_s].concat()))
+                }
+                (Value::StringV(_), _) => Err(InterpError::new(
+                        "Expected string in concatenation operation.",
+                        token.clone(),
+                )),
+                (Value::Integer(_) | Value::Number(_), _) => {
+                    number_operation!(left_v, right_v, checked_add, +, token);
                 }
                 _ => Err(InterpError::new("Invalid operation.", token.clone())),
             },
             TokenKind::Minus => {
-                number_operation!(left_v, right_v, -, token);
+                number_operation!(left_v, right_v, checked_sub, -, token);
             }
             TokenKind::Star => {
-                number_operation!(left_v, right_v, *, token);
+                number_operation!(left_v, right_v, checked_mul, *, token);
             }
             TokenKind::Slash => {
-                number_operation!(left_v, right_v, /, token);
+                // Integer division only stays integral when it is exact, so `7 / 2` is 3.5.
+                if let (Value::Integer(i1), Value::Integer(i2)) = (&left_v, &right_v) {
+                    if i1.checked_rem(*i2) == Some(0) {
+                        return Ok(Value::Integer(i1 / i2));
+                    }
+                }
+                #[cfg(feature = "bignum")]
+                if let (Some(b1), Some(b2)) = (left_v.as_big_integer(), right_v.as_big_integer()) {
+                    let zero = BigInt::from(0);
+                    if b2 != zero && &b1 % &b2 == zero {
+                        return Ok(Value::from_big_integer(b1 / b2));
+                    }
+                }
+                if let (Some(n1), Some(n2)) = (left_v.as_number(), right_v.as_number()) {
+                    Ok(Value::Number(n1 / n2))
+                } else {
+                    Err(InterpError::new("Expected number in expression.", token.clone()))
+                }
             }
-            TokenKind::BangEqual => Ok(Value::Boolean(left_v != right_v)),
-            TokenKind::EqualEqual => Ok(Value::Boolean(left_v == right_v)),
+            TokenKind::Is => match (&left_v, &right_v) {
+                (Value::Object(object), Value::Class(class)) => {
+                    Ok(Value::Boolean(is_subclass(&object.borrow().class, class)))
+                }
+                (_, Value::Class(_)) => Ok(Value::Boolean(false)),
+                _ => Err(InterpError::new("Right operand of 'is' must be a class.", token.clone())),
+            },
+            TokenKind::In => Ok(Value::Boolean(self.contains(&left_v, &right_v, token)?)),
+            TokenKind::DotDot | TokenKind::DotDotEqual => range(&left_v, &right_v, token),
+            TokenKind::BangEqual => Ok(Value::Boolean(!self.values_equal(&left_v, &right_v, token)?)),
+            TokenKind::EqualEqual => Ok(Value::Boolean(self.values_equal(&left_v, &right_v, token)?)),
             TokenKind::LessEqual => {
                 number_comparison!(left_v, right_v, <=, token);
             }
@@ -287,19 +1219,30 @@ impl Interpreter {
         }
     }
 
+    fn nil_operands(&self, left_v: Value, right_v: Value, token: &Token) -> Result<(Value, Value), InterpError> {
+        let replacement = |other: &Value| match other {
+            Value::StringV(_) => Value::new_string(String::new()),
+            _ => Value::Integer(0),
+        };
+        match (&left_v, &right_v, self.nil_operands) {
+            (Value::Nil, _, NilOperands::Strict) | (_, Value::Nil, NilOperands::Strict) => {
+                let side = if left_v == Value::Nil { "Left" } else { "Right" };
+                let msg = format!("{} operand of '{}' is nil.", side, token.kind.operator());
+                Err(InterpError::new(&msg, token.clone()))
+            }
+            (Value::Nil, _, NilOperands::Permissive) | (_, Value::Nil, NilOperands::Permissive) => {
+                let left_v = if left_v == Value::Nil { replacement(&right_v) } else { left_v };
+                let right_v = if right_v == Value::Nil { replacement(&left_v) } else { right_v };
+                Ok((left_v, right_v))
+            }
+            _ => Ok((left_v, right_v)),
+        }
+    }
+
     fn visit_unary(&mut self, environment: &mut Environment, expr: &Expr, token: &Token) -> InterpResult {
         let value = self.visit_expr(environment, expr)?;
         match &token.kind {
-            TokenKind::Minus => {
-                if let Value::Number(n) = value {
-                    Ok(Value::Number(-n))
-                } else {
-                    Err(InterpError::new(
-                            "Expected number in expression.",
-                            token.clone(),
-                    ))
-                }
-            }
+            TokenKind::Minus => negate(value, token),
             TokenKind::Bang => Ok(Value::Boolean(!value.is_truthy())),
             _ => unreachable!(),
         }
@@ -307,6 +1250,12 @@ impl Interpreter {
 
     fn visit_logical(&mut self, environment: &mut Environment, logical: &BinaryExpr, token: &Token) -> InterpResult {
         let left_v = self.visit_expr(environment, &logical.left)?;
+        if token.kind == TokenKind::QuestionQuestion {
+            return match left_v {
+                Value::Nil => self.visit_expr(environment, &logical.right),
+                _ => Ok(left_v),
+            };
+        }
         let boolean = match token.kind {
             TokenKind::And => left_v.is_truthy() && self.visit_expr(environment, &logical.right)?.is_truthy(),
             TokenKind::Or => left_v.is_truthy() || self.visit_expr(environment, &logical.right)?.is_truthy(),
@@ -323,26 +1272,69 @@ impl Interpreter {
         calling_environment: &mut Environment,
         function: Function,
     ) -> InterpResult {
-        let mut arguments = Vec::new();
-        for arg in &call.arguments {
-            // TODO: 2 environments?
-            arguments.push(self.visit_expr(calling_environment, arg)?);
+        // TODO: 2 environments?
+        let arguments = self.visit_elements(calling_environment, &call.arguments)?;
+        let name = &call.callee.token.content;
+        let Some(profiler) = &mut self.profiler else {
+            return self.call_function(function, arguments, name, closing_paren);
+        };
+        let key = profiler.enter(&function);
+        let start = self.platform.now();
+        let result = self.call_function(function, arguments, name, closing_paren);
+        let elapsed = self.platform.now().saturating_sub(start);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit(&key, elapsed);
         }
+        result
+    }
+
+    // Evaluates call arguments or list elements, expanding spreads in place.
+    fn visit_elements(&mut self, environment: &mut Environment, exprs: &[Expr]) -> Result<Vec<Value>, InterpError> {
+        let mut values = Vec::new();
+        for expr in exprs {
+            match &expr.kind {
+                ExprKind::Spread(list) => match self.visit_expr(environment, list)? {
+                    Value::List(list) => values.extend(list.borrow().iter().cloned()),
+                    _ => return Err(InterpError::new("Can only spread lists.", expr.token.clone())),
+                },
+                _ => values.push(self.visit_expr(environment, expr)?),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Calls a function with already evaluated arguments. name is only used in
+    /// the arity error and token locates any runtime error.
+    pub(crate) fn call_function(
+        &mut self,
+        function: Function,
+        arguments: Vec<Value>,
+        name: &str,
+        closing_paren: &Token,
+    ) -> InterpResult {
+        // Getters, setters, equals and toString are called from here too, so
+        // recursion through any of them is stopped.
+        if self.call_depth >= self.max_call_depth {
+            return Err(InterpError::new("Stack overflow.", closing_paren.clone()));
+        }
+        self.call_depth += 1;
+        let result = self.call_nested(function, arguments, name, closing_paren);
+        self.call_depth -= 1;
+        result
+    }
+
+    // Calls function once call_function has counted the call.
+    fn call_nested(&mut self, function: Function, mut arguments: Vec<Value>, name: &str, closing_paren: &Token) -> InterpResult {
+        check_arity(&function, arguments.len(), name, closing_paren)?;
+        self.trace(|| format!("call {} [line {}]", name, closing_paren.line));
         match function {
             Function::UserDefined(rc) => {
                 let declaration = rc.declaration.borrow();
-                if arguments.len() != declaration.params.len() {
-                    let msg = format!(
-                        "Arity mismatch: declaration {} expected {} arguments, received {}.",
-                        call.callee.token.content,
-                        declaration.params.len(),
-                        arguments.len()
-                    );
-                    return Err(InterpError::new(&msg, closing_paren.clone()));
-                }
                 let mut environment = rc.environment.new_block();
-                println!("{:?}", environment.maybe_get_at(1, "this"));
                 environment.bind_arguments(arguments, &declaration.params);
+                if declaration.generator {
+                    return Ok(Value::Generator(GeneratorState::new_generator(&rc.declaration, environment)));
+                }
                 let result = self.visit_declarations(&declaration.body, &mut environment);
                 match result {
                     Ok(()) => {
@@ -357,43 +1349,122 @@ impl Interpreter {
                     Err(error) => Err(error),
                 }
             }
-            Function::Builtin => match call.callee.token.content.as_str() {
-                "clock" => {
-                    let time = self.start.elapsed().unwrap();
-                    Ok(Value::Number(time.as_millis() as f64))
-                }
-                _ => {
-                    unreachable!();
+            Function::Builtin(builtin) => (builtin.function)(self, arguments, closing_paren),
+            Function::Memoized(memoized) => {
+                let key: Vec<ValueKey> = arguments.iter().cloned().map(ValueKey).collect();
+                if let Some(value) = memoized.cache.borrow().get(&key) {
+                    return Ok(value.clone());
                 }
+                let value = self.call_nested(*memoized.function, arguments, name, closing_paren)?;
+                memoized.cache.borrow_mut().insert(key, value.clone());
+                Ok(value)
+            }
+            Function::BoundBuiltin(builtin, receiver) => {
+                arguments.insert(0, *receiver);
+                (builtin.function)(self, arguments, closing_paren)
+            }
+            Function::Compiled(compiled) => vm::call(self, compiled, arguments),
+        }
+    }
+
+    /// Calls a function or class with already evaluated arguments, as a call
+    /// expression does.
+    pub(crate) fn call_value(&mut self, callee: Value, arguments: Vec<Value>, name: &str, closing_paren: &Token) -> InterpResult {
+        match callee {
+            Value::Function(function) => self.call_function(function, arguments, name, closing_paren),
+            Value::Class(class) => match bound_initializer(&class) {
+                Some(init) => self.call_function(init, arguments, name, closing_paren),
+                None => self.call_class(&class),
             },
+            _ => Err(InterpError::new("Can only call functions and classes.", closing_paren.clone())),
+        }
+    }
+
+    /// `==` for Lox. An instance whose class defines equals(other) decides for
+    /// itself; other instances are only equal to themselves.
+    pub(crate) fn values_equal(&mut self, left: &Value, right: &Value, token: &Token) -> Result<bool, InterpError> {
+        if let Value::Object(object) = left {
+            let method = object.borrow().class.borrow().find_method("equals");
+            if let Some(method) = method {
+                let function = Function::UserDefined(ObjectStruct::bind(object, &method));
+                return Ok(self.call_function(function, vec![right.clone()], "equals", token)?.is_truthy());
+            }
+        }
+        Ok(left == right)
+    }
+
+    /// `needle in haystack`: whether a list has an element equal to needle, a
+    /// range counts through needle, a string contains needle as a substring, or
+    /// an instance has a field named needle.
+    fn contains(&mut self, needle: &Value, haystack: &Value, token: &Token) -> Result<bool, InterpError> {
+        match (needle, haystack) {
+            (_, Value::List(list)) => {
+                // Copied, since an equals method could change the list mid-search.
+                let elements = list.borrow().clone();
+                for element in &elements {
+                    if self.values_equal(element, needle, token)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            (Value::Integer(i), Value::Range(range)) => Ok(range.contains(*i)),
+            (_, Value::Range(_)) => Ok(false),
+            (_, Value::Set(set)) => Ok(set.borrow().contains(needle)),
+            (Value::StringV(needle), Value::StringV(s)) => Ok(s.contains(&**needle)),
+            (Value::StringV(name), Value::Object(object)) => Ok(object.borrow().fields.contains_key(&**name)),
+            (_, Value::StringV(_) | Value::Object(_)) => {
+                let message = "Left operand of 'in' must be a string when the right is a string or instance.";
+                Err(InterpError::new(message, token.clone()))
+            }
+            _ => Err(InterpError::new(
+                "Right operand of 'in' must be a list, range, set, string or instance.",
+                token.clone(),
+            )),
         }
     }
 
-    fn call_class(&mut self, call: &Call, class: &IClass, closing_paren: &Token) -> InterpResult {
+    /// The text print shows for a value. Instances whose class defines a
+    /// toString method are shown as whatever string it returns.
+    pub fn stringify(&mut self, value: &Value, token: &Token) -> Result<String, InterpError> {
+        if let Value::Object(object) = value {
+            let method = object.borrow().class.borrow().find_method("toString");
+            if let Some(method) = method {
+                let function = Function::UserDefined(ObjectStruct::bind(object, &method));
+                return match self.call_function(function, vec![], "toString", token)? {
+                    Value::StringV(s) => Ok(s.to_string()),
+                    _ => Err(InterpError::new("toString must return a string.", token.clone())),
+                };
+            }
+        }
+        Ok(value.to_string())
+    }
+
+    fn call_class(&mut self, class: &IClass) -> InterpResult {
         Ok(Value::Object(ObjectStruct::new_object(class)))
     }
 
-    fn get_global(&mut self, token: &Token) -> InterpResult {
-        self.globals.get(token)
+    fn get_global(&mut self, environment: &Environment, token: &Token) -> InterpResult {
+        environment.globals().get(token)
     }
 
     fn visit_call(&mut self, call: &Call, closing_paren: &Token, environment: &mut Environment) -> InterpResult {
-        let value = self.visit_expr(environment, &call.callee)?;
-        println!("Call : {:?}", closing_paren);
+        // `object?.method()` skips the call, and its arguments, when object is nil.
+        let value = if let ExprKind::OptionalGet(get) = &call.callee.kind {
+            match self.visit_expr(environment, &get.object)? {
+                Value::Nil => return Ok(Value::Nil),
+                value => self.find_property(value, &call.callee.token, Some(&get.cache))?,
+            }
+        } else {
+            self.visit_expr(environment, &call.callee)?
+        };
         match value {
             Value::Function(function) => {
                 self.finish_call(call, closing_paren, environment, function)
             },
-            Value::Class(class) => {
-                if let Some(user_defined) = class.borrow().methods.get("init") {
-                    let object = ObjectStruct::new_object(&class);
-                    let mut user_defined_clone = user_defined.clone();
-                    user_defined_clone.environment.bind_this(&object);
-                    let function = Function::UserDefined(user_defined_clone);
-                    self.finish_call(call, &closing_paren, environment, function)
-                } else {
-                    self.call_class(call, &class, closing_paren)
-                }
+            Value::Class(class) => match bound_initializer(&class) {
+                Some(init) => self.finish_call(call, closing_paren, environment, init),
+                None => self.call_class(&class),
             },
             _ => {
                 Err(InterpError::new(
@@ -404,7 +1475,8 @@ impl Interpreter {
         }
     }
 
-    fn visit_expr(&mut self, environment: &mut Environment, expr: &Expr) -> InterpResult {
+    pub(crate) fn visit_expr(&mut self, environment: &mut Environment, expr: &Expr) -> InterpResult {
+        self.tick()?;
         match expr {
             Expr {
                 kind: ExprKind::Assign(assign_expr),
@@ -414,26 +1486,58 @@ impl Interpreter {
                 kind: ExprKind::Binary(binary_expr),
                 token,
             } => self.visit_binary_expr(binary_expr, environment, token),
+            Expr {
+                kind: ExprKind::Chain(chain),
+                token: _,
+            } => self.visit_chain(environment, chain),
             Expr {
                 kind: ExprKind::Call(expr),
                 token,
-            } => self.visit_call(&*expr, token, environment),
+            } => self.visit_call(expr, token, environment),
             Expr {
-                kind: ExprKind::Literal,
-                token,
-            } => token.visit(),
+                kind: ExprKind::Literal(value),
+                token: _,
+            } => Ok(value.clone()),
             Expr {
                 kind: ExprKind::Logical(logical),
                 token,
             } => self.visit_logical(environment, logical, token),
             Expr {
-                kind: ExprKind::Get(object),
+                kind: ExprKind::Get(get),
+                token,
+            } => self.visit_get(environment, get, token),
+            Expr {
+                kind: ExprKind::OptionalGet(get),
                 token,
-            } => self.visit_get(environment, object, token),
+            } => match self.visit_expr(environment, &get.object)? {
+                Value::Nil => Ok(Value::Nil),
+                value => self.find_property(value, token, Some(&get.cache)),
+            },
             Expr {
                 kind: ExprKind::Grouping(expr),
                 token: _,
             } => self.visit_expr(environment, expr),
+            Expr {
+                kind: ExprKind::List(elements),
+                token: _,
+            } => Ok(Value::new_list(self.visit_elements(environment, elements)?)),
+            Expr {
+                kind: ExprKind::Comprehension(comprehension),
+                token: _,
+            } => self.visit_comprehension(environment, comprehension),
+            Expr {
+                kind: ExprKind::Index(index),
+                token,
+            } => self.visit_index(environment, index, token),
+            Expr {
+                kind: ExprKind::SetIndex(set_index),
+                token,
+            } => self.visit_set_index(environment, set_index, token),
+            // The parser only allows spreads where visit_elements expands them.
+            Expr {
+                kind: ExprKind::Spread(_),
+                token,
+            } => Err(InterpError::new("Can only spread in calls and lists.", token.clone())),
             Expr {
                 kind: ExprKind::Set(set),
                 token,
@@ -445,7 +1549,7 @@ impl Interpreter {
             Expr {
                 kind: ExprKind::Unary(expr),
                 token,
-            } => self.visit_unary(environment, &*expr, token),
+            } => self.visit_unary(environment, expr, token),
             Expr {
                 kind: ExprKind::Variable(depth),
                 token,
@@ -457,62 +1561,214 @@ impl Interpreter {
         }
     }
 
-    fn visit_get(&mut self, environment: &mut Environment, object: &Expr, identifier: &Token) -> InterpResult {
-        let value = self.visit_expr(environment, object)?;
-        if let Value::Object(object) = value {
-            ObjectStruct::get(&object, identifier)
-        } else {
-            Err(InterpError::new("Field access should be preceded by object.", identifier.clone()))
+    fn visit_get(&mut self, environment: &mut Environment, get: &Get, identifier: &Token) -> InterpResult {
+        let value = self.visit_expr(environment, &get.object)?;
+        self.find_property(value, identifier, Some(&get.cache))
+    }
+
+    fn call_getter(&mut self, object: &Object, getter: &UserDefined, identifier: &Token) -> InterpResult {
+        let getter = Function::UserDefined(ObjectStruct::bind(object, getter));
+        self.call_function(getter, Vec::new(), &identifier.content, identifier)
+    }
+
+    pub(crate) fn get_property(&mut self, value: Value, identifier: &Token) -> InterpResult {
+        self.find_property(value, identifier, None)
+    }
+
+    // Gets a property, finding the methods of instances through cache when an
+    // access site has one.
+    fn find_property(&mut self, value: Value, identifier: &Token, cache: Option<&PropertyCache>) -> InterpResult {
+        match value {
+            Value::Object(object) => {
+                // A field of the same name hides a method or getter.
+                let method = {
+                    let object_struct = object.borrow();
+                    if let Some(value) = object_struct.fields.get(&identifier.content) {
+                        return Ok(value.clone());
+                    }
+                    match cache {
+                        Some(cache) => cache.find_method(&object_struct.class, &identifier.content),
+                        None => object_struct.class.borrow().find_method(&identifier.content),
+                    }
+                };
+                match method {
+                    Some(getter) if getter.declaration.borrow().getter => self.call_getter(&object, &getter, identifier),
+                    Some(method) => Ok(Value::Function(Function::UserDefined(ObjectStruct::bind(&object, &method)))),
+                    None => ObjectStruct::get(&object, identifier),
+                }
+            }
+            Value::Class(class) => match class.borrow().find_field(&identifier.content) {
+                Some(value) => Ok(value),
+                None if &*identifier.content == "name" => Ok(Value::new_string(class.borrow().name.clone())),
+                None => Err(InterpError::new("Static field not found on class.", identifier.clone())),
+            },
+            Value::Namespace(namespace) if namespace.has_member(&identifier.content) => {
+                namespace.environment.get(identifier)
+            }
+            Value::Namespace(namespace) => {
+                let message = format!("Module '{}' has no member '{}'.", namespace.name, identifier.content);
+                Err(InterpError::new(&message, identifier.clone()))
+            }
+            Value::StringV(_) => match builtins::string_method(&identifier.content) {
+                Some(builtin) => Ok(Value::Function(Function::BoundBuiltin(builtin, Box::new(value)))),
+                None => Err(InterpError::new("Undefined string method.", identifier.clone())),
+            },
+            Value::Function(function) => match &*identifier.content {
+                "arity" => Ok(Value::Integer(function.arity() as i64)),
+                "name" => Ok(Value::new_string(function.name())),
+                _ => Err(InterpError::new("Functions only have 'arity' and 'name' properties.", identifier.clone())),
+            },
+            Value::Range(_) => match builtins::range_method(&identifier.content) {
+                Some(builtin) => Ok(Value::Function(Function::BoundBuiltin(builtin, Box::new(value)))),
+                None => Err(InterpError::new("Undefined range method.", identifier.clone())),
+            },
+            Value::Generator(_) => match builtins::generator_method(&identifier.content) {
+                Some(builtin) => Ok(Value::Function(Function::BoundBuiltin(builtin, Box::new(value)))),
+                None => Err(InterpError::new("Undefined generator method.", identifier.clone())),
+            },
+            Value::Set(_) => match builtins::set_method(&identifier.content) {
+                Some(builtin) => Ok(Value::Function(Function::BoundBuiltin(builtin, Box::new(value)))),
+                None => Err(InterpError::new("Undefined set method.", identifier.clone())),
+            },
+            Value::Deque(ref deque) => match builtins::deque_method(&identifier.content) {
+                Some(builtin) => Ok(Value::Function(Function::BoundBuiltin(builtin, Box::new(value)))),
+                None => {
+                    let message = format!("Undefined {} method.", deque.borrow().kind.name().to_lowercase());
+                    Err(InterpError::new(&message, identifier.clone()))
+                }
+            },
+            _ => Err(InterpError::new("Field access should be preceded by object.", identifier.clone())),
         }
     }
 
     fn visit_assign_expr(&mut self, assign_expr: &AssignExpr, environment: &mut Environment, token: &Token) -> InterpResult {
-        let value = self.visit_expr(environment, &*assign_expr.initializer)?;
-        if let Some(depth) = assign_expr.depth {
-            environment
-                .assign_at(depth, token.content.clone(), value.clone());
+        let value = self.visit_expr(environment, &assign_expr.initializer)?;
+        if let Some(slot) = assign_expr.depth {
+            environment.assign_slot(slot, &token.content, value.clone());
             Ok(value)
         } else {
-            self.assign_global(token, value)
+            self.assign_global(environment, token, value)
         }
     }
 
     fn visit_set(&mut self, environment: &mut Environment, name: &Token, set: &Set) -> InterpResult {
-        let left_value = self.visit_expr(environment, &set.object)?;
-        if let Value::Object(object) = left_value {
-            let right_value = self.visit_expr(environment, &set.value)?;
-            println!("insert {}", &name.content);
-            object.borrow_mut().fields.insert(name.content.clone(), right_value.clone());
-            Ok(right_value)
-        } else {
-            Err(InterpError::new("Can only set properties of objects.", name.clone()))
+        let target = self.visit_expr(environment, &set.object)?;
+        match &target {
+            Value::Object(_) | Value::Class(_) => {}
+            Value::Namespace(namespace) if namespace.has_member(&name.content) => {}
+            _ => return self.set_property(target, name, Value::Nil),
+        }
+        let value = self.visit_expr(environment, &set.value)?;
+        self.set_property(target, name, value)
+    }
+
+    /// `target.name = value`, through a setter if the class defines one.
+    pub(crate) fn set_property(&mut self, target: Value, name: &Token, right_value: Value) -> InterpResult {
+        match target {
+            Value::Object(object) => {
+                let setter = object.borrow().class.borrow().find_setter(&name.content);
+                if let Some(setter) = setter {
+                    let setter = Function::UserDefined(ObjectStruct::bind(&object, &setter));
+                    self.call_function(setter, vec![right_value.clone()], &name.content, name)?;
+                    return Ok(right_value);
+                }
+                self.trace(|| format!("set field {} [line {}]", name.content, name.line));
+                object.borrow_mut().fields.insert(name.content.clone(), right_value.clone());
+                Ok(right_value)
+            }
+            Value::Class(class) => {
+                class.borrow_mut().fields.insert(name.content.clone(), right_value.clone());
+                Ok(right_value)
+            }
+            Value::Namespace(namespace) if namespace.has_member(&name.content) => {
+                namespace.environment.clone().assign(name, right_value)
+            }
+            Value::Namespace(namespace) => {
+                let message = format!("Module '{}' has no member '{}'.", namespace.name, name.content);
+                Err(InterpError::new(&message, name.clone()))
+            }
+            _ => Err(InterpError::new("Can only set properties of objects and classes.", name.clone())),
+        }
+    }
+
+    // Lists and strings are indexed by position; instances by field name, which
+    // reads and writes fields directly, skipping methods, getters and setters.
+    fn visit_index(&mut self, environment: &mut Environment, index: &Index, bracket: &Token) -> InterpResult {
+        let target = self.visit_expr(environment, &index.object)?;
+        let key = self.visit_expr(environment, &index.index)?;
+        self.index_value(&target, &key, bracket)
+    }
+
+    pub(crate) fn index_value(&mut self, target: &Value, key: &Value, bracket: &Token) -> InterpResult {
+        match target {
+            Value::List(list) => {
+                let list = list.borrow();
+                Ok(list[position(key, list.len(), bracket)?].clone())
+            }
+            Value::StringV(s) => {
+                let i = position(key, s.chars().count(), bracket)?;
+                Ok(Value::new_string(s.chars().nth(i).unwrap().to_string()))
+            }
+            Value::Object(object) => {
+                let name = field_name(key, bracket)?;
+                match object.borrow().fields.get(name) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(InterpError::new("Property not found on object.", bracket.clone())),
+                }
+            }
+            _ => Err(InterpError::new("Can only index lists, strings and instances.", bracket.clone())),
         }
     }
 
-    fn visit_this(&mut self, depth: &Option<u32>, environment: &mut Environment, this: &Token) -> InterpResult {
-        if let Some(depth) = depth {
-            Ok(environment.get_at(*depth, &this.content))
+    fn visit_set_index(&mut self, environment: &mut Environment, set_index: &SetIndex, bracket: &Token) -> InterpResult {
+        let target = self.visit_expr(environment, &set_index.object)?;
+        let key = self.visit_expr(environment, &set_index.index)?;
+        let value = self.visit_expr(environment, &set_index.value)?;
+        self.set_index_value(&target, &key, value, bracket)
+    }
+
+    pub(crate) fn set_index_value(&mut self, target: &Value, key: &Value, value: Value, bracket: &Token) -> InterpResult {
+        match target {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                let i = position(key, list.len(), bracket)?;
+                list[i] = value.clone();
+            }
+            Value::Object(object) => {
+                let name = intern(field_name(key, bracket)?);
+                object.borrow_mut().fields.insert(name, value.clone());
+            }
+            _ => return Err(InterpError::new("Can only assign by index into lists and instances.", bracket.clone())),
+        }
+        Ok(value)
+    }
+
+    fn visit_this(&mut self, depth: &Depth, environment: &mut Environment, this: &Token) -> InterpResult {
+        if let Some(slot) = depth {
+            Ok(environment.get_slot(*slot, &this.content))
         } else {
             Err(InterpError::new("Cannot access this in global context.", this.clone()))
         }
     }
 
-    fn visit_var_expr(&mut self, depth: &Option<u32>, environment: &mut Environment, token: &Token) -> InterpResult {
-        if let Some(depth) = depth {
-            Ok(environment.get_at(*depth, &token.content))
+    fn visit_var_expr(&mut self, depth: &Depth, environment: &mut Environment, token: &Token) -> InterpResult {
+        if let Some(slot) = depth {
+            Ok(environment.get_slot(*slot, &token.content))
         } else {
-            self.get_global(token)
+            self.get_global(environment, token)
         }
     }
 
-    fn visit_super(&mut self, depth: &Option<u32>, environment: &mut Environment, method: &Token, token: &Token) -> InterpResult {
-        let depth = depth.unwrap();
-        let superclass_value = environment.get_at(depth, &token.content);
-        if let Value::Object(object) = environment.get_at(depth - 1, "this") {
+    fn visit_super(&mut self, depth: &Depth, environment: &mut Environment, method: &Token, token: &Token) -> InterpResult {
+        let slot = depth.unwrap();
+        let superclass_value = environment.get_slot(slot, &token.content);
+        if let Value::Object(object) = environment.get_at(slot.depth - 1, "this") {
             if let Value::Class(superclass) = superclass_value {
                 if let Some(method) = superclass.borrow().find_method(&method.content) {
-                    environment.bind_this(&object);
-                    Ok(Value::Function(Function::UserDefined(method)))
+                    if method.declaration.borrow().getter {
+                        return self.call_getter(&object, &method, token);
+                    }
+                    Ok(Value::Function(Function::UserDefined(ObjectStruct::bind(&object, &method))))
                 } else {
                     Err(InterpError::new("Method not found on 'super'.", token.clone()))
                 }
@@ -526,35 +1782,56 @@ impl Interpreter {
 }
 
 impl IClassStruct {
-    pub fn find_method(&self, content: &str) -> Option<UserDefined> {
-        if let Some(method) = self.methods.get(content).clone() {
-            println!("Getting method {} off of {:?}", content, self);
-            Some(method.clone())
-        } else {
-            if let Some(superclass) = &self.superclass {
-                superclass.borrow().find_method(content)
-            } else {
-                None
-            }
+    pub fn find_method(&self, content: &str) -> Option<Rc<UserDefined>> {
+        self.all_methods.get(content).cloned()
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Rc<UserDefined>> {
+        self.all_setters.get(name).cloned()
+    }
+
+    /// Copies of every method and setter the class has, its own or inherited,
+    /// with overrides applied, for another class to take as its own.
+    pub fn method_table(&self) -> (HashMap<Symbol, Rc<UserDefined>>, HashMap<Symbol, Rc<UserDefined>>) {
+        let copy = |table: &HashMap<Symbol, Rc<UserDefined>>| {
+            table.iter().map(|(name, method)| (name.clone(), Rc::new(UserDefined::clone(method)))).collect()
+        };
+        (copy(&self.all_methods), copy(&self.all_setters))
+    }
+
+    /// The names of every method the class has, its own or inherited, sorted.
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.all_methods.keys().map(|name| name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    /// Looks up a static field, falling back to those of superclasses.
+    pub fn find_field(&self, name: &str) -> Option<Value> {
+        match self.fields.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.superclass.as_ref().and_then(|superclass| superclass.borrow().find_field(name)),
         }
     }
 }
 
 impl ObjectStruct {
+    /// Binds this to object in a copy of method.
+    pub fn bind(object: &Object, method: &UserDefined) -> UserDefined {
+        let mut closure = method.environment.new_block();
+        closure.bind_this(object);
+        Value::new_user_defined(&method.declaration, closure, method.is_initializer)
+    }
+
     pub fn get(object: &Object, identifier: &Token) -> InterpResult {
         let object_struct = object.borrow();
         if let Some(value) = object_struct.fields.get(&identifier.content) {
             Ok(value.clone())
         } else {
             if let Some(user_defined) = object_struct.class.borrow().find_method(&identifier.content) {
-                let mut closure = user_defined.environment.new_block();
-                closure.bind_this(object);
-                let new_user_defined = Value::new_user_defined(
-                    &user_defined.declaration,
-                    closure,
-                    user_defined.is_initializer
-                );
-                Ok(Value::Function(Function::UserDefined(new_user_defined)))
+                Ok(Value::Function(Function::UserDefined(ObjectStruct::bind(object, &user_defined))))
+            } else if &*identifier.content == "class" {
+                Ok(Value::Class(object_struct.class.clone()))
             } else {
                 Err(InterpError::new("Property not found on object.", identifier.clone()))
             }
@@ -562,23 +1839,79 @@ impl ObjectStruct {
     }
 }
     
-fn generate_methods(class_methods: &HashMap<String, FunDeclaration>, environment: &mut Environment) -> HashMap<String, UserDefined> {
-    let mut methods = HashMap::new();
-    for (name, fun_declaration) in class_methods {
-        let new_function = Value::new_user_defined(fun_declaration, environment.clone(), name == "init");
-        methods.insert(name.clone(), new_function);
+// The position an index value names in a list or string of length len.
+/// Checks the number of arguments a call passes against the function's arity.
+pub(crate) fn check_arity(function: &Function, count: usize, name: &str, closing_paren: &Token) -> StatementResult {
+    let arity = function.arity();
+    let variadic = function.is_variadic();
+    if count != arity && !(variadic && count > arity) {
+        let msg = format!(
+            "Arity mismatch: declaration {} expected {}{} arguments, received {}.",
+            name,
+            if variadic { "at least " } else { "" },
+            arity,
+            count
+        );
+        return Err(InterpError::new(&msg, closing_paren.clone()));
+    }
+    Ok(())
+}
+
+// A new instance's init method, bound to it, for a class that has one.
+fn bound_initializer(class: &IClass) -> Option<Function> {
+    let init = class.borrow().find_method("init")?;
+    let object = ObjectStruct::new_object(class);
+    Some(Function::UserDefined(ObjectStruct::bind(&object, &init)))
+}
+
+/// Unary minus.
+pub(crate) fn negate(value: Value, token: &Token) -> InterpResult {
+    match value {
+        Value::Integer(i) => Ok(match i.checked_neg() {
+            Some(negated) => Value::Integer(negated),
+            None => overflowed_integer(&(-(i as i128)).to_string()),
+        }),
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(b) => Ok(Value::from_big_integer(-*b)),
+        Value::Number(n) => Ok(Value::Number(-n)),
+        _ => Err(InterpError::new("Expected number in expression.", token.clone())),
     }
-    methods
 }
 
+pub(crate) fn position(key: &Value, len: usize, bracket: &Token) -> Result<usize, InterpError> {
+    match key {
+        Value::Integer(i) => match usize::try_from(*i) {
+            Ok(i) if i < len => Ok(i),
+            _ => Err(InterpError::new("Index out of range.", bracket.clone())),
+        },
+        _ => Err(InterpError::new("Index must be an integer.", bracket.clone())),
+    }
+}
 
-#[cfg(test)]
-pub mod test_utils {
-    use crate::test_utils::*;
-    use crate::value::Value;
-    pub fn test_interpret(code: &str, variable_name: &str) -> Value {
-        test_run(code)
-            .get_global(&new_var(variable_name))
-            .expect("variable not found.")
+fn field_name<'a>(key: &'a Value, bracket: &Token) -> Result<&'a str, InterpError> {
+    match key {
+        Value::StringV(name) => Ok(name),
+        _ => Err(InterpError::new("Field names must be strings.", bracket.clone())),
+    }
+}
+
+/// Whether class is ancestor or inherits from it.
+fn is_subclass(class: &IClass, ancestor: &IClass) -> bool {
+    let mut class = Some(class.clone());
+    while let Some(current) = class {
+        if Rc::ptr_eq(&current, ancestor) {
+            return true;
+        }
+        class = current.borrow().superclass.clone();
     }
+    false
+}
+
+fn generate_methods(class_methods: &HashMap<Symbol, FunDeclaration>, environment: &mut Environment) -> HashMap<Symbol, Rc<UserDefined>> {
+    let mut methods = HashMap::new();
+    for (name, fun_declaration) in class_methods {
+        let new_function = Value::new_user_defined(fun_declaration, environment.clone(), &**name == "init");
+        methods.insert(name.clone(), Rc::new(new_function));
+    }
+    methods
 }
@@ -0,0 +1,435 @@
+//! JSON text to and from values. Arrays are lists, and objects are instances,
+//! of the class given when parsing, whose fields are the object's members.
+//! Integers without a fraction or exponent parse as integers (big integers
+//! too, in bignum mode) and everything else as floats.
+//!
+//! Any instance converts to a JSON object of its fields, sorted by name, so the
+//! output does not depend on field order. Sets convert to arrays, as do queues
+//! and stacks, in the order they would be popped. Functions, classes,
+//! generators, modules and ranges have no JSON form, nor do NaN, the
+//! infinities, or a collection that contains itself.
+
+use std::rc::Rc;
+
+use crate::interpreter::overflowed_integer;
+use crate::symbol::{intern, Symbol};
+use crate::value::{IClass, ObjectStruct, Value};
+
+// Deeper documents are rejected rather than risk overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+impl Value {
+    pub fn to_json(&self) -> Result<String, String> {
+        let mut out = String::new();
+        let mut visiting = vec![];
+        write_value(self, &mut out, &mut visiting)?;
+        Ok(out)
+    }
+
+    pub fn from_json(text: &str, object_class: &IClass) -> Result<Value, String> {
+        let mut reader = Reader {
+            text,
+            position: 0,
+            depth: 0,
+            object_class,
+        };
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        if reader.position != text.len() {
+            return Err(reader.error("Unexpected trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_value(value: &Value, out: &mut String, visiting: &mut Vec<*const ()>) -> Result<(), String> {
+    match value {
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(b) => out.push_str(&b.to_string()),
+        Value::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Class(class) => return Err(format!("Can't convert class {} to JSON.", class.borrow().name)),
+        Value::Function(_) => return Err("Can't convert a function to JSON.".to_string()),
+        Value::Generator(generator) => {
+            return Err(format!("Can't convert generator {} to JSON.", generator.borrow().name))
+        }
+        Value::Namespace(namespace) => return Err(format!("Can't convert module {} to JSON.", namespace.name)),
+        Value::Range(_) => return Err("Can't convert a range to JSON.".to_string()),
+        Value::Integer(i) => out.push_str(&i.to_string()),
+        Value::List(list) => {
+            let pointer = Rc::as_ptr(list) as *const ();
+            write_array(&list.borrow(), pointer, "list", out, visiting)?;
+        }
+        Value::Nil => out.push_str("null"),
+        Value::Number(n) if n.is_finite() => out.push_str(&format!("{:?}", n)),
+        Value::Number(n) => return Err(format!("Can't convert {} to JSON.", n)),
+        Value::Object(object) => {
+            let pointer = Rc::as_ptr(object) as *const ();
+            if visiting.contains(&pointer) {
+                return Err("Can't convert an instance that contains itself to JSON.".to_string());
+            }
+            visiting.push(pointer);
+            let object = object.borrow();
+            let mut names: Vec<&Symbol> = object.fields.keys().collect();
+            names.sort();
+            out.push('{');
+            for (i, name) in names.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(name, out);
+                out.push(':');
+                write_value(&object.fields[name], out, visiting)?;
+            }
+            out.push('}');
+            visiting.pop();
+        }
+        Value::Set(set) => {
+            let pointer = Rc::as_ptr(set) as *const ();
+            write_array(set.borrow().members(), pointer, "set", out, visiting)?;
+        }
+        Value::Deque(deque) => {
+            let pointer = Rc::as_ptr(deque) as *const ();
+            let (kind, values) = (deque.borrow().kind.name().to_lowercase(), deque.borrow().to_vec());
+            write_array(&values, pointer, &kind, out, visiting)?;
+        }
+        Value::StringV(s) => write_string(s, out),
+    }
+    Ok(())
+}
+
+// The elements of a list or set, found at pointer.
+fn write_array(
+    values: &[Value],
+    pointer: *const (),
+    kind: &str,
+    out: &mut String,
+    visiting: &mut Vec<*const ()>,
+) -> Result<(), String> {
+    if visiting.contains(&pointer) {
+        return Err(format!("Can't convert a {} that contains itself to JSON.", kind));
+    }
+    visiting.push(pointer);
+    out.push('[');
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_value(value, out, visiting)?;
+    }
+    out.push(']');
+    visiting.pop();
+    Ok(())
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    position: usize,
+    depth: usize,
+    object_class: &'a IClass,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{} in JSON at offset {}.", message, self.position)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    // Consumes expected, after any whitespace.
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", expected as char)))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.position..].starts_with(word) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("Unexpected character"))
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.position;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.position - start
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        let integer_start = self.position;
+        let integer_digits = self.digits();
+        if integer_digits == 0 || (integer_digits > 1 && self.text.as_bytes()[integer_start] == b'0') {
+            return Err(self.error("Invalid number"));
+        }
+        let mut integral = true;
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            integral = false;
+            if self.digits() == 0 {
+                return Err(self.error("Invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.position += 1;
+            integral = false;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.position += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error("Invalid number"));
+            }
+        }
+        let text = &self.text[start..self.position];
+        if integral {
+            return Ok(match text.parse() {
+                Ok(i) => Value::Integer(i),
+                Err(_) => overflowed_integer(text),
+            });
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+            _ => Err(self.error("Number out of range")),
+        }
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let code = self
+            .text
+            .get(self.position..self.position + 4)
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+        match code {
+            Some(code) => {
+                self.position += 4;
+                Ok(code)
+            }
+            None => Err(self.error("Invalid unicode escape")),
+        }
+    }
+
+    // A \u escape, joining a surrogate pair into one character.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex_escape()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.text[self.position..].starts_with("\\u") {
+                return Err(self.error("Unpaired surrogate"));
+            }
+            self.position += 2;
+            let low = self.hex_escape()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("Unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("Unpaired surrogate"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let Some(c) = self.text[self.position..].chars().next() else {
+                return Err(self.error("Unterminated string"));
+            };
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("Unterminated string"))?;
+                    self.position += 1;
+                    match escape {
+                        b'"' => s.push('"'),
+                        b'\\' => s.push('\\'),
+                        b'/' => s.push('/'),
+                        b'b' => s.push('\u{8}'),
+                        b'f' => s.push('\u{c}'),
+                        b'n' => s.push('\n'),
+                        b'r' => s.push('\r'),
+                        b't' => s.push('\t'),
+                        b'u' => s.push(self.unicode_escape()?),
+                        _ => {
+                            self.position -= 1;
+                            return Err(self.error("Invalid escape"));
+                        }
+                    }
+                }
+                c if c < ' ' => {
+                    self.position -= 1;
+                    return Err(self.error("Control character in string"));
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    // Calls element once per item between open and close, which must be
+    // separated by commas.
+    fn sequence(&mut self, open: u8, close: u8, mut element: impl FnMut(&mut Self) -> Result<(), String>) -> Result<(), String> {
+        self.expect(open)?;
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("Nested too deeply"));
+        }
+        self.skip_whitespace();
+        if self.peek() == Some(close) {
+            self.position += 1;
+        } else {
+            loop {
+                element(self)?;
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(b',') => self.position += 1,
+                    Some(b) if b == close => {
+                        self.position += 1;
+                        break;
+                    }
+                    _ => return Err(self.error(&format!("Expected ',' or '{}'", close as char))),
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("Unexpected end")),
+            Some(b'n') => self.literal("null", Value::Nil),
+            Some(b't') => self.literal("true", Value::Boolean(true)),
+            Some(b'f') => self.literal("false", Value::Boolean(false)),
+            Some(b'"') => self.string().map(Value::new_string),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                let mut values = vec![];
+                self.sequence(b'[', b']', |reader| {
+                    values.push(reader.value()?);
+                    Ok(())
+                })?;
+                Ok(Value::new_list(values))
+            }
+            Some(b'{') => {
+                let object = ObjectStruct::new_object(self.object_class);
+                self.sequence(b'{', b'}', |reader| {
+                    let name = reader.string()?;
+                    reader.expect(b':')?;
+                    let value = reader.value()?;
+                    object.borrow_mut().fields.insert(intern(&name), value);
+                    Ok(())
+                })?;
+                Ok(Value::Object(object))
+            }
+            Some(_) => Err(self.error("Unexpected character")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::value::IClassStruct;
+
+    fn parse(text: &str) -> Result<Value, String> {
+        let class = IClassStruct::new_i_class(HashMap::new(), HashMap::new(), "JsonObject", None);
+        Value::from_json(text, &class)
+    }
+
+    #[test]
+    fn test_primitives() {
+        let cases = [
+            ("null", Value::Nil),
+            (" true ", Value::Boolean(true)),
+            ("false", Value::Boolean(false)),
+            ("-42", Value::Integer(-42)),
+            ("0.5", Value::Number(0.5)),
+            ("1e3", Value::Number(1000.0)),
+            ("\"a\\\"\\u00e9\\ud83d\\ude00\\n\"", Value::new_string("a\"é😀\n")),
+            ("[1, [], [null]]", Value::new_list(vec![
+                Value::Integer(1),
+                Value::new_list(vec![]),
+                Value::new_list(vec![Value::Nil]),
+            ])),
+        ];
+        for (text, value) in cases {
+            assert_eq!(parse(text), Ok(value.clone()));
+            assert_eq!(parse(&value.to_json().unwrap()), Ok(value));
+        }
+        assert_eq!(Value::Number(2.0).to_json(), Ok("2.0".to_string()));
+        assert_eq!(Value::new_string("\u{1}\t").to_json(), Ok("\"\\u0001\\t\"".to_string()));
+    }
+
+    #[test]
+    fn test_objects() {
+        let value = parse("{\"name\": \"lox\", \"tags\": [\"a\"], \"meta\": {}}").unwrap();
+        let Value::Object(object) = &value else {
+            panic!("Expected an instance, got {:?}", value);
+        };
+        assert_eq!(object.borrow().class.borrow().name, "JsonObject");
+        assert_eq!(object.borrow().fields["name"], Value::new_string("lox"));
+        assert_eq!(value.to_json(), Ok("{\"meta\":{},\"name\":\"lox\",\"tags\":[\"a\"]}".to_string()));
+    }
+
+    #[test]
+    fn test_rejected() {
+        for text in [
+            "", "nul", "01", "1.", "-", "[1,]", "[1 2]", "{\"a\" 1}", "{a: 1}", "\"abc",
+            "\"\\x\"", "\"\\ud800\"", "\"\t\"", "1 2", "1e999",
+        ] {
+            assert!(parse(text).is_err(), "{:?} should not parse", text);
+        }
+        assert!(parse(&"[".repeat(MAX_DEPTH + 1)).is_err());
+        assert!(parse(&format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH))).is_ok());
+
+        assert!(Value::Number(f64::NAN).to_json().is_err());
+        let list = Value::new_list(vec![]);
+        if let Value::List(elements) = &list {
+            elements.borrow_mut().push(list.clone());
+        }
+        assert!(list.to_json().is_err());
+        if let Value::List(elements) = &list {
+            elements.borrow_mut().clear();
+        }
+    }
+}
@@ -0,0 +1,2799 @@
+#[macro_use]
+extern crate maplit;
+
+pub mod ast;
+mod builtins;
+mod calendar;
+pub mod chunk;
+pub mod compiler;
+mod csv;
+mod encoding;
+pub mod dead_code;
+pub mod environment;
+pub mod error;
+pub mod fold;
+mod formatter;
+pub mod gc;
+pub mod generator;
+pub mod interp_error;
+pub mod interpreter;
+mod json;
+mod memory;
+pub mod parser;
+pub mod platform;
+pub mod pool;
+pub mod profiler;
+pub mod random;
+pub mod resolver;
+pub mod scanner;
+pub mod scheduler;
+mod serialize;
+pub mod symbol;
+pub mod testing;
+pub mod token;
+pub mod value;
+mod vm;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::*;
+    use value::Value;
+
+    #[test]
+    fn test_arithmetic() {
+        let c = test_interpret("var a = 1; var b = 2; var c = a + b;", "c");
+        assert!(matches!(c, Value::Integer(n) if n == 3));
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let s = "
+        var a = 7 / 2;
+        var b = 8 / 2;
+        var c = 1 + 0.5;
+        var d = 9223372036854775807 + 1;
+        var e = 1 == 1.0;";
+        assert!(matches!(test_interpret(s, "a"), Value::Number(n) if n == 3.5));
+        assert!(matches!(test_interpret(s, "b"), Value::Integer(4)));
+        assert!(matches!(test_interpret(s, "c"), Value::Number(n) if n == 1.5));
+        #[cfg(not(feature = "bignum"))]
+        assert!(matches!(test_interpret(s, "d"), Value::Number(n) if n == 9223372036854775808.0));
+        assert_eq!(test_interpret(s, "e"), Value::Boolean(true));
+        assert_eq!(Value::Integer(2).to_string(), "2");
+    }
+
+    #[test]
+    fn test_string_comparison() {
+        let s = "
+        var a = \"apple\" < \"banana\";
+        var b = \"b\" <= \"abc\";
+        var c = \"Zebra\" < \"apple\";
+        var d = \"app\" >= \"app\";";
+        assert_eq!(test_interpret(s, "a"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "b"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "c"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "d"), Value::Boolean(true));
+
+        let outcome = run_and_capture("var a = \"1\" < 2;");
+        assert_eq!(outcome.diagnostics[0].message, "Expected number in expression.");
+    }
+
+    #[test]
+    fn test_nil_operands() {
+        let outcome = run_and_capture("var a = 1 + nil;");
+        assert_eq!(outcome.diagnostics[0].message, "Right operand of '+' is nil.");
+        let outcome = run_and_capture("var a = nil < 1;");
+        assert_eq!(outcome.diagnostics[0].message, "Left operand of '<' is nil.");
+
+        let s = "
+        var a = nil + 1;
+        var b = \"x\" + nil;
+        var c = nil * nil;
+        var d = nil == nil;";
+        let mut ast = scan_parse(s);
+        resolver::Resolver::new().run(&mut ast).unwrap();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_nil_operands(interpreter::NilOperands::Permissive);
+        interpreter.run(ast).unwrap();
+        assert_eq!(interpreter.global("a"), Some(Value::Integer(1)));
+        assert_eq!(interpreter.global("b"), Some(Value::new_string("x")));
+        assert_eq!(interpreter.global("c"), Some(Value::Integer(0)));
+        assert_eq!(interpreter.global("d"), Some(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn test_const() {
+        let s = "
+        const a = 1;
+        var b = a + 1;
+        {
+            var a = 5;
+            a = 6;
+        }";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(1));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(2));
+
+        let outcome = run_and_capture("const a = 1;\nfun f() { a = 2; }");
+        assert_eq!(outcome.diagnostics[0].message, "Can't assign to constant 'a'.");
+        assert_eq!(outcome.diagnostics[0].line, 1);
+        let outcome = run_and_capture("{ const a = 1; a = 2; }");
+        assert_eq!(outcome.diagnostics[0].message, "Can't assign to constant 'a'.");
+        assert_eq!(run_and_capture("const a;").diagnostics.len(), 1);
+
+        // Each REPL line is resolved on its own, so the globals hold the line.
+        let mut interpreter = interpreter::Interpreter::new();
+        let mut run = |source: &str| {
+            let mut ast = scan_parse(source);
+            resolver::Resolver::new().run(&mut ast).unwrap();
+            interpreter.run(ast).map_err(|error| match error {
+                interp_error::InterpError::Error(error) => error.message().to_string(),
+                _ => unreachable!(),
+            })
+        };
+        assert_eq!(run("const a = 1;"), Ok(()));
+        assert_eq!(run("a = 2;"), Err("Can't assign to constant 'a'.".to_string()));
+        assert_eq!(run("var a = 3;"), Err("Can't redefine constant 'a'.".to_string()));
+        assert_eq!(interpreter.global("a"), Some(Value::Integer(1)));
+    }
+
+    #[test]
+    fn test_static_fields() {
+        let s = "
+        class Counter {
+            static var count = 0;
+            static var unset;
+            static var instance = Counter();
+
+            init() {
+                Counter.count = Counter.count + 1;
+            }
+        }
+        class Sub < Counter {}
+        Counter();
+        Sub();
+        var a = Counter.count;
+        var b = Sub.count;
+        var c = Counter.unset;
+        Sub.count = 10;
+        var d = Counter.count;
+        var e = Sub.count;";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(3));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(3));
+        assert_eq!(test_interpret(s, "c"), Value::Nil);
+        assert_eq!(test_interpret(s, "d"), Value::Integer(3));
+        assert_eq!(test_interpret(s, "e"), Value::Integer(10));
+
+        let outcome = run_and_capture("class A {}\nprint A.missing;");
+        assert_eq!(outcome.diagnostics[0].message, "Static field not found on class.");
+    }
+
+    #[test]
+    fn test_getters_and_setters() {
+        let s = "
+        class Rect {
+            init(w, h) {
+                this.w = w;
+                this.h = h;
+            }
+
+            area {
+                return this.w * this.h;
+            }
+
+            set width(value) {
+                this.w = value;
+            }
+
+            set(x) {
+                return x;
+            }
+        }
+        class Square < Rect {
+            init(side) {
+                super.init(side, side);
+            }
+
+            area {
+                return super.area + 0;
+            }
+        }
+        var r = Rect(2, 3);
+        var a = r.area;
+        r.width = 5;
+        var b = r.area;
+        var d = Square(4).area;
+        var e = r.set(7);";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(6));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(15));
+        assert_eq!(test_interpret(s, "d"), Value::Integer(16));
+        assert_eq!(test_interpret(s, "e"), Value::Integer(7));
+
+        // A setter alone does not make the property readable.
+        let outcome = run_and_capture("class A { set x(v) {} }\nA().x = 1;\nprint A().x;");
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(2, "Property not found on object.")]);
+        let outcome = run_and_capture("class A { set x() {} }");
+        assert_eq!(outcome.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_nil_coalescing() {
+        let s = "
+        var calls = 0;
+        fun fallback() {
+            calls = calls + 1;
+            return 2;
+        }
+        var a = nil ?? fallback();
+        var b = false ?? fallback();
+        var c = nil ?? nil ?? 3;
+        var d = nil ?? false or true;";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(2));
+        assert_eq!(test_interpret(s, "b"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "c"), Value::Integer(3));
+        assert_eq!(test_interpret(s, "d"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "calls"), Value::Integer(1));
+    }
+
+    #[test]
+    fn test_optional_chaining() {
+        let s = "
+        class Foo {
+            bar() {
+                return 1;
+            }
+        }
+        var foo = Foo();
+        foo.field = 2;
+        var missing = nil;
+        var calls = 0;
+        fun count() {
+            calls = calls + 1;
+        }
+        var a = missing?.field;
+        var b = missing?.bar(count());
+        var c = foo?.field;
+        var d = foo?.bar();
+        var e = \"abc\"?.length();";
+        assert_eq!(test_interpret(s, "a"), Value::Nil);
+        assert_eq!(test_interpret(s, "b"), Value::Nil);
+        assert_eq!(test_interpret(s, "c"), Value::Integer(2));
+        assert_eq!(test_interpret(s, "d"), Value::Integer(1));
+        assert_eq!(test_interpret(s, "e"), Value::Integer(3));
+        assert_eq!(test_interpret(s, "calls"), Value::Integer(0));
+
+        let outcome = run_and_capture("var a = nil; var b = a?.c.d;");
+        assert_eq!(outcome.diagnostics[0].message, "Field access should be preceded by object.");
+    }
+
+    #[test]
+    fn test_number_formatting() {
+        let cases = [
+            (2.0, "2"),
+            (2.5, "2.5"),
+            (-0.0, "-0"),
+            (0.001, "0.001"),
+            (1234567.5, "1234567.5"),
+            (1e7, "1.0E7"),
+            (1.5e21, "1.5E21"),
+            (0.0001, "1.0E-4"),
+            (f64::NAN, "NaN"),
+            (f64::NEG_INFINITY, "-Infinity"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(Value::Number(n).to_string(), expected);
+        }
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn test_bignum_arithmetic() {
+        let s = "
+        var a = 9223372036854775807 + 1;
+        var b = 18446744073709551616 * 3 - 18446744073709551616 * 2;
+        var c = b / 4 - 4611686018427387904;
+        var d = -9223372036854775807 - 1 - 1;
+        var e = a > 9223372036854775807;";
+        assert_eq!(test_interpret(s, "a").to_string(), "9223372036854775808");
+        assert_eq!(test_interpret(s, "b").to_string(), "18446744073709551616");
+        assert!(matches!(test_interpret(s, "c"), Value::Integer(0)));
+        assert_eq!(test_interpret(s, "d").to_string(), "-9223372036854775809");
+        assert_eq!(test_interpret(s, "e"), Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_block() {
+        let s = "
+        var a = 1;
+        {
+            var a = 2;
+        }";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_if() {
+        let a = test_interpret(
+            "
+            var a = 1;
+            if (1 == 1)
+                a = 2;",
+            "a",
+        );
+
+        assert_eq!(a, Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_while() {
+        let s = "var a = 1;
+        while (a < 3)
+            a = a + 1;";
+        let a = test_interpret(s, "a");
+        assert_eq!(a, Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_for() {
+        let s = "
+        var j = 0;
+        for (var i = 0;
+        i < 4;
+        i = i + 1)
+            j = j + i;";
+        let j = test_interpret(s, "j");
+        assert_eq!(j, Value::Number(6.0));
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let s = "
+        var a = 0;
+        for (var i = 0; i < 10; i = i + 1) {
+            if (i == 2) continue;
+            if (i == 5) break;
+            a = a + i;
+        }
+        var b = 0;
+        outer: while (true) {
+            var j = 0;
+            inner: for (;;) {
+                j = j + 1;
+                b = b + 1;
+                if (j == 2) continue outer;
+                if (b > 6) break outer;
+            }
+        }";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(8));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(7));
+
+        let outcome = run_and_capture("while (true) { fun f() { break; } }");
+        assert_eq!(outcome.diagnostics[0].message, "Can't use 'break' outside of a loop.");
+        let outcome = run_and_capture("a: while (true) { continue b; }");
+        assert_eq!(outcome.diagnostics[0].message, "No enclosing loop labeled 'b'.");
+        let outcome = run_and_capture("a: print 1;");
+        assert_eq!(outcome.diagnostics[0].message, "Parse error: Only loops can be labeled.");
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let s = "
+        fun fail(n) {
+            if (n > 2) throw \"too big\";
+            return n;
+        }
+        var a = 0;
+        var b;
+        try {
+            a = fail(1);
+            a = fail(3);
+            a = 100;
+        } catch (e) {
+            b = e;
+        }
+        var c;
+        try {
+            try {
+                throw 1;
+            } catch (e) {
+                throw e + 1;
+            }
+        } catch (e) {
+            c = e;
+        }";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(1));
+        assert_eq!(test_interpret(s, "b"), Value::new_string("too big"));
+        assert_eq!(test_interpret(s, "c"), Value::Integer(2));
+
+        let outcome = run_and_capture("print 1;\nthrow \"oops\";");
+        assert_eq!(outcome.diagnostics[0].to_string(), "[line 1] Uncaught exception: oops");
+    }
+
+    #[test]
+    fn test_finally() {
+        let s = "
+        var log = \"\";
+        fun f() {
+            try {
+                return 1;
+            } finally {
+                log = log + \"a\";
+            }
+        }
+        var a = f();
+        try {
+            try {
+                throw \"x\";
+            } finally {
+                log = log + \"b\";
+            }
+        } catch (e) {
+            log = log + e;
+        }
+        try {
+            throw \"y\";
+        } catch (e) {
+            log = log + e;
+        } finally {
+            log = log + \"c\";
+        }
+        for (var i = 0; i < 3; i = i + 1) {
+            try {
+                if (i == 1) break;
+            } finally {
+                log = log + \"d\";
+            }
+        }";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(1));
+        assert_eq!(test_interpret(s, "log"), Value::new_string("abxycdd"));
+    }
+
+    #[test]
+    fn test_error_objects() {
+        let s = "
+        class MyError < Error {}
+        var a;
+        var b;
+        try {
+            var x = 1 + nil;
+        } catch (e) {
+            a = e.message;
+            b = e.line;
+        }
+        var c;
+        var d;
+        try {
+
+            throw MyError(\"boom\");
+        } catch (e) {
+            c = e.message;
+            d = e.line;
+        }";
+        assert_eq!(test_interpret(s, "a"), Value::new_string("Right operand of '+' is nil."));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(5));
+        assert_eq!(test_interpret(s, "c"), Value::new_string("boom"));
+        assert_eq!(test_interpret(s, "d"), Value::Integer(14));
+
+        let outcome = run_and_capture("throw Error(\"bad\");");
+        assert_eq!(outcome.diagnostics[0].message, "Uncaught exception: bad");
+    }
+
+    #[test]
+    fn test_assert() {
+        let s = "
+        assert 1 < 2, \"unreachable\";
+        var a;
+        var b;
+        try {
+            assert nil;
+        } catch (e) {
+            a = e.message;
+        }
+        try {
+
+            assert 1 > 2, \"one is not \" + \"bigger\";
+        } catch (e) {
+            b = e.line;
+        }";
+        assert_eq!(test_interpret(s, "a"), Value::new_string("Assertion failed."));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(11));
+
+        let outcome = run_and_capture("var x = 3;\nassert x == 4, x;");
+        assert_eq!(outcome.diagnostics[0].message, "Assertion failed: 3");
+        assert_eq!(outcome.diagnostics[0].line, 1);
+    }
+
+    #[test]
+    fn test_logical_and() {
+        let s = "
+        var a = 0;
+        if (false and true)
+            a = 1;";
+        let a = test_interpret(s, "a");
+        assert_eq!(a, Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_logical_or() {
+        let s = "
+        var a = 0;
+        if (false or true)
+            a = 1;";
+        let a = test_interpret(s, "a");
+        assert_eq!(a, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_call() {
+        let s = "
+        var a = clock();
+        var c = 0;
+        for (var i = 0; i < 10000; i = i + 1) {
+           c = c + 1; 
+        }
+        var b = clock() - a;";
+        let b = test_interpret(s, "b");
+        assert!(matches!(b, Value::Number(n) if n > 0.0));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let s = "
+        class Point {}
+        var p = Point();
+        p.x = 1.5;
+        p.label = \"origin\";
+        var text = encode(p);
+        var q = decode(text);
+        var a = q.x;
+        var b = q.label;";
+        assert_eq!(test_interpret(s, "text"), Value::new_string("os5:Point2;s5:labels6:origins1:xd1.5;"));
+        assert_eq!(test_interpret(s, "a"), Value::Number(1.5));
+        assert_eq!(test_interpret(s, "b"), Value::new_string("origin"));
+        let outcome = run_and_capture("fun f() {} var a = encode(f);");
+        assert_eq!(outcome.diagnostics[0].message, "Cannot serialize a function.");
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let s = "
+        var s = \"hello, world\";
+        var a = s.length();
+        var b = s.substring(1, 5);
+        var c = s.indexOf(\"world\");
+        var d = s.split(\", \");
+        var e = s.replace(\"o\", \"0\");
+        var f = s.contains(\"lo,\");
+        var g = s.charAt(1);
+        var h = \"ab\".split(\"\");";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(12));
+        assert_eq!(test_interpret(s, "b"), Value::new_string("ello"));
+        assert_eq!(test_interpret(s, "c"), Value::Integer(7));
+        assert_eq!(test_interpret(s, "d").to_string(), "[hello, world]");
+        assert_eq!(test_interpret(s, "e"), Value::new_string("hell0, w0rld"));
+        assert_eq!(test_interpret(s, "f"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "g"), Value::new_string("e"));
+        assert_eq!(test_interpret(s, "h").to_string(), "[a, b]");
+
+        let outcome = run_and_capture("var a = \"abc\".charAt(3);");
+        assert_eq!(outcome.diagnostics[0].message, "String index out of range.");
+
+        // Each lookup shares the one builtin rather than making another.
+        let length = builtins::string_method("length").unwrap();
+        assert!(std::rc::Rc::ptr_eq(&length, &builtins::string_method("length").unwrap()));
+        assert!(builtins::string_method("reverse").is_none());
+    }
+
+    #[test]
+    fn test_string_case_and_trim() {
+        let s = "
+        var s = \"  Mixed Case \";
+        var a = s.upper();
+        var b = s.lower();
+        var c = s.trim();
+        var d = c.startsWith(\"Mix\") and c.endsWith(\"Case\");
+        var e = c.startsWith(\"Case\");";
+        assert_eq!(test_interpret(s, "a"), Value::new_string("  MIXED CASE "));
+        assert_eq!(test_interpret(s, "b"), Value::new_string("  mixed case "));
+        assert_eq!(test_interpret(s, "c"), Value::new_string("Mixed Case"));
+        assert_eq!(test_interpret(s, "d"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "e"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_memoize() {
+        let s = "
+        var calls = 0;
+        fun fib(n) {
+            calls = calls + 1;
+            if (n < 2) return n;
+            return fib(n - 1) + fib(n - 2);
+        }
+        fib = memoize(fib);
+        var a = fib(60);
+        var b = calls;
+        var c = fib(60.0);
+        var d = calls;";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(1548008755920));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(61));
+        assert_eq!(test_interpret(s, "c"), Value::Integer(1548008755920));
+        assert_eq!(test_interpret(s, "d"), Value::Integer(61));
+
+        let outcome = run_and_capture("var f = memoize(1);");
+        assert_eq!(outcome.diagnostics[0].message, "memoize expects a function.");
+    }
+
+    #[test]
+    fn test_nested_call() {
+        let s = "
+        var a = 1;
+        fun foo() {
+            fun bar() {
+                a = 2;
+            }
+            bar();
+        }
+        fun bar() {
+        }
+        foo();";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 2));
+    }
+
+    #[test]
+    fn test_duplicate_vars() {
+        let s = "
+        var a = 1;
+        var a = 1;";
+        let _ = test_interpret(s, "a");
+    }
+
+    #[test]
+    fn test_nesting_function() {
+        let s = "
+        var a = 1;
+        fun make_a() {
+            var a = 2;
+            {
+                var a = 3;
+                return;
+            }
+        }";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+    
+    #[test]
+    fn test_nesting_function2() {
+        let s = "
+        fun calc_b() {
+            var a = 2;
+            {
+                var a = 3;
+                return a;
+            }
+        }
+
+        var b = calc_b();";
+        let b = test_interpret(s, "b");
+        assert!(matches!(b, Value::Integer(n) if n == 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "Parse failed")]
+    fn test_var_in_loop() {
+        let s = "
+        var a = 1;
+        for (var i = 0; i < 2; i = i + 1) var a = 2;";
+        let _ = test_interpret(s, "a");
+
+    }
+
+    #[test]
+    fn test_simple_class() {
+        let s = "
+        class Foo {
+            method() {
+            }
+        }
+
+        var foo = Foo();
+        foo.method();";
+        let _ = test_run(s);
+    }
+
+    #[test]
+    fn test_class() {
+        let s = "
+        class Foo {
+            bar() {
+                class Foo {
+                    bar2() {
+                    }
+                }
+                var foo = Foo();
+                foo.bar2();
+
+            }
+        }
+
+        var foo = Foo();
+        foo.bar();";
+        let _ = test_run(s);
+    }
+
+
+    #[test]
+    fn test_method() {
+        let s = "
+        class Foo {
+            bar() {
+                return 1;
+            }
+        }
+        var foo = Foo();
+        var a = foo.bar();";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_object_assign() {
+        let s = "
+        class Foo {
+        }
+
+        var foo = Foo();
+        foo.bar = 1;";
+        let _ = test_run(s);
+    }
+    
+    #[test]
+    fn test_object_assign2() {
+        let s = "
+        class Foo {
+        }
+
+        var foo = Foo();
+        foo.bar = 1;
+        var a = foo.bar;";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_dynamic_fields() {
+        let s = "
+        class Foo {
+            bar() {
+                return this.count;
+            }
+        }
+        var foo = Foo();
+        var name = \"co\" + \"unt\";
+        var a = set_field(foo, name, 2);
+        var b = get_field(foo, \"count\");
+        var c = get_field(foo, \"bar\")();";
+        assert_eq!(test_interpret(s, "a"), Value::Integer(2));
+        assert_eq!(test_interpret(s, "b"), Value::Integer(2));
+        assert_eq!(test_interpret(s, "c"), Value::Integer(2));
+
+        let outcome = run_and_capture("class Foo {} var a = get_field(Foo(), \"missing\");");
+        assert_eq!(outcome.diagnostics[0].message, "Property not found on object.");
+    }
+
+    #[test]
+    fn test_equals() {
+        let s = "
+        class Point {
+            init(x) {
+                this.x = x;
+            }
+            equals(other) {
+                return other != nil and this.x == other.x;
+            }
+        }
+        class Plain {}
+        var p = Plain();
+        var a = Point(1) == Point(1);
+        var b = Point(1) != Point(2);
+        var c = Point(1) == nil;
+        var d = Plain() == Plain();
+        var e = p == p;
+        var f = nil == Point(1);";
+        assert_eq!(test_interpret(s, "a"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "b"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "c"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "d"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "e"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "f"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_is() {
+        let s = "
+        class Shape {}
+        class Circle < Shape {}
+        class Other {}
+        var c = Circle();
+        var a = c is Circle;
+        var b = c is Shape;
+        var d = c is Other;
+        var e = Shape() is Circle;
+        var f = nil is Shape;
+        var g = !(1 is Shape) == true;";
+        assert_eq!(test_interpret(s, "a"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "b"), Value::Boolean(true));
+        assert_eq!(test_interpret(s, "d"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "e"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "f"), Value::Boolean(false));
+        assert_eq!(test_interpret(s, "g"), Value::Boolean(true));
+
+        let outcome = run_and_capture("class A {} print A() is 1;");
+        assert_eq!(outcome.diagnostics[0].message, "Right operand of 'is' must be a class.");
+    }
+
+    #[test]
+    fn test_reflection() {
+        let outcome = run_and_capture("
+        class Shape {
+            area() {
+                return 0;
+            }
+        }
+        class Square < Shape {
+            init(side) {
+                this.side = side;
+                this.color = \"red\";
+            }
+            area() {
+                return this.side * this.side;
+            }
+            perimeter {
+                return 4 * this.side;
+            }
+        }
+        var s = Square(2);
+        print Square.name;
+        print s.class;
+        print s.class == Square;
+        print fields(s);
+        print methods(Square);
+        print methods(Shape);");
+        assert_eq!(
+            outcome.stdout,
+            "Square\n<class Square>\ntrue\n[color, side]\n[area, init, perimeter]\n[area]\n"
+        );
+
+        let outcome = run_and_capture("print methods(1);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected class argument.");
+    }
+
+    #[test]
+    fn test_mixins() {
+        let outcome = run_and_capture("
+        class Named {
+            describe() {
+                return \"named \" + this.name;
+            }
+            greet() {
+                return \"hi from Named\";
+            }
+        }
+        class Loud {
+            greet() {
+                return \"HI\";
+            }
+            set volume(v) {
+                this.level = v;
+            }
+        }
+        class Base {
+            kind() {
+                return \"base\";
+            }
+        }
+        class Dog < Base with Named, Loud {
+            init(name) {
+                this.name = name;
+            }
+            describe() {
+                return \"dog \" + this.name;
+            }
+        }
+        var d = Dog(\"rex\");
+        d.volume = 11;
+        print d.describe();
+        print d.greet();
+        print d.kind();
+        print d.level;
+        print d is Named;");
+        assert_eq!(outcome.stdout, "dog rex\nHI\nbase\n11\nfalse\n");
+
+        let outcome = run_and_capture("var x = 1;\nclass A with x {}");
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(1, "Can only mix in classes.")]);
+    }
+
+    #[test]
+    fn test_interfaces() {
+        let outcome = run_and_capture("
+        interface Shape {
+            area();
+            scale(factor);
+        }
+        interface Named {
+            name();
+        }
+        class Base {
+            area() {
+                return 0;
+            }
+        }
+        class HasName {
+            name() {
+                return \"square\";
+            }
+        }
+        class Square < Base with HasName : Shape, Named {
+            scale(factor) {
+                return factor;
+            }
+        }
+        print Square().name();");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "square\n");
+
+        let outcome = run_and_capture("interface Shape { area(); }\nclass Circle : Shape {}");
+        assert_eq!(
+            outcome.diagnostics,
+            vec![error::Diagnostic::new(1, "Class 'Circle' is missing method 'area' required by interface 'Shape'.")]
+        );
+        let outcome = run_and_capture("interface Shape { scale(f); }\nclass Circle : Shape { scale() {} }");
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "Method 'scale' of class 'Circle' takes 0 parameters but interface 'Shape' requires 1."
+        );
+        let outcome = run_and_capture("class Circle : Shape {}");
+        assert_eq!(outcome.diagnostics[0].message, "Undefined interface 'Shape'.");
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = std::env::temp_dir().join(format!("lox_imports_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, source: &str| std::fs::write(dir.join(name), source).unwrap();
+        write("util.lox", "print \"loading util\";\nfun double(n) { return n * 2; }");
+        write(
+            "lib/math.lox",
+            "import \"../util.lox\";\nvar count = 0;\nfun bump() { count = count + 1; return double(count); }",
+        );
+        write("a.lox", "import \"./b.lox\";");
+        write("b.lox", "import \"a.lox\";");
+        write("peek.lox", "print secret;");
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        let source = format!(
+            "import \"{}\";\nimport \"{}\";\nbump();\nprint bump();\nprint count;\nprint double(5);",
+            path("lib/math.lox"),
+            path("util.lox")
+        );
+        let outcome = run_and_capture(&source);
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "loading util\n4\n0\n10\n");
+
+        let outcome = run_and_capture(&format!("var secret = 1;\nimport \"{}\";", path("peek.lox")));
+        assert_eq!(outcome.diagnostics[0].message, "Variable not found.");
+
+        let outcome = run_and_capture(&format!("import \"{}\";", path("a.lox")));
+        assert!(outcome.diagnostics[0].message.starts_with("Import cycle: "));
+
+        let outcome = run_and_capture(&format!("import \"{}\";", path("missing.lox")));
+        assert!(outcome.diagnostics[0].message.starts_with("Could not read module "));
+
+        let source = format!(
+            "import \"{}\" as m;\nprint m.bump();\nm.count = 5;\nprint m.bump();\nprint m;\nprint count;",
+            path("lib/math.lox")
+        );
+        let outcome = run_and_capture(&source);
+        assert_eq!(outcome.stdout, "loading util\n2\n12\n<module m>\n");
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(5, "Variable not found.")]);
+        let outcome = run_and_capture(&format!("import \"{}\" as m;\nm.double(1);", path("lib/math.lox")));
+        assert_eq!(outcome.diagnostics[0].message, "Module 'm' has no member 'double'.");
+
+        let outcome = run_and_capture("{ import \"x.lox\"; }");
+        assert_eq!(outcome.diagnostics[0].message, "Imports must be at the top level.");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_generators() {
+        let outcome = run_and_capture("
+        fun count(n) {
+            print \"start\";
+            for (var i = 0; i < n; i = i + 1) {
+                if (i == 1) continue;
+                yield i;
+            }
+            yield;
+        }
+        var g = count(3);
+        print g;
+        print g.next();
+        print g.hasNext();
+        print g.next();
+        print g.next();
+        print g.hasNext();
+        print g.next();
+
+        fun evens() {
+            var n = 0;
+            outer: while (true) {
+                while (true) {
+                    n = n + 1;
+                    if (n > 6) break outer;
+                    if (n == 2 or n == 4 or n == 6) break;
+                }
+                yield n;
+            }
+            return 99;
+        }
+        var e = evens();
+        while (e.hasNext()) print e.next();");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "<generator count>\nstart\n0\ntrue\n2\nnil\nfalse\nnil\n2\n4\n6\n");
+
+        let outcome = run_and_capture("fun f() { yield 1; g.next(); }\nvar g = f();\ng.next();\ng.next();");
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(0, "Generator is already running.")]);
+        let outcome = run_and_capture("yield 1;");
+        assert_eq!(outcome.diagnostics[0].message, "Can't yield outside of a function.");
+        let outcome = run_and_capture("fun f() { try { yield 1; } finally {} }");
+        assert_eq!(outcome.diagnostics[0].message, "Can't yield inside a try statement.");
+        let outcome = run_and_capture("class A { init() { yield 1; } }");
+        assert_eq!(outcome.diagnostics[0].message, "Can't yield from an initializer.");
+    }
+
+    #[test]
+    fn test_tasks() {
+        let outcome = run_and_capture("
+        fun worker(name, steps) {
+            fun task() {
+                for (var i = 1; i <= steps; i = i + 1) {
+                    print name;
+                    yield;
+                }
+            }
+            return task;
+        }
+        fun once() {
+            print \"once\";
+            spawn(worker(\"c\", 1));
+        }
+        spawn(worker(\"a\", 3));
+        spawn(worker(\"b\", 2));
+        spawn(once);
+        runTasks();
+        print \"done\";");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "a\nb\nonce\na\nb\nc\na\ndone\n");
+
+        // The end of the script doesn't run tasks it left queued.
+        let outcome = run_and_capture("fun task() { print \"ran\"; } spawn(task); print \"end\";");
+        assert_eq!(outcome.stdout, "end\n");
+        assert!(outcome.interpreter().has_tasks());
+
+        let mut interpreter = test_run("var ticks = 0; fun tick() { while (true) { ticks = ticks + 1; yield; } } spawn(tick);");
+        assert_eq!(interpreter.global("ticks"), Some(Value::Integer(0)));
+        for _ in 0..3 {
+            interpreter.run_task_round().unwrap();
+        }
+        assert!(interpreter.has_tasks());
+        assert_eq!(interpreter.global("ticks"), Some(Value::Integer(3)));
+
+        let outcome = run_and_capture("fun f(x) {}\nspawn(f);");
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(1, "Tasks must take no arguments.")]);
+    }
+
+    #[test]
+    fn test_match() {
+        let outcome = run_and_capture("
+        class Point {
+            init(x, y) {
+                this.x = x;
+                this.y = y;
+            }
+        }
+        class Point3 < Point {}
+        fun describe(value) {
+            match (value) {
+                case 0: print \"zero\";
+                case -1: print \"minus one\";
+                case \"hi\": print \"greeting\";
+                case Point(0, y): print y;
+                case Point(x, _): {
+                    print \"point\";
+                    print x;
+                }
+                case [\"a\", rest]: print \"a then \" + rest;
+                case [_, _, _]: print \"three\";
+                case other: print other;
+            }
+        }
+        describe(0);
+        describe(-1);
+        describe(\"hi\");
+        describe(Point(0, 5));
+        describe(Point3(2, 5));
+        describe(\"a,b\".split(\",\"));
+        describe(\"a,b,c\".split(\",\"));
+        describe(nil);
+        match (1) {}");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "zero\nminus one\ngreeting\n5\npoint\n2\na then b\nthree\nnil\n"
+        );
+
+        let outcome = run_and_capture("
+        fun labels(n) {
+            for (var i = 0; i < n; i = i + 1) {
+                match (i) {
+                    case 0: yield \"first\";
+                    case x: yield x;
+                }
+            }
+        }
+        var g = labels(2);
+        print g.next();
+        print g.next();");
+        assert_eq!(outcome.stdout, "first\n1\n");
+
+        let outcome = run_and_capture("match (1) { case [x, x]: print x; }");
+        assert_eq!(outcome.diagnostics[0].message, "'x' is bound twice in one pattern.");
+        let outcome = run_and_capture("class P { init(x) {} }\nmatch (P(1)) { case P(a, b): print a; }");
+        assert_eq!(
+            outcome.diagnostics,
+            vec![error::Diagnostic::new(1, "Class pattern has 2 fields but P's init takes 1 parameters.")]
+        );
+    }
+
+    #[test]
+    fn test_spread() {
+        let outcome = run_and_capture("
+        fun add3(a, b, c) {
+            return a + b + c;
+        }
+        var rest = [2, 3];
+        print add3(...[1, 2, 3]);
+        print add3(1, ...rest);
+        print [0, ...rest, 4, ...[]];
+        print [];
+        print [[1], ...\"x,y\".split(\",\")];");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "6\n6\n[0, 2, 3, 4]\n[]\n[[1], x, y]\n");
+
+        let outcome = run_and_capture("fun f(a) {}\nf(...[1, 2]);");
+        assert_eq!(
+            outcome.diagnostics,
+            vec![error::Diagnostic::new(1, "Arity mismatch: declaration f expected 1 arguments, received 2.")]
+        );
+        let outcome = run_and_capture("print [...1];");
+        assert_eq!(outcome.diagnostics[0].message, "Can only spread lists.");
+    }
+
+    #[test]
+    fn test_index() {
+        let outcome = run_and_capture("
+        class Config {}
+        var config = Config();
+        var key = \"depth\";
+        config[key] = 3;
+        config[\"na\" + \"me\"] = \"lox\";
+        print config.depth;
+        print config[\"name\"];
+        var xs = [1, [2, 3]];
+        xs[0] = xs[1][0] + 10;
+        print xs;
+        print xs[1][1];
+        print \"hello\"[1];");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "3\nlox\n[12, [2, 3]]\n3\ne\n");
+
+        let outcome = run_and_capture("var xs = [1];\nprint xs[1];");
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(1, "Index out of range.")]);
+        let outcome = run_and_capture("print [1][\"0\"];");
+        assert_eq!(outcome.diagnostics[0].message, "Index must be an integer.");
+        let outcome = run_and_capture("class A {}\nA()[1] = 2;");
+        assert_eq!(outcome.diagnostics[0].message, "Field names must be strings.");
+        let outcome = run_and_capture("class A { m() {} }\nprint A()[\"m\"];");
+        assert_eq!(outcome.diagnostics[0].message, "Property not found on object.");
+        let outcome = run_and_capture("var n = 1;\nn[0] = 1;");
+        assert_eq!(outcome.diagnostics[0].message, "Can only assign by index into lists and instances.");
+    }
+
+    #[test]
+    fn test_function_properties() {
+        let outcome = run_and_capture("
+        fun add(a, b) {
+            return a + b;
+        }
+        class Point {
+            init(x, y) {}
+            norm() {}
+        }
+        print add.arity;
+        print add.name;
+        print Point(1, 2).norm.name;
+        print Point(1, 2).norm.arity;
+        print clock.name;
+        print clock.arity;
+        print \"s\".split.arity;
+        print memoize(add).name;
+        print add.arity + memoize(add).arity;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "2\nadd\nnorm\n0\nclock\n0\n1\nadd\n4\n");
+
+        let outcome = run_and_capture("fun f() {}\nprint f.body;");
+        assert_eq!(
+            outcome.diagnostics,
+            vec![error::Diagnostic::new(1, "Functions only have 'arity' and 'name' properties.")]
+        );
+    }
+
+    #[test]
+    fn test_in() {
+        let outcome = run_and_capture("
+        class Id {
+            init(n) {
+                this.n = n;
+            }
+            equals(other) {
+                return other is Id and other.n == this.n;
+            }
+        }
+        var xs = [1, \"two\", nil, Id(3)];
+        print 1.0 in xs;
+        print \"two\" in xs;
+        print nil in xs;
+        print Id(3) in xs;
+        print 4 in xs;
+        print \"ell\" in \"hello\";
+        print \"\" in \"\";
+        print \"n\" in Id(1);
+        print \"m\" in Id(1);
+        print !(1 in []) == true;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "true\ntrue\ntrue\ntrue\nfalse\ntrue\ntrue\ntrue\nfalse\ntrue\n");
+
+        let outcome = run_and_capture("print 1 in \"1\";");
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "Left operand of 'in' must be a string when the right is a string or instance."
+        );
+        let outcome = run_and_capture("print 1 in 2;");
+        assert_eq!(outcome.diagnostics[0].message, "Right operand of 'in' must be a list, range, set, string or instance.");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let outcome = run_and_capture("
+        var n = 3;
+        for (var i in 0..n) print i;
+        for (var i in 1..=n - 1) print i;
+        for (var i in 5..1) print i;
+        print 1..=3;
+        print (0..4).toList();
+        print 2 in 0..3;
+        print 3 in 0..3;
+        print 3 in 0..=3;
+        var total = 0;
+        outer: for (var xs in [[1, 2], [3, 4]]) {
+            for (var x in xs) {
+                if (x == 3) break outer;
+                total = total + x;
+            }
+        }
+        print total;
+        for (var c in \"ab\") print c;
+        var fs = [];
+        for (var i in 0..2) {
+            fun f() {
+                return i;
+            }
+            fs = [...fs, f];
+        }
+        print fs[0]() + fs[1]();
+
+        fun squares(limit) {
+            for (var i in 1..=limit) {
+                if (i == 2) continue;
+                yield i * i;
+            }
+        }
+        for (var s in squares(3)) print s;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "0\n1\n2\n1\n2\n1..=3\n[0, 1, 2, 3]\ntrue\nfalse\ntrue\n3\na\nb\n1\n1\n9\n"
+        );
+
+        let outcome = run_and_capture("for (var x in 1) print x;");
+        assert_eq!(outcome.diagnostics[0].message, "Can only iterate over lists, ranges, sets, strings and generators.");
+        let outcome = run_and_capture("print 1..2.5;");
+        assert_eq!(outcome.diagnostics[0].message, "Range bounds must be integers.");
+    }
+
+    #[test]
+    fn test_comprehensions() {
+        let outcome = run_and_capture("
+        var xs = [3, -1, 4, -1, 5];
+        print [x * x for x in xs if x > 0];
+        print [x for x in 1..=3];
+        print [c + c for c in \"ab\"];
+        print [[x, x * 10] for x in 0..3 if x in [y + 1 for y in 0..2]];
+        var x = \"outer\";
+        print [x + 1 for x in 0..2];
+        print x;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "[9, 16, 25]\n[1, 2, 3]\n[aa, bb]\n[[1, 10], [2, 20]]\n[1, 2]\nouter\n");
+
+        let outcome = run_and_capture("print [...xs for x in xs];");
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "Parse error: Can't spread the element of a comprehension."
+        );
+    }
+
+    #[test]
+    fn test_map_filter_reduce() {
+        let outcome = run_and_capture("
+        fun square(x) {
+            return x * x;
+        }
+        fun positive(x) {
+            return x > 0;
+        }
+        fun add(total, x) {
+            return total + x;
+        }
+        var xs = [3, -1, 4];
+        print map(xs, square);
+        print filter(xs, positive);
+        print reduce(xs, add, 0);
+        print reduce([], add, \"empty\");
+        print map([0, 2], \"xyz\".charAt);
+        print xs;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "[9, 1, 16]\n[3, 4]\n6\nempty\n[x, z]\n[3, -1, 4]\n");
+
+        let outcome = run_and_capture("print map(1, clock);");
+        assert_eq!(outcome.diagnostics[0].message, "map expects a list and a function.");
+        let outcome = run_and_capture("fun f() {}\nprint filter([1], f);");
+        assert_eq!(
+            outcome.diagnostics,
+            vec![error::Diagnostic::new(1, "Arity mismatch: declaration f expected 0 arguments, received 1.")]
+        );
+    }
+
+    #[test]
+    fn test_with() {
+        let outcome = run_and_capture("
+        class Resource {
+            init(name) {
+                this.name = name;
+            }
+
+            close() {
+                print this.name + \" closed\";
+            }
+        }
+        with (Resource(\"a\")) as r {
+            print r.name;
+        }
+        fun early() {
+            with (Resource(\"b\")) as r {
+                return r.name;
+            }
+        }
+        print early();
+        while (true) {
+            with (Resource(\"c\")) as r {
+                break;
+            }
+        }
+        try {
+            with (Resource(\"d\")) as r {
+                throw \"oops\";
+            }
+        } catch (e) {
+            print e;
+        }
+        with (Resource(\"e\")) as r {
+            r.missing;
+        }");
+        assert_eq!(
+            outcome.stdout,
+            "a\na closed\nb closed\nb\nc closed\nd closed\noops\ne closed\n"
+        );
+        assert_eq!(outcome.diagnostics, vec![error::Diagnostic::new(32, "Property not found on object.")]);
+
+        let outcome = run_and_capture("with (1) as r {}");
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "The resource of a with statement must have a close method."
+        );
+        let outcome = run_and_capture("fun f() { with (1) as r { yield; } }");
+        assert_eq!(outcome.diagnostics[0].message, "Can't yield inside a with statement.");
+    }
+
+    #[test]
+    fn test_format() {
+        let outcome = run_and_capture("
+        var pi = 3.14159;
+        print format(\"pi = {:.2}\", pi);
+        print format(\"{} and {}, {0} again\", \"a\", 2);
+        print format(\"[{:5}] [{:<5}] [{:^5}] [{:>5}]\", 42, 42, \"ab\", \"ab\");
+        print format(\"[{:8.3}] [{:.2}] [{:.3}]\", 1, \"truncated\", 7);
+        print format(\"{{literal}} {}\", nil);
+        print format(\"no placeholders\");
+        printf(\"{} + {}\", 1, 2);
+        printf(\" = {:.1}\\n\", 3);");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "pi = 3.14\na and 2, a again\n[   42] [42   ] [ ab  ] [   ab]\n[   1.000] [tr] [7.000]\n{literal} nil\nno placeholders\n1 + 2 = 3.0\n"
+        );
+
+        let errors = [
+            ("print format(\"{} {}\", 1);", "Format string refers to argument 1, but only 1 were given."),
+            ("print format(\"{\", 1);", "Unmatched '{' in format string."),
+            ("print format(\"}\", 1);", "Unmatched '}' in format string."),
+            ("print format(\"{:x}\", 1);", "Invalid placeholder '{:x}' in format string."),
+            (
+                "print format(\"{:.999999999999}\", 1.5);",
+                "Invalid placeholder '{:.999999999999}' in format string.",
+            ),
+            ("printf(\"{:99999999999}\", 1);", "Invalid placeholder '{:99999999999}' in format string."),
+            ("print format(1);", "format expects a string."),
+            ("printf(nil);", "printf expects a string."),
+            ("print format();", "Arity mismatch: declaration format expected at least 1 arguments, received 0."),
+        ];
+        for (source, message) in errors {
+            assert_eq!(run_and_capture(source).diagnostics[0].message, message);
+        }
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        let outcome = run_and_capture("
+        var calls = 0;
+        fun two() {
+            calls = calls + 1;
+            return 2;
+        }
+        print 1 < two() < 3;
+        print calls;
+        print 3 > two() >= 2 > 1;
+        print 1 < 3 < two();
+        print 5 < two() < missing;
+        print 1 < 2 == 2 < 3;
+        print (1 < 2) == true;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "true\n1\ntrue\nfalse\nfalse\ntrue\ntrue\n");
+
+        let outcome = run_and_capture("print 1 < 2 < \"3\";");
+        assert_eq!(outcome.diagnostics[0].message, "Expected number in expression.");
+    }
+
+    #[test]
+    fn test_file_io() {
+        let dir = std::env::temp_dir().join(format!("lox_file_io_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        let source = format!(
+            "var path = \"{}\";
+            print writeFile(path, \"one\\ntwo\");
+            var lines = readFile(path).split(\"\\n\");
+            print lines;
+            writeFile(path, \"replaced\");
+            print readFile(path);",
+            path("notes.txt")
+        );
+        let outcome = run_and_capture(&source);
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "true\n[one, two]\nreplaced\n");
+
+        let outcome = run_and_capture(&format!("readFile(\"{}\");", path("missing.txt")));
+        assert!(outcome.diagnostics[0].message.starts_with("Could not read file "));
+        let outcome = run_and_capture(&format!("writeFile(\"{}\", \"x\");", path("no/such/dir.txt")));
+        assert!(outcome.diagnostics[0].message.starts_with("Could not write file "));
+        let outcome = run_and_capture("writeFile(\"x.txt\", 1);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected string argument.");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filesystem() {
+        let dir = std::env::temp_dir().join(format!("lox_filesystem_{}", std::process::id()));
+        let dir = dir.to_string_lossy();
+        let source = format!(
+            "var dir = \"{}\";
+            print exists(dir);
+            print mkdir(dir + \"/sub\");
+            print mkdir(dir + \"/sub\");
+            writeFile(dir + \"/b.txt\", \"\");
+            writeFile(dir + \"/a.txt\", \"\");
+            print listDir(dir);
+            print remove(dir + \"/a.txt\");
+            print remove(dir + \"/a.txt\");
+            print exists(dir + \"/b.txt\");
+            remove(dir + \"/b.txt\");
+            remove(dir + \"/sub\");
+            print listDir(dir);
+            print remove(dir);
+            print exists(dir);",
+            dir
+        );
+        let outcome = run_and_capture(&source);
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "false\ntrue\nfalse\n[a.txt, b.txt, sub]\ntrue\nfalse\ntrue\n[]\ntrue\nfalse\n"
+        );
+
+        let outcome = run_and_capture(&format!("listDir(\"{}/missing\");", dir));
+        assert!(outcome.diagnostics[0].message.starts_with("Could not list directory "));
+    }
+
+    #[test]
+    fn test_paths() {
+        let outcome = run_and_capture("
+        print pathJoin(\"docs\", \"notes\", \"todo.txt\");
+        print pathJoin(\"docs\", \"/etc\");
+        print dirname(\"docs/notes/todo.txt\");
+        print dirname(\"todo.txt\");
+        print dirname(\"/\");
+        print basename(\"docs/notes/todo.txt\");
+        print basename(\"/\");
+        print extension(\"archive.tar.gz\");
+        print extension(\"docs/README\");");
+        assert!(outcome.diagnostics.is_empty());
+        #[cfg(unix)]
+        assert_eq!(
+            outcome.stdout,
+            "docs/notes/todo.txt\n/etc\ndocs/notes\n\nnil\ntodo.txt\nnil\ngz\nnil\n"
+        );
+
+        let outcome = run_and_capture("print pathJoin(\"a\", 1);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected string argument.");
+    }
+
+    #[test]
+    fn test_random() {
+        let s = "
+        seed(42);
+        var a = [random(), randInt(1, 6), randInt(-3, -3)];
+        seed(42);
+        var b = [random(), randInt(1, 6), randInt(-3, -3)];
+        var in_range = true;
+        for (var i in 0..200) {
+            var r = random();
+            var n = randInt(-2, 2);
+            in_range = in_range and 0 <= r < 1 and -2 <= n <= 2;
+        }";
+        let mut interpreter = test_run(s);
+        assert_eq!(interpreter.global("a").unwrap().to_string(), interpreter.global("b").unwrap().to_string());
+        assert_eq!(interpreter.global("in_range"), Some(Value::Boolean(true)));
+
+        // A reset forgets the seed, so the next script's numbers are its own.
+        interpreter.run(scan_parse("seed(42); var first = random();")).unwrap();
+        let seeded = interpreter.global("first");
+        interpreter.reset();
+        interpreter.run(scan_parse("var first = random();")).unwrap();
+        assert_ne!(interpreter.global("first"), seeded);
+
+        let outcome = run_and_capture("print randInt(9223372036854775807, 9223372036854775807);");
+        assert_eq!(outcome.stdout, "9223372036854775807\n");
+        let outcome = run_and_capture("randInt(2, 1);");
+        assert_eq!(outcome.diagnostics[0].message, "randInt's low bound is above its high bound.");
+        let outcome = run_and_capture("randInt(1, 2.5);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected integer argument.");
+    }
+
+    #[test]
+    fn test_math() {
+        let outcome = run_and_capture("
+        print Math.sqrt(16);
+        print Math.abs(-3);
+        print Math.abs(-2.5);
+        print Math.floor(2.7);
+        print Math.ceil(-2.7);
+        print Math.round(2.5);
+        print [Math.round(-2.5), Math.floor(4)];
+        print Math.sin(0) + Math.cos(0);
+        print Math.pow(2, 10);
+        print Math.pow(4, 0.5);
+        print Math.pow(2, -1);
+        print Math.min(3, 1.5, 2);
+        print Math.max(3, 1.5, 7);
+        print Math.PI > 3.14 and Math.E < 2.72;
+        print Math.floor(Math.PI) == 3;
+        print Math;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "4\n3\n2.5\n2\n-2\n3\n[-3, 4]\n1\n1024\n2\n0.5\n1.5\n7\ntrue\ntrue\n<module Math>\n"
+        );
+
+        #[cfg(feature = "bignum")]
+        {
+            let outcome = run_and_capture("print Math.pow(2, 64);\nprint Math.abs(-9223372036854775807 - 1);");
+            assert_eq!(outcome.stdout, "18446744073709551616\n9223372036854775808\n");
+        }
+
+        let outcome = run_and_capture("print Math.sqrt(\"4\");");
+        assert_eq!(outcome.diagnostics[0].message, "Expected number argument.");
+        let outcome = run_and_capture("print Math.max(1, nil);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected number argument.");
+        let outcome = run_and_capture("print 1;\nprint Math.min();");
+        assert_eq!(outcome.diagnostics[0], error::Diagnostic::new(1, "Math.min expects at least one number."));
+        let outcome = run_and_capture("print Math.max();");
+        assert_eq!(outcome.diagnostics[0].message, "Math.max expects at least one number.");
+        let outcome = run_and_capture("print Math.tan(1);");
+        assert_eq!(outcome.diagnostics[0].message, "Module 'Math' has no member 'tan'.");
+    }
+
+    #[test]
+    fn test_number_string_conversion() {
+        let outcome = run_and_capture("
+        print num(\"42\") + 1;
+        print num(\" -2.5\\n\") * 2;
+        print num(\"1e3\");
+        print [num(\"\"), num(\"abc\"), num(\"12abc\"), num(\"inf\"), num(\"-\")];
+        class Point {
+            toString() {
+                return \"(1, 2)\";
+            }
+        }
+        print str(12) + \"!\";
+        print str(1.5) + str(nil) + str(true) + str([1, \"a\"]);
+        print str(Point()) + str(Point);
+        print num(str(3.25)) == 3.25;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "43\n-5\n1000\n[nil, nil, nil, nil, nil]\n12!\n1.5niltrue[1, a]\n(1, 2)<class Point>\ntrue\n"
+        );
+
+        let outcome = run_and_capture("print num(\"9223372036854775808\");");
+        #[cfg(feature = "bignum")]
+        assert_eq!(outcome.stdout, "9223372036854775808\n");
+        #[cfg(not(feature = "bignum"))]
+        assert_eq!(outcome.stdout, "9.223372036854776E18\n");
+        let outcome = run_and_capture("num(1);");
+        assert_eq!(outcome.diagnostics[0].message, "num expects a string.");
+    }
+
+    #[test]
+    fn test_type() {
+        let outcome = run_and_capture("
+        class Point {
+            norm() {
+                return 0;
+            }
+        }
+        fun f() {}
+        var p = Point();
+        print [type(1), type(1.5), type(\"a\"), type(true), type(nil)];
+        print [type(f), type(clock), type(p.norm), type(Point), type(p)];
+        print [type([1]), type(1..3), type(Math)];
+        if (type(p) == \"Point\") print \"point\";");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "[number, number, string, boolean, nil]\n[function, function, function, class, Point]\n[list, range, namespace]\npoint\n"
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let outcome = run_and_capture("
+        fun check(n) {
+            if (n > 2) {
+                try {
+                    exit(3);
+                } catch (e) {
+                    print \"caught\";
+                } finally {
+                    print \"cleanup\";
+                }
+            }
+            print n;
+            check(n + 1);
+        }
+        check(1);
+        print \"unreachable\";");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.exit_code, Some(3));
+        assert_eq!(outcome.stdout, "1\n2\ncleanup\n");
+
+        let outcome = run_and_capture("print 1;");
+        assert_eq!(outcome.exit_code, None);
+        let outcome = run_and_capture("exit(1.5);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected integer argument.");
+        let outcome = run_and_capture("exit(4294967296);");
+        assert_eq!(outcome.diagnostics[0].message, "Exit code out of range.");
+    }
+
+    #[test]
+    fn test_environment_variables() {
+        let outcome = run_and_capture("
+        print getenv(\"LOX_TEST_UNSET_VARIABLE\");
+        setenv(\"LOX_TEST_VARIABLE\", \"debug\");
+        print getenv(\"LOX_TEST_VARIABLE\");");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "nil\ndebug\n");
+        assert_eq!(std::env::var("LOX_TEST_VARIABLE").as_deref(), Ok("debug"));
+
+        let outcome = run_and_capture("setenv(\"A=B\", \"c\");");
+        assert_eq!(outcome.diagnostics[0].message, "Invalid environment variable name 'A=B'.");
+        let outcome = run_and_capture("getenv(1);");
+        assert_eq!(outcome.diagnostics[0].message, "Expected string argument.");
+    }
+
+    #[test]
+    fn test_script_arguments() {
+        assert_eq!(run_and_capture("print ARGS;").stdout, "[]\n");
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_arguments(vec!["input.txt".to_string(), "-v".to_string()]);
+        let mut ast = scan_parse("var count = 0; for (var a in ARGS) count = count + 1; var first = ARGS[0];");
+        resolver::Resolver::new().run(&mut ast).unwrap();
+        interpreter.run(ast).unwrap();
+        assert_eq!(interpreter.global("count"), Some(Value::Integer(2)));
+        assert_eq!(interpreter.global("first"), Some(Value::new_string("input.txt")));
+
+        // Arguments outlive a reset, as the script path does.
+        interpreter.reset();
+        assert_eq!(
+            interpreter.global("ARGS"),
+            Some(Value::new_list(vec![Value::new_string("input.txt"), Value::new_string("-v")]))
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let outcome = run_and_capture("
+        var config = jsonParse(\"{\\\"name\\\": \\\"demo\\\", \\\"ports\\\": [80, 443], \\\"debug\\\": false}\");
+        print config.name;
+        print config.ports[1] + 1;
+        print type(config);
+        config.debug = true;
+        config.ratio = 0.5;
+        print jsonStringify(config);
+        print jsonStringify([nil, \"tab\\t\", 1..3 == nil]);");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "demo\n444\nJsonObject\n{\"debug\":true,\"name\":\"demo\",\"ports\":[80,443],\"ratio\":0.5}\n[null,\"tab\\t\",false]\n"
+        );
+
+        let outcome = run_and_capture("jsonParse(\"[1,\");");
+        assert_eq!(outcome.diagnostics[0].message, "Unexpected end in JSON at offset 3.");
+        let outcome = run_and_capture("jsonStringify(clock);");
+        assert_eq!(outcome.diagnostics[0].message, "Can't convert a function to JSON.");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        let outcome = run_and_capture("
+        print regexMatch(\"^[a-z]+$\", \"lox\");
+        print regexMatch(\"^[a-z]+$\", \"Lox\");
+        print regexFind(\"(\\\\d+)-(\\\\d+)?\", \"pages 12- and 3-4\");
+        print regexFind(\"x\", \"abc\");
+        print regexReplace(\"(?P<word>\\\\w+)@\", \"a@ b@\", \"<${word}>\");");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "true\nfalse\n[12-, 12, nil]\nnil\n<a> <b>\n");
+
+        let outcome = run_and_capture("\nregexMatch(\"(\", \"\");");
+        assert!(outcome.diagnostics[0].message.starts_with("Invalid regex: "));
+        assert_eq!(outcome.diagnostics[0].line, 1);
+    }
+
+    #[test]
+    fn test_time() {
+        let outcome = run_and_capture("
+        var line = \"[2024-03-09 14:05:00] started\";
+        var start = Time.parse(line.substring(1, 20), \"%Y-%m-%d %H:%M:%S\");
+        print start;
+        print Time.format(start + 3600.7, \"%H:%M on %d.%m.%Y\");
+        print Time.parse(\"2023-02-29\", \"%Y-%m-%d\");
+        print Time.now() > 1700000000;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "1709993100\n15:05 on 09.03.2024\nnil\ntrue\n");
+
+        let outcome = run_and_capture("Time.format(0, \"%Q\");");
+        assert_eq!(outcome.diagnostics[0].message, "Unknown time format field '%Q'.");
+    }
+
+    #[test]
+    fn test_tcp() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            (&stream).write_all(format!("echo: {}", line).as_bytes()).unwrap();
+        });
+        let outcome = run_and_capture(&format!("
+        var connection = tcpConnect(\"127.0.0.1\", {});
+        tcpSend(connection, \"héllo\\n\");
+        var reply = \"\";
+        var chunk = tcpRecv(connection);
+        while (chunk != nil) {{
+            reply = reply + chunk;
+            chunk = tcpRecv(connection);
+        }}
+        print reply;
+        tcpClose(connection);
+        tcpClose(connection);", port));
+        server.join().unwrap();
+        assert_eq!(outcome.stdout, "echo: héllo\n\n");
+        assert_eq!(outcome.diagnostics[0].message, "Could not close connection: not an open connection");
+
+        // A character split across two reads arrives whole.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let bytes = "h☃!".as_bytes();
+            stream.write_all(&bytes[..2]).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            stream.write_all(&bytes[2..]).unwrap();
+        });
+        let outcome = run_and_capture(&format!("
+        var connection = tcpConnect(\"127.0.0.1\", {});
+        var chunk = tcpRecv(connection);
+        while (chunk != nil) {{
+            print chunk;
+            chunk = tcpRecv(connection);
+        }}", port));
+        server.join().unwrap();
+        assert!(!outcome.stdout.contains('\u{FFFD}'), "{}", outcome.stdout);
+        assert_eq!(outcome.stdout.replace('\n', ""), "h☃!");
+
+        let outcome = run_and_capture("tcpConnect(\"127.0.0.1\", 70000);");
+        assert_eq!(outcome.diagnostics[0].message, "Port out of range.");
+    }
+
+    #[test]
+    fn test_collections() {
+        let outcome = run_and_capture("
+        var xs = [1, 2];
+        push(xs, 3);
+        insert(xs, 0, 0);
+        insert(xs, 4, 4);
+        print xs;
+        print [pop(xs), removeAt(xs, 1), xs, len(xs)];
+        print [len(\"héllo\"), len(1..=10), len(5..1)];
+        print [contains(xs, 3), contains(xs, \"3\"), contains(\"lox\", \"ox\"), contains(0..3, 3)];
+        var point = jsonParse(\"{\\\"y\\\": 2, \\\"x\\\": 1}\");
+        print [keys(point), values(point), len(point), contains(point, \"x\")];");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "[0, 1, 2, 3, 4]\n[4, 1, [0, 2, 3], 3]\n[5, 10, 0]\n[true, false, true, false]\n[[x, y], [1, 2], 2, true]\n"
+        );
+
+        let cases = [
+            ("push(\"a\", 1);", "push expects a list."),
+            ("pop([]);", "Can't pop from an empty list."),
+            ("insert([1], 2, 0);", "Index out of range."),
+            ("removeAt([1], -1);", "Index out of range."),
+            ("keys([1]);", "keys expects an instance."),
+            ("len(nil);", "len expects a string, list, queue, range, set, stack or instance."),
+            ("contains(\"abc\", 1);", "contains expects a string to look for."),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run_and_capture(source).diagnostics[0].message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_sort() {
+        let outcome = run_and_capture("
+        var xs = [3, 1.5, -2, 10, 1];
+        print sort(xs);
+        print xs;
+        print sort([\"pear\", \"Apple\", \"fig\"]);
+        fun byLength(a, b) {
+            return a.length() - b.length();
+        }
+        print sort([\"ccc\", \"a\", \"bb\", \"d\", \"ee\"], byLength);
+        fun backwards(a, b) {
+            return b - a;
+        }
+        print sort((0..6).toList(), backwards);
+        print sort([]);");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "[-2, 1, 1.5, 3, 10]\n[3, 1.5, -2, 10, 1]\n[Apple, fig, pear]\n[a, d, bb, ee, ccc]\n[5, 4, 3, 2, 1, 0]\n[]\n"
+        );
+
+        let outcome = run_and_capture("fun f(a, b) { return nil; }\nsort([1, 2], f);");
+        assert_eq!(outcome.diagnostics[0].message, "sort's comparator must return a number.");
+        let outcome = run_and_capture("sort(0..6);");
+        assert_eq!(outcome.diagnostics[0].message, "sort expects a list and an optional comparator function.");
+        let outcome = run_and_capture("sort([1, \"a\"]);");
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "sort can only compare numbers with numbers and strings with strings."
+        );
+        // An inconsistent comparator gives some order rather than failing.
+        let outcome = run_and_capture("fun coin(a, b) { return random() - 0.5; }\nprint len(sort((0..=99).toList(), coin));");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "100\n");
+    }
+
+    #[test]
+    fn test_sets() {
+        let outcome = run_and_capture("
+        var seen = Set(3, 1, 3, 1.0);
+        print seen;
+        print [seen.add(2), seen.add(2), seen.remove(3), seen.remove(3)];
+        print [seen.has(1), seen.has(3), 2 in seen, len(seen), contains(seen, 1)];
+        var a = Set(1, 2, 3);
+        var b = Set(2, 3, 4);
+        print [a.union(b), a.intersect(b), a, Set()];
+        print Set(1, 2) == Set(2, 1);
+        var list = [1];
+        var lists = Set(list, list, [1]);
+        print len(lists);
+        for (var x in a) a.remove(x);
+        print [a, type(a)];
+        print jsonStringify(b);");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "{3, 1}\n[true, false, true, false]\n[true, false, true, 2, true]\n\
+            [{1, 2, 3, 4}, {2, 3}, {1, 2, 3}, {}]\ntrue\n2\n[{}, set]\n[2,3,4]\n"
+        );
+
+        let outcome = run_and_capture("Set(1).union([2]);");
+        assert_eq!(outcome.diagnostics[0].message, "union expects a set.");
+        let outcome = run_and_capture("Set(1).size();");
+        assert_eq!(outcome.diagnostics[0].message, "Undefined set method.");
+    }
+
+    #[test]
+    fn test_encodings() {
+        let outcome = run_and_capture("
+        print base64Encode(\"héllo, lox\");
+        print base64Decode(base64Encode(\"héllo, lox\"));
+        print hexEncode(\"Lox!\");
+        print hexDecode(\"4C6f7821\");");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "aMOpbGxvLCBsb3g=\nhéllo, lox\n4c6f7821\nLox!\n");
+
+        let outcome = run_and_capture("base64Decode(\"a$==\");");
+        assert_eq!(outcome.diagnostics[0].message, "Invalid base64 character '$'.");
+        let outcome = run_and_capture("hexDecode(\"ff\");");
+        assert_eq!(outcome.diagnostics[0].message, "Decoded bytes are not valid UTF-8.");
+    }
+
+    #[test]
+    fn test_hash() {
+        let outcome = run_and_capture("
+        var list = [1];
+        print hash(1) == hash(1.0);
+        print hash(\"lox\") == hash(\"lo\" + \"x\");
+        print hash(\"lox\") == hash(\"xol\");
+        print hash(list) == hash(list);
+        print type(hash(nil));");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, "true\ntrue\nfalse\ntrue\nnumber\n");
+    }
+
+    #[cfg(feature = "hashing")]
+    #[test]
+    fn test_digests() {
+        let outcome = run_and_capture("
+        print sha256(\"\");
+        print sha256(\"abc\");
+        print md5(\"\");
+        print md5(\"The quick brown fox jumps over the lazy dog\");");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n\
+            ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\n\
+            d41d8cd98f00b204e9800998ecf8427e\n\
+            9e107d9d372bb6826bd81d3542a419d6\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let outcome = run_and_capture("
+        var text = \"name,age\\nAda,36\\n\\\"Lovelace, A.\\\",\\n\";
+        print csvParse(text);
+        var people = csvParse(text, true);
+        print people[0].name + \" is \" + people[0].age;
+        print [people[1].age, type(people[1])];
+        print csvStringify([[\"id\", \"quote\"], [1, \"said \\\"hi\\\"\"], [2.5, nil]]);");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            outcome.stdout,
+            "[[name, age], [Ada, 36], [Lovelace, A., ]]\nAda is 36\n[, CsvRow]\nid,quote\n1,\"said \"\"hi\"\"\"\n2.5,\n\n"
+        );
+
+        let outcome = run_and_capture("csvParse(\"a\\n1,2\", true);");
+        assert_eq!(outcome.diagnostics[0].message, "Row 2 has more fields than the header.");
+        let outcome = run_and_capture("csvParse(\"\\\"a\");");
+        assert_eq!(outcome.diagnostics[0].message, "Unterminated quoted field on line 1.");
+        let outcome = run_and_capture("csvStringify([1]);");
+        assert_eq!(outcome.diagnostics[0].message, "csvStringify expects a list of lists.");
+    }
+
+    #[test]
+    fn test_platform_info() {
+        // chdir only goes where the process already is, since tests share it.
+        let outcome = run_and_capture("
+        print platform();
+        var here = cwd();
+        chdir(here);
+        print cwd() == here;
+        print len(hostname()) > 0;");
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(outcome.stdout, format!("{}\ntrue\ntrue\n", std::env::consts::OS));
+
+        let outcome = run_and_capture("chdir(\"/no/such/lox/directory\");");
+        assert!(outcome.diagnostics[0].message.starts_with("Could not change directory to '/no/such/lox/directory': "));
+    }
+
+    #[test]
+    fn test_unit_tests() {
+        let s = "
+        fun add(a, b) { return a + b; }
+        fun adds() { assertEq(add(1, 2), 3); assertTrue(add(1, 1) == 2); }
+        test(\"adds\", adds);
+        fun wrong() {
+            assertEq(add(2, 2), 5);
+            print \"unreachable\";
+        }
+        test(\"compares\", wrong);
+        fun throws() { throw Error(\"boom\"); }
+        test(\"throws\", throws);
+        fun falsey() { assertTrue(nil); }
+        test(\"falsey\", falsey);
+        var after = true;";
+        let mut ast = scan_parse(s);
+        resolver::Resolver::new().run(&mut ast).unwrap();
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.collect_test_results();
+        interpreter.run(ast).unwrap();
+        assert_eq!(interpreter.global("after"), Some(Value::Boolean(true)));
+        let results = interpreter.take_test_results();
+        let summary: Vec<_> = results
+            .iter()
+            .map(|result| (result.name.as_str(), result.line, result.failure.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("adds", 3, None),
+                ("compares", 8, Some(error::Diagnostic::new(5, "assertEq failed: expected 5, got 4."))),
+                ("throws", 10, Some(error::Diagnostic::new(9, "Uncaught exception: boom"))),
+                ("falsey", 12, Some(error::Diagnostic::new(11, "assertTrue failed: got nil."))),
+            ]
+        );
+        assert!(interpreter.take_test_results().is_empty());
+
+        // A reset discards results not yet taken, and keeps collecting.
+        interpreter.run(scan_parse("fun t() { assertTrue(nil); } test(\"t\", t);")).unwrap();
+        interpreter.reset();
+        assert!(interpreter.take_test_results().is_empty());
+        interpreter.run(scan_parse("fun t() { assertTrue(nil); } test(\"t\", t);")).unwrap();
+        assert_eq!(interpreter.take_test_results().len(), 1);
+
+        // Without collecting, a failing test stops the script like any error.
+        let outcome = run_and_capture("fun t() { assertEq(1, 2); } test(\"t\", t); print \"after\";");
+        assert_eq!(outcome.stdout, "");
+        assert_eq!(outcome.diagnostics[0].message, "assertEq failed: expected 2, got 1.");
+        let outcome = run_and_capture("fun t() { print \"ran\"; } test(\"t\", t);");
+        assert_eq!(outcome.stdout, "ran\n");
+        assert!(outcome.diagnostics.is_empty());
+
+        let outcome = run_and_capture("test(\"t\", 1);");
+        assert_eq!(outcome.diagnostics[0].message, "test expects a name and a function.");
+    }
+
+    #[test]
+    fn test_queues_and_stacks() {
+        let s = "
+        var q = Queue(1, 2);
+        q.push(3);
+        var stack = Stack(1, 2);
+        stack.push(3);
+        print q;
+        print stack;
+        print [q.peek(), q.pop(), q.pop(), len(q)];
+        print [stack.peek(), stack.pop(), stack.pop(), len(stack)];
+        print [q.isEmpty(), Queue().isEmpty(), type(q), type(stack)];
+        print [Queue(1, 2) == Queue(1, 2), Queue(1) == Stack(1), q == Queue(3)];
+        print jsonStringify([Queue(1, 2), Stack(1, 2)]);";
+        let outcome = run_and_capture(s);
+        assert!(outcome.diagnostics.is_empty(), "{:?}", outcome.diagnostics);
+        assert_eq!(
+            outcome.stdout,
+            "Queue[1, 2, 3]\nStack[3, 2, 1]\n[1, 1, 2, 1]\n[3, 3, 2, 1]\n\
+             [false, true, queue, stack]\n[true, false, true]\n[[1,2],[2,1]]\n"
+        );
+
+        let cases = [
+            ("Queue().pop();", "Can't pop from an empty queue."),
+            ("Stack().peek();", "Can't peek at an empty stack."),
+            ("Stack().shift();", "Undefined stack method."),
+        ];
+        for (source, message) in cases {
+            let outcome = run_and_capture(source);
+            assert_eq!(outcome.diagnostics[0].message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_mem_stats() {
+        let s = "
+        class Node {
+            init() { this.next = nil; }
+        }
+        fun churn() { var a = Node(); var b = Node(); a.next = b; }
+        fun leak() { var a = Node(); var b = Node(); a.next = b; b.next = a; }
+        var before = memStats();
+        churn();
+        var churned = memStats();
+        leak();
+        var leaked = memStats();
+        print [churned.objects - before.objects, leaked.objects - churned.objects];
+        print [churned.classes - before.classes, churned.environments - before.environments];";
+        let outcome = run_and_capture(s);
+        assert!(outcome.diagnostics.is_empty(), "{:?}", outcome.diagnostics);
+        // Each memStats() result is itself a live instance of a class of its
+        // own. Only the pair of Nodes pointing at each other outlives its call.
+        assert_eq!(outcome.stdout, "[1, 3]\n[1, 0]\n");
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let s = "
+        class Node {
+            init() { this.next = nil; }
+            method() { return this; }
+        }
+        fun pair() { var a = Node(); var b = Node(); a.next = b; b.next = a; }
+        fun bound() { var node = Node(); node.next = node.method; }
+        fun recursive() { fun f() { return f; } }
+        fun nested() { var list = []; push(list, list); }
+        var kept = Node();
+        kept.next = kept;
+        var before = memStats();
+        pair();
+        bound();
+        recursive();
+        nested();
+        var leaked = memStats();
+        print collect();
+        var after = memStats();
+        print [leaked.objects - before.objects, after.objects - before.objects];
+        print after.environments - before.environments;
+        print kept.next == kept and kept.next.next == kept;
+        print collect();
+        fun running() { var node = Node(); node.next = node; collect(); return node.next == node; }
+        print running();
+        print memStats().collections - before.collections;";
+        let outcome = run_and_capture(s);
+        assert!(outcome.diagnostics.is_empty(), "{:?}", outcome.diagnostics);
+        // Two nodes, a node and its bound method's scope, a function's scope
+        // and a list. The memStats() results before and leaked stay live.
+        assert_eq!(outcome.stdout, "6\n[4, 2]\n0\ntrue\n0\ntrue\n3\n");
+    }
+
+    #[test]
+    fn test_bytecode_backend() {
+        use interpreter::Backend;
+
+        let s = "
+        fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        fun square(x) { return x * x; }
+        var total = 0;
+        outer: for (var i = 0; i < 5; i = i + 1) {
+            var j = 0;
+            while (true) {
+                j = j + 1;
+                if (j > i) continue outer;
+                if (i * j == 6) break outer;
+                total = total + i * j;
+            }
+        }
+        var xs = [1, 2, 3];
+        xs[1] = \"two\";
+        print fib(15);
+        print total;
+        print xs;
+        print map([1, 2, 3], square);
+        print 1 < 2 < 3 and !(nil ?? false);
+        print \"a\" + str(len(xs));";
+        let walked = run_and_capture(s);
+        let compiled = run_and_capture_on(s, Backend::Bytecode);
+        assert!(compiled.diagnostics.is_empty(), "{:?}", compiled.diagnostics);
+        assert_eq!(compiled.stdout, "610\n10\n[1, two, 3]\n[1, 4, 9]\ntrue\na3\n");
+        assert_eq!(compiled.stdout, walked.stdout);
+        assert_eq!(compiled.value_of("total"), Some(Value::Integer(10)));
+
+        // Runtime errors keep their lines.
+        let s = "fun f(a) { return a; }\nprint f(1);\nprint f();\nprint 1 + nil;";
+        let outcome = run_and_capture_on(s, Backend::Bytecode);
+        assert_eq!(outcome.stdout, "1\n");
+        let message = &outcome.diagnostics[0].message;
+        assert_eq!((outcome.diagnostics[0].line, message.as_str()), (2, "Arity mismatch: declaration f expected 1 arguments, received 0."));
+
+        // Classes are left to the tree-walker.
+        let classes = "class A { get() { return 1; } }\nprint A().get();";
+        let result = compiler::compile(&scan_parse(classes));
+        assert_eq!(result.unwrap_err(), compiler::Unsupported { construct: "classes", line: 0 });
+        assert_eq!(run_and_capture_on(classes, Backend::Bytecode).stdout, "1\n");
+        let mut closure = scan_parse("fun f() { var a = 1; fun g() { return a; } return g; }\nprint f()();");
+        resolver::Resolver::new().run(&mut closure).unwrap();
+        let script = compiler::compile(&closure).unwrap();
+        assert!(script.chunk.disassemble(&script.name).contains("<fn g> [local 0]"));
+
+        let script = compiler::compile(&scan_parse("var a = -1 + 2;")).unwrap();
+        assert_eq!(
+            script.chunk.disassemble(&script.name),
+            "== script ==\n0000    0 Constant 0 '1'\n0001    | Negate\n0002    | Constant 1 '2'\n\
+             0003    | Binary Plus\n0004    | DefineGlobal 'a'\n0005    | Nil\n0006    | Return\n"
+        );
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_backend(Backend::Bytecode);
+        interpreter.set_timeout(Some(std::time::Duration::from_millis(50)));
+        let result = interpreter.run(scan_parse("while (true) {}"));
+        assert_eq!(result, Err(interp_error::InterpError::Timeout));
+    }
+
+    #[test]
+    fn test_bytecode_closures() {
+        use interpreter::Backend;
+
+        let s = "
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+        }
+        var a = counter();
+        var b = counter();
+        a(); a();
+        print [a(), b()];
+
+        fun outer() {
+            var x = \"outer\";
+            fun middle() {
+                fun inner() { return x; }
+                return inner;
+            }
+            x = \"changed\";
+            return middle;
+        }
+        print outer()()();
+
+        fun adders() {
+            var result = [];
+            for (var i = 0; i < 3; i = i + 1) {
+                var j = i;
+                fun add(n) { return n + j; }
+                push(result, add);
+            }
+            return result;
+        }
+        var fs = adders();
+        print [fs[0](10), fs[1](10), fs[2](10)];
+
+        fun scaled(xs, factor) {
+            fun scale(x) { return x * factor; }
+            return map(xs, scale);
+        }
+        print scaled([1, 2, 3], 10);
+
+        fun countdown(n) {
+            fun go(k) { if (k == 0) return \"done\"; return go(k - 1); }
+            return go(n);
+        }
+        print countdown(5);
+
+        fun early() {
+            var fs = [];
+            while (true) {
+                var v = len(fs);
+                fun get() { return v; }
+                push(fs, get);
+                if (len(fs) == 2) break;
+            }
+            return fs;
+        }
+        var gs = early();
+        print [gs[0](), gs[1]()];";
+        let walked = run_and_capture(s);
+        let compiled = run_and_capture_on(s, Backend::Bytecode);
+        assert!(compiled.diagnostics.is_empty(), "{:?}", compiled.diagnostics);
+        assert_eq!(compiled.stdout, "[3, 1]\nchanged\n[10, 11, 12]\n[10, 20, 30]\ndone\n[0, 1]\n");
+        assert_eq!(compiled.stdout, walked.stdout);
+
+        // Each closure captures only what it uses, and shares it with closures
+        // capturing the same variable.
+        let mut ast = scan_parse("fun f() { var a = 1; var b = 2; fun g() { a = a + 1; } fun h() { return a; } }");
+        resolver::Resolver::new().run(&mut ast).unwrap();
+        let script = compiler::compile(&ast).unwrap();
+        let listing = script.chunk.disassemble(&script.name);
+        assert!(listing.contains("<fn g> [local 0]"), "{}", listing);
+        assert!(listing.contains("<fn h> [local 0]"), "{}", listing);
+    }
+
+    #[test]
+    fn test_property_cache() {
+        // One access site sees instances of several classes in turn, so its
+        // cache is replaced each time the class differs.
+        let outcome = run_and_capture(
+            "
+            class A { name() { return \"a\"; } kind { return \"getter\"; } }
+            class B < A { name() { return \"b\"; } }
+            class C < A {}
+            fun field() { return \"field\"; }
+            var shadowed = A();
+            shadowed.name = field;
+            for (var i = 0; i < 2; i = i + 1) {
+                var results = [];
+                for (var x in [A(), B(), C(), shadowed, A()]) {
+                    push(results, x.name() + \"/\" + x?.kind);
+                }
+                print results;
+            }
+            fun missing(x) { return x.nothing; }
+            print missing(A());",
+        );
+        let line = "[a/getter, b/getter, a/getter, field/getter, a/getter]\n";
+        assert_eq!(outcome.stdout, line.repeat(2));
+        assert_eq!(outcome.diagnostics[0].message, "Property not found on object.");
+    }
+
+    #[test]
+    fn test_method_table() {
+        let s = "
+        class Base {
+            name() { return \"base\"; }
+            greet() { return \"hi from \" + this.name(); }
+            set label(value) { this.text = \"base \" + value; }
+        }
+        class Loud { shout() { return this.name() + \"!\"; } }
+        class Middle < Base { name() { return \"middle\"; } }
+        class Leaf < Middle with Loud {}
+        var leaf = Leaf();
+        leaf.label = \"leaf\";
+        print [leaf.greet(), leaf.shout(), leaf.text];
+        print methods(Leaf);
+        collect();
+        print [Loud().shout == nil, Leaf().shout()];";
+        let outcome = run_and_capture(s);
+        assert!(outcome.diagnostics.is_empty(), "{:?}", outcome.diagnostics);
+        // Leaf has its own copies of the methods it mixes in, and collecting
+        // leaves both classes working.
+        assert_eq!(outcome.stdout, "[hi from middle, middle!, base leaf]\n[greet, name, shout]\n[false, middle!]\n");
+    }
+
+    #[test]
+    fn test_verbosity() {
+        use interpreter::{Interpreter, Verbosity};
+
+        assert_eq!(Interpreter::new().verbosity(), Verbosity::Quiet);
+        // Tracing goes to standard error, leaving the script's output alone.
+        let s = "class A { init() { this.x = 1; } } class B < A {} print B().x;";
+        let outcome = run_and_capture_with(s, |interpreter| interpreter.set_verbosity(Verbosity::Trace));
+        assert_eq!(outcome.stdout, run_and_capture(s).stdout);
+        assert_eq!(outcome.stdout, "1\n");
+    }
+
+    #[test]
+    fn test_profiler() {
+        let s = "
+        fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        class A { twice(n) { return 2 * n; } }
+        print A().twice(fib(10));
+        ";
+        assert!(run_and_capture(s).interpreter().profiler().is_none());
+        let outcome = run_and_capture_with(s, |interpreter| interpreter.set_profiling(true));
+        assert_eq!(outcome.stdout, "110\n");
+        let profiler = outcome.interpreter().profiler().unwrap();
+        let functions = profiler.functions();
+        let fib = functions.iter().find(|function| function.name == "fib").unwrap();
+        assert_eq!((fib.calls, fib.line), (177, Some(1)));
+        let twice = functions.iter().find(|function| function.name == "twice").unwrap();
+        assert_eq!(twice.calls, 1);
+        assert!(profiler.report().contains("fib [line 1]"));
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        let limit = |interpreter: &mut interpreter::Interpreter| interpreter.set_max_call_depth(50);
+        let s = "
+        fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+        print depth(49);
+        print depth(50);
+        ";
+        let outcome = run_and_capture_with(s, limit);
+        assert_eq!(outcome.stdout, "49\n");
+        assert_eq!(outcome.diagnostics[0].message, "Stack overflow.");
+        assert_eq!(outcome.diagnostics[0].line, 1);
+
+        // The depth unwinds with the error, so calls work again once it is caught.
+        let s = "
+        fun forever() { return forever(); }
+        class Nested { init() { Nested(); } }
+        try { forever(); } catch (e) { print e.message; }
+        try { Nested(); } catch (e) { print e.message; }
+        fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+        print depth(49);
+        ";
+        assert_eq!(run_and_capture_with(s, limit).stdout, "Stack overflow.\nStack overflow.\n49\n");
+
+        // Getters, setters and equals are calls too.
+        for s in [
+            "class A { x { return this.x; } } print A().x;",
+            "class A { init() { this.y = 1; } set y(v) { this.y = v; } } A();",
+            "class P { equals(o) { return this == o; } } print P() == P();",
+        ] {
+            let outcome = run_and_capture_with(s, limit);
+            assert_eq!(outcome.diagnostics[0].message, "Stack overflow.", "{}", s);
+        }
+
+        // The VM's frames count against the same limit.
+        let s = "
+        fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+        print depth(49);
+        print depth(50);
+        ";
+        let outcome = run_and_capture_with(s, |interpreter| {
+            interpreter.set_backend(interpreter::Backend::Bytecode);
+            interpreter.set_max_call_depth(50);
+        });
+        assert_eq!(outcome.stdout, "49\n");
+        assert_eq!(outcome.diagnostics[0].message, "Stack overflow.");
+    }
+
+    #[test]
+    fn test_fuel() {
+        use interp_error::InterpError;
+        use interpreter::Backend;
+        use std::sync::atomic::Ordering;
+
+        // Running out of fuel skips finally bodies, as a timeout does.
+        let s = "print 1; try { while (true) {} } finally { print 2; }";
+        let outcome = run_and_capture_with(s, |interpreter| interpreter.set_fuel(Some(100)));
+        assert_eq!(outcome.stdout, "1\n");
+        assert_eq!(outcome.diagnostics[0].message, "Execution ran out of fuel.");
+        assert_eq!(outcome.interpreter().fuel(), Some(0));
+
+        // Each statement and expression costs one.
+        let outcome = run_and_capture_with("var a = 1; print a + a;", |interpreter| interpreter.set_fuel(Some(10)));
+        assert_eq!((outcome.stdout.as_str(), outcome.interpreter().fuel()), ("2\n", Some(5)));
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.set_backend(Backend::Bytecode);
+        interpreter.set_fuel(Some(1000));
+        assert_eq!(interpreter.run(scan_parse("while (true) {}")), Err(InterpError::OutOfFuel));
+
+        // Another thread stops an endless loop, and the flag is cleared for the next run.
+        let mut interpreter = interpreter::Interpreter::new();
+        let interrupt = interpreter.interrupt();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            interrupt.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(interpreter.run(scan_parse("while (true) {}")), Err(InterpError::Interrupted));
+        interrupter.join().unwrap();
+        assert_eq!(interpreter.run(scan_parse("var a = 1;")), Ok(()));
+        interpreter.interrupt().store(true, Ordering::Relaxed);
+        interpreter.set_backend(Backend::Bytecode);
+        assert_eq!(interpreter.run(scan_parse("while (true) {}")), Err(InterpError::Interrupted));
+
+        // A sleeping script is interrupted without waiting out the sleep.
+        let interrupt = interpreter.interrupt();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            interrupt.store(true, Ordering::Relaxed);
+        });
+        let start = std::time::Instant::now();
+        assert_eq!(interpreter.run(scan_parse("sleep(60000);")), Err(InterpError::Interrupted));
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        interrupter.join().unwrap();
+    }
+
+    #[test]
+    fn test_lists_containing_themselves() {
+        let s = "
+        var a = [1];
+        push(a, a);
+        var b = [1];
+        push(b, b);
+        print a;
+        print a == a;
+        print a == b;
+        print a == [1, [1]];
+        var q = Queue();
+        q.push(q);
+        print q;
+        ";
+        assert_eq!(run_and_capture(s).stdout, "[1, [...]]\ntrue\ntrue\nfalse\nQueue[Queue[...]]\n");
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let s = "
+        fun sign(n) {
+            if (n < 0) return -1; else { return 1; }
+            print \"after if\";
+        }
+        fun first(xs) {
+            for (var i = 0; i < len(xs); i = i + 1) {
+                return xs[i];
+                print \"after return\";
+            }
+        }
+        if (1 > 2) print \"folded away\";
+        else print \"else\";
+        while (false) print \"never\";
+        if (!nil) print \"kept\";
+        print [sign(-5), sign(5), first([7, 8])];";
+        let outcome = run_and_capture(s);
+        assert!(outcome.diagnostics.is_empty(), "{:?}", outcome.diagnostics);
+        assert_eq!(outcome.stdout, "else\nkept\n[-1, 1, 7]\n");
+        let lines: Vec<_> = outcome.warnings.iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, vec![3, 8, 11, 13]);
+        assert!(outcome.warnings.iter().all(|warning| warning.message == "Unreachable code."));
+
+        // Nothing reachable is reported.
+        let outcome = run_and_capture("var x = 1; if (x > 0) print x; while (x < 3) x = x + 1;");
+        assert!(outcome.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_shared_strings() {
+        let interpreter = test_run("var a = \"hello\"; var b = a; var xs = [b]; var c = xs[0]; var d = a + \"!\";");
+        let (Some(Value::StringV(a)), Some(Value::StringV(c))) = (interpreter.global("a"), interpreter.global("c")) else {
+            panic!("Expected strings.");
+        };
+        // Assigning and storing a string shares it rather than copying it.
+        assert!(std::rc::Rc::ptr_eq(&a, &c));
+        assert_eq!(interpreter.global("d"), Some(Value::new_string("hello!")));
+        assert_eq!(Value::new_string("hello"), Value::StringV(a));
+    }
+
+    #[test]
+    fn test_slot_locals() {
+        // Closures, recursion, shadowing and super all read locals by slot.
+        let outcome = run_and_capture(
+            "
+            fun counter() {
+                var count = 0;
+                fun next() {
+                    count = count + 1;
+                    return count;
+                }
+                return next;
+            }
+            var next = counter();
+            next();
+            print next();
+            fun fib(n) {
+                if (n < 2) return n;
+                var a = fib(n - 1);
+                var b = fib(n - 2);
+                return a + b;
+            }
+            print fib(10);
+            {
+                var x = 1;
+                {
+                    var y = x;
+                    var x = y + 1;
+                    print x + y;
+                }
+                print x;
+            }
+            class A {
+                init(v) { this.v = v; }
+                get() { return this.v; }
+            }
+            class B < A {
+                get() { return super.get() * 2; }
+            }
+            print B(4).get();",
+        );
+        assert_eq!(outcome.stdout, "2\n55\n3\n1\n8\n");
+    }
+
+    #[test]
+    fn test_class_printing() {
+        let outcome = run_and_capture("
+        class Foo {}
+        class Point {
+            init(x) {
+                this.x = x;
+            }
+            toString() {
+                return \"Point(\" + this.x + \")\";
+            }
+        }
+        print Foo;
+        print Foo();
+        print Point(\"1\");");
+        assert_eq!(outcome.stdout, "<class Foo>\n<instance of Foo>\nPoint(1)\n");
+
+        let outcome = run_and_capture("class Foo { toString() { return 1; } } print Foo();");
+        assert_eq!(outcome.diagnostics[0].message, "toString must return a string.");
+
+        let outcome = run_and_capture("
+        class Foo {}
+        class Point {
+            toString() {
+                return \"Point\";
+            }
+        }
+        print \"at \" + Point();
+        print Point() + \"!\";
+        print \"a \" + Foo();");
+        assert_eq!(outcome.stdout, "at Point\nPoint!\na <instance of Foo>\n");
+        let outcome = run_and_capture("class Foo {} print Foo() + Foo();");
+        assert_eq!(outcome.diagnostics[0].message, "Invalid operation.");
+    }
+
+    #[test]
+    fn test_this() {
+        let s = "
+        class Foo {
+            f() {
+                this.bar = 1;
+                return this.bar;
+            }
+        }
+        var foo = Foo();
+        var a = foo.f();";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_this2() {
+        let s = "
+        class Foo {
+            init() {
+                this.a = 1;
+            }
+
+            do_thing() {
+                return this.a;
+            }
+        }
+
+        var foo1 = Foo();
+        var foo2 = Foo();
+        foo2.a = 2;
+        foo2.do_thing = foo1.do_thing;
+        var a = foo2.do_thing();";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+    
+    #[test]
+    fn test_this3() {
+        let s = "
+        class Foo {
+            do_thing() {
+                return this.a;
+            }
+        }
+
+        var foo = Foo();
+        foo.a = 1;
+        var a = foo.do_thing();";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_closure() {
+        let s = "
+        fun create_closure() {
+            var a = 1;
+            fun closure() {
+                return a;
+            }
+            return closure;
+        }
+        var my_closure = create_closure();
+        var a = my_closure();";
+        let a = test_interpret(s, "a");
+        assert!(matches!(a, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_init1() {
+        let s = "
+        class A {
+            init() {
+                this.field = 1;
+            }
+        }
+        var a = A();
+        var b = a.field;";
+        let b = test_interpret(s, "b");
+        assert!(matches!(b, Value::Integer(n) if n == 1));
+    }
+
+    #[test]
+    fn test_init2() {
+        let s = "
+        class A {
+            init() {
+                this.field = 1;
+            }
+        }
+        var a = A();
+        var object = a.init();
+        var b = a.field + object.field;";
+        let b = test_interpret(s, "b");
+        assert!(matches!(b, Value::Integer(n) if n == 2));
+
+    }
+
+    #[test]
+    fn test_super_class() {
+        let s = "
+        class B {
+            do_thing() {
+                return 5;
+            }
+        }
+        class A < B {}
+
+        var a = A();
+        var c = a.do_thing();";
+        let c = test_interpret(s, "c");
+        assert!(matches!(c, Value::Integer(n) if n == 5));
+    }
+
+    #[test]
+    fn test_super_call() {
+        let s = "
+        class B {
+            do_thing() {
+                return 5;
+            }
+        }
+        class A < B {
+            do_thing() {
+                return super.do_thing() + 1;
+            }
+        }
+        var a = A();
+        var c = a.do_thing();";
+        let c = test_interpret(s, "c");
+        assert!(matches!(c, Value::Integer(n) if n == 6));
+    }
+}
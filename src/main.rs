@@ -1,40 +1,60 @@
-use io::Write;
-use std::{env, fs, io};
-
-#[macro_use]
-extern crate maplit;
-
-mod ast;
-mod environment;
-mod error;
-mod interp_error;
-mod interpreter;
-mod parser;
-mod resolver;
-mod scanner;
-mod test_utils;
-mod token;
-mod value;
-
-use interpreter::Interpreter;
-use parser::Parser;
-use resolver::Resolver;
-use scanner::Scanner;
-
-fn run(source: String, interpreter: &mut Interpreter) {
+use std::sync::atomic::Ordering;
+use std::{env, process, thread};
+
+use lox_interpreter::compiler;
+use lox_interpreter::dead_code::eliminate_dead_code;
+use lox_interpreter::fold::fold_constants;
+use lox_interpreter::interp_error::InterpError;
+use lox_interpreter::interpreter::{Backend, Interpreter, TestResult, Verbosity};
+use lox_interpreter::parser::Parser;
+use lox_interpreter::resolver::Resolver;
+use lox_interpreter::scanner::Scanner;
+use lox_interpreter::token::{dump_tokens, load_tokens, Token};
+use signal_hook::consts::SIGINT;
+
+fn run(source: String, interpreter: &mut Interpreter, warnings: bool) {
     let mut scanner = Scanner::new(source);
     let tokens = scanner.scan_tokens();
-    println!("{:?}", tokens);
+    run_tokens(tokens, interpreter, warnings);
+}
+
+// warnings prints the unreachable code removed before running.
+fn run_tokens(tokens: Vec<Token>, interpreter: &mut Interpreter, warnings: bool) {
+    let verbose = interpreter.verbosity() >= Verbosity::Verbose;
+    if verbose {
+        eprintln!("{:?}", tokens);
+    }
     let mut parser = Parser::new(tokens);
 
     if let Ok(mut ast) = parser.parse() {
-        println!("Parsed successfully.");
-        println!("{:?}", ast);
+        if verbose {
+            eprintln!("{:?}", ast);
+        }
         let mut resolver = Resolver::new();
         match resolver.run(&mut ast) {
             Ok(()) => {
-                if let Err(err) = interpreter.run(ast) {
-                    println!("{:?}", err);
+                fold_constants(&mut ast);
+                let unreachable = eliminate_dead_code(&mut ast);
+                if warnings {
+                    for warning in unreachable {
+                        eprintln!("Warning: {}", warning);
+                    }
+                }
+                // Scripts the compiler can't handle fall back to the tree-walker,
+                // which someone asking for the VM should hear about.
+                if interpreter.backend() == Backend::Bytecode {
+                    if let Err(unsupported) = compiler::compile(&ast) {
+                        eprintln!("{} Running the script on the tree-walker instead.", unsupported);
+                    }
+                }
+                let result = interpreter.run(ast);
+                if let Some(profiler) = interpreter.profiler() {
+                    eprint!("{}", profiler.report());
+                }
+                match result {
+                    Ok(()) => {}
+                    Err(InterpError::Exit(code)) => process::exit(code),
+                    Err(err) => println!("{:?}", err),
                 }
             }
             Err(err) => println!("{:?}", err),
@@ -44,394 +64,181 @@ fn run(source: String, interpreter: &mut Interpreter) {
     }
 }
 
-fn run_file(file: &String) {
-    let contents = fs::read_to_string(file).expect("Expected file.");
-    println!("{}", contents);
-}
-
-fn run_prompt() {
-    println!("interactive lox");
+fn run_file(
+    file: &str,
+    arguments: &[String],
+    emit_tokens: Option<&String>,
+    backend: Backend,
+    warnings: bool,
+    verbosity: Verbosity,
+    profile: bool,
+) {
     let mut interpreter = Interpreter::new();
-    loop {
-        print!(">");
-        io::stdout().flush().unwrap();
-        let mut line = String::new();
-        io::stdin()
-            .read_line(&mut line)
-            .expect("Failed to read line");
-        run(line, &mut interpreter);
-    }
+    interpreter.set_verbosity(verbosity);
+    interpreter.set_profiling(profile);
+    interpreter.set_backend(backend);
+    interpreter.set_script_path(file);
+    interpreter.set_arguments(arguments.to_vec());
+    let contents = interpreter.platform().read_file(file).expect("Expected file.");
+    if let Some(token_file) = emit_tokens {
+        let tokens = Scanner::new(contents.clone()).scan_tokens();
+        interpreter
+            .platform()
+            .write_file(token_file, &dump_tokens(&tokens))
+            .expect("Could not write token file.");
+    }
+    run(contents, &mut interpreter, warnings);
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    match &args[..] {
-        [_] => run_prompt(),
-        [_, file] => run_file(file),
-        _ => println!("Usage: lox [script]"),
+// Runs a script of test() calls, reporting each one and exiting with status 1
+// if any failed.
+fn run_tests(file: &str, arguments: &[String], verbosity: Verbosity) {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_verbosity(verbosity);
+    interpreter.set_script_path(file);
+    interpreter.set_arguments(arguments.to_vec());
+    interpreter.collect_test_results();
+    let contents = interpreter.platform().read_file(file).expect("Expected file.");
+    run(contents, &mut interpreter, false);
+    let results = interpreter.take_test_results();
+    report_tests(&results);
+    if results.iter().any(|result| result.failure.is_some()) {
+        process::exit(1);
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use interpreter::test_utils::test_interpret;
-    use test_utils::*;
-    use value::Value;
-
-    #[test]
-    fn test_arithmetic() {
-        let c = test_interpret("var a = 1; var b = 2; var c = a + b;", "c");
-        assert!(matches!(c, Value::Number(n) if n == 3.0));
-    }
-
-    #[test]
-    fn test_block() {
-        let s = "
-        var a = 1;
-        {
-            var a = 2;
-        }";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
-    }
-
-    #[test]
-    fn test_if() {
-        let a = test_interpret(
-            "
-            var a = 1;
-            if (1 == 1)
-                a = 2;",
-            "a",
-        );
-
-        assert_eq!(a, Value::Number(2.0));
-    }
-
-    #[test]
-    fn test_while() {
-        let s = "var a = 1;
-        while (a < 3)
-            a = a + 1;";
-        let a = test_interpret(s, "a");
-        assert_eq!(a, Value::Number(3.0));
-    }
-
-    #[test]
-    fn test_for() {
-        let s = "
-        var j = 0;
-        for (var i = 0;
-        i < 4;
-        i = i + 1)
-            j = j + i;";
-        let j = test_interpret(s, "j");
-        assert_eq!(j, Value::Number(6.0));
-    }
-
-    #[test]
-    fn test_logical_and() {
-        let s = "
-        var a = 0;
-        if (false and true)
-            a = 1;";
-        let a = test_interpret(s, "a");
-        assert_eq!(a, Value::Number(0.0));
-    }
-
-    #[test]
-    fn test_logical_or() {
-        let s = "
-        var a = 0;
-        if (false or true)
-            a = 1;";
-        let a = test_interpret(s, "a");
-        assert_eq!(a, Value::Number(1.0));
-    }
-
-    #[test]
-    fn test_call() {
-        let s = "
-        var a = clock();
-        var c = 0;
-        for (var i = 0; i < 10000; i = i + 1) {
-           c = c + 1; 
-        }
-        var b = clock() - a;";
-        let b = test_interpret(s, "b");
-        assert!(matches!(b, Value::Number(n) if n > 0.0));
-    }
-
-    #[test]
-    fn test_nested_call() {
-        let s = "
-        var a = 1;
-        fun foo() {
-            fun bar() {
-                a = 2;
-            }
-            bar();
-        }
-        fun bar() {
-        }
-        foo();";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 2.0));
-    }
-
-    #[test]
-    fn test_duplicate_vars() {
-        let s = "
-        var a = 1;
-        var a = 1;";
-        let _ = test_interpret(s, "a");
-    }
-
-    #[test]
-    fn test_nesting_function() {
-        let s = "
-        var a = 1;
-        fun make_a() {
-            var a = 2;
-            {
-                var a = 3;
-                return;
-            }
-        }";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
-    }
-    
-    #[test]
-    fn test_nesting_function2() {
-        let s = "
-        fun calc_b() {
-            var a = 2;
-            {
-                var a = 3;
-                return a;
-            }
-        }
-
-        var b = calc_b();";
-        let b = test_interpret(s, "b");
-        assert!(matches!(b, Value::Number(n) if n == 2.0));
-    }
-
-    #[test]
-    #[should_panic(expected = "Parse failed")]
-    fn test_var_in_loop() {
-        let s = "
-        var a = 1;
-        for (var i = 0; i < 2; i = i + 1) var a = 2;";
-        let _ = test_interpret(s, "a");
-
-    }
-
-    #[test]
-    fn test_simple_class() {
-        let s = "
-        class Foo {
-            method() {
-            }
-        }
-
-        var foo = Foo();
-        foo.method();";
-        let _ = test_run(s);
-    }
-
-    #[test]
-    fn test_class() {
-        let s = "
-        class Foo {
-            bar() {
-                class Foo {
-                    bar2() {
-                    }
-                }
-                var foo = Foo();
-                foo.bar2();
-
-            }
-        }
-
-        var foo = Foo();
-        foo.bar();";
-        let _ = test_run(s);
-    }
-
-
-    #[test]
-    fn test_method() {
-        let s = "
-        class Foo {
-            bar() {
-                return 1;
-            }
-        }
-        var foo = Foo();
-        var a = foo.bar();";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
-    }
-
-    #[test]
-    fn test_object_assign() {
-        let s = "
-        class Foo {
-        }
-
-        var foo = Foo();
-        foo.bar = 1;";
-        let _ = test_run(s);
-    }
-    
-    #[test]
-    fn test_object_assign2() {
-        let s = "
-        class Foo {
+fn report_tests(results: &[TestResult]) {
+    for result in results {
+        match &result.failure {
+            None => println!("PASS {} [line {}]", result.name, result.line),
+            Some(failure) => println!("FAIL {} [line {}]: {}", result.name, result.line, failure),
         }
-
-        var foo = Foo();
-        foo.bar = 1;
-        var a = foo.bar;";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
     }
+    let failed = results.iter().filter(|result| result.failure.is_some()).count();
+    println!("{} passed, {} failed.", results.len() - failed, failed);
+}
 
-    #[test]
-    fn test_this() {
-        let s = "
-        class Foo {
-            f() {
-                this.bar = 1;
-                return this.bar;
-            }
-        }
-        var foo = Foo();
-        var a = foo.f();";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
-    }
-
-    #[test]
-    fn test_this2() {
-        let s = "
-        class Foo {
-            init() {
-                this.a = 1;
-            }
-
-            do_thing() {
-                return this.a;
-            }
-        }
-
-        var foo1 = Foo();
-        var foo2 = Foo();
-        foo2.a = 2;
-        foo2.do_thing = foo1.do_thing;
-        var a = foo2.do_thing();";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
+// Prints the bytecode a script compiles to, without running it.
+fn disassemble(file: &str) {
+    let mut interpreter = Interpreter::new();
+    let contents = interpreter.platform().read_file(file).expect("Expected file.");
+    let tokens = Scanner::new(contents).scan_tokens();
+    let Ok(mut ast) = Parser::new(tokens).parse() else {
+        println!("Error while parsing.");
+        process::exit(65);
+    };
+    if let Err(err) = Resolver::new().run(&mut ast) {
+        println!("{:?}", err);
+        process::exit(65);
     }
-    
-    #[test]
-    fn test_this3() {
-        let s = "
-        class Foo {
-            do_thing() {
-                return this.a;
-            }
+    fold_constants(&mut ast);
+    eliminate_dead_code(&mut ast);
+    match compiler::compile(&ast) {
+        Ok(script) => print!("{}", script.chunk.disassemble(&script.name)),
+        Err(unsupported) => {
+            println!("{}", unsupported);
+            process::exit(1);
         }
-
-        var foo = Foo();
-        foo.a = 1;
-        var a = foo.do_thing();";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
     }
+}
 
-    #[test]
-    fn test_closure() {
-        let s = "
-        fun create_closure() {
-            var a = 1;
-            fun closure() {
-                return a;
-            }
-            return closure;
+// Replays a token stream saved with --emit-tokens, skipping the scanner.
+fn run_token_file(token_file: &str, verbosity: Verbosity) {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_verbosity(verbosity);
+    let contents = interpreter.platform().read_file(token_file).expect("Expected token file.");
+    match load_tokens(&contents) {
+        Ok(tokens) => run_tokens(tokens, &mut interpreter, false),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(65);
         }
-        var my_closure = create_closure();
-        var a = my_closure();";
-        let a = test_interpret(s, "a");
-        assert!(matches!(a, Value::Number(n) if n == 1.0));
     }
+}
 
-    #[test]
-    fn test_init1() {
-        let s = "
-        class A {
-            init() {
-                this.field = 1;
+fn run_prompt(verbosity: Verbosity) {
+    println!("interactive lox");
+    let mut interpreter = Interpreter::new();
+    interpreter.set_verbosity(verbosity);
+    // Ctrl-C stops the line being run, rather than the whole session.
+    let interrupt = interpreter.interrupt();
+    signal_hook::flag::register(SIGINT, interrupt.clone()).expect("Failed to handle Ctrl-C");
+    loop {
+        interpreter.platform().write_output(">").unwrap();
+        match interpreter.platform().read_line().expect("Failed to read line") {
+            Some(line) => {
+                interrupt.store(false, Ordering::Relaxed);
+                run(line, &mut interpreter, false)
             }
+            None => break,
         }
-        var a = A();
-        var b = a.field;";
-        let b = test_interpret(s, "b");
-        assert!(matches!(b, Value::Number(n) if n == 1.0));
     }
+}
 
-    #[test]
-    fn test_init2() {
-        let s = "
-        class A {
-            init() {
-                this.field = 1;
-            }
+// Takes the -v and --trace options, which come before any other, off the
+// front of the arguments.
+fn take_verbosity(args: &mut Vec<String>) -> Verbosity {
+    let mut verbosity = Verbosity::Quiet;
+    while args.len() > 1 {
+        match args[1].as_str() {
+            "-v" if verbosity == Verbosity::Quiet => verbosity = Verbosity::Verbose,
+            "-v" => {}
+            "--trace" => verbosity = Verbosity::Trace,
+            _ => break,
         }
-        var a = A();
-        var object = a.init();
-        var b = a.field + object.field;";
-        let b = test_interpret(s, "b");
-        assert!(matches!(b, Value::Number(n) if n == 2.0));
-
+        args.remove(1);
     }
+    verbosity
+}
 
-    #[test]
-    fn test_super_class() {
-        let s = "
-        class B {
-            do_thing() {
-                return 5;
-            }
-        }
-        class A < B {}
+// The native stack the interpreter runs on, enough for the default call depth
+// in a debug build.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
-        var a = A();
-        var c = a.do_thing();";
-        let c = test_interpret(s, "c");
-        assert!(matches!(c, Value::Number(n) if n == 5.0));
+fn main() {
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_command_line)
+        .expect("The interpreter's thread should start.");
+    if interpreter.join().is_err() {
+        process::exit(101);
     }
+}
 
-    #[test]
-    fn test_super_call() {
-        let s = "
-        class B {
-            do_thing() {
-                return 5;
-            }
-        }
-        class A < B {
-            do_thing() {
-                return super.do_thing() + 1;
-            }
+fn run_command_line() {
+    let mut args: Vec<String> = env::args().collect();
+    let verbosity = take_verbosity(&mut args);
+    match &args[..] {
+        [_] => run_prompt(verbosity),
+        [_, flag, token_file, file, arguments @ ..] if flag == "--emit-tokens" => {
+            run_file(file, arguments, Some(token_file), Backend::TreeWalker, false, verbosity, false)
+        }
+        [_, flag, token_file] if flag == "--from-tokens" => run_token_file(token_file, verbosity),
+        [_, flag, file, arguments @ ..] if flag == "--test" => run_tests(file, arguments, verbosity),
+        [_, flag, file, arguments @ ..] if flag == "--vm" => {
+            run_file(file, arguments, None, Backend::Bytecode, false, verbosity, false)
+        }
+        [_, flag, file, arguments @ ..] if flag == "--warnings" => {
+            run_file(file, arguments, None, Backend::TreeWalker, true, verbosity, false)
+        }
+        [_, flag, file, arguments @ ..] if flag == "--profile" => {
+            run_file(file, arguments, None, Backend::TreeWalker, false, verbosity, true)
+        }
+        [_, flag, file] if flag == "--disassemble" => disassemble(file),
+        [_, file, arguments @ ..] if !file.starts_with("--") => {
+            run_file(file, arguments, None, Backend::TreeWalker, false, verbosity, false)
+        }
+        _ => {
+            println!("Usage: lox [-v | --trace] [script [args...]] | lox --emit-tokens file.tok script [args...] | lox --from-tokens file.tok");
+            println!("       lox --test script [args...] runs a script's test() calls and reports which failed.");
+            println!("       lox --vm script [args...] runs a script on the bytecode VM; lox --disassemble script prints its bytecode.");
+            println!("       The VM does not support classes, exceptions, for-in, comprehensions, spreads, optional calls, imports,");
+            println!("       match, with or generators; --vm runs scripts using them on the tree-walker, saying so on standard error.");
+            println!("       lox --warnings script [args...] also reports unreachable code, which is never run.");
+            println!("       lox --profile script [args...] reports each function's calls and time when the script ends.");
+            println!("-v prints each script's tokens and syntax tree, and --trace also its calls, to standard error.");
+            println!("A script's arguments are in the global list ARGS.");
+            println!("A script's first line is skipped if it starts with #!, as in #!/usr/bin/env lox.");
         }
-        var a = A();
-        var c = a.do_thing();";
-        let c = test_interpret(s, "c");
-        assert!(matches!(c, Value::Number(n) if n == 6.0));
     }
 }
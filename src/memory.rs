@@ -0,0 +1,68 @@
+//! Counts of the instances, classes and environment scopes alive on this
+//! thread, for memStats(). Values are reference counted, so anything caught in
+//! a cycle, such as an instance whose field holds the instance itself, stays
+//! counted after the script lets go of it, until collect() frees it. The counts
+//! cover every interpreter on the thread.
+
+use std::cell::Cell;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub objects: usize,
+    pub classes: usize,
+    pub environments: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Object,
+    Class,
+    Environment,
+}
+
+thread_local! {
+    static LIVE: Cell<Counts> = Cell::new(Counts::default());
+}
+
+fn update(kind: Kind, change: fn(usize) -> usize) {
+    LIVE.with(|live| {
+        let mut counts = live.get();
+        let count = match kind {
+            Kind::Object => &mut counts.objects,
+            Kind::Class => &mut counts.classes,
+            Kind::Environment => &mut counts.environments,
+        };
+        *count = change(*count);
+        live.set(counts);
+    });
+}
+
+pub fn created(kind: Kind) {
+    update(kind, |count| count + 1);
+}
+
+pub fn dropped(kind: Kind) {
+    update(kind, |count| count.saturating_sub(1));
+}
+
+pub fn live() -> Counts {
+    LIVE.with(Cell::get)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let before = live();
+        created(Kind::Object);
+        created(Kind::Object);
+        created(Kind::Environment);
+        dropped(Kind::Object);
+        let after = live();
+        assert_eq!(after.objects, before.objects + 1);
+        assert_eq!(after.classes, before.classes);
+        assert_eq!(after.environments, before.environments + 1);
+    }
+}
@@ -1,13 +1,17 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
 use crate::ast::*;
-use crate::error::report;
+use crate::error::{report, Diagnostic};
 use crate::token::*;
 use TokenKind::*;
 
+/// A recursive descent parser over the scanner's tokens, read through a cursor
+/// so any token ahead of it can be looked at without consuming it.
 pub struct Parser {
-    tokens: VecDeque<Token>,
-    previous: Option<Token>,
+    tokens: Vec<Token>,
+    // The next token to consume.
+    current: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
 type ExprResult = Result<Expr, ParseErr>;
@@ -28,15 +32,16 @@ impl ParseErr {
         }
     }
 
-    fn report(&self) {
+    fn report(&self) -> Diagnostic {
         report(self.line, &self.message);
+        Diagnostic::new(self.line, &self.message)
     }
 }
 
 impl Parser {
     fn error(&mut self, message: &str) -> ParseErr {
         self.advance();
-        ParseErr::new(&self.previous(), &format!("Parse error: {}", message))
+        ParseErr::new(self.previous(), &format!("Parse error: {}", message))
     }
 
     fn synchronize(&mut self) {
@@ -48,13 +53,22 @@ impl Parser {
 
             match self.peek().kind {
                 TokenKind::Class
+                | TokenKind::Interface
+                | TokenKind::Const
                 | TokenKind::Fun
                 | TokenKind::Var
                 | TokenKind::For
                 | TokenKind::If
                 | TokenKind::While
                 | TokenKind::Print
-                | TokenKind::Return => {
+                | TokenKind::Return
+                | TokenKind::Assert
+                | TokenKind::Import
+                | TokenKind::Throw
+                | TokenKind::Try
+                | TokenKind::With
+                | TokenKind::Match
+                | TokenKind::Yield => {
                     return;
                 }
                 _ => {}
@@ -74,25 +88,30 @@ impl Parser {
     }
 
     fn is_at_end(&self) -> bool {
-        self.tokens.len() == 0
+        self.current >= self.tokens.len()
     }
 
     fn peek(&self) -> &Token {
-        &self.tokens[0]
+        &self.tokens[self.current]
     }
 
-    fn previous(&mut self) -> Token {
-        self.previous.clone().unwrap()
+    // The token distance places past the next one, if there is one.
+    fn peek_ahead(&self, distance: usize) -> Option<&Token> {
+        self.tokens.get(self.current + distance)
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current - 1]
     }
 
     fn advance(&mut self) {
-        if let Some(token) = self.tokens.pop_front() {
-            self.previous = Some(token);
+        if !self.is_at_end() {
+            self.current += 1;
         }
     }
 
-    fn equal(&mut self, types: Vec<TokenKind>) -> bool {
-        for t in types {
+    fn equal(&mut self, types: &[TokenKind]) -> bool {
+        for &t in types {
             if self.check(t) {
                 self.advance();
                 return true;
@@ -114,42 +133,79 @@ impl Parser {
     }
 
     fn primary(&mut self) -> ExprResult {
-        if self.equal(vec![False, True, Nil, Number, StringT]) {
-            Ok(Expr::new_literal(self.previous()))
-        } else if self.equal(vec![Identifier]) {
-            Ok(Expr::new_variable(self.previous()))
-        } else if self.equal(vec![LeftParen]) {
+        if self.equal(&[False, True, Nil, Number, StringT]) {
+            Ok(Expr::new_literal(self.previous().clone()))
+        } else if self.equal(&[Identifier]) {
+            Ok(Expr::new_variable(self.previous().clone()))
+        } else if self.equal(&[LeftParen]) {
             let expr = self.expression()?;
             // TODO: Switch to new way of handling errors.
             self.consume(TokenKind::RightParen, "Expected ')' after expression.")?;
-            Ok(Expr::new_grouping(self.previous(), expr))
-        } else if self.equal(vec![This]) {
-            Ok(Expr::new_this(self.previous()))
-        } else if self.equal(vec![Super]) {
-            let token = self.previous();
+            Ok(Expr::new_grouping(self.previous().clone(), expr))
+        } else if self.equal(&[LeftBracket]) {
+            let bracket = self.previous().clone();
+            let mut elements = Vec::new();
+            if !self.check(RightBracket) {
+                let element = self.element()?;
+                if self.check(For) {
+                    return self.comprehension(bracket, element);
+                }
+                elements.push(element);
+                while self.equal(&[Comma]) {
+                    elements.push(self.element()?);
+                }
+            }
+            self.consume(RightBracket, "Expected ']' after list elements")?;
+            Ok(Expr::new_list(bracket, elements))
+        } else if self.equal(&[This]) {
+            Ok(Expr::new_this(self.previous().clone()))
+        } else if self.equal(&[Super]) {
+            let token = self.previous().clone();
             self.consume(Dot, "Expect '.' after 'super'.")?;
             self.consume(Identifier, "Expected identifier after '.'")?;
-            let method = self.previous();
+            let method = self.previous().clone();
             Ok(Expr::new_super(method, token))
         } else {
             Err(self.error("Expected expression."))
         }
     }
 
+    // `class` names a property as well as starting a declaration, so that
+    // `instance.class` reads.
+    fn property_name(&mut self, message: &str) -> Result<Token, ParseErr> {
+        if self.equal(&[Class]) {
+            return Ok(Token { kind: Identifier, ..self.previous().clone() });
+        }
+        self.consume(Identifier, message)?;
+        Ok(self.previous().clone())
+    }
+
     fn call(&mut self) -> ExprResult {
         let mut expr = self.primary()?;
         loop {
-            if self.equal(vec![LeftParen]) {
+            if self.equal(&[LeftParen]) {
                 expr = self.finish_call(expr)?;
-            } else if self.equal(vec![Dot]) {
-                self.consume(Identifier, "Expected property name after '.'.")?;
-                let token = self.previous();
-                expr = if self.equal(vec![Equal]) {
+            } else if self.equal(&[Dot]) {
+                let token = self.property_name("Expected property name after '.'.")?;
+                expr = if self.equal(&[Equal]) {
                     let value = self.expression()?;
                     Expr::new_set(token, expr, value)
                 } else {
                     Expr::new_get(token, expr)
                 };
+            } else if self.equal(&[LeftBracket]) {
+                let index = self.expression()?;
+                self.consume(RightBracket, "Expected ']' after index")?;
+                let bracket = self.previous().clone();
+                expr = if self.equal(&[Equal]) {
+                    let value = self.expression()?;
+                    Expr::new_set_index(expr, index, value, bracket)
+                } else {
+                    Expr::new_index(expr, index, bracket)
+                };
+            } else if self.equal(&[QuestionDot]) {
+                let token = self.property_name("Expected property name after '?.'.")?;
+                expr = Expr::new_optional_get(token, expr);
             } else {
                 break;
             }
@@ -157,6 +213,36 @@ impl Parser {
         Ok(expr)
     }
 
+    // The rest of `[element for name in iterable if condition]`, from the
+    // 'for'.
+    fn comprehension(&mut self, bracket: Token, element: Expr) -> ExprResult {
+        if let ExprKind::Spread(_) = element.kind {
+            return Err(self.error("Can't spread the element of a comprehension."));
+        }
+        self.advance();
+        self.consume(Identifier, "Expected a variable name after 'for'.")?;
+        let name = self.previous().clone();
+        self.consume(In, "Expected 'in' after the comprehension's variable.")?;
+        let iterable = self.expression()?;
+        let condition = if self.equal(&[If]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(RightBracket, "Expected ']' after comprehension")?;
+        Ok(Expr::new_comprehension(bracket, element, name, iterable, condition))
+    }
+
+    // A list element or call argument, which may spread a list.
+    fn element(&mut self) -> ExprResult {
+        if self.equal(&[Ellipsis]) {
+            let ellipsis = self.previous().clone();
+            Ok(Expr::new_spread(ellipsis, self.expression()?))
+        } else {
+            self.expression()
+        }
+    }
+
     fn finish_call(&mut self, callee: Expr) -> ExprResult {
         let mut arguments = Vec::new();
         if !self.check(RightParen) {
@@ -164,19 +250,19 @@ impl Parser {
                 if arguments.len() >= 255 {
                     return Err(self.error("Can't have more than 255 arguments"));
                 }
-                arguments.push(self.expression()?);
-                if self.equal(vec![Comma]) {
+                arguments.push(self.element()?);
+                if !self.equal(&[Comma]) {
                     break;
                 }
             }
         }
         self.consume(RightParen, "Expected closing paren to follow argument list")?;
-        Ok(Expr::new_call(callee, arguments, self.previous()))
+        Ok(Expr::new_call(callee, arguments, self.previous().clone()))
     }
 
     fn unary(&mut self) -> ExprResult {
-        if self.equal(vec![Bang, Minus]) {
-            let operator = self.previous();
+        if self.equal(&[Bang, Minus]) {
+            let operator = self.previous().clone();
             let right = self.unary()?;
             Ok(Expr::new_unary(operator, right))
         } else {
@@ -186,9 +272,9 @@ impl Parser {
 
     fn factor(&mut self) -> ExprResult {
         let mut expr = self.unary()?;
-        while self.equal(vec![Slash, Star]) {
-            let operator = self.previous();
-            let right = self.factor()?;
+        while self.equal(&[Slash, Star]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
             let expr2 = Expr::new_binary(expr, operator, right);
             expr = expr2;
         }
@@ -197,8 +283,8 @@ impl Parser {
 
     fn term(&mut self) -> ExprResult {
         let mut expr = self.factor()?;
-        while self.equal(vec![Minus, Plus]) {
-            let operator = self.previous();
+        while self.equal(&[Minus, Plus]) {
+            let operator = self.previous().clone();
             let right = self.factor()?;
             let expr2 = Expr::new_binary(expr, operator, right);
             expr = expr2;
@@ -206,21 +292,45 @@ impl Parser {
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> ExprResult {
+    // Binds tighter than comparisons, so `i in 0..n` reads naturally, and looser
+    // than arithmetic, so `0..n + 1` does.
+    fn range(&mut self) -> ExprResult {
         let mut expr = self.term()?;
-        while self.equal(vec![Greater, GreaterEqual, Less, LessEqual]) {
-            let operator = self.previous();
-            let right = self.comparison()?;
-            let expr2 = Expr::new_binary(expr, operator, right);
-            expr = expr2;
+        while self.equal(&[DotDot, DotDotEqual]) {
+            let operator = self.previous().clone();
+            let right = self.term()?;
+            expr = Expr::new_binary(expr, operator, right);
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> ExprResult {
+        let mut expr = self.range()?;
+        while self.equal(&[Greater, GreaterEqual, Less, LessEqual, Is, In]) {
+            let operator = self.previous().clone();
+            let right = self.range()?;
+            let orderings = [Greater, GreaterEqual, Less, LessEqual];
+            // `a < b < c` compares b with both sides, rather than comparing a
+            // boolean with c.
+            if orderings.contains(&operator.kind) && orderings.iter().any(|kind| self.check(*kind)) {
+                let mut operands = vec![expr, right];
+                let mut operators = vec![operator];
+                while self.equal(&orderings) {
+                    operators.push(self.previous().clone());
+                    operands.push(self.range()?);
+                }
+                expr = Expr::new_chain(operands, operators);
+            } else {
+                expr = Expr::new_binary(expr, operator, right);
+            }
         }
         Ok(expr)
     }
 
     fn equality(&mut self) -> ExprResult {
         let mut expr = self.comparison()?;
-        while self.equal(vec![BangEqual, EqualEqual]) {
-            let operator = self.previous();
+        while self.equal(&[BangEqual, EqualEqual]) {
+            let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::new_binary(expr, operator, right);
         }
@@ -232,10 +342,12 @@ impl Parser {
     }
 
     fn if_statement(&mut self) -> StatementResult {
-        let cond = self.equality()?;
-        let true_branch = self.statement()?;
-        let else_branch = if self.equal(vec![Else]) {
-            let block = self.statement()?;
+        self.consume(LeftParen, "Expected '(' following 'if'")?;
+        let cond = self.expression()?;
+        self.consume(RightParen, "Expected ')' following condition")?;
+        let true_branch = self.body("if")?;
+        let else_branch = if self.equal(&[Else]) {
+            let block = self.body("else")?;
             Some(block)
         } else {
             None
@@ -245,9 +357,9 @@ impl Parser {
 
     fn while_statement(&mut self) -> StatementResult {
         self.consume(LeftParen, "Expected '(' following 'while'")?;
-        let cond = self.equality()?;
+        let cond = self.expression()?;
         self.consume(RightParen, "Expected ')' following condition")?;
-        let body = self.statement()?;
+        let body = self.body("while")?;
         Ok(Statement::new_while(cond, body))
     }
 
@@ -255,7 +367,7 @@ impl Parser {
         let mut declarations: Vec<Declaration> = Vec::new();
         // TODO: Currently, confusing error messages occur when right brace
         // is left off of block.
-        while !self.equal(vec![RightBrace]) {
+        while !self.equal(&[RightBrace]) {
             let new_element = self.declaration()?;
             declarations.push(new_element);
         }
@@ -276,9 +388,22 @@ impl Parser {
 
     fn for_statement(&mut self) -> StatementResult {
         self.consume(LeftParen, "Expected '(' following 'for'")?;
-        let initializer = if self.equal(vec![Semicolon]) {
+        let is_for_in = self.check(Var)
+            && self.peek_ahead(1).is_some_and(|token| token.kind == Identifier)
+            && self.peek_ahead(2).is_some_and(|token| token.kind == In);
+        if is_for_in {
+            self.advance();
+            self.advance();
+            let name = self.previous().clone();
+            self.advance();
+            let iterable = self.expression()?;
+            self.consume(RightParen, "Expected ')' following iterable")?;
+            let body = self.body("for")?;
+            return Ok(Statement::new_for_in(name, iterable, body));
+        }
+        let initializer = if self.equal(&[Semicolon]) {
             None
-        } else if self.equal(vec![Var]) {
+        } else if self.equal(&[Var]) {
             Some(Initializer::VarDeclaration(self.var_declaration()?))
         } else {
             Some(Initializer::Expr(self.expr_statement()?))
@@ -291,9 +416,13 @@ impl Parser {
         };
         self.consume_semicolon()?;
 
-        let increment = Some(self.expression()?);
+        let increment = if !self.check(RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
         self.consume(RightParen, "Expected ')' following condition")?;
-        let body = self.statement()?;
+        let body = self.body("for")?;
         Ok(Statement::new_for(initializer, cond, increment, body))
     }
 
@@ -307,19 +436,217 @@ impl Parser {
         Ok(Statement::Return(value))
     }
 
+    // The grammar only allows a statement as the body of a control flow
+    // statement, so a declaration there gets a dedicated error.
+    fn body(&mut self, keyword: &str) -> StatementResult {
+        let declaration = [Var, Const, Fun, Class, Interface];
+        if declaration.iter().any(|&kind| self.check(kind)) {
+            let message = format!(
+                "Expect expression; declarations are not allowed as the body of '{}'. Wrap it in a block.",
+                keyword
+            );
+            return Err(self.error(&message));
+        }
+        self.statement()
+    }
+
+    fn jump(&mut self) -> Result<Jump, ParseErr> {
+        let keyword = self.previous().clone();
+        let label = if self.equal(&[Identifier]) {
+            Some(self.previous().clone())
+        } else {
+            None
+        };
+        self.consume_semicolon()?;
+        Ok(Jump { keyword, label })
+    }
+
+    fn is_label(&self) -> bool {
+        self.check(Identifier) && self.peek_ahead(1).is_some_and(|token| token.kind == Colon)
+    }
+
+    fn assert_statement(&mut self) -> StatementResult {
+        let keyword = self.previous().clone();
+        let cond = self.expression()?;
+        let message = if self.equal(&[Comma]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume_semicolon()?;
+        Ok(Statement::new_assert(keyword, cond, message))
+    }
+
+    fn import_statement(&mut self) -> StatementResult {
+        let keyword = self.previous().clone();
+        self.consume(StringT, "Expected a file path string after 'import'.")?;
+        let path = self.previous().clone();
+        // `as` is only a keyword here, so it can still name variables.
+        let alias = if self.check(Identifier) && &*self.peek().content == "as" {
+            self.advance();
+            self.consume(Identifier, "Expected a namespace name after 'as'.")?;
+            Some(self.previous().clone())
+        } else {
+            None
+        };
+        self.consume_semicolon()?;
+        Ok(Statement::new_import(keyword, path, alias))
+    }
+
+    // Patterns separated by commas, up to closing, which is consumed.
+    fn patterns(&mut self, closing: TokenKind) -> Result<Vec<Pattern>, ParseErr> {
+        let mut patterns = Vec::new();
+        if !self.check(closing) {
+            loop {
+                patterns.push(self.pattern()?);
+                if !self.equal(&[Comma]) {
+                    break;
+                }
+            }
+        }
+        let message = format!("Expected '{}' following patterns", if closing == RightParen { ")" } else { "]" });
+        self.consume(closing, &message)?;
+        Ok(patterns)
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseErr> {
+        if self.equal(&[Number, StringT, True, False, Nil]) {
+            Ok(Pattern::Literal(Expr::new_literal(self.previous().clone())))
+        } else if self.equal(&[Minus]) {
+            let operator = self.previous().clone();
+            self.consume(Number, "Expected a number after '-' in a pattern")?;
+            Ok(Pattern::Literal(Expr::new_unary(operator, Expr::new_literal(self.previous().clone()))))
+        } else if self.equal(&[LeftBracket]) {
+            Ok(Pattern::List(self.patterns(RightBracket)?))
+        } else if self.equal(&[Identifier]) {
+            let name = self.previous().clone();
+            if self.equal(&[LeftParen]) {
+                Ok(Pattern::Class(Expr::new_variable(name), self.patterns(RightParen)?))
+            } else {
+                Ok(Pattern::Binding(name))
+            }
+        } else {
+            Err(self.error("Expected a pattern"))
+        }
+    }
+
+    fn match_statement(&mut self) -> StatementResult {
+        let keyword = self.previous().clone();
+        self.consume(LeftParen, "Expected '(' following 'match'")?;
+        let value = self.expression()?;
+        self.consume(RightParen, "Expected ')' following matched value")?;
+        self.consume(LeftBrace, "Expected '{' before match arms")?;
+        let mut arms = Vec::new();
+        while self.equal(&[Case]) {
+            let pattern = self.pattern()?;
+            self.consume(Colon, "Expected ':' following pattern")?;
+            let body = self.body("case")?;
+            arms.push(MatchArm { pattern, body });
+        }
+        self.consume(RightBrace, "Expected 'case' or '}' in match")?;
+        Ok(Statement::new_match(keyword, value, arms))
+    }
+
+    fn yield_statement(&mut self) -> StatementResult {
+        let keyword = self.previous().clone();
+        let value = if !self.check(Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume_semicolon()?;
+        Ok(Statement::new_yield(keyword, value))
+    }
+
+    fn throw_statement(&mut self) -> StatementResult {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume_semicolon()?;
+        Ok(Statement::new_throw(keyword, value))
+    }
+
+    fn try_statement(&mut self) -> StatementResult {
+        self.consume(LeftBrace, "Expected '{' following 'try'")?;
+        let body = self.block()?;
+        let catch = if self.equal(&[Catch]) {
+            self.consume(LeftParen, "Expected '(' following 'catch'")?;
+            self.consume(Identifier, "Expected a name for the caught value")?;
+            let name = self.previous().clone();
+            self.consume(RightParen, "Expected ')' following caught value name")?;
+            self.consume(LeftBrace, "Expected '{' following catch")?;
+            Some(CatchClause { name, body: self.block()? })
+        } else {
+            None
+        };
+        let finally = if self.equal(&[Finally]) {
+            self.consume(LeftBrace, "Expected '{' following 'finally'")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error("Expected 'catch' or 'finally' following try block"));
+        }
+        Ok(Statement::new_try(body, catch, finally))
+    }
+
+    fn with_statement(&mut self) -> StatementResult {
+        let keyword = self.previous().clone();
+        self.consume(LeftParen, "Expected '(' following 'with'")?;
+        let resource = self.expression()?;
+        self.consume(RightParen, "Expected ')' following resource")?;
+        // As in imports, `as` is only a keyword here.
+        if !(self.check(Identifier) && &*self.peek().content == "as") {
+            return Err(self.error("Expected 'as' following resource"));
+        }
+        self.advance();
+        self.consume(Identifier, "Expected a name for the resource after 'as'.")?;
+        let name = self.previous().clone();
+        self.consume(LeftBrace, "Expected '{' following resource name")?;
+        Ok(Statement::new_with(keyword, resource, name, self.block()?))
+    }
+
     fn statement(&mut self) -> StatementResult {
-        if self.equal(vec![Print]) {
+        if self.is_label() {
+            self.advance();
+            let label = self.previous().clone();
+            self.advance();
+            let statement = self.statement()?;
+            return match statement.with_label(label) {
+                Some(statement) => Ok(statement),
+                None => Err(self.error("Only loops can be labeled.")),
+            };
+        }
+        if self.equal(&[Print]) {
             self.print_statement()
-        } else if self.equal(vec![LeftBrace]) {
+        } else if self.equal(&[LeftBrace]) {
             Ok(Statement::new_block(self.block()?))
-        } else if self.equal(vec![If]) {
+        } else if self.equal(&[If]) {
             self.if_statement()
-        } else if self.equal(vec![While]) {
+        } else if self.equal(&[While]) {
             self.while_statement()
-        } else if self.equal(vec![For]) {
+        } else if self.equal(&[For]) {
             self.for_statement()
-        } else if self.equal(vec![Return]) {
+        } else if self.equal(&[Return]) {
             self.return_statement()
+        } else if self.equal(&[Assert]) {
+            self.assert_statement()
+        } else if self.equal(&[Import]) {
+            self.import_statement()
+        } else if self.equal(&[Throw]) {
+            self.throw_statement()
+        } else if self.equal(&[Match]) {
+            self.match_statement()
+        } else if self.equal(&[Yield]) {
+            self.yield_statement()
+        } else if self.equal(&[Try]) {
+            self.try_statement()
+        } else if self.equal(&[With]) {
+            self.with_statement()
+        } else if self.equal(&[Break]) {
+            Ok(Statement::Break(self.jump()?))
+        } else if self.equal(&[Continue]) {
+            Ok(Statement::Continue(self.jump()?))
         } else {
             Ok(Statement::new_expr_statement(self.expr_statement()?))
         }
@@ -327,9 +654,9 @@ impl Parser {
 
     fn and(&mut self) -> ExprResult {
         let mut expr = self.equality()?;
-        while self.equal(vec![And]) {
-            let operator = self.previous();
-            let right = self.and()?;
+        while self.equal(&[And]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
             expr = Expr::new_logical(expr, operator, right);
         }
         Ok(expr)
@@ -337,17 +664,28 @@ impl Parser {
 
     fn or(&mut self) -> ExprResult {
         let mut expr = self.and()?;
-        while self.equal(vec![Or]) {
-            let operator = self.previous();
+        while self.equal(&[Or]) {
+            let operator = self.previous().clone();
             let right = self.and()?;
             expr = Expr::new_logical(expr, operator, right);
         }
         Ok(expr)
     }
 
+    // `a ?? b` binds more loosely than `or`.
+    fn coalesce(&mut self) -> ExprResult {
+        let mut expr = self.or()?;
+        while self.equal(&[QuestionQuestion]) {
+            let operator = self.previous().clone();
+            let right = self.or()?;
+            expr = Expr::new_logical(expr, operator, right);
+        }
+        Ok(expr)
+    }
+
     fn assignment(&mut self) -> ExprResult {
-        let expr = self.or()?;
-        if self.equal(vec![TokenKind::Equal]) {
+        let expr = self.coalesce()?;
+        if self.equal(&[TokenKind::Equal]) {
             let rvalue = self.assignment()?;
             if let ExprKind::Variable(_) = expr.kind {
                 // TODO: In the future, this will have to be reworked to take something other than a
@@ -362,9 +700,9 @@ impl Parser {
 
     fn var_declaration(&mut self) -> Result<VarDeclaration, ParseErr> {
         self.consume(Identifier, "Expected variable name.")?;
-        let name = self.previous();
+        let name = self.previous().clone();
 
-        let initializer = if self.equal(vec![Equal]) {
+        let initializer = if self.equal(&[Equal]) {
             let expr = self.expression()?;
             Some(expr)
         } else {
@@ -375,9 +713,18 @@ impl Parser {
         Ok(VarDeclaration::new(name, initializer))
     }
 
+    fn const_declaration(&mut self) -> Result<VarDeclaration, ParseErr> {
+        self.consume(Identifier, "Expected constant name.")?;
+        let name = self.previous().clone();
+        self.consume(Equal, "Expected '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume_semicolon()?;
+        Ok(VarDeclaration::new_const(name, initializer))
+    }
+
     fn function(&mut self, s: &str) -> Result<FunDeclaration, ParseErr> {
         self.consume(Identifier, &format!("Expected {} name.", s))?;
-        let name = self.previous();
+        let name = self.previous().clone();
         self.consume(LeftParen, &format!("Expect '(' after {} name.", s))?;
         let mut parameters = Vec::new();
         if !self.check(RightParen) {
@@ -386,8 +733,8 @@ impl Parser {
                 if parameters.len() >= 255 {
                     return Err(self.error("Can't have more than 255 parameters"));
                 }
-                parameters.push(self.previous());
-                if !self.equal(vec![Comma]) {
+                parameters.push(self.previous().clone());
+                if !self.equal(&[Comma]) {
                     break;
                 }
             }
@@ -400,32 +747,116 @@ impl Parser {
     
     fn class(&mut self) -> DeclarationResult {
         self.consume(Identifier, "Expected class name")?;
-        let name = self.previous();
-        let superclass = if self.equal(vec![Less]) {
+        let name = self.previous().clone();
+        let superclass = if self.equal(&[Less]) {
             self.consume(Identifier, "Expected class name.")?;
-            Some(Expr::new_variable(self.previous()))
+            Some(Expr::new_variable(self.previous().clone()))
         } else {
             None
         };
+        let mut mixins = Vec::new();
+        if self.equal(&[With]) {
+            loop {
+                self.consume(Identifier, "Expected mixin name.")?;
+                mixins.push(Expr::new_variable(self.previous().clone()));
+                if !self.equal(&[Comma]) {
+                    break;
+                }
+            }
+        }
+        let mut interfaces = Vec::new();
+        if self.equal(&[Colon]) {
+            loop {
+                self.consume(Identifier, "Expected interface name.")?;
+                interfaces.push(self.previous().clone());
+                if !self.equal(&[Comma]) {
+                    break;
+                }
+            }
+        }
         self.consume(LeftBrace, "Expected left brace")?;
         let mut methods = HashMap::new();
+        let mut setters = HashMap::new();
+        let mut statics = Vec::new();
         while !self.is_at_end() && !self.check(RightBrace) {
-            let function = self.function("method")?;
+            if self.equal(&[Static]) {
+                self.consume(Var, "Expected 'var' after 'static'.")?;
+                statics.push(self.var_declaration()?);
+                continue;
+            }
+            // `set` is only a keyword when it starts a setter, so it can still name a method.
+            let is_setter = self.check(Identifier)
+                && &*self.peek().content == "set"
+                && self.peek_ahead(1).is_some_and(|token| token.kind == Identifier);
+            if is_setter {
+                self.advance();
+                let setter = self.function("setter")?;
+                if setter.borrow().params.len() != 1 {
+                    return Err(self.error("A setter takes exactly one parameter."));
+                }
+                let name = setter.borrow().name.content.clone();
+                setters.insert(name, setter);
+                continue;
+            }
+            let function = if self.peek_ahead(1).is_some_and(|token| token.kind == LeftBrace) {
+                self.getter()?
+            } else {
+                self.function("method")?
+            };
             let name = {
                 function.borrow().name.content.clone()
             };
             methods.insert(name, function);
         }
         self.consume(RightBrace, "Expected right brace.")?;
-        Ok(Declaration::new_class(methods, name, superclass))
+        Ok(Declaration::new_class(interfaces, methods, mixins, name, setters, statics, superclass))
+    }
+
+    fn interface(&mut self) -> DeclarationResult {
+        self.consume(Identifier, "Expected interface name.")?;
+        let name = self.previous().clone();
+        self.consume(LeftBrace, "Expected '{' after interface name.")?;
+        let mut methods = Vec::new();
+        while !self.is_at_end() && !self.check(RightBrace) {
+            self.consume(Identifier, "Expected method name.")?;
+            let method_name = self.previous().clone();
+            self.consume(LeftParen, "Expect '(' after method name.")?;
+            let mut params = Vec::new();
+            if !self.check(RightParen) {
+                loop {
+                    self.consume(Identifier, "Expected parameter name.")?;
+                    params.push(self.previous().clone());
+                    if !self.equal(&[Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(RightParen, "Expected ')' to follow '('")?;
+            self.consume_semicolon()?;
+            methods.push(MethodSignature { name: method_name, params });
+        }
+        self.consume(RightBrace, "Expected right brace.")?;
+        Ok(Declaration::new_interface(name, methods))
+    }
+
+    fn getter(&mut self) -> Result<FunDeclaration, ParseErr> {
+        self.consume(Identifier, "Expected getter name.")?;
+        let name = self.previous().clone();
+        self.consume(LeftBrace, "Expected '{' before getter body")?;
+        let body = self.block()?;
+        Ok(FunDeclarationStruct::new_getter(name, body))
     }
 
     fn declaration(&mut self) -> DeclarationResult {
-        if self.equal(vec![Class]) {
+        if self.equal(&[Class]) {
             self.class()
-        } else if self.equal(vec![Var]) {
+        } else if self.equal(&[Interface]) {
+            self.interface()
+        } else if self.equal(&[Var]) {
             Ok(Declaration::VarDeclaration(self.var_declaration()?))
-        } else if self.equal(vec![Fun]) {
+        } else if self.equal(&[Const]) {
+            Ok(Declaration::VarDeclaration(self.const_declaration()?))
+        } else if self.equal(&[Fun]) {
             let function = self.function("function")?;
             Ok(Declaration::FunDeclaration(function))
         } else {
@@ -434,6 +865,8 @@ impl Parser {
         }
     }
 
+    // Errors are reported as they are encountered, so callers only learn that parsing failed.
+    #[allow(clippy::result_unit_err)]
     pub fn parse(&mut self) -> AstResult {
         let mut declarations: Vec<Declaration> = Vec::new();
         let mut had_error = false;
@@ -441,7 +874,8 @@ impl Parser {
             match self.declaration() {
                 Ok(declaration) => declarations.push(declaration),
                 Err(parse_error) => {
-                    parse_error.report();
+                    let diagnostic = parse_error.report();
+                    self.diagnostics.push(diagnostic);
                     had_error = true;
                     self.synchronize();
                 }
@@ -454,17 +888,25 @@ impl Parser {
         }
     }
 
-    pub fn new(tokens: VecDeque<Token>) -> Parser {
+    pub fn new(tokens: Vec<Token>) -> Parser {
         Parser {
             tokens,
-            previous: None,
+            current: 0,
+            diagnostics: Vec::new(),
         }
     }
+
+    /// Every error reported by parse.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::test_utils::*;
+    use super::*;
+    use crate::scanner::Scanner;
+    use crate::testing::*;
     #[test]
     fn test_if() {
         let _ast = scan_parse(
@@ -487,6 +929,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_declaration_as_body() {
+        let cases = [
+            ("if (true) var a = 1;", "if"),
+            ("if (true) const a = 1;", "if"),
+            ("if (true) {} else fun f() {}", "else"),
+            ("while (false) class A {}", "while"),
+            ("while (false) interface I {}", "while"),
+            ("for (;;) var a = 2;", "for"),
+        ];
+        for (source, keyword) in cases {
+            let mut parser = Parser::new(Scanner::new(source.to_string()).scan_tokens());
+            assert!(parser.parse().is_err());
+            let expected = format!(
+                "Parse error: Expect expression; declarations are not allowed as the body of '{}'. Wrap it in a block.",
+                keyword
+            );
+            assert_eq!(parser.diagnostics()[0].message, expected);
+        }
+        scan_parse("if (true) { var a = 1; } else { fun f() {} }");
+    }
+
     #[test]
     fn test_fun() {
         let s = "
@@ -502,4 +966,33 @@ mod test {
         }";
         scan_parse(s);
     }
+
+    #[test]
+    fn test_literal_values() {
+        use crate::value::Value;
+
+        let ast = scan_parse("print [42, 1.5, \"text\", true, nil];");
+        let Declaration::Statement(Statement::Print(list)) = &ast.declarations[0] else {
+            panic!("Expected a print statement.");
+        };
+        let ExprKind::List(elements) = &list.kind else {
+            panic!("Expected a list.");
+        };
+        let values: Vec<&Value> = elements
+            .iter()
+            .map(|element| match &element.kind {
+                ExprKind::Literal(value) => value,
+                kind => panic!("Expected a literal, got {:?}.", kind),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            [&Value::Integer(42), &Value::Number(1.5), &Value::new_string("text"), &Value::Boolean(true), &Value::Nil]
+        );
+        // A string literal's value shares the token's text.
+        let (Value::StringV(text), content) = (values[2], &elements[2].token.content) else {
+            panic!("Expected a string.");
+        };
+        assert!(std::rc::Rc::ptr_eq(text, content));
+    }
 }
@@ -0,0 +1,398 @@
+//! Everything the interpreter needs from the operating system. Hosts without
+//! a full std environment, such as embedded devices or WASM, implement
+//! Platform themselves; StdPlatform is the usual desktop one.
+
+use std::collections::HashMap;
+use std::{env, fs};
+use std::io::{self, BufRead, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Platform {
+    /// Monotonic time since some fixed origin, used by clock(), monotonicMillis() and timeouts.
+    fn now(&self) -> Duration;
+
+    /// Wall-clock time since the Unix epoch, used by Time.now() and epochMillis().
+    fn wall_time(&self) -> Duration;
+
+    /// Blocks for duration, as measured by now().
+    fn sleep(&mut self, duration: Duration);
+
+    /// Where print statements go.
+    fn write_output(&mut self, text: &str) -> io::Result<()>;
+
+    /// The next line of input including its newline, or None at end of input.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+
+    fn read_file(&mut self, path: &str) -> io::Result<String>;
+
+    fn write_file(&mut self, path: &str, contents: &str) -> io::Result<()>;
+
+    fn exists(&mut self, path: &str) -> bool;
+
+    /// The names of a directory's entries, sorted.
+    fn list_dir(&mut self, path: &str) -> io::Result<Vec<String>>;
+
+    /// Creates a directory along with any missing parents.
+    fn create_dir(&mut self, path: &str) -> io::Result<()>;
+
+    /// Removes a file or an empty directory.
+    fn remove(&mut self, path: &str) -> io::Result<()>;
+
+    /// The value of an environment variable, or None if it is unset.
+    fn env_var(&mut self, name: &str) -> Option<String>;
+
+    fn set_env_var(&mut self, name: &str, value: &str);
+
+    /// The operating system's name, such as "linux", "macos" or "windows".
+    fn os_name(&self) -> String {
+        "unknown".to_string()
+    }
+
+    fn current_dir(&mut self) -> io::Result<String> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn set_current_dir(&mut self, _path: &str) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn hostname(&mut self) -> io::Result<String> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Opens a TCP connection, returning a handle for the other tcp_ methods.
+    /// Platforms without networking keep this default.
+    fn tcp_connect(&mut self, _host: &str, _port: u16) -> io::Result<i64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn tcp_send(&mut self, _handle: i64, _data: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Waits for data on the connection, returning what arrived; empty once
+    /// the other end has closed it.
+    fn tcp_recv(&mut self, _handle: i64) -> io::Result<Vec<u8>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn tcp_close(&mut self, _handle: i64) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Closes every connection still open, as when the interpreter is reset.
+    fn tcp_close_all(&mut self) {}
+}
+
+fn unknown_connection() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "not an open connection")
+}
+
+/// Uses the process's stdin, stdout, file system and clock.
+pub struct StdPlatform {
+    // Open TCP connections by handle, and the handle the next one gets.
+    connections: HashMap<i64, TcpStream>,
+    next_connection: i64,
+    origin: Instant,
+    output: Box<dyn Write>,
+}
+
+impl Default for StdPlatform {
+    fn default() -> StdPlatform {
+        StdPlatform::new()
+    }
+}
+
+impl StdPlatform {
+    pub fn new() -> StdPlatform {
+        StdPlatform::with_output(Box::new(io::stdout()))
+    }
+
+    /// Sends print output to output instead of stdout.
+    pub fn with_output(output: Box<dyn Write>) -> StdPlatform {
+        StdPlatform {
+            connections: HashMap::new(),
+            next_connection: 1,
+            origin: Instant::now(),
+            output,
+        }
+    }
+}
+
+impl Platform for StdPlatform {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn wall_time(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn write_output(&mut self, text: &str) -> io::Result<()> {
+        self.output.write_all(text.as_bytes())?;
+        self.output.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+
+    fn read_file(&mut self, path: &str) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write_file(&mut self, path: &str, contents: &str) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn list_dir(&mut self, path: &str) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn create_dir(&mut self, path: &str) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove(&mut self, path: &str) -> io::Result<()> {
+        if Path::new(path).is_dir() {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn env_var(&mut self, name: &str) -> Option<String> {
+        env::var(name).ok()
+    }
+
+    fn set_env_var(&mut self, name: &str, value: &str) {
+        env::set_var(name, value);
+    }
+
+    fn os_name(&self) -> String {
+        env::consts::OS.to_string()
+    }
+
+    fn current_dir(&mut self) -> io::Result<String> {
+        Ok(env::current_dir()?.to_string_lossy().into_owned())
+    }
+
+    fn set_current_dir(&mut self, path: &str) -> io::Result<()> {
+        env::set_current_dir(path)
+    }
+
+    // std has no gethostname, so ask Linux's proc file system, then the
+    // hostname command that Unix and Windows both have.
+    fn hostname(&mut self) -> io::Result<String> {
+        let name = match fs::read_to_string("/proc/sys/kernel/hostname") {
+            Ok(name) => name,
+            Err(_) => {
+                let output = Command::new("hostname").output()?;
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+        };
+        match name.trim() {
+            "" => Err(io::Error::other("no hostname set")),
+            name => Ok(name.to_string()),
+        }
+    }
+
+    fn tcp_connect(&mut self, host: &str, port: u16) -> io::Result<i64> {
+        let stream = TcpStream::connect((host, port))?;
+        let handle = self.next_connection;
+        self.next_connection += 1;
+        self.connections.insert(handle, stream);
+        Ok(handle)
+    }
+
+    fn tcp_send(&mut self, handle: i64, data: &[u8]) -> io::Result<()> {
+        self.connections.get_mut(&handle).ok_or_else(unknown_connection)?.write_all(data)
+    }
+
+    fn tcp_recv(&mut self, handle: i64) -> io::Result<Vec<u8>> {
+        let stream = self.connections.get_mut(&handle).ok_or_else(unknown_connection)?;
+        let mut buffer = vec![0; 64 * 1024];
+        let length = stream.read(&mut buffer)?;
+        buffer.truncate(length);
+        Ok(buffer)
+    }
+
+    fn tcp_close(&mut self, handle: i64) -> io::Result<()> {
+        self.connections.remove(&handle).ok_or_else(unknown_connection)?;
+        Ok(())
+    }
+
+    fn tcp_close_all(&mut self) {
+        self.connections.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::interp_error::InterpError;
+    use crate::interpreter::Interpreter;
+    use crate::testing::scan_parse;
+    use crate::value::Value;
+
+    // Time stands still unless the test moves it, input is scripted, and
+    // output is kept.
+    struct FakePlatform {
+        now: Duration,
+        input: Vec<String>,
+        output: Rc<RefCell<String>>,
+    }
+
+    impl Platform for FakePlatform {
+        fn now(&self) -> Duration {
+            self.now
+        }
+
+        fn wall_time(&self) -> Duration {
+            Duration::from_secs(1_700_000_000) + self.now
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration;
+        }
+
+        fn write_output(&mut self, text: &str) -> io::Result<()> {
+            self.output.borrow_mut().push_str(text);
+            Ok(())
+        }
+
+        fn read_line(&mut self) -> io::Result<Option<String>> {
+            if self.input.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(self.input.remove(0)))
+            }
+        }
+
+        fn read_file(&mut self, _path: &str) -> io::Result<String> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+
+        fn write_file(&mut self, _path: &str, _contents: &str) -> io::Result<()> {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+
+        fn exists(&mut self, _path: &str) -> bool {
+            false
+        }
+
+        fn list_dir(&mut self, _path: &str) -> io::Result<Vec<String>> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+
+        fn create_dir(&mut self, _path: &str) -> io::Result<()> {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+
+        fn remove(&mut self, _path: &str) -> io::Result<()> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+
+        fn env_var(&mut self, _name: &str) -> Option<String> {
+            None
+        }
+
+        fn set_env_var(&mut self, _name: &str, _value: &str) {}
+    }
+
+    #[test]
+    fn test_custom_platform() {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_platform(Box::new(FakePlatform {
+            now: Duration::from_secs(7),
+            input: Vec::new(),
+            output: output.clone(),
+        }));
+        interpreter.run(scan_parse("var a = clock(); print \"hi\";")).unwrap();
+        assert_eq!(interpreter.global("a"), Some(Value::Number(0.0)));
+        assert_eq!(output.borrow().as_str(), "hi\n");
+    }
+
+    #[test]
+    fn test_read_line() {
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_platform(Box::new(FakePlatform {
+            now: Duration::ZERO,
+            input: vec!["Ada\n".to_string(), "Lovelace\r\n".to_string(), "no newline".to_string()],
+            output: output.clone(),
+        }));
+        let source = "
+        var line = readLine();
+        while (line != nil) {
+            print \"[\" + line + \"]\";
+            line = readLine();
+        }";
+        interpreter.run(scan_parse(source)).unwrap();
+        assert_eq!(output.borrow().as_str(), "[Ada]\n[Lovelace]\n[no newline]\n");
+    }
+
+    #[test]
+    fn test_wall_time() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_platform(Box::new(FakePlatform {
+            now: Duration::from_millis(500),
+            input: Vec::new(),
+            output: Rc::new(RefCell::new(String::new())),
+        }));
+        let source = "
+        var now = Time.now();
+        var millis = epochMillis();
+        var text = Time.format(now, \"%Y-%m-%d %H:%M:%S\");";
+        interpreter.run(scan_parse(source)).unwrap();
+        assert_eq!(interpreter.global("now"), Some(Value::Number(1_700_000_000.5)));
+        assert_eq!(interpreter.global("millis"), Some(Value::Integer(1_700_000_000_500)));
+        assert_eq!(interpreter.global("text"), Some(Value::new_string("2023-11-14 22:13:20")));
+    }
+
+    #[test]
+    fn test_sleep() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_platform(Box::new(FakePlatform {
+            now: Duration::ZERO,
+            input: Vec::new(),
+            output: Rc::new(RefCell::new(String::new())),
+        }));
+        interpreter.run(scan_parse("sleep(250); sleep(0.5); var a = monotonicMillis();")).unwrap();
+        assert_eq!(interpreter.global("a"), Some(Value::Integer(250)));
+
+        // A timeout cuts a sleep short rather than waiting it out.
+        interpreter.set_timeout(Some(Duration::from_secs(1)));
+        let result = interpreter.run(scan_parse("sleep(60000);"));
+        assert_eq!(result, Err(InterpError::Timeout));
+        interpreter.set_timeout(None);
+        interpreter.run(scan_parse("var b = monotonicMillis(); var c = clock();")).unwrap();
+        assert_eq!(interpreter.global("b"), Some(Value::Integer(1250)));
+        assert_eq!(interpreter.global("c"), Some(Value::Number(1.2505)));
+    }
+}
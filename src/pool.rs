@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use crate::dead_code::eliminate_dead_code;
+use crate::fold::fold_constants;
+use crate::interp_error::{Error, InterpError};
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    // Parse errors have already been reported by the parser.
+    Parse,
+    Resolve(Error),
+    Runtime(InterpError),
+    TimedOut,
+}
+
+/// An interpreter checked out of a LoxPool. Hand it back with LoxPool::checkin
+/// so it can be reset and reused.
+pub struct PooledInterpreter {
+    interpreter: Interpreter,
+    timeout: Duration,
+}
+
+impl PooledInterpreter {
+    /// Runs a whole script, failing with ScriptError::TimedOut if it is still
+    /// running once the pool's timeout has elapsed.
+    pub fn run(&mut self, source: &str) -> Result<(), ScriptError> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut ast = Parser::new(tokens).parse().map_err(|()| ScriptError::Parse)?;
+        Resolver::new().run(&mut ast).map_err(ScriptError::Resolve)?;
+        fold_constants(&mut ast);
+        eliminate_dead_code(&mut ast);
+
+        self.interpreter.set_timeout(Some(self.timeout));
+        let result = self.interpreter.run(ast);
+        self.interpreter.set_timeout(None);
+        match result {
+            Ok(()) => Ok(()),
+            Err(InterpError::Timeout) => Err(ScriptError::TimedOut),
+            Err(error) => Err(ScriptError::Runtime(error)),
+        }
+    }
+}
+
+/// A fixed number of interpreters for embedders running many scripts, such as a
+/// server. Each interpreter owns its globals and is reset when checked back in,
+/// so nothing one script defines is visible to the next. Interpreters hold Rc
+/// values and are not Send, so a multi-threaded host keeps one pool per thread.
+pub struct LoxPool {
+    idle: Vec<Interpreter>,
+    size: usize,
+    timeout: Duration,
+}
+
+impl LoxPool {
+    pub fn new(size: usize, timeout: Duration) -> LoxPool {
+        LoxPool {
+            idle: (0..size).map(|_| Interpreter::new()).collect(),
+            size,
+            timeout,
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Returns None when every interpreter in the pool is already checked out.
+    pub fn checkout(&mut self) -> Option<PooledInterpreter> {
+        self.idle.pop().map(|interpreter| PooledInterpreter {
+            interpreter,
+            timeout: self.timeout,
+        })
+    }
+
+    pub fn checkin(&mut self, pooled: PooledInterpreter) {
+        let mut interpreter = pooled.interpreter;
+        interpreter.reset();
+        if self.idle.len() < self.size {
+            self.idle.push(interpreter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_checkout_limit() {
+        let mut pool = LoxPool::new(1, Duration::from_secs(5));
+        let pooled = pool.checkout().unwrap();
+        assert!(pool.checkout().is_none());
+        pool.checkin(pooled);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn test_reset_between_uses() {
+        let mut pool = LoxPool::new(1, Duration::from_secs(5));
+        let mut pooled = pool.checkout().unwrap();
+        assert_eq!(pooled.run("var a = 1; print a;"), Ok(()));
+        pool.checkin(pooled);
+
+        let mut pooled = pool.checkout().unwrap();
+        assert!(matches!(pooled.run("print a;"), Err(ScriptError::Runtime(_))));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut pool = LoxPool::new(1, Duration::from_millis(50));
+        let mut pooled = pool.checkout().unwrap();
+        assert_eq!(pooled.run("for (;;) {}"), Err(ScriptError::TimedOut));
+        assert_eq!(pooled.run("var a = 1;"), Ok(()));
+    }
+}
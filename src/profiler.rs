@@ -0,0 +1,84 @@
+//! Call counts and times per function, recorded by the interpreter for
+//! `lox --profile`. Each call expression the tree-walker evaluates is timed by
+//! the platform's clock. A function's time includes the functions it calls,
+//! but a recursive call is not timed again inside the outermost one, so the
+//! total never exceeds the time the program ran.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::value::Function;
+
+/// What the profiler recorded for one function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    /// The line the function was declared on, if it was declared in Lox.
+    pub line: Option<usize>,
+    pub calls: u64,
+    /// Time spent in the function, including the functions it called.
+    pub time: Duration,
+    // Calls to the function still running.
+    active: u32,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    functions: HashMap<(String, Option<usize>), FunctionProfile>,
+}
+
+impl Profiler {
+    // Counts a call to function as it starts, returning what exit needs to
+    // know about it.
+    pub(crate) fn enter(&mut self, function: &Function) -> (String, Option<usize>) {
+        let line = match function {
+            Function::UserDefined(user_defined) => Some(user_defined.declaration.borrow().name.line),
+            _ => None,
+        };
+        let key = (function.name(), line);
+        let profile = self.functions.entry(key.clone()).or_insert_with(|| FunctionProfile {
+            name: key.0.clone(),
+            line,
+            calls: 0,
+            time: Duration::ZERO,
+            active: 0,
+        });
+        profile.calls += 1;
+        profile.active += 1;
+        key
+    }
+
+    // Ends a call begun by enter, which took elapsed.
+    pub(crate) fn exit(&mut self, key: &(String, Option<usize>), elapsed: Duration) {
+        // Profiling may have restarted during the call.
+        let Some(profile) = self.functions.get_mut(key) else {
+            return;
+        };
+        profile.active -= 1;
+        if profile.active == 0 {
+            profile.time += elapsed;
+        }
+    }
+
+    /// Every function called so far, those that took longest first.
+    pub fn functions(&self) -> Vec<&FunctionProfile> {
+        let mut functions: Vec<&FunctionProfile> = self.functions.values().collect();
+        functions.sort_by(|a, b| b.time.cmp(&a.time).then(b.calls.cmp(&a.calls)).then(a.name.cmp(&b.name)));
+        functions
+    }
+
+    /// A table of the functions, one per line, for printing.
+    pub fn report(&self) -> String {
+        let mut out = format!("{:>10} {:>12}  function\n", "calls", "total ms");
+        for function in self.functions() {
+            let location = match function.line {
+                Some(line) => format!(" [line {}]", line),
+                None => String::new(),
+            };
+            let millis = function.time.as_secs_f64() * 1000.0;
+            let _ = writeln!(out, "{:>10} {:>12.3}  {}{}", function.calls, millis, function.name, location);
+        }
+        out
+    }
+}
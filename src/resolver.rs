@@ -1,7 +1,8 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::ast::*;
 use crate::interp_error::Error;
+use crate::symbol::{intern, Symbol};
 use crate::token::Token;
 use Status::*;
 
@@ -11,22 +12,117 @@ fn error(message: &str, token: Token) -> ResolverResult {
     Err(Error::new(message, token))
 }
 
+#[derive(Clone, Copy)]
 enum Status {
     Declared,
     Defined,
+    Constant,
+}
+
+// A name declared in a scope, numbered in declaration order so the
+// interpreter can find its value without searching by name.
+#[derive(Clone, Copy)]
+struct Local {
+    status: Status,
+    slot: u32,
+}
+
+// Where a yield statement would be.
+#[derive(Clone, Copy)]
+enum YieldScope {
+    TopLevel,
+    Function,
+    Try,
+    With,
+}
+
+/// A single resolved reference: the name and line of the token, and the slot
+/// its declaration was found in (None for globals).
+#[derive(Debug, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub line: usize,
+    pub slot: Depth,
+}
+
+/// Every reference the resolver visited, in source order.
+#[derive(Debug, Default)]
+pub struct Bindings {
+    pub bindings: Vec<Binding>,
+}
+
+impl Bindings {
+    pub fn depths(&self, name: &str) -> Vec<Option<u32>> {
+        self.bindings
+            .iter()
+            .filter(|binding| binding.name == name)
+            .map(|binding| binding.slot.map(|slot| slot.depth))
+            .collect()
+    }
+
+    pub fn depth_at(&self, name: &str, line: usize) -> Option<Option<u32>> {
+        self.slot_at(name, line).map(|slot| slot.map(|slot| slot.depth))
+    }
+
+    pub fn slot_at(&self, name: &str, line: usize) -> Option<Depth> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.name == name && binding.line == line)
+            .map(|binding| binding.slot)
+    }
 }
 
 pub struct Resolver {
-    scopes: VecDeque<HashMap<String, Status>>,
+    scopes: VecDeque<HashMap<Symbol, Local>>,
+    // Constants declared at the top level, which has no entry in scopes.
+    global_constants: HashSet<Symbol>,
+    // The methods each interface requires, with their parameter counts.
+    interfaces: HashMap<Symbol, Vec<(Symbol, usize)>>,
+    // Parameter counts of each class's methods, inherited and mixed in ones
+    // included. None when an ancestor was declared out of the resolver's sight.
+    classes: HashMap<Symbol, Option<HashMap<Symbol, usize>>>,
+    bindings: Option<Bindings>,
+    // Labels of the loops enclosing the current statement, innermost last.
+    loops: Vec<Option<Symbol>>,
+    yield_scope: YieldScope,
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new()
+    }
 }
 
 impl Resolver {
     pub fn new() -> Resolver {
         Resolver {
             scopes: VecDeque::new(),
+            global_constants: HashSet::new(),
+            interfaces: HashMap::new(),
+            classes: HashMap::new(),
+            bindings: None,
+            loops: Vec::new(),
+            yield_scope: YieldScope::TopLevel,
+        }
+    }
+
+    /// A resolver that also records a Bindings table, retrieved with take_bindings.
+    pub fn new_with_bindings() -> Resolver {
+        Resolver {
+            scopes: VecDeque::new(),
+            global_constants: HashSet::new(),
+            interfaces: HashMap::new(),
+            classes: HashMap::new(),
+            bindings: Some(Bindings::default()),
+            loops: Vec::new(),
+            yield_scope: YieldScope::TopLevel,
         }
     }
 
+    pub fn take_bindings(&mut self) -> Option<Bindings> {
+        self.bindings.take()
+    }
+
     pub fn run(&mut self, ast: &mut Ast) -> ResolverResult {
         self.visit_declarations(&mut ast.declarations)
     }
@@ -36,14 +132,21 @@ impl Resolver {
     }
 
     fn declare(&mut self, token: &Token) {
-        if let Some(scope) = self.scopes.front_mut() {
-            scope.insert(token.content.clone(), Declared);
-        }
+        self.set_status(token, Declared);
     }
 
     fn define(&mut self, token: &Token) {
+        self.set_status(token, Defined);
+    }
+
+    // A name keeps its slot when declared again, as the interpreter reuses it.
+    fn set_status(&mut self, token: &Token, status: Status) {
         if let Some(scope) = self.scopes.front_mut() {
-            scope.insert(token.content.clone(), Defined);
+            let slot = match scope.get(&token.content) {
+                Some(local) => local.slot,
+                None => scope.len() as u32,
+            };
+            scope.insert(token.content.clone(), Local { status, slot });
         }
     }
 
@@ -53,6 +156,13 @@ impl Resolver {
 
     fn visit_assign_expr(&mut self, assign_expr: &mut AssignExpr, token: &Token) -> ResolverResult {
         self.visit_expr(&mut assign_expr.initializer)?;
+        let constant = match self.scopes.iter().find_map(|scope| scope.get(&token.content)) {
+            Some(local) => matches!(local.status, Constant),
+            None => self.global_constants.contains(&token.content),
+        };
+        if constant {
+            return error(&format!("Can't assign to constant '{}'.", token.content), token.clone());
+        }
         self.resolve_local(&mut assign_expr.depth, token)?;
         Ok(())
     }
@@ -80,7 +190,7 @@ impl Resolver {
 
     fn visit_class(&mut self, class: &mut Class) -> ResolverResult {
         let mut class_struct = class.borrow_mut();
-        if let ClassStruct { name, superclass: Some(superclass_expr), methods: _ } = &mut *class_struct {
+        if let ClassStruct { name, superclass: Some(superclass_expr), .. } = &mut *class_struct {
             if superclass_expr.token.content == name.content {
                 return error("A class cannot inherit from itself.", superclass_expr.token.clone());
             } else {
@@ -91,16 +201,36 @@ impl Resolver {
                 }
             }
         }
-        self.define(&(*class_struct).name);
+        for mixin in class_struct.mixins.iter_mut() {
+            if let Expr { kind: ExprKind::Variable(depth), token } = mixin {
+                self.resolve_local(depth, token)?;
+            }
+        }
+        self.check_interfaces(&class_struct)?;
+        self.define(&class_struct.name);
+        // Static initializers run in the scope the class is declared in.
+        for field in class_struct.statics.iter_mut() {
+            if let Some(initializer) = &mut field.initializer {
+                self.visit_expr(initializer)?;
+            }
+        }
         if class_struct.superclass.is_some() {
-            let super_scope = hashmap!["super".to_string() => Status::Defined];
+            let super_scope = hashmap![intern("super") => Local { status: Defined, slot: 0 }];
             self.scopes.push_front(super_scope);
         }
-        let scope = hashmap!["this".to_string() => Status::Defined];
+        let scope = hashmap![intern("this") => Local { status: Defined, slot: 0 }];
         self.scopes.push_front(scope);
+        if let Some(init) = class_struct.methods.get("init") {
+            if init.borrow().generator {
+                return error("Can't yield from an initializer.", init.borrow().name.clone());
+            }
+        }
         for f in class_struct.methods.values_mut() {
             self.visit_fun_declaration(f)?;
         }
+        for f in class_struct.setters.values_mut() {
+            self.visit_fun_declaration(f)?;
+        }
         self.end_scope();
         if class_struct.superclass.is_some() {
             self.end_scope();
@@ -108,6 +238,54 @@ impl Resolver {
         Ok(())
     }
 
+    // Records the class's methods and checks them against its interfaces. When
+    // an ancestor's methods are unknown, such as a class from an earlier REPL
+    // line, nothing can be proven missing, so only the interface names are checked.
+    fn check_interfaces(&mut self, class: &ClassStruct) -> ResolverResult {
+        let ancestors = class.superclass.iter().chain(class.mixins.iter());
+        let mut methods = Some(HashMap::new());
+        for ancestor in ancestors {
+            match (&mut methods, self.classes.get(&ancestor.token.content)) {
+                (Some(methods), Some(Some(inherited))) => methods.extend(inherited.clone()),
+                _ => methods = None,
+            }
+        }
+        if let Some(methods) = &mut methods {
+            for (name, method) in &class.methods {
+                methods.insert(name.clone(), method.borrow().params.len());
+            }
+        }
+        for interface in &class.interfaces {
+            let Some(required) = self.interfaces.get(&interface.content) else {
+                return error(&format!("Undefined interface '{}'.", interface.content), interface.clone());
+            };
+            let Some(methods) = &methods else {
+                continue;
+            };
+            for (name, arity) in required {
+                match methods.get(name) {
+                    None => {
+                        let message = format!(
+                            "Class '{}' is missing method '{}' required by interface '{}'.",
+                            class.name.content, name, interface.content
+                        );
+                        return error(&message, interface.clone());
+                    }
+                    Some(count) if count != arity => {
+                        let message = format!(
+                            "Method '{}' of class '{}' takes {} parameters but interface '{}' requires {}.",
+                            name, class.name.content, count, interface.content, arity
+                        );
+                        return error(&message, interface.clone());
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        self.classes.insert(class.name.content.clone(), methods);
+        Ok(())
+    }
+
     fn visit_declarations(&mut self, declarations: &mut Vec<Declaration>) -> ResolverResult {
         for declaration in declarations {
             self.visit_declaration(declaration)?;
@@ -118,6 +296,15 @@ impl Resolver {
     fn visit_declaration(&mut self, declaration: &mut Declaration) -> ResolverResult {
         match declaration {
             Declaration::Class(class) => self.visit_class(class),
+            Declaration::Interface(interface) => {
+                let methods = interface
+                    .methods
+                    .iter()
+                    .map(|method| (method.name.content.clone(), method.params.len()))
+                    .collect();
+                self.interfaces.insert(interface.name.content.clone(), methods);
+                Ok(())
+            }
             Declaration::FunDeclaration(fun_declaration) => {
                 self.visit_fun_declaration(fun_declaration)
             }
@@ -143,15 +330,52 @@ impl Resolver {
                 token: _,
             } => self.visit_call(call),
             Expr {
-                kind: ExprKind::Get(object),
+                kind: ExprKind::Get(get) | ExprKind::OptionalGet(get),
                 token: _,
-            } => self.visit_expr(object),
+            } => self.visit_expr(&mut get.object),
             Expr {
-                kind: ExprKind::Grouping(expr),
+                kind: ExprKind::Grouping(expr) | ExprKind::Spread(expr),
                 token: _,
             } => self.visit_expr(expr),
             Expr {
-                kind: ExprKind::Literal,
+                kind: ExprKind::List(elements),
+                token: _,
+            } => elements.iter_mut().try_for_each(|element| self.visit_expr(element)),
+            Expr {
+                kind: ExprKind::Chain(chain),
+                token: _,
+            } => chain.operands.iter_mut().try_for_each(|operand| self.visit_expr(operand)),
+            Expr {
+                kind: ExprKind::Comprehension(comprehension),
+                token: _,
+            } => {
+                self.visit_expr(&mut comprehension.iterable)?;
+                self.begin_scope();
+                self.define(&comprehension.name);
+                self.visit_expr(&mut comprehension.element)?;
+                if let Some(condition) = &mut comprehension.condition {
+                    self.visit_expr(condition)?;
+                }
+                self.end_scope();
+                Ok(())
+            }
+            Expr {
+                kind: ExprKind::Index(index),
+                token: _,
+            } => {
+                self.visit_expr(&mut index.object)?;
+                self.visit_expr(&mut index.index)
+            }
+            Expr {
+                kind: ExprKind::SetIndex(set_index),
+                token: _,
+            } => {
+                self.visit_expr(&mut set_index.object)?;
+                self.visit_expr(&mut set_index.index)?;
+                self.visit_expr(&mut set_index.value)
+            }
+            Expr {
+                kind: ExprKind::Literal(_),
                 token: _,
             } => Ok(()),
             Expr {
@@ -188,7 +412,7 @@ impl Resolver {
         }
         self.visit_option_expr(&mut for_statement.cond)?;
         self.visit_option_expr(&mut for_statement.increment)?;
-        self.visit_statement(&mut for_statement.body)?;
+        self.visit_loop_body(&mut for_statement.body, &for_statement.label)?;
         self.end_scope();
         Ok(())
     }
@@ -200,7 +424,13 @@ impl Resolver {
         for param in &fun_declaration.params {
             self.define(param);
         }
-        self.visit_declarations(&mut fun_declaration.body)?;
+        // Loops outside a function cannot be broken out of from inside it.
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        let enclosing_yield_scope = std::mem::replace(&mut self.yield_scope, YieldScope::Function);
+        let result = self.visit_declarations(&mut fun_declaration.body);
+        self.loops = enclosing_loops;
+        self.yield_scope = enclosing_yield_scope;
+        result?;
         self.end_scope();
         self.define(&fun_declaration.name);
         Ok(())
@@ -238,7 +468,7 @@ impl Resolver {
         self.visit_expr(&mut set.value)
     }
 
-    fn visit_this(&mut self, depth: &mut Option<u32>, token: &Token) -> ResolverResult {
+    fn visit_this(&mut self, depth: &mut Depth, token: &Token) -> ResolverResult {
         self.resolve_local(depth, token)
     }
 
@@ -248,12 +478,19 @@ impl Resolver {
             self.visit_expr(initializer)?;
         }
         self.define(&declaration.name);
+        if declaration.constant {
+            if self.scopes.is_empty() {
+                self.global_constants.insert(declaration.name.content.clone());
+            } else {
+                self.set_status(&declaration.name, Constant);
+            }
+        }
         Ok(())
     }
 
-    fn visit_var_expr(&mut self, depth: &mut Option<u32>, token: &Token) -> ResolverResult {
+    fn visit_var_expr(&mut self, depth: &mut Depth, token: &Token) -> ResolverResult {
         if let Some(scope) = self.scopes.front() {
-            if let Some(Declared) = scope.get(&token.content) {
+            if let Some(Local { status: Declared, .. }) = scope.get(&token.content) {
                 return error(
                     "Can't read local variable in its own initializer",
                     token.clone(),
@@ -274,35 +511,219 @@ impl Resolver {
 
     fn visit_statement(&mut self, statement: &mut Statement) -> ResolverResult {
         match statement {
+            Statement::Assert(assert) => {
+                self.visit_expr(&mut assert.cond)?;
+                match &mut assert.message {
+                    Some(message) => self.visit_expr(message),
+                    None => Ok(()),
+                }
+            }
             Statement::Block(declarations) => self.visit_block(declarations),
+            Statement::Break(jump) | Statement::Continue(jump) => self.visit_jump(jump),
             Statement::ExprStatement(expr) => self.visit_expr(expr),
-            Statement::If(if_statement) => self.visit_if_statement(&mut **if_statement),
+            Statement::If(if_statement) => self.visit_if_statement(if_statement),
             Statement::For(for_statement) => self.visit_for_statement(for_statement),
+            Statement::ForIn(for_in) => {
+                self.visit_expr(&mut for_in.iterable)?;
+                self.begin_scope();
+                self.define(&for_in.name);
+                self.visit_loop_body(&mut for_in.body, &for_in.label)?;
+                self.end_scope();
+                Ok(())
+            }
+            // Imported names are defined as globals, which locals could not see.
+            Statement::Import(import) if !self.scopes.is_empty() => {
+                error("Imports must be at the top level.", import.keyword.clone())
+            }
+            Statement::Import(_) => Ok(()),
             Statement::Print(expr) => self.visit_expr(expr),
             Statement::Return(return_expr) => self.visit_return_expr(return_expr),
+            Statement::Throw(throw) => self.visit_expr(&mut throw.value),
+            Statement::Try(try_statement) => self.visit_try(try_statement),
+            Statement::With(with) => self.visit_with(with),
             Statement::While(while_statement) => self.visit_while_statement(while_statement),
+            Statement::Yield(yield_statement) => self.visit_yield(yield_statement),
+            Statement::Match(match_statement) => self.visit_match(match_statement),
+        }
+    }
+
+    fn visit_match(&mut self, match_statement: &mut Match) -> ResolverResult {
+        self.visit_expr(&mut match_statement.value)?;
+        for arm in match_statement.arms.iter_mut() {
+            self.begin_scope();
+            self.visit_pattern(&mut arm.pattern)?;
+            self.visit_statement(&mut arm.body)?;
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) -> ResolverResult {
+        match pattern {
+            Pattern::Literal(expr) => self.visit_expr(expr),
+            Pattern::Binding(name) if &*name.content == "_" => Ok(()),
+            Pattern::Binding(name) => {
+                if self.scopes.front().is_some_and(|scope| scope.contains_key(&name.content)) {
+                    return error(&format!("'{}' is bound twice in one pattern.", name.content), name.clone());
+                }
+                self.define(name);
+                Ok(())
+            }
+            Pattern::List(patterns) => patterns.iter_mut().try_for_each(|pattern| self.visit_pattern(pattern)),
+            Pattern::Class(class, patterns) => {
+                self.visit_expr(class)?;
+                patterns.iter_mut().try_for_each(|pattern| self.visit_pattern(pattern))
+            }
+        }
+    }
+
+    fn visit_yield(&mut self, yield_statement: &mut Yield) -> ResolverResult {
+        match self.yield_scope {
+            YieldScope::TopLevel => error("Can't yield outside of a function.", yield_statement.keyword.clone()),
+            // A generator suspended inside a try could never run its finally.
+            YieldScope::Try => error("Can't yield inside a try statement.", yield_statement.keyword.clone()),
+            YieldScope::With => error("Can't yield inside a with statement.", yield_statement.keyword.clone()),
+            YieldScope::Function => self.visit_option_expr(&mut yield_statement.value),
+        }
+    }
+
+    fn visit_try(&mut self, try_statement: &mut Try) -> ResolverResult {
+        let enclosing_yield_scope = self.yield_scope;
+        if let YieldScope::Function = enclosing_yield_scope {
+            self.yield_scope = YieldScope::Try;
+        }
+        let result = self.visit_try_clauses(try_statement);
+        self.yield_scope = enclosing_yield_scope;
+        result
+    }
+
+    fn visit_try_clauses(&mut self, try_statement: &mut Try) -> ResolverResult {
+        self.visit_block(&mut try_statement.body)?;
+        if let Some(catch) = &mut try_statement.catch {
+            self.begin_scope();
+            self.define(&catch.name);
+            self.visit_declarations(&mut catch.body)?;
+            self.end_scope();
+        }
+        if let Some(finally) = &mut try_statement.finally {
+            self.visit_block(finally)?;
+        }
+        Ok(())
+    }
+
+    // Like a catch clause, the name and the body share one scope.
+    fn visit_with(&mut self, with: &mut With) -> ResolverResult {
+        self.visit_expr(&mut with.resource)?;
+        let enclosing_yield_scope = self.yield_scope;
+        if let YieldScope::Function = enclosing_yield_scope {
+            self.yield_scope = YieldScope::With;
         }
+        self.begin_scope();
+        self.define(&with.name);
+        let result = self.visit_declarations(&mut with.body);
+        self.end_scope();
+        self.yield_scope = enclosing_yield_scope;
+        result
     }
 
-    fn visit_super(&mut self, depth: &mut Option<u32>, token: &Token) -> ResolverResult {
+    fn visit_super(&mut self, depth: &mut Depth, token: &Token) -> ResolverResult {
         self.resolve_local(depth, token)
     }
 
     fn visit_while_statement(&mut self, while_statement: &mut While) -> ResolverResult {
         self.visit_expr(&mut while_statement.cond)?;
-        self.visit_statement(&mut while_statement.body)
+        self.visit_loop_body(&mut while_statement.body, &while_statement.label)
     }
 
-    fn resolve_local(&mut self, depth: &mut Option<u32>, token: &Token) -> ResolverResult {
-        for (i, scope) in self.scopes.iter().enumerate() {
-            if scope.contains_key(&token.content) {
-                if let Ok(new_depth) = u32::try_from(i) {
-                    let _ = std::mem::replace(depth, Some(new_depth));
-                } else {
-                    return error("Exceeded maximum scope depth.", token.clone());
-                }
+    fn visit_loop_body(&mut self, body: &mut Statement, label: &Option<Token>) -> ResolverResult {
+        self.loops.push(label.as_ref().map(|label| label.content.clone()));
+        let result = self.visit_statement(body);
+        self.loops.pop();
+        result
+    }
+
+    fn visit_jump(&mut self, jump: &Jump) -> ResolverResult {
+        match &jump.label {
+            _ if self.loops.is_empty() => {
+                let message = format!("Can't use '{}' outside of a loop.", jump.keyword.content);
+                error(&message, jump.keyword.clone())
+            }
+            Some(label) if !self.loops.contains(&Some(label.content.clone())) => {
+                let message = format!("No enclosing loop labeled '{}'.", label.content);
+                error(&message, label.clone())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_local(&mut self, depth: &mut Depth, token: &Token) -> ResolverResult {
+        let found = self.scopes.iter().enumerate().find_map(|(i, scope)| Some((i, scope.get(&token.content)?.slot)));
+        if let Some((i, index)) = found {
+            if let Ok(new_depth) = u32::try_from(i) {
+                let _ = depth.replace(Slot { depth: new_depth, index });
+            } else {
+                return error("Exceeded maximum scope depth.", token.clone());
             }
         }
+        if let Some(bindings) = &mut self.bindings {
+            bindings.bindings.push(Binding {
+                name: token.content.to_string(),
+                line: token.line,
+                slot: *depth,
+            });
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::ast::Slot;
+    use crate::testing::*;
+
+    #[test]
+    fn test_closure_depth() {
+        let s = "
+        fun outer() {
+            var a = 1;
+            fun inner() {
+                return a;
+            }
+        }";
+        assert_eq!(test_resolve(s).depth_at("a", 4), Some(Some(1)));
+    }
+
+    #[test]
+    fn test_shadowed_depth() {
+        let s = "
+        fun f() {
+            var a = 1;
+            {
+                var a = 2;
+                print a;
+            }
+            print a;
+        }
+        print a;";
+        assert_eq!(test_resolve(s).depths("a"), vec![Some(0), Some(0), None]);
+    }
+
+    #[test]
+    fn test_slots() {
+        let s = "
+        fun f(a, b) {
+            var c = a;
+            {
+                var d = b;
+                var a = d;
+                print a + c;
+            }
+        }";
+        let bindings = test_resolve(s);
+        assert_eq!(bindings.slot_at("a", 2), Some(Some(Slot { depth: 0, index: 0 })));
+        assert_eq!(bindings.slot_at("b", 4), Some(Some(Slot { depth: 1, index: 1 })));
+        assert_eq!(bindings.slot_at("d", 5), Some(Some(Slot { depth: 0, index: 0 })));
+        assert_eq!(bindings.slot_at("a", 6), Some(Some(Slot { depth: 0, index: 1 })));
+        assert_eq!(bindings.slot_at("c", 6), Some(Some(Slot { depth: 1, index: 2 })));
+    }
+}
@@ -1,82 +1,108 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
-use crate::error::report;
+use crate::error::{report, Diagnostic};
+use crate::symbol::intern;
 use crate::token::{Token, TokenKind};
 
 fn is_digit(c: char) -> bool {
-    '0' <= c && c <= '9'
+    c.is_ascii_digit()
 }
 
+// Identifiers may use letters from any script.
 fn is_alpha(c: char) -> bool {
-    ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z') || c == '_'
+    c.is_alphabetic() || c == '_'
 }
 
 fn is_alpha_numeric(c: char) -> bool {
     is_digit(c) || is_alpha(c)
 }
 
+/// Splits source text into tokens. The source is held as characters, so current
+/// and start count characters rather than bytes, and advancing or peeking is an
+/// index into it: scanning takes time linear in the length of the source.
 pub struct Scanner {
-    source: String,
+    source: Vec<char>,
     start: usize,
     line: usize,
     current: usize,
     keywords: HashMap<String, TokenKind>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Scanner {
         let keywords = hashmap! {
             "and".to_string() => TokenKind::And,
+            "assert".to_string() => TokenKind::Assert,
+            "break".to_string() => TokenKind::Break,
+            "case".to_string() => TokenKind::Case,
+            "catch".to_string() => TokenKind::Catch,
             "class".to_string() => TokenKind::Class,
+            "const".to_string() => TokenKind::Const,
+            "continue".to_string() => TokenKind::Continue,
             "else".to_string() => TokenKind::Else,
             "false".to_string() => TokenKind::False,
+            "finally".to_string() => TokenKind::Finally,
             "for".to_string() => TokenKind::For,
             "fun".to_string() => TokenKind::Fun,
             "if".to_string() => TokenKind::If,
+            "import".to_string() => TokenKind::Import,
+            "in".to_string() => TokenKind::In,
+            "interface".to_string() => TokenKind::Interface,
+            "is".to_string() => TokenKind::Is,
+            "match".to_string() => TokenKind::Match,
             "nil".to_string() => TokenKind::Nil,
             "or".to_string() => TokenKind::Or,
             "print".to_string() => TokenKind::Print,
             "return".to_string() => TokenKind::Return,
+            "static".to_string() => TokenKind::Static,
             "super".to_string() => TokenKind::Super,
             "this".to_string() => TokenKind::This,
+            "throw".to_string() => TokenKind::Throw,
             "true".to_string() => TokenKind::True,
+            "try".to_string() => TokenKind::Try,
             "var".to_string() => TokenKind::Var,
             "while".to_string() => TokenKind::While,
+            "with".to_string() => TokenKind::With,
+            "yield".to_string() => TokenKind::Yield,
         };
 
         Scanner {
-            source,
+            source: source.chars().collect(),
             current: 0,
             line: 0,
             start: 0,
             keywords,
+            diagnostics: Vec::new(),
         }
     }
 
+    /// Every error reported so far.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn error(&mut self, message: &str) {
+        report(self.line, message);
+        self.diagnostics.push(Diagnostic::new(self.line, message));
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
     fn advance(&mut self) -> char {
-        let r = self.source.chars().nth(self.current).unwrap();
+        let c = self.source[self.current];
         self.current += 1;
-        r
+        c
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current).unwrap()
-        }
+        self.source.get(self.current).copied().unwrap_or('\0')
     }
 
     fn equal(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
 
@@ -84,44 +110,107 @@ impl Scanner {
         true
     }
 
-    fn string(&mut self) -> String {
+    // The string's contents, or None if one of its escapes was invalid.
+    fn string(&mut self) -> Option<String> {
         let mut s = String::new();
+        let mut valid = true;
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            match self.advance() {
+                '\\' if !self.is_at_end() => match self.escape() {
+                    Ok(c) => s.push(c),
+                    Err(message) => {
+                        self.error(&message);
+                        valid = false;
+                    }
+                },
+                c => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    s.push(c);
+                }
             }
-            s.push(self.advance());
         }
         if self.is_at_end() {
-            report(self.line, "Unterminated string.");
+            self.error("Unterminated string.");
         } else {
             self.advance();
         }
-        s
+        valid.then_some(s)
+    }
+
+    // The character a backslash in a string stands for, with the backslash
+    // already consumed.
+    fn escape(&mut self) -> Result<char, String> {
+        match self.advance() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            'u' => self.unicode_escape(),
+            c => {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                Err(format!("Invalid escape sequence '\\{}'.", c))
+            }
+        }
+    }
+
+    // `\u{1F600}`: one to six hex digits naming a code point.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let invalid = || "Invalid unicode escape.".to_string();
+        if !self.equal('{') {
+            return Err(invalid());
+        }
+        let mut hex = String::new();
+        while self.peek().is_ascii_hexdigit() && hex.len() < 6 {
+            hex.push(self.advance());
+        }
+        if !self.equal('}') {
+            return Err(invalid());
+        }
+        u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or_else(invalid)
     }
 
     fn peek_next(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current + 1).unwrap()
+        self.source.get(self.current + 1).copied().unwrap_or('\0')
+    }
+
+    // Consumes a run of digits, dropping the single `_` separators allowed
+    // between them. Returns false if a separator is doubled or trailing.
+    fn digits(&mut self, s: &mut String) -> bool {
+        let mut valid = true;
+        let mut previous = '0';
+        while is_digit(self.peek()) || self.peek() == '_' {
+            let c = self.advance();
+            if c == '_' {
+                valid = valid && previous != '_';
+            } else {
+                s.push(c);
+            }
+            previous = c;
         }
+        valid && previous != '_'
     }
 
-    fn number(&mut self) -> String {
+    fn number(&mut self) -> Option<String> {
         let mut s = String::new();
-        while is_digit(self.peek()) {
-            s.push(self.advance());
-        }
+        let mut valid = self.digits(&mut s);
 
         if self.peek() == '.' && is_digit(self.peek_next()) {
             s.push(self.advance());
+            valid = self.digits(&mut s) && valid;
         }
 
-        while is_digit(self.peek()) {
-            s.push(self.advance());
+        if valid {
+            Some(s)
+        } else {
+            self.error("Numeric separators must appear between digits.");
+            None
         }
-        s
     }
 
     fn identifier(&mut self) -> String {
@@ -140,7 +229,21 @@ impl Scanner {
             ')' => TokenKind::RightParen,
             '{' => TokenKind::LeftBrace,
             '}' => TokenKind::RightBrace,
+            '[' => TokenKind::LeftBracket,
+            ']' => TokenKind::RightBracket,
+            ':' => TokenKind::Colon,
             ',' => TokenKind::Comma,
+            '.' if self.peek() == '.' && self.peek_next() == '.' => {
+                self.advance();
+                self.advance();
+                TokenKind::Ellipsis
+            }
+            '.' if self.peek() == '.' && self.peek_next() == '=' => {
+                self.advance();
+                self.advance();
+                TokenKind::DotDotEqual
+            }
+            '.' if self.equal('.') => TokenKind::DotDot,
             '.' => TokenKind::Dot,
             '-' => TokenKind::Minus,
             '+' => TokenKind::Plus,
@@ -161,25 +264,33 @@ impl Scanner {
             '<' => TokenKind::Less,
             '>' if self.equal('=') => TokenKind::GreaterEqual,
             '>' => TokenKind::Greater,
+            '?' if self.equal('?') => TokenKind::QuestionQuestion,
+            '?' if self.equal('.') => TokenKind::QuestionDot,
             ' ' | '\r' | '\t' => TokenKind::WhiteSpace,
             '\n' => {
                 self.line += 1;
                 TokenKind::WhiteSpace
             }
-            '"' => {
-                content = self.string();
-                TokenKind::StringT
-            }
-            c if is_digit(c) => {
-                content = self.number();
-                content.insert(0, c);
-                TokenKind::Number
-            }
+            '"' => match self.string() {
+                Some(s) => {
+                    content = s;
+                    TokenKind::StringT
+                }
+                None => TokenKind::Error,
+            },
+            c if is_digit(c) => match self.number() {
+                Some(digits) => {
+                    content = digits;
+                    content.insert(0, c);
+                    TokenKind::Number
+                }
+                None => TokenKind::Error,
+            },
             c if is_alpha(c) => {
                 content.push(c);
                 content = content + &self.identifier();
                 if let Some(keyword) = self.keywords.get(&content) {
-                    keyword.clone()
+                    *keyword
                 } else {
                     TokenKind::Identifier
                 }
@@ -187,25 +298,36 @@ impl Scanner {
             _ => {
                 let mut msg = "Unexpected character: ".to_string();
                 msg.push(c);
-                report(self.line, &msg);
+                self.error(&msg);
                 TokenKind::Error
             }
         };
         Token {
             line: self.line,
             kind,
-            content,
+            content: intern(&content),
         }
     }
 
-    pub fn scan_tokens(&mut self) -> VecDeque<Token> {
-        let mut tokens = VecDeque::new();
+    // Skips a `#!/usr/bin/env lox` first line, leaving its newline to be
+    // counted, so scripts can be run directly.
+    fn skip_shebang(&mut self) {
+        if self.current == 0 && self.source.starts_with(&['#', '!']) {
+            while self.peek() != '\n' && !self.is_at_end() {
+                self.advance();
+            }
+        }
+    }
+
+    pub fn scan_tokens(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        self.skip_shebang();
         while !self.is_at_end() {
             self.start = self.current;
             let t = self.scan_token();
             if let TokenKind::WhiteSpace = t.kind {
             } else {
-                tokens.push_back(t);
+                tokens.push(t);
             }
         }
         tokens
@@ -218,3 +340,67 @@ fn test_alpha_numeric() {
     assert!(is_alpha('t'));
     assert!(is_digit('1'));
 }
+
+#[test]
+fn test_numeric_separators() {
+    let tokens = Scanner::new("1_000_000 3.141_592".to_string()).scan_tokens();
+    let contents: Vec<&str> = tokens.iter().map(|t| &*t.content).collect();
+    assert_eq!(contents, vec!["1000000", "3.141592"]);
+}
+
+#[test]
+fn test_misplaced_numeric_separators() {
+    for source in ["1__000", "1000_", "1_.5", "1.5_"] {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        assert_eq!(tokens[0].kind, TokenKind::Error, "{}", source);
+        assert_eq!(tokens.len(), 1, "{}", source);
+    }
+}
+
+#[test]
+fn test_unicode_source() {
+    let source = "var café = \"naïve ☃\"; // ünïcode comment\nprint café;";
+    let tokens = Scanner::new(source.to_string()).scan_tokens();
+    let contents: Vec<&str> = tokens.iter().map(|t| &*t.content).collect();
+    assert_eq!(contents, vec!["var", "café", "", "naïve ☃", "", "", "print", "café", ""]);
+    assert_eq!(tokens[1].kind, TokenKind::Identifier);
+    assert_eq!(tokens[6].line, 1);
+}
+
+#[test]
+fn test_string_escapes() {
+    let source = r#""a\tb\n\"q\" \\ \u{1F600}\u{e9}""#;
+    let tokens = Scanner::new(source.to_string()).scan_tokens();
+    assert_eq!(&*tokens[0].content, "a\tb\n\"q\" \\ \u{1F600}\u{e9}");
+
+    for (source, message) in [
+        (r#""\q""#, "Invalid escape sequence '\\q'."),
+        (r#""\u{110000}""#, "Invalid unicode escape."),
+        (r#""\u{}""#, "Invalid unicode escape."),
+        (r#""\u1F600""#, "Invalid unicode escape."),
+    ] {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens();
+        assert_eq!(scanner.diagnostics()[0].message, message, "{}", source);
+        assert_eq!(tokens[0].kind, TokenKind::Error, "{}", source);
+    }
+}
+
+#[test]
+fn test_large_source() {
+    let source = "var naïve = \"☃\"; // ünïcode\n".repeat(20_000);
+    let tokens = Scanner::new(source).scan_tokens();
+    assert_eq!(tokens.len(), 6 * 20_000);
+    assert_eq!(tokens.last().unwrap().line, 19_999);
+}
+
+#[test]
+fn test_shebang() {
+    let tokens = Scanner::new("#!/usr/bin/env lox\nprint 1;".to_string()).scan_tokens();
+    assert_eq!(tokens[0].kind, TokenKind::Print);
+    assert_eq!(tokens[0].line, 1);
+
+    let mut scanner = Scanner::new("print 1;\n#!/usr/bin/env lox".to_string());
+    scanner.scan_tokens();
+    assert_eq!(scanner.diagnostics()[0].message, "Unexpected character: #");
+}
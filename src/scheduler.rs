@@ -0,0 +1,41 @@
+use std::collections::VecDeque;
+
+use crate::generator::Generator;
+use crate::token::Token;
+use crate::value::Function;
+
+// Tasks take turns: each runs until it yields or finishes, then waits at the
+// back of the queue. A task is a function that yields, so its generator keeps
+// its place between turns; a function that never yields runs in one turn.
+
+pub(crate) enum Task {
+    // Spawned but not yet called.
+    Start(Function),
+    // Yielded on an earlier turn.
+    Resume(Generator),
+}
+
+/// The tasks started with spawn, in the order they get their turns. Each keeps
+/// the token of its spawn call, for locating the errors it raises.
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: VecDeque<(Task, Token)>,
+}
+
+impl Scheduler {
+    pub(crate) fn push(&mut self, task: Task, token: Token) {
+        self.tasks.push_back((task, token));
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<(Task, Token)> {
+        self.tasks.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
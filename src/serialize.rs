@@ -0,0 +1,314 @@
+//! A compact tagged text encoding of plain-data values. Every value starts with
+//! a one-character tag:
+//!
+//! - `n` nil, `t` true, `f` false
+//! - `i<digits>;` an integer (big integers too, in bignum mode)
+//! - `d<float>;` a float, written with Rust's shortest round-tripping formatting
+//! - `s<byte length>:<bytes>` a string
+//! - `l<length>;` followed by each element
+//! - `o<class name string><field count>;` followed by each field as a name
+//!   string and a value, sorted by name
+//!
+//! Nothing depends on the host locale, and decoding an encoding gives back an
+//! equal value. Functions, builtins and classes cannot be encoded, nor can a
+//! list or instance that contains itself. Lists and instances shared between
+//! several places are encoded once per reference and decode as separate copies.
+
+use std::rc::Rc;
+
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+
+use crate::symbol::{intern, Symbol};
+use crate::value::{IClass, ObjectStruct, Value};
+
+// Deeper values are rejected rather than risk overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+impl Value {
+    pub fn serialize(&self) -> Result<String, String> {
+        let mut out = String::new();
+        let mut visiting = vec![];
+        write_value(self, &mut out, &mut visiting)?;
+        Ok(out)
+    }
+
+    /// find_class resolves the class named in an encoded instance. Decoded
+    /// instances get their fields directly; init is not called.
+    pub fn deserialize(text: &str, find_class: &dyn Fn(&str) -> Option<IClass>) -> Result<Value, String> {
+        let mut reader = Reader {
+            text,
+            position: 0,
+            find_class,
+            depth: 0,
+        };
+        let value = reader.value()?;
+        if reader.position != text.len() {
+            return Err(reader.error("Trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push_str(&format!("s{}:{}", s.len(), s));
+}
+
+fn write_value(value: &Value, out: &mut String, visiting: &mut Vec<*const ()>) -> Result<(), String> {
+    match value {
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(b) => out.push_str(&format!("i{};", b)),
+        Value::Boolean(true) => out.push('t'),
+        Value::Boolean(false) => out.push('f'),
+        Value::Class(class) => return Err(format!("Cannot serialize class {}.", class.borrow().name)),
+        Value::Function(_) => return Err("Cannot serialize a function.".to_string()),
+        Value::Generator(generator) => return Err(format!("Cannot serialize generator {}.", generator.borrow().name)),
+        Value::Namespace(namespace) => return Err(format!("Cannot serialize module {}.", namespace.name)),
+        Value::Range(_) => return Err("Cannot serialize a range.".to_string()),
+        Value::Set(_) => return Err("Cannot serialize a set.".to_string()),
+        Value::Deque(deque) => return Err(format!("Cannot serialize a {}.", deque.borrow().kind.name().to_lowercase())),
+        Value::Integer(i) => out.push_str(&format!("i{};", i)),
+        Value::List(list) => {
+            let pointer = Rc::as_ptr(list) as *const ();
+            if visiting.contains(&pointer) {
+                return Err("Cannot serialize a list that contains itself.".to_string());
+            }
+            visiting.push(pointer);
+            out.push_str(&format!("l{};", list.borrow().len()));
+            for value in list.borrow().iter() {
+                write_value(value, out, visiting)?;
+            }
+            visiting.pop();
+        }
+        Value::Nil => out.push('n'),
+        Value::Number(n) => out.push_str(&format!("d{};", n)),
+        Value::Object(object) => {
+            let pointer = Rc::as_ptr(object) as *const ();
+            if visiting.contains(&pointer) {
+                return Err("Cannot serialize an instance that contains itself.".to_string());
+            }
+            visiting.push(pointer);
+            let object = object.borrow();
+            out.push('o');
+            write_string(&object.class.borrow().name, out);
+            out.push_str(&format!("{};", object.fields.len()));
+            let mut names: Vec<&Symbol> = object.fields.keys().collect();
+            names.sort();
+            for name in names {
+                write_string(name, out);
+                write_value(&object.fields[name], out, visiting)?;
+            }
+            visiting.pop();
+        }
+        Value::StringV(s) => write_string(s, out),
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    position: usize,
+    find_class: &'a dyn Fn(&str) -> Option<IClass>,
+    // Lists and instances the reader is inside.
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{} in encoded value at offset {}.", message, self.position)
+    }
+
+    fn tag(&mut self) -> Result<u8, String> {
+        match self.text.as_bytes().get(self.position) {
+            Some(&tag) => {
+                self.position += 1;
+                Ok(tag)
+            }
+            None => Err(self.error("Unexpected end")),
+        }
+    }
+
+    // Everything up to (and consuming) the terminator.
+    fn until(&mut self, terminator: char) -> Result<&'a str, String> {
+        let rest = &self.text[self.position..];
+        match rest.find(terminator) {
+            Some(end) => {
+                self.position += end + 1;
+                Ok(&rest[..end])
+            }
+            None => Err(self.error("Unterminated field")),
+        }
+    }
+
+    fn count(&mut self, terminator: char) -> Result<usize, String> {
+        let digits = self.until(terminator)?;
+        digits.parse().map_err(|_| self.error("Invalid length"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.tag()? != b's' {
+            return Err(self.error("Expected a string"));
+        }
+        let length = self.count(':')?;
+        let end = self.position.checked_add(length);
+        match end.and_then(|end| Some((end, self.text.get(self.position..end)?))) {
+            Some((end, s)) => {
+                self.position = end;
+                Ok(s.to_string())
+            }
+            None => Err(self.error("Invalid string length")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("Nested too deeply"));
+        }
+        let value = self.tagged_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn tagged_value(&mut self) -> Result<Value, String> {
+        match self.tag()? {
+            b'n' => Ok(Value::Nil),
+            b't' => Ok(Value::Boolean(true)),
+            b'f' => Ok(Value::Boolean(false)),
+            b'i' => {
+                let digits = self.until(';')?;
+                match digits.parse::<i64>() {
+                    Ok(i) => Ok(Value::Integer(i)),
+                    #[cfg(feature = "bignum")]
+                    Err(_) => match digits.parse::<BigInt>() {
+                        Ok(b) => Ok(Value::from_big_integer(b)),
+                        Err(_) => Err(self.error("Invalid integer")),
+                    },
+                    #[cfg(not(feature = "bignum"))]
+                    Err(_) => Err(self.error("Invalid integer")),
+                }
+            }
+            b'd' => {
+                let digits = self.until(';')?;
+                digits.parse().map(Value::Number).map_err(|_| self.error("Invalid float"))
+            }
+            b's' => {
+                self.position -= 1;
+                self.string().map(Value::new_string)
+            }
+            b'l' => {
+                let mut values = vec![];
+                for _ in 0..self.count(';')? {
+                    values.push(self.value()?);
+                }
+                Ok(Value::new_list(values))
+            }
+            b'o' => {
+                let name = self.string()?;
+                let class = match (self.find_class)(&name) {
+                    Some(class) => class,
+                    None => return Err(format!("Undefined class {} in encoded value.", name)),
+                };
+                let object = ObjectStruct::new_object(&class);
+                for _ in 0..self.count(';')? {
+                    let field = self.string()?;
+                    let value = self.value()?;
+                    object.borrow_mut().fields.insert(intern(&field), value);
+                }
+                Ok(Value::Object(object))
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.error("Unknown tag"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::value::IClassStruct;
+
+    fn no_classes(_name: &str) -> Option<IClass> {
+        None
+    }
+
+    fn round_trip(value: &Value) -> Value {
+        Value::deserialize(&value.serialize().unwrap(), &no_classes).unwrap()
+    }
+
+    #[test]
+    fn test_primitives() {
+        let values = [
+            Value::Nil,
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Integer(-42),
+            Value::Number(0.1),
+            Value::Number(-1e300),
+            Value::Number(f64::INFINITY),
+            Value::new_string(""),
+            Value::new_string("a;b:s3:ü"),
+            Value::new_list(vec![]),
+            Value::new_list(vec![Value::Integer(1), Value::new_list(vec![Value::Nil])]),
+        ];
+        for value in values.iter() {
+            assert_eq!(&round_trip(value), value);
+        }
+        assert!(matches!(round_trip(&Value::Number(2.0)), Value::Number(_)));
+        assert_eq!(Value::new_string("héllo").serialize().unwrap(), "s6:héllo");
+    }
+
+    #[test]
+    fn test_instances() {
+        let class = IClassStruct::new_i_class(HashMap::new(), HashMap::new(), "Point", None);
+        let inner = ObjectStruct::new_object(&class);
+        inner.borrow_mut().fields.insert(intern("x"), Value::Integer(1));
+        let outer = ObjectStruct::new_object(&class);
+        outer.borrow_mut().fields.insert(intern("y"), Value::new_string("two"));
+        outer.borrow_mut().fields.insert(intern("inner"), Value::Object(inner));
+
+        let text = Value::Object(outer.clone()).serialize().unwrap();
+        assert_eq!(text, "os5:Point2;s5:inneros5:Point1;s1:xi1;s1:ys3:two");
+        let find_class = |name: &str| if name == "Point" { Some(class.clone()) } else { None };
+        let decoded = Value::deserialize(&text, &find_class).unwrap();
+        assert_eq!(decoded.serialize().unwrap(), text);
+        assert!(Value::deserialize(&text, &no_classes).is_err());
+    }
+
+    #[test]
+    fn test_rejected() {
+        let class = IClassStruct::new_i_class(HashMap::new(), HashMap::new(), "Node", None);
+        assert!(Value::Class(class.clone()).serialize().is_err());
+
+        let node = ObjectStruct::new_object(&class);
+        node.borrow_mut().fields.insert(intern("next"), Value::Object(node.clone()));
+        assert!(Value::Object(node.clone()).serialize().is_err());
+        node.borrow_mut().fields.clear();
+
+        let list = Value::new_list(vec![]);
+        if let Value::List(elements) = &list {
+            elements.borrow_mut().push(list.clone());
+        }
+        assert!(list.serialize().is_err());
+        if let Value::List(elements) = &list {
+            elements.borrow_mut().clear();
+        }
+
+        for text in ["", "x", "i12", "s5:abc", "s18446744073709551615:", "d1.5;n", "o"] {
+            assert!(Value::deserialize(text, &no_classes).is_err());
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}i1;", "l1;".repeat(depth));
+        assert!(Value::deserialize(&nested(MAX_DEPTH - 1), &no_classes).is_ok());
+        let error = Value::deserialize(&nested(MAX_DEPTH), &no_classes).unwrap_err();
+        assert!(error.starts_with("Nested too deeply"), "{}", error);
+        assert!(Value::deserialize(&nested(300_000), &no_classes).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! Interned names. Identifiers, field and method names, and the keys of every
+//! scope are symbols: shared strings that are allocated once per distinct name
+//! on a thread, so storing a name under another key or cloning a token only
+//! bumps a reference count. Equal symbols from intern are the same allocation,
+//! which Rc's equality checks before comparing bytes.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+pub type Symbol = Rc<str>;
+
+thread_local! {
+    static SYMBOLS: RefCell<HashSet<Symbol>> = RefCell::new(HashSet::new());
+}
+
+/// The symbol for name, shared with every other use of the same name.
+pub fn intern(name: &str) -> Symbol {
+    SYMBOLS.with(|symbols| {
+        let mut symbols = symbols.borrow_mut();
+        if let Some(symbol) = symbols.get(name) {
+            return symbol.clone();
+        }
+        let symbol: Symbol = Rc::from(name);
+        symbols.insert(symbol.clone());
+        symbol
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = intern("name");
+        let b = intern(&String::from("name"));
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(&*a, "name");
+        assert!(!Rc::ptr_eq(&a, &intern("other")));
+    }
+}
@@ -0,0 +1,195 @@
+//! Helpers for testing Lox scripts, for this crate and for hosts embedding it.
+//! The test_* helpers panic as soon as any stage fails; run_and_capture instead
+//! collects everything a script printed or reported.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::ast::Ast;
+use crate::dead_code::eliminate_dead_code;
+use crate::error::Diagnostic;
+use crate::fold::fold_constants;
+use crate::interp_error::InterpError;
+use crate::interpreter::{Backend, Interpreter};
+use crate::parser::Parser;
+use crate::platform::StdPlatform;
+use crate::resolver::{Bindings, Resolver};
+use crate::scanner::Scanner;
+use crate::symbol::intern;
+use crate::token::{Token, TokenKind};
+use crate::value::Value;
+
+pub fn new_var(s: &str) -> Token {
+    Token {
+        kind: TokenKind::Identifier,
+        line: 0,
+        content: intern(s),
+    }
+}
+
+pub fn scan_parse(s: &str) -> Ast {
+    let mut parser = Parser::new(Scanner::new(s.to_string()).scan_tokens());
+    match parser.parse() {
+        Ok(ast) => ast,
+        Err(()) => panic!("Parse failed: {:?}", parser.diagnostics()),
+    }
+}
+
+pub fn test_resolve(code: &str) -> Bindings {
+    let mut ast = scan_parse(code);
+    let mut resolver = Resolver::new_with_bindings();
+    resolver.run(&mut ast).unwrap();
+    resolver.take_bindings().unwrap()
+}
+
+pub fn test_run(code: &str) -> Interpreter {
+    let mut ast = scan_parse(code);
+    let mut resolver = Resolver::new();
+    resolver.run(&mut ast).unwrap();
+    fold_constants(&mut ast);
+    eliminate_dead_code(&mut ast);
+    let mut interpreter = Interpreter::new();
+    interpreter.run(ast).unwrap();
+    interpreter
+}
+
+pub fn test_interpret(code: &str, variable_name: &str) -> Value {
+    test_run(code)
+        .global(variable_name)
+        .expect("variable not found.")
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Everything observable about one script run.
+pub struct RunOutcome {
+    pub stdout: String,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Unreachable code removed before the script ran.
+    pub warnings: Vec<Diagnostic>,
+    /// The status code the script passed to exit, if it called it.
+    pub exit_code: Option<i32>,
+    interpreter: Interpreter,
+}
+
+impl RunOutcome {
+    /// The value of a global once the script has stopped, if it was defined.
+    pub fn value_of(&self, name: &str) -> Option<Value> {
+        self.interpreter.global(name)
+    }
+
+    /// The interpreter the script ran on.
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+}
+
+/// Runs source through every stage, stopping at the first stage that reports
+/// errors, and captures what print statements wrote.
+pub fn run_and_capture(source: &str) -> RunOutcome {
+    run_and_capture_on(source, Backend::TreeWalker)
+}
+
+/// run_and_capture with the given backend, to check that both agree.
+pub fn run_and_capture_on(source: &str, backend: Backend) -> RunOutcome {
+    run_and_capture_with(source, |interpreter| interpreter.set_backend(backend))
+}
+
+/// run_and_capture on an interpreter set up by setup first.
+pub fn run_and_capture_with(source: &str, setup: impl FnOnce(&mut Interpreter)) -> RunOutcome {
+    let stdout = SharedBuffer::default();
+    let mut interpreter = Interpreter::new();
+    setup(&mut interpreter);
+    interpreter.set_platform(Box::new(StdPlatform::with_output(Box::new(stdout.clone()))));
+    let mut warnings = Vec::new();
+    let (diagnostics, exit_code) = run_stages(source, &mut interpreter, &mut warnings);
+    let stdout = String::from_utf8_lossy(&stdout.0.borrow()).into_owned();
+    RunOutcome {
+        stdout,
+        diagnostics,
+        warnings,
+        exit_code,
+        interpreter,
+    }
+}
+
+fn run_stages(
+    source: &str,
+    interpreter: &mut Interpreter,
+    warnings: &mut Vec<Diagnostic>,
+) -> (Vec<Diagnostic>, Option<i32>) {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens();
+    let mut parser = Parser::new(tokens);
+    let parsed = parser.parse();
+    let mut diagnostics = scanner.diagnostics().to_vec();
+    diagnostics.extend_from_slice(parser.diagnostics());
+    let mut ast = match parsed {
+        Ok(ast) if diagnostics.is_empty() => ast,
+        _ => return (diagnostics, None),
+    };
+
+    if let Err(error) = Resolver::new().run(&mut ast) {
+        return (vec![error.diagnostic()], None);
+    }
+    fold_constants(&mut ast);
+    *warnings = eliminate_dead_code(&mut ast);
+
+    let diagnostics = match interpreter.run(ast) {
+        Ok(()) => vec![],
+        Err(InterpError::Error(error)) => vec![error.diagnostic()],
+        Err(InterpError::Return(_)) => vec![Diagnostic::new(0, "Can't return from top-level code.")],
+        // The resolver rejects a break or continue with no loop to unwind to.
+        Err(InterpError::Break(_)) | Err(InterpError::Continue(_)) => unreachable!(),
+        Err(InterpError::Thrown(value, token)) => {
+            let message = match interpreter.stringify(&value, &token) {
+                Ok(text) => format!("Uncaught exception: {}", text),
+                Err(_) => format!("Uncaught exception: {}", value),
+            };
+            vec![Diagnostic::new(token.line, &message)]
+        }
+        Err(InterpError::Timeout) => vec![Diagnostic::new(0, "Execution timed out.")],
+        Err(InterpError::Interrupted) => vec![Diagnostic::new(0, "Execution interrupted.")],
+        Err(InterpError::OutOfFuel) => vec![Diagnostic::new(0, "Execution ran out of fuel.")],
+        Err(InterpError::Exit(code)) => return (vec![], Some(code)),
+    };
+    (diagnostics, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_and_capture() {
+        let outcome = run_and_capture("var a = 1 + 2; print a; print \"done\";");
+        assert_eq!(outcome.stdout, "3\ndone\n");
+        assert_eq!(outcome.value_of("a"), Some(Value::Integer(3)));
+        assert!(outcome.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_capture_diagnostics() {
+        let outcome = run_and_capture("print 1;\nprint nope;");
+        assert_eq!(outcome.stdout, "1\n");
+        assert_eq!(outcome.diagnostics, vec![Diagnostic::new(1, "Variable not found.")]);
+
+        let outcome = run_and_capture("var a = ;");
+        assert_eq!(outcome.stdout, "");
+        assert_eq!(outcome.diagnostics.len(), 1);
+        assert_eq!(outcome.value_of("a"), None);
+    }
+}
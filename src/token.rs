@@ -1,8 +1,11 @@
+
+use crate::symbol::{intern, Symbol};
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
     pub line: usize,
-    pub content: String,
+    pub content: Symbol,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -11,8 +14,15 @@ pub enum TokenKind {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
+    DotDot,
+    DotDotEqual,
+    Ellipsis,
+    QuestionDot,
     Minus,
     Plus,
     Semicolon,
@@ -27,25 +37,209 @@ pub enum TokenKind {
     Less,
     GreaterEqual,
     Greater,
+    QuestionQuestion,
     WhiteSpace,
     StringT,
     Number,
     Identifier,
     And,
+    Assert,
+    Break,
+    Case,
+    Catch,
     Class,
+    Const,
+    Continue,
     Else,
     False,
+    Finally,
     For,
     Fun,
     If,
+    Import,
+    In,
+    Interface,
+    Is,
+    Match,
     Nil,
     Or,
     Print,
     Return,
+    Static,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
+    With,
+    Yield,
     Error,
 }
+
+// Every kind, for reading token streams back in; keep in step with TokenKind.
+const TOKEN_KINDS: [TokenKind; 66] = [
+    TokenKind::LeftParen,
+    TokenKind::RightParen,
+    TokenKind::LeftBrace,
+    TokenKind::RightBrace,
+    TokenKind::LeftBracket,
+    TokenKind::RightBracket,
+    TokenKind::Colon,
+    TokenKind::Comma,
+    TokenKind::Dot,
+    TokenKind::DotDot,
+    TokenKind::DotDotEqual,
+    TokenKind::Ellipsis,
+    TokenKind::QuestionDot,
+    TokenKind::Minus,
+    TokenKind::Plus,
+    TokenKind::Semicolon,
+    TokenKind::Star,
+    TokenKind::Slash,
+    TokenKind::Comment,
+    TokenKind::BangEqual,
+    TokenKind::Bang,
+    TokenKind::EqualEqual,
+    TokenKind::Equal,
+    TokenKind::LessEqual,
+    TokenKind::Less,
+    TokenKind::GreaterEqual,
+    TokenKind::Greater,
+    TokenKind::QuestionQuestion,
+    TokenKind::WhiteSpace,
+    TokenKind::StringT,
+    TokenKind::Number,
+    TokenKind::Identifier,
+    TokenKind::And,
+    TokenKind::Assert,
+    TokenKind::Break,
+    TokenKind::Case,
+    TokenKind::Catch,
+    TokenKind::Class,
+    TokenKind::Const,
+    TokenKind::Continue,
+    TokenKind::Else,
+    TokenKind::False,
+    TokenKind::Finally,
+    TokenKind::For,
+    TokenKind::Fun,
+    TokenKind::If,
+    TokenKind::Import,
+    TokenKind::In,
+    TokenKind::Interface,
+    TokenKind::Is,
+    TokenKind::Match,
+    TokenKind::Nil,
+    TokenKind::Or,
+    TokenKind::Print,
+    TokenKind::Return,
+    TokenKind::Static,
+    TokenKind::Super,
+    TokenKind::This,
+    TokenKind::Throw,
+    TokenKind::True,
+    TokenKind::Try,
+    TokenKind::Var,
+    TokenKind::While,
+    TokenKind::With,
+    TokenKind::Yield,
+    TokenKind::Error,
+];
+
+impl TokenKind {
+    fn from_name(name: &str) -> Option<TokenKind> {
+        TOKEN_KINDS.iter().copied().find(|kind| format!("{:?}", kind) == name)
+    }
+
+    /// The source text of an operator. Operator tokens leave content empty.
+    pub fn operator(self) -> &'static str {
+        match self {
+            TokenKind::Minus => "-",
+            TokenKind::Plus => "+",
+            TokenKind::Star => "*",
+            TokenKind::Slash => "/",
+            TokenKind::Bang => "!",
+            TokenKind::BangEqual => "!=",
+            TokenKind::EqualEqual => "==",
+            TokenKind::Less => "<",
+            TokenKind::LessEqual => "<=",
+            TokenKind::Greater => ">",
+            TokenKind::GreaterEqual => ">=",
+            TokenKind::And => "and",
+            TokenKind::Or => "or",
+            TokenKind::Is => "is",
+            TokenKind::In => "in",
+            TokenKind::QuestionQuestion => "??",
+            TokenKind::DotDot => "..",
+            TokenKind::DotDotEqual => "..=",
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Writes a token stream as text, one `kind line content` token per line, so a
+/// scanner run can be saved and replayed into the parser on its own.
+pub fn dump_tokens(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let mut content = String::new();
+        for c in token.content.chars() {
+            match c {
+                '\\' => content.push_str("\\\\"),
+                '\n' => content.push_str("\\n"),
+                '\r' => content.push_str("\\r"),
+                '\t' => content.push_str("\\t"),
+                c => content.push(c),
+            }
+        }
+        out.push_str(&format!("{:?}\t{}\t{}\n", token.kind, token.line, content));
+    }
+    out
+}
+
+/// Reads back a token stream written by dump_tokens.
+pub fn load_tokens(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = || format!("Malformed token on line {} of the token stream.", i + 1);
+        let mut fields = line.splitn(3, '\t');
+        let kind = fields.next().and_then(TokenKind::from_name).ok_or_else(error)?;
+        let line = fields.next().and_then(|l| l.parse().ok()).ok_or_else(error)?;
+        let escaped = fields.next().ok_or_else(error)?;
+        let mut content = String::new();
+        let mut chars = escaped.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                content.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('\\') => content.push('\\'),
+                Some('n') => content.push('\n'),
+                Some('r') => content.push('\r'),
+                Some('t') => content.push('\t'),
+                _ => return Err(error()),
+            }
+        }
+        tokens.push(Token { kind, line, content: intern(&content) });
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_token_stream_round_trip() {
+        let source = "var a = \"tab\\there\\nand\n\\\\ line\"; // note\nprint a ?? 1.5;";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let text = dump_tokens(&tokens);
+        assert_eq!(load_tokens(&text), Ok(tokens));
+        assert!(load_tokens("Bogus\t1\t").is_err());
+        assert!(load_tokens("Identifier\tx\ta").is_err());
+    }
+}
@@ -1,28 +1,359 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::mem;
 use std::cmp;
-use std::rc::Rc;
+use std::hash::{Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+#[cfg(feature = "bignum")]
+use num_traits::ToPrimitive;
 
 use crate::ast::FunDeclaration;
+use crate::chunk::Closure;
 use crate::environment::Environment;
+use crate::gc::{self, Id, Trace};
+use crate::generator::Generator;
+use crate::interp_error::InterpResult;
+use crate::interpreter::Interpreter;
+use crate::memory::{self, Kind};
+use crate::symbol::Symbol;
+use crate::token::Token;
 
 pub type IClass = Rc<RefCell<IClassStruct>>;
 
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub struct IClassStruct {
     pub name: String,
-    pub methods: HashMap<String, UserDefined>,
+    // The class's own methods, shared so property caches can point at one
+    // without keeping it alive. Each is held by only this class's tables and
+    // those of its subclasses.
+    pub methods: HashMap<Symbol, Rc<UserDefined>>,
+    pub setters: HashMap<Symbol, Rc<UserDefined>>,
+    // Static fields, read and written through the class value.
+    pub fields: HashMap<Symbol, Value>,
     pub superclass: Option<IClass>,
+    // Every method and setter the class has, its own or inherited, with
+    // overrides applied, so finding one never walks the superclasses.
+    pub(crate) all_methods: HashMap<Symbol, Rc<UserDefined>>,
+    pub(crate) all_setters: HashMap<Symbol, Rc<UserDefined>>,
+}
+
+// A static field can hold an instance of its own class, so only field names
+// are shown.
+impl fmt::Debug for IClassStruct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IClassStruct")
+            .field("name", &self.name)
+            .field("methods", &self.methods)
+            .field("setters", &self.setters)
+            .field("fields", &self.fields.keys().collect::<Vec<_>>())
+            .field("superclass", &self.superclass)
+            .finish()
+    }
 }
 
 impl IClassStruct {
-    pub fn new_i_class(methods: HashMap<String, UserDefined>, name: &str, superclass: Option<IClass>) -> IClass {
-        Rc::new(RefCell::new(IClassStruct {
+    /// Makes a class, with its methods flattened into one table along with
+    /// those it inherits.
+    pub fn new_i_class(
+        methods: HashMap<Symbol, Rc<UserDefined>>,
+        setters: HashMap<Symbol, Rc<UserDefined>>,
+        name: &str,
+        superclass: Option<IClass>,
+    ) -> IClass {
+        memory::created(Kind::Class);
+        let (mut all_methods, mut all_setters) = match &superclass {
+            Some(superclass) => {
+                let superclass = superclass.borrow();
+                (superclass.all_methods.clone(), superclass.all_setters.clone())
+            }
+            None => (HashMap::new(), HashMap::new()),
+        };
+        all_methods.extend(methods.iter().map(|(name, method)| (name.clone(), method.clone())));
+        all_setters.extend(setters.iter().map(|(name, setter)| (name.clone(), setter.clone())));
+        let class = Rc::new(RefCell::new(IClassStruct {
             methods,
+            setters,
             name: name.to_string(),
+            fields: HashMap::new(),
             superclass,
-        }))
+            all_methods,
+            all_setters,
+        }));
+        gc::track(&class);
+        class
+    }
+}
+
+/// What a property access last found on an instance: its class, and the
+/// method of the property's name the class has, if any. Later accesses on
+/// instances of the same class take the method from here instead of looking
+/// it up in the class and its superclasses. Nothing is kept alive by it.
+#[derive(Default)]
+pub struct PropertyCache(RefCell<Option<CachedMethod>>);
+
+struct CachedMethod {
+    class: Weak<RefCell<IClassStruct>>,
+    method: Option<Weak<UserDefined>>,
+}
+
+impl PropertyCache {
+    pub fn find_method(&self, class: &IClass, name: &str) -> Option<Rc<UserDefined>> {
+        if let Some(cached) = &*self.0.borrow() {
+            // The Weak keeps the class's allocation, so no other class can
+            // have taken its address.
+            if cached.class.as_ptr() == Rc::as_ptr(class) {
+                match cached.method.as_ref().map(Weak::upgrade) {
+                    None => return None,
+                    Some(Some(method)) => return Some(method),
+                    // Collected along with a cycle the class was in.
+                    Some(None) => {}
+                }
+            }
+        }
+        let method = class.borrow().find_method(name);
+        *self.0.borrow_mut() = Some(CachedMethod {
+            class: Rc::downgrade(class),
+            method: method.as_ref().map(Rc::downgrade),
+        });
+        method
+    }
+}
+
+// Caches say nothing about the program, so any two are alike.
+impl fmt::Debug for PropertyCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PropertyCache")
+    }
+}
+
+impl PartialEq for PropertyCache {
+    fn eq(&self, _: &PropertyCache) -> bool {
+        true
+    }
+}
+
+impl Drop for IClassStruct {
+    fn drop(&mut self) {
+        memory::dropped(Kind::Class);
+    }
+}
+
+impl Trace for RefCell<IClassStruct> {
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool {
+        let Ok(class) = self.try_borrow() else {
+            return false;
+        };
+        // Inherited methods are the superclass's to account for.
+        for method in class.methods.values().chain(class.setters.values()) {
+            visit(method.environment.gc_id());
+        }
+        class.fields.values().for_each(|value| gc::trace_value(value, visit));
+        if let Some(superclass) = &class.superclass {
+            visit(gc::shared(superclass));
+        }
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut class) = self.try_borrow_mut() {
+            let contents = (
+                mem::take(&mut class.methods),
+                mem::take(&mut class.setters),
+                mem::take(&mut class.fields),
+                class.superclass.take(),
+                mem::take(&mut class.all_methods),
+                mem::take(&mut class.all_setters),
+            );
+            drop(class);
+            drop(contents);
+        }
+    }
+}
+
+pub type List = Rc<RefCell<Vec<Value>>>;
+
+impl Trace for RefCell<Vec<Value>> {
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool {
+        let Ok(list) = self.try_borrow() else {
+            return false;
+        };
+        list.iter().for_each(|value| gc::trace_value(value, visit));
+        true
+    }
+
+    fn clear(&self) {
+        let contents = self.try_borrow_mut().map(|mut list| mem::take(&mut *list));
+        drop(contents);
+    }
+}
+
+// Named apart from ast::Set, the property assignment expression.
+pub type ValueSet = Rc<RefCell<SetStruct>>;
+
+/// Distinct values, kept in the order they were first added. Members are told
+/// apart as memoize's keys are: numbers by value, lists and instances by
+/// identity.
+#[derive(Debug, Default)]
+pub struct SetStruct {
+    members: Vec<Value>,
+    keys: HashSet<ValueKey>,
+}
+
+impl SetStruct {
+    pub fn new_set(values: impl IntoIterator<Item = Value>) -> ValueSet {
+        let mut set = SetStruct::default();
+        for value in values {
+            set.insert(value);
+        }
+        let set = Rc::new(RefCell::new(set));
+        gc::track(&set);
+        set
+    }
+
+    /// Adds value, returning false if it was already a member.
+    pub fn insert(&mut self, value: Value) -> bool {
+        let added = self.keys.insert(ValueKey(value.clone()));
+        if added {
+            self.members.push(value);
+        }
+        added
+    }
+
+    /// Removes value, returning false if it was not a member.
+    pub fn remove(&mut self, value: &Value) -> bool {
+        let key = ValueKey(value.clone());
+        let removed = self.keys.remove(&key);
+        if removed {
+            self.members.retain(|member| ValueKey(member.clone()) != key);
+        }
+        removed
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        self.keys.contains(&ValueKey(value.clone()))
+    }
+
+    pub fn members(&self) -> &[Value] {
+        &self.members
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+// Each member is held twice, once in order and once as a key.
+impl Trace for RefCell<SetStruct> {
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool {
+        let Ok(set) = self.try_borrow() else {
+            return false;
+        };
+        set.members.iter().for_each(|value| gc::trace_value(value, visit));
+        set.keys.iter().for_each(|key| gc::trace_value(&key.0, visit));
+        true
+    }
+
+    fn clear(&self) {
+        let contents = self.try_borrow_mut().map(|mut set| mem::take(&mut *set));
+        drop(contents);
+    }
+}
+
+pub type Deque = Rc<RefCell<DequeStruct>>;
+
+/// Which end of a deque pop and peek take from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DequeKind {
+    /// First in, first out.
+    Queue,
+    /// Last in, first out.
+    Stack,
+}
+
+impl DequeKind {
+    /// The name of the global constructing this kind, and of its type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DequeKind::Queue => "Queue",
+            DequeKind::Stack => "Stack",
+        }
+    }
+}
+
+/// A queue or stack. Values are pushed onto the back and popped from the
+/// front of a queue or the back of a stack, in constant time either way.
+#[derive(Debug, PartialEq)]
+pub struct DequeStruct {
+    pub kind: DequeKind,
+    items: VecDeque<Value>,
+}
+
+impl DequeStruct {
+    pub fn new_deque(kind: DequeKind, values: impl IntoIterator<Item = Value>) -> Deque {
+        let deque = Rc::new(RefCell::new(DequeStruct {
+            kind,
+            items: values.into_iter().collect(),
+        }));
+        gc::track(&deque);
+        deque
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.items.push_back(value);
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        match self.kind {
+            DequeKind::Queue => self.items.pop_front(),
+            DequeKind::Stack => self.items.pop_back(),
+        }
+    }
+
+    /// The value pop would take next.
+    pub fn peek(&self) -> Option<&Value> {
+        match self.kind {
+            DequeKind::Queue => self.items.front(),
+            DequeKind::Stack => self.items.back(),
+        }
+    }
+
+    /// Every value, in the order pop would take them.
+    pub fn to_vec(&self) -> Vec<Value> {
+        match self.kind {
+            DequeKind::Queue => self.items.iter().cloned().collect(),
+            DequeKind::Stack => self.items.iter().rev().cloned().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl Trace for RefCell<DequeStruct> {
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool {
+        let Ok(deque) = self.try_borrow() else {
+            return false;
+        };
+        deque.items.iter().for_each(|value| gc::trace_value(value, visit));
+        true
+    }
+
+    fn clear(&self) {
+        let contents = self.try_borrow_mut().map(|mut deque| mem::take(&mut deque.items));
+        drop(contents);
     }
 }
 
@@ -31,15 +362,42 @@ pub type Object = Rc<RefCell<ObjectStruct>>;
 #[derive(Debug, PartialEq)]
 pub struct ObjectStruct {
     pub class: IClass,
-    pub fields: HashMap<String, Value>,
+    pub fields: HashMap<Symbol, Value>,
 }
 
 impl ObjectStruct {
     pub fn new_object(class: &IClass) -> Object {
-        Rc::new(RefCell::new(ObjectStruct {
+        memory::created(Kind::Object);
+        let object = Rc::new(RefCell::new(ObjectStruct {
             class: class.clone(),
             fields: HashMap::new(),
-        }))
+        }));
+        gc::track(&object);
+        object
+    }
+}
+
+impl Drop for ObjectStruct {
+    fn drop(&mut self) {
+        memory::dropped(Kind::Object);
+    }
+}
+
+// An instance holds its class, which can only be in a cycle with it through
+// the class's own contents, so only the fields are cleared.
+impl Trace for RefCell<ObjectStruct> {
+    fn trace(&self, visit: &mut dyn FnMut(Id)) -> bool {
+        let Ok(object) = self.try_borrow() else {
+            return false;
+        };
+        visit(gc::shared(&object.class));
+        object.fields.values().for_each(|value| gc::trace_value(value, visit));
+        true
+    }
+
+    fn clear(&self) {
+        let contents = self.try_borrow_mut().map(|mut object| mem::take(&mut object.fields));
+        drop(contents);
     }
 }
 
@@ -62,24 +420,315 @@ impl cmp::PartialEq for UserDefined {
    }
 }
 
+/// The top-level names of an imported module, bound by
+/// `import "path" as name;` and read through the module's globals.
+#[derive(Clone)]
+pub struct Namespace {
+    pub name: String,
+    pub environment: Environment,
+    pub members: Vec<Symbol>,
+}
+
+impl Namespace {
+    pub fn has_member(&self, name: &str) -> bool {
+        self.members.iter().any(|member| &**member == name)
+    }
+}
+
+impl fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Namespace({})", self.name)
+    }
+}
+
+// Two namespaces are the same when they are the same module.
+impl cmp::PartialEq for Namespace {
+    fn eq(&self, other: &Namespace) -> bool {
+        self.environment.id() == other.environment.id()
+    }
+}
+
+/// `start..end`, or `start..=end` when inclusive: the integers from start
+/// counting up by one to end. Empty when end comes first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    pub fn contains(&self, i: i64) -> bool {
+        if self.inclusive {
+            (self.start..=self.end).contains(&i)
+        } else {
+            (self.start..self.end).contains(&i)
+        }
+    }
+
+    /// The integer after i in the range, if any.
+    pub fn after(&self, i: i64) -> Option<i64> {
+        i.checked_add(1).filter(|next| self.contains(*next))
+    }
+
+    /// How many integers the range holds, which can be more than an i64 holds.
+    pub fn len(&self) -> i128 {
+        let end = self.end as i128 + if self.inclusive { 1 } else { 0 };
+        (end - self.start as i128).max(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn first(&self) -> Option<i64> {
+        Some(self.start).filter(|start| self.contains(*start))
+    }
+
+    pub fn to_vec(&self) -> Vec<Value> {
+        let mut values = Vec::new();
+        let mut i = self.first();
+        while let Some(current) = i {
+            values.push(Value::Integer(current));
+            i = self.after(current);
+        }
+        values
+    }
+}
+
+pub type BuiltinFn = fn(&mut Interpreter, Vec<Value>, &Token) -> InterpResult;
+
+/// A function implemented in Rust. The token passed to it is the call's closing
+/// paren, for attaching runtime errors. A variadic builtin takes arity or more
+/// arguments.
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    pub variadic: bool,
+    pub function: BuiltinFn,
+}
+
+impl Builtin {
+    pub fn new(name: &'static str, arity: usize, function: BuiltinFn) -> Builtin {
+        Builtin {
+            name,
+            arity,
+            variadic: false,
+            function,
+        }
+    }
+
+    pub fn new_variadic(name: &'static str, arity: usize, function: BuiltinFn) -> Builtin {
+        Builtin {
+            variadic: true,
+            ..Builtin::new(name, arity, function)
+        }
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl cmp::PartialEq for Builtin {
+    fn eq(&self, other: &Builtin) -> bool {
+        self.name == other.name
+    }
+}
+
+/// A function wrapped by the memoize native, with the results it has returned
+/// so far keyed by its arguments.
+#[derive(Clone)]
+pub struct Memoized {
+    pub function: Box<Function>,
+    pub cache: Rc<RefCell<HashMap<Vec<ValueKey>, Value>>>,
+}
+
+impl fmt::Debug for Memoized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Memoized({:?})", self.function)
+    }
+}
+
+impl cmp::PartialEq for Memoized {
+    fn eq(&self, other: &Memoized) -> bool {
+        Rc::ptr_eq(&self.cache, &other.cache)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Function {
     UserDefined(UserDefined),
-    Builtin,
+    Builtin(Rc<Builtin>),
+    // A builtin method looked up on a value; the receiver is passed as the
+    // first argument.
+    BoundBuiltin(Rc<Builtin>, Box<Value>),
+    Memoized(Memoized),
+    // A function compiled for the VM backend.
+    Compiled(Rc<Closure>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Function {
+    pub fn arity(&self) -> usize {
+        match self {
+            Function::UserDefined(user_defined) => user_defined.declaration.borrow().params.len(),
+            Function::Builtin(builtin) | Function::BoundBuiltin(builtin, _) => builtin.arity,
+            Function::Memoized(memoized) => memoized.function.arity(),
+            Function::Compiled(compiled) => compiled.function.arity,
+        }
+    }
+
+    /// Whether the function takes any number of arguments from its arity up.
+    pub fn is_variadic(&self) -> bool {
+        match self {
+            Function::UserDefined(_) | Function::Compiled(_) => false,
+            Function::Builtin(builtin) | Function::BoundBuiltin(builtin, _) => builtin.variadic,
+            Function::Memoized(memoized) => memoized.function.is_variadic(),
+        }
+    }
+
+    /// The name the function was declared or registered with.
+    pub fn name(&self) -> String {
+        match self {
+            Function::UserDefined(user_defined) => user_defined.declaration.borrow().name.content.to_string(),
+            Function::Builtin(builtin) | Function::BoundBuiltin(builtin, _) => builtin.name.to_string(),
+            Function::Memoized(memoized) => memoized.function.name(),
+            Function::Compiled(compiled) => compiled.function.name.clone(),
+        }
+    }
+}
+
+/// A Lox value. Payloads larger than three words are kept behind a pointer, so
+/// the copy made on each read of a variable or stack slot is four words at
+/// most, and numbers, booleans and nil are copied without touching the heap.
+#[derive(Debug, Clone)]
 pub enum Value {
+    // Only produced in bignum mode, for integers that do not fit in an i64.
+    #[cfg(feature = "bignum")]
+    BigInteger(Box<BigInt>),
     Boolean(bool),
     Class(IClass),
+    Deque(Deque),
     Function(Function),
+    Generator(Generator),
+    Integer(i64),
+    List(List),
+    Namespace(Rc<Namespace>),
     Nil,
     Number(f64),
     Object(Object),
-    StringV(String),
+    Range(Range),
+    Set(ValueSet),
+    StringV(Rc<str>),
+}
+
+// Integers and floats compare by numeric value, so `1 == 1.0` holds in Lox.
+impl cmp::PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        equal(self, other, &mut HashSet::new())
+    }
+}
+
+// Compares lists and deques element by element. A pair already being compared
+// further up is taken to be equal, so comparing lists that contain themselves
+// ends.
+fn equal(a: &Value, b: &Value, comparing: &mut HashSet<(*const (), *const ())>) -> bool {
+    let mut elements_equal = |p1: *const (), p2: *const (), e1: &[Value], e2: &[Value]| {
+        if p1 == p2 || !comparing.insert((p1, p2)) {
+            return true;
+        }
+        let equal = e1.len() == e2.len() && e1.iter().zip(e2).all(|(v1, v2)| equal(v1, v2, comparing));
+        comparing.remove(&(p1, p2));
+        equal
+    };
+    match (a, b) {
+        #[cfg(feature = "bignum")]
+        (Value::BigInteger(b1), Value::BigInteger(b2)) => b1 == b2,
+        #[cfg(feature = "bignum")]
+        (Value::BigInteger(_), Value::Number(n)) | (Value::Number(n), Value::BigInteger(_)) => {
+            a.as_number() == Some(*n) && b.as_number() == Some(*n)
+        }
+        (Value::Boolean(b1), Value::Boolean(b2)) => b1 == b2,
+        (Value::Class(c1), Value::Class(c2)) => c1 == c2,
+        (Value::Deque(d1), Value::Deque(d2)) => {
+            let (p1, p2) = (Rc::as_ptr(d1) as *const (), Rc::as_ptr(d2) as *const ());
+            let (d1, d2) = (d1.borrow(), d2.borrow());
+            d1.kind == d2.kind && elements_equal(p1, p2, &d1.to_vec(), &d2.to_vec())
+        }
+        (Value::Function(f1), Value::Function(f2)) => f1 == f2,
+        (Value::Generator(g1), Value::Generator(g2)) => Rc::ptr_eq(g1, g2),
+        (Value::Integer(i1), Value::Integer(i2)) => i1 == i2,
+        (Value::Integer(i), Value::Number(n)) | (Value::Number(n), Value::Integer(i)) => *i as f64 == *n,
+        (Value::List(l1), Value::List(l2)) => {
+            let (p1, p2) = (Rc::as_ptr(l1) as *const (), Rc::as_ptr(l2) as *const ());
+            elements_equal(p1, p2, &l1.borrow(), &l2.borrow())
+        }
+        (Value::Namespace(n1), Value::Namespace(n2)) => n1 == n2,
+        (Value::Nil, Value::Nil) => true,
+        (Value::Number(n1), Value::Number(n2)) => n1 == n2,
+        (Value::Object(o1), Value::Object(o2)) => Rc::ptr_eq(o1, o2),
+        (Value::Range(r1), Value::Range(r2)) => r1 == r2,
+        (Value::Set(s1), Value::Set(s2)) => {
+            let (s1, s2) = (s1.borrow(), s2.borrow());
+            s1.len() == s2.len() && s1.members().iter().all(|member| s2.contains(member))
+        }
+        (Value::StringV(s1), Value::StringV(s2)) => s1 == s2,
+        _ => false,
+    }
 }
 
 impl Value {
+    /// Widens either numeric representation to a float for mixed arithmetic.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            #[cfg(feature = "bignum")]
+            Value::BigInteger(b) => b.to_f64(),
+            Value::Integer(i) => Some(*i as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Widens either integer representation for exact arithmetic in bignum mode.
+    #[cfg(feature = "bignum")]
+    pub fn as_big_integer(&self) -> Option<BigInt> {
+        match self {
+            Value::BigInteger(b) => Some((**b).clone()),
+            Value::Integer(i) => Some(BigInt::from(*i)),
+            _ => None,
+        }
+    }
+
+    /// Narrows a big result back to an Integer whenever it fits.
+    #[cfg(feature = "bignum")]
+    pub fn from_big_integer(b: BigInt) -> Value {
+        match b.to_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::BigInteger(Box::new(b)),
+        }
+    }
+
+    pub fn new_string(s: impl Into<Rc<str>>) -> Value {
+        Value::StringV(s.into())
+    }
+
+    pub fn new_list(values: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(values));
+        gc::track(&list);
+        Value::List(list)
+    }
+
+    pub fn new_set(values: impl IntoIterator<Item = Value>) -> Value {
+        Value::Set(SetStruct::new_set(values))
+    }
+
+    pub fn new_deque(kind: DequeKind, values: impl IntoIterator<Item = Value>) -> Value {
+        Value::Deque(DequeStruct::new_deque(kind, values))
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
             Value::Boolean(b) => *b,
@@ -100,15 +749,191 @@ impl Value {
         }
     }
 
-    pub fn to_string(self) -> String {
-        match self {
-            Value::Boolean(b) => format!("{}", b),
-            Value::Class(class) => format!("CLASS {:?}", class.borrow()),
-            Value::Function(_function) => "FUNCTION".to_string(),
-            Value::Nil => "nil".to_string(),
-            Value::Number(n) => format!("{}", n),
-            Value::Object(object) => format!("Instance of {:?}", object.borrow().class.borrow().name),
-            Value::StringV(s) => s,
+}
+
+/// Formats a float the way jlox does: Java's Double.toString, minus any
+/// trailing ".0". Magnitudes outside [1e-3, 1e7) use scientific notation.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return "NaN".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    let magnitude = n.abs();
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        return format!("{}", n);
+    }
+    // Rust's {:e} already gives the shortest round-tripping digits, as Java
+    // does; Java always writes at least one fractional digit.
+    let scientific = format!("{:e}", n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exponent)
+    } else {
+        format!("{}.0E{}", mantissa, exponent)
+    }
+}
+
+/// A value used as a hash map key. Strings, booleans, nil, ranges and numbers are keys
+/// by value, with integral floats equal to the matching integer as under ==,
+/// and NaN equal to itself. Lists, instances and classes can be mutated or
+/// hold mutable state, so they are keys by identity, as are generators and user functions
+/// (by declaration and closure). Builtins are keys by name and receiver.
+#[derive(Debug, Clone)]
+pub struct ValueKey(pub Value);
+
+impl ValueKey {
+    // Integral floats hash as integers so that 1 and 1.0 are the same key.
+    fn integral(&self) -> Option<i64> {
+        match &self.0 {
+            Value::Integer(i) => Some(*i),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 9.2e18 => Some(*n as i64),
+            _ => None,
         }
     }
 }
+
+fn function_eq(f1: &Function, f2: &Function) -> bool {
+    match (f1, f2) {
+        (Function::UserDefined(u1), Function::UserDefined(u2)) => {
+            Rc::ptr_eq(&u1.declaration, &u2.declaration) && u1.environment.id() == u2.environment.id()
+        }
+        (Function::Builtin(b1), Function::Builtin(b2)) => b1 == b2,
+        (Function::BoundBuiltin(b1, r1), Function::BoundBuiltin(b2, r2)) => {
+            b1 == b2 && ValueKey((**r1).clone()) == ValueKey((**r2).clone())
+        }
+        (Function::Memoized(m1), Function::Memoized(m2)) => m1 == m2,
+        (Function::Compiled(c1), Function::Compiled(c2)) => Rc::ptr_eq(c1, c2),
+        _ => false,
+    }
+}
+
+impl cmp::PartialEq for ValueKey {
+    fn eq(&self, other: &ValueKey) -> bool {
+        if let (Some(i1), Some(i2)) = (self.integral(), other.integral()) {
+            return i1 == i2;
+        }
+        match (&self.0, &other.0) {
+            #[cfg(feature = "bignum")]
+            (Value::BigInteger(b1), Value::BigInteger(b2)) => b1 == b2,
+            (Value::Boolean(b1), Value::Boolean(b2)) => b1 == b2,
+            (Value::Class(c1), Value::Class(c2)) => Rc::ptr_eq(c1, c2),
+            (Value::Deque(d1), Value::Deque(d2)) => Rc::ptr_eq(d1, d2),
+            (Value::Function(f1), Value::Function(f2)) => function_eq(f1, f2),
+            (Value::Generator(g1), Value::Generator(g2)) => Rc::ptr_eq(g1, g2),
+            (Value::List(l1), Value::List(l2)) => Rc::ptr_eq(l1, l2),
+            (Value::Namespace(n1), Value::Namespace(n2)) => n1 == n2,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Number(n1), Value::Number(n2)) => n1.to_bits() == n2.to_bits() || n1 == n2,
+            (Value::Object(o1), Value::Object(o2)) => Rc::ptr_eq(o1, o2),
+            (Value::Range(r1), Value::Range(r2)) => r1 == r2,
+            (Value::Set(s1), Value::Set(s2)) => Rc::ptr_eq(s1, s2),
+            (Value::StringV(s1), Value::StringV(s2)) => s1 == s2,
+            _ => false,
+        }
+    }
+}
+
+impl cmp::Eq for ValueKey {}
+
+impl Hash for ValueKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Some(i) = self.integral() {
+            return i.hash(state);
+        }
+        match &self.0 {
+            #[cfg(feature = "bignum")]
+            Value::BigInteger(b) => b.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Class(class) => Rc::as_ptr(class).hash(state),
+            Value::Deque(deque) => Rc::as_ptr(deque).hash(state),
+            Value::Function(Function::UserDefined(user_defined)) => Rc::as_ptr(&user_defined.declaration).hash(state),
+            Value::Function(Function::Builtin(builtin)) | Value::Function(Function::BoundBuiltin(builtin, _)) => {
+                builtin.name.hash(state)
+            }
+            Value::Function(Function::Memoized(memoized)) => Rc::as_ptr(&memoized.cache).hash(state),
+            Value::Function(Function::Compiled(compiled)) => Rc::as_ptr(compiled).hash(state),
+            Value::Generator(generator) => Rc::as_ptr(generator).hash(state),
+            Value::Integer(i) => i.hash(state),
+            Value::List(list) => Rc::as_ptr(list).hash(state),
+            Value::Namespace(namespace) => namespace.environment.id().hash(state),
+            Value::Nil => 0_u8.hash(state),
+            // -0.0 == 0.0 is caught by integral above, so bits are consistent with eq.
+            Value::Number(n) => n.to_bits().hash(state),
+            Value::Object(object) => Rc::as_ptr(object).hash(state),
+            Value::Range(range) => (range.start, range.end, range.inclusive).hash(state),
+            Value::Set(set) => Rc::as_ptr(set).hash(state),
+            Value::StringV(s) => s.hash(state),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_value(self, f, &mut HashSet::new())
+    }
+}
+
+// visiting holds the collections being written further up, any of which is
+// written as ... where it contains itself.
+fn write_value(value: &Value, f: &mut fmt::Formatter, visiting: &mut HashSet<*const ()>) -> fmt::Result {
+    match value {
+        #[cfg(feature = "bignum")]
+        Value::BigInteger(b) => write!(f, "{}", b),
+        Value::Boolean(b) => write!(f, "{}", b),
+        Value::Class(class) => write!(f, "<class {}>", class.borrow().name),
+        Value::Function(_function) => write!(f, "FUNCTION"),
+        Value::Deque(deque) => {
+            let pointer = Rc::as_ptr(deque) as *const ();
+            let deque = deque.borrow();
+            write!(f, "{}", deque.kind.name())?;
+            write_elements(f, "[", &deque.to_vec(), "]", pointer, visiting)
+        }
+        Value::Generator(generator) => write!(f, "<generator {}>", generator.borrow().name),
+        Value::Integer(i) => write!(f, "{}", i),
+        Value::List(list) => write_elements(f, "[", &list.borrow(), "]", Rc::as_ptr(list) as *const (), visiting),
+        Value::Namespace(namespace) => write!(f, "<module {}>", namespace.name),
+        Value::Nil => write!(f, "nil"),
+        Value::Number(n) => write!(f, "{}", format_number(*n)),
+        Value::Object(object) => write!(f, "<instance of {}>", object.borrow().class.borrow().name),
+        Value::Range(range) => {
+            let operator = if range.inclusive { "..=" } else { ".." };
+            write!(f, "{}{}{}", range.start, operator, range.end)
+        }
+        Value::Set(set) => write_elements(f, "{", set.borrow().members(), "}", Rc::as_ptr(set) as *const (), visiting),
+        Value::StringV(s) => write!(f, "{}", s),
+    }
+}
+
+fn write_elements(
+    f: &mut fmt::Formatter,
+    open: &str,
+    values: &[Value],
+    close: &str,
+    pointer: *const (),
+    visiting: &mut HashSet<*const ()>,
+) -> fmt::Result {
+    if !visiting.insert(pointer) {
+        return write!(f, "{}...{}", open, close);
+    }
+    write!(f, "{}", open)?;
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write_value(value, f, visiting)?;
+    }
+    visiting.remove(&pointer);
+    write!(f, "{}", close)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_value_size() {
+        assert!(std::mem::size_of::<Value>() <= 4 * std::mem::size_of::<usize>());
+    }
+}
@@ -0,0 +1,361 @@
+//! Runs bytecode from the compiler module on a stack of values, one frame per
+//! call of a compiled function. The values, globals and builtins are the
+//! interpreter's own, so compiled code calls builtins, and builtins call
+//! compiled functions, through the interpreter as the tree-walker does.
+//!
+//! The stack lives on the interpreter, shared by every run of compiled code,
+//! since a closure called back from a builtin may use the locals of a call
+//! still waiting on that builtin. Each run pushes its frames above those of
+//! the runs below it and leaves the stack as it found it.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use crate::chunk::{Closure, Compiled, CompiledFunction, Instruction, Upvalue};
+use crate::interp_error::{InterpError, InterpResult, StatementResult};
+use crate::interpreter::{check_arity, negate, Interpreter};
+use crate::token::{Token, TokenKind};
+use crate::value::{Function, Value};
+
+/// The values of every run of compiled code, and the upvalues still open on
+/// them.
+#[derive(Default)]
+pub(crate) struct VmState {
+    stack: Vec<Value>,
+    // Ordered by slot, so those closed as a scope ends are the last ones.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl VmState {
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().unwrap()
+    }
+
+    // The upvalue for the local in slot, shared with any closure that has
+    // already captured it.
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self.open_upvalues.partition_point(|upvalue| open_slot(upvalue) < slot);
+        if let Some(upvalue) = self.open_upvalues.get(position) {
+            if open_slot(upvalue) == slot {
+                return upvalue.clone();
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+
+    // Moves the values of the locals from slot first up into the upvalues
+    // capturing them.
+    fn close(&mut self, first: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = open_slot(upvalue);
+            if slot < first {
+                break;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn get_upvalue(&self, upvalue: &RefCell<Upvalue>) -> Value {
+        match &*upvalue.borrow() {
+            Upvalue::Open(slot) => self.stack[*slot].clone(),
+            Upvalue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn set_upvalue(&mut self, upvalue: &RefCell<Upvalue>, value: Value) {
+        match &mut *upvalue.borrow_mut() {
+            Upvalue::Open(slot) => self.stack[*slot] = value,
+            Upvalue::Closed(closed) => *closed = value,
+        }
+    }
+}
+
+fn open_slot(upvalue: &RefCell<Upvalue>) -> usize {
+    match *upvalue.borrow() {
+        Upvalue::Open(slot) => slot,
+        Upvalue::Closed(_) => unreachable!("Closed upvalues are not kept open."),
+    }
+}
+
+// A call waiting for the one it made to return.
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Where the function's locals start on the stack, its arguments first.
+    base: usize,
+}
+
+pub(crate) fn run_script(interpreter: &mut Interpreter, script: Compiled) -> StatementResult {
+    execute(interpreter, Rc::new(Closure::new(script)), Vec::new()).map(drop)
+}
+
+/// Calls a compiled function with arguments already checked against its arity.
+pub(crate) fn call(interpreter: &mut Interpreter, closure: Rc<Closure>, arguments: Vec<Value>) -> InterpResult {
+    execute(interpreter, closure, arguments)
+}
+
+fn token(function: &CompiledFunction, index: u32) -> &Token {
+    &function.chunk.tokens[index as usize]
+}
+// Integer arithmetic and comparison, the bulk of most loops, without the
+// interpreter's general dispatch. None leaves the operation to it, including
+// on overflow.
+fn integer_operation(left: &Value, right: &Value, operator: TokenKind) -> Option<Value> {
+    let (Value::Integer(i1), Value::Integer(i2)) = (left, right) else {
+        return None;
+    };
+    let value = match operator {
+        TokenKind::Plus => Value::Integer(i1.checked_add(*i2)?),
+        TokenKind::Minus => Value::Integer(i1.checked_sub(*i2)?),
+        TokenKind::Star => Value::Integer(i1.checked_mul(*i2)?),
+        TokenKind::Less => Value::Boolean(i1 < i2),
+        TokenKind::LessEqual => Value::Boolean(i1 <= i2),
+        TokenKind::Greater => Value::Boolean(i1 > i2),
+        TokenKind::GreaterEqual => Value::Boolean(i1 >= i2),
+        TokenKind::EqualEqual => Value::Boolean(i1 == i2),
+        TokenKind::BangEqual => Value::Boolean(i1 != i2),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn execute(interpreter: &mut Interpreter, closure: Rc<Closure>, arguments: Vec<Value>) -> InterpResult {
+    let floor = interpreter.vm.stack.len();
+    interpreter.vm.stack.extend(arguments);
+    let depth = interpreter.call_depth;
+    let result = run(interpreter, closure, floor);
+    // Frames left by a failing run are no longer nested calls.
+    interpreter.call_depth = depth;
+    // Whatever the run left, on returning or failing, is closed over and popped.
+    interpreter.vm.close(floor);
+    interpreter.vm.stack.truncate(floor);
+    result
+}
+
+fn run(interpreter: &mut Interpreter, mut closure: Rc<Closure>, mut base: usize) -> InterpResult {
+    let mut globals = interpreter.globals.clone();
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut ip = 0;
+    loop {
+        let instruction = closure.function.chunk.code[ip];
+        ip += 1;
+        let vm = &mut interpreter.vm;
+        match instruction {
+            Instruction::Constant(index) => vm.push(closure.function.chunk.constants[index as usize].clone()),
+            Instruction::Nil => vm.push(Value::Nil),
+            Instruction::True => vm.push(Value::Boolean(true)),
+            Instruction::False => vm.push(Value::Boolean(false)),
+            Instruction::Pop => {
+                vm.pop();
+            }
+            Instruction::PopN(count) => vm.stack.truncate(vm.stack.len() - count as usize),
+            Instruction::GetLocal(slot) => vm.push(vm.stack[base + slot as usize].clone()),
+            Instruction::SetLocal(slot) => vm.stack[base + slot as usize] = vm.peek().clone(),
+            Instruction::GetUpvalue(index) => vm.push(vm.get_upvalue(&closure.upvalues[index as usize])),
+            Instruction::SetUpvalue(index) => {
+                let value = vm.peek().clone();
+                vm.set_upvalue(&closure.upvalues[index as usize], value);
+            }
+            Instruction::CloseUpvalues(slot) => vm.close(base + slot as usize),
+            Instruction::Closure(index) => {
+                let Value::Function(Function::Compiled(prototype)) = &closure.function.chunk.constants[index as usize] else {
+                    unreachable!("Closures are made of compiled functions.");
+                };
+                let function = prototype.function.clone();
+                let upvalues = function
+                    .captures
+                    .iter()
+                    .map(|capture| match capture.local {
+                        true => vm.capture(base + capture.index as usize),
+                        false => closure.upvalues[capture.index as usize].clone(),
+                    })
+                    .collect();
+                vm.push(Value::Function(Function::Compiled(Rc::new(Closure { function, upvalues }))));
+            }
+            Instruction::DefineGlobal(name) => {
+                let value = vm.pop();
+                globals.declare_and_assign(token(&closure.function, name), value)?;
+            }
+            Instruction::DefineConstant(name) => {
+                let value = vm.pop();
+                globals.declare_constant(token(&closure.function, name), value)?;
+            }
+            Instruction::GetGlobal(name) => vm.push(globals.get(token(&closure.function, name))?),
+            Instruction::SetGlobal(name) => {
+                let value = vm.peek().clone();
+                globals.assign(token(&closure.function, name), value)?;
+            }
+            Instruction::GetProperty(name) => {
+                let target = vm.pop();
+                let value = interpreter.get_property(target, token(&closure.function, name))?;
+                interpreter.vm.push(value);
+            }
+            Instruction::SetProperty(name) => {
+                let value = vm.pop();
+                let target = vm.pop();
+                let value = interpreter.set_property(target, token(&closure.function, name), value)?;
+                interpreter.vm.push(value);
+            }
+            Instruction::Index(bracket) => {
+                let key = vm.pop();
+                let target = vm.pop();
+                let value = interpreter.index_value(&target, &key, token(&closure.function, bracket))?;
+                interpreter.vm.push(value);
+            }
+            Instruction::SetIndex(bracket) => {
+                let value = vm.pop();
+                let key = vm.pop();
+                let target = vm.pop();
+                let value = interpreter.set_index_value(&target, &key, value, token(&closure.function, bracket))?;
+                interpreter.vm.push(value);
+            }
+            Instruction::Binary(operator) => {
+                let right = vm.pop();
+                let left = vm.pop();
+                let operator = token(&closure.function, operator);
+                let value = match integer_operation(&left, &right, operator.kind) {
+                    Some(value) => value,
+                    None => interpreter.binary_operation(left, right, operator)?,
+                };
+                interpreter.vm.push(value);
+            }
+            Instruction::Chain(operator, end) => {
+                let right = vm.pop();
+                let left = vm.pop();
+                if interpreter.binary_operation(left, right.clone(), token(&closure.function, operator))?.is_truthy() {
+                    interpreter.vm.push(right);
+                } else {
+                    interpreter.vm.push(Value::Boolean(false));
+                    ip = end as usize;
+                }
+            }
+            Instruction::Negate(operator) => {
+                let value = vm.pop();
+                vm.push(negate(value, token(&closure.function, operator))?);
+            }
+            Instruction::Not => {
+                let value = vm.pop();
+                vm.push(Value::Boolean(!value.is_truthy()));
+            }
+            Instruction::ToBoolean => {
+                let value = vm.pop();
+                vm.push(Value::Boolean(value.is_truthy()));
+            }
+            Instruction::Jump(target) => {
+                // Jumping back is how loops repeat, so a timeout stops them here.
+                if (target as usize) < ip {
+                    interpreter.tick()?;
+                    interpreter.check_deadline()?;
+                }
+                ip = target as usize;
+            }
+            Instruction::JumpIfFalse(target) => {
+                if !vm.pop().is_truthy() {
+                    ip = target as usize;
+                }
+            }
+            Instruction::And(target) => {
+                if vm.peek().is_truthy() {
+                    vm.pop();
+                } else {
+                    *vm.stack.last_mut().unwrap() = Value::Boolean(false);
+                    ip = target as usize;
+                }
+            }
+            Instruction::Or(target) => {
+                if vm.peek().is_truthy() {
+                    *vm.stack.last_mut().unwrap() = Value::Boolean(true);
+                    ip = target as usize;
+                } else {
+                    vm.pop();
+                }
+            }
+            Instruction::JumpIfNotNil(target) => {
+                if let Value::Nil = vm.peek() {
+                    vm.pop();
+                } else {
+                    ip = target as usize;
+                }
+            }
+            Instruction::JumpIfNil(target) => {
+                if let Value::Nil = vm.peek() {
+                    ip = target as usize;
+                }
+            }
+            Instruction::Call(count, callee_name, paren) => {
+                interpreter.tick()?;
+                interpreter.check_deadline()?;
+                let vm = &mut interpreter.vm;
+                let callee_slot = vm.stack.len() - count as usize - 1;
+                if let Value::Function(Function::Compiled(callee)) = &vm.stack[callee_slot] {
+                    let callee = callee.clone();
+                    let name = &token(&closure.function, callee_name).content;
+                    check_arity(&Function::Compiled(callee.clone()), count as usize, name, token(&closure.function, paren))?;
+                    // Each frame counts toward the interpreter's call depth
+                    // limit, as a call on the tree-walker does.
+                    if interpreter.call_depth >= interpreter.max_call_depth {
+                        return Err(InterpError::new("Stack overflow.", token(&closure.function, paren).clone()));
+                    }
+                    interpreter.call_depth += 1;
+                    frames.push(Frame {
+                        closure: mem::replace(&mut closure, callee),
+                        ip,
+                        base,
+                    });
+                    ip = 0;
+                    base = callee_slot + 1;
+                } else {
+                    let arguments = vm.stack.split_off(callee_slot + 1);
+                    let callee = vm.pop();
+                    let name = &token(&closure.function, callee_name).content;
+                    let value = interpreter.call_value(callee, arguments, name, token(&closure.function, paren))?;
+                    interpreter.vm.push(value);
+                }
+            }
+            Instruction::List(count) => {
+                let elements = vm.stack.split_off(vm.stack.len() - count as usize);
+                vm.push(Value::new_list(elements));
+            }
+            Instruction::Print(expr) => {
+                let value = vm.pop();
+                interpreter.print_value(&value, token(&closure.function, expr))?;
+            }
+            Instruction::AssertFailed(keyword, has_message) => {
+                let keyword = token(&closure.function, keyword);
+                let message = if has_message {
+                    let message = vm.pop();
+                    format!("Assertion failed: {}", interpreter.stringify(&message, keyword)?)
+                } else {
+                    "Assertion failed.".to_string()
+                };
+                return Err(InterpError::new(&message, keyword.clone()));
+            }
+            Instruction::Return => {
+                let result = vm.pop();
+                vm.close(base);
+                let Some(frame) = frames.pop() else {
+                    return Ok(result);
+                };
+                interpreter.call_depth -= 1;
+                // The callee's slot sits just below its locals.
+                vm.stack.truncate(base - 1);
+                vm.push(result);
+                closure = frame.closure;
+                ip = frame.ip;
+                base = frame.base;
+            }
+        }
+    }
+}